@@ -0,0 +1,101 @@
+//! Throughput of some common (de)serializers, run via `cargo bench --features bench`.
+//!
+//! The serializers write into a `NullWriter` and the deserializers read from a byte slice, so the
+//! measurements do not include any IO. Every benchmark runs a fixed number of iterations after a
+//! warmup and prints the mean time per iteration.
+#![allow(deprecated)]
+
+extern crate async_serialization;
+extern crate futures_core;
+
+use std::borrow::Cow;
+use std::hint::black_box;
+use std::sync::Arc;
+use std::time::Instant;
+
+use futures_core::{Async, Future};
+use futures_core::task::{Context, LocalMap, Wake, Waker};
+
+use async_serialization::{AsyncDeserialize, AsyncSerialize, AsyncSerializeRef};
+use async_serialization::arc_bytes::DeserArcBytes;
+use async_serialization::cow::SerCowBytes;
+use async_serialization::protobuf_wire::{ReadFixed32, WriteFixed32};
+use async_serialization::testing::NullWriter;
+
+const WARMUP: u32 = 100;
+const ITERATIONS: u32 = 1000;
+
+struct NoopWake;
+
+impl Wake for NoopWake {
+    fn wake(_: &Arc<NoopWake>) {}
+}
+
+// Poll a future that does not wait for anything, which thus completes on its first poll.
+fn ready<F: Future>(mut fut: F, cx: &mut Context) -> F::Item {
+    match fut.poll(cx) {
+        Ok(Async::Ready(item)) => item,
+        Ok(Async::Pending) => panic!("the benchmarked future is pending"),
+        Err(_) => panic!("the benchmarked future failed"),
+    }
+}
+
+// Run `f` `ITERATIONS` times after a warmup, and print the mean time and throughput of an
+// iteration that processes `bytes` bytes.
+fn bench<F: FnMut()>(name: &str, bytes: usize, mut f: F) {
+    for _ in 0..WARMUP {
+        f();
+    }
+
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        f();
+    }
+    let elapsed = start.elapsed();
+
+    let mb_per_sec = (bytes as f64 * ITERATIONS as f64) / elapsed.as_secs_f64() / 1e6;
+    println!("{:<24} {:>12?}/iter {:>10.1} MB/s",
+             name,
+             elapsed / ITERATIONS,
+             mb_per_sec);
+}
+
+fn main() {
+    let waker = Waker::from(Arc::new(NoopWake));
+    let mut map = LocalMap::new();
+    let mut cx = Context::without_spawn(&mut map, &waker);
+
+    let mut writer = Some(NullWriter::new());
+    bench("serialize 1000 u32", 4000, || {
+        let mut w = writer.take().unwrap();
+        for i in 0..1000u32 {
+            w = ready(WriteFixed32::from_val(w, black_box(i)), &mut cx).0;
+        }
+        writer = Some(w);
+    });
+
+    let blob = Cow::Owned(vec![0xab; 1024]);
+    bench("serialize 1KB Vec<u8>", 1028, || {
+        let w = writer.take().unwrap();
+        writer = Some(ready(SerCowBytes::from_ref(w, black_box(&blob)), &mut cx).0);
+    });
+
+    let u32s: Vec<u8> = (0..1000u32).flat_map(|i| i.to_le_bytes()).collect();
+    bench("deserialize 1000 u32", 4000, || {
+        let mut reader = &u32s[..];
+        for _ in 0..1000 {
+            let (rest, val, _) = ready(ReadFixed32::from_reader(reader), &mut cx);
+            black_box(val);
+            reader = rest;
+        }
+    });
+
+    let mut encoded = (1024u32).to_be_bytes().to_vec();
+    encoded.extend_from_slice(&blob);
+    bench("deserialize 1KB Vec<u8>", 1028, || {
+        let (_, val, _) = ready(DeserArcBytes::from_reader(&encoded[..]), &mut cx);
+        black_box(val);
+    });
+
+    black_box(writer.unwrap().written());
+}
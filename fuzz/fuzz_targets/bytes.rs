@@ -0,0 +1,10 @@
+#![no_main]
+#![allow(deprecated)]
+
+use async_serialization::fuzz_support::{drive_from_bytes, FuzzReader};
+use async_serialization::protobuf_wire::ReadBytes;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = drive_from_bytes::<ReadBytes<FuzzReader>, _, _>(data);
+});
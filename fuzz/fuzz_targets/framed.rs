@@ -0,0 +1,14 @@
+#![no_main]
+#![allow(deprecated)]
+
+use async_serialization::framed::ReadFramed;
+use async_serialization::fuzz_support::{drive_from_bytes, FuzzReader};
+use async_serialization::take_reader::TakeReader;
+use async_serialization::varint::{ReadVarint, VarintError};
+use libfuzzer_sys::fuzz_target;
+
+type Frame<'a> = ReadFramed<ReadVarint<TakeReader<FuzzReader<'a>>>, FuzzReader<'a>, u64, VarintError>;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = drive_from_bytes::<Frame, _, _>(data);
+});
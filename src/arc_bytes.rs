@@ -0,0 +1,124 @@
+//! Serialization of shared byte buffers.
+//!
+//! An `Arc<[u8]>` is encoded as its length as a big-endian `u32`, followed by the bytes, the same
+//! as the body of a [path](../path/index.html).
+//!
+//! Buffers longer than `u32::MAX` bytes can not be serialized, the serializer fails with an
+//! `ErrorKind::InvalidInput` error without writing anything.
+
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::sync::Arc;
+
+use futures_core::{Async, Future, Poll};
+use futures_core::task::Context;
+use futures_io::{AsyncRead, AsyncWrite, Error as FutIoErr};
+
+use {AsyncDeserialize, AsyncSerialize, AsyncSerializeLen, AsyncWriterFuture,
+     AsyncWriterFutureLen, DeserializeError, Restorable};
+use progress::PayloadRemaining;
+use util::{prefix_fits, ReadLenPrefixed, TryWriteLenPrefixed};
+
+/// Serializes an `Arc<[u8]>`, without copying the bytes.
+///
+/// For buffers that can not be serialized, `total_bytes` returns zero.
+pub struct SerArcBytes<W>(TryWriteLenPrefixed<W, Arc<[u8]>>);
+
+impl<W: AsyncWrite> Future for SerArcBytes<W> {
+    type Item = (W, usize);
+    type Error = (W, FutIoErr);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        self.0.poll(cx)
+    }
+}
+
+impl<W: AsyncWrite> AsyncWriterFuture<W> for SerArcBytes<W> {
+    fn already_written(&self) -> usize {
+        self.0.already_written()
+    }
+}
+
+impl<W: AsyncWrite> AsyncWriterFutureLen<W> for SerArcBytes<W> {
+    fn remaining_bytes(&self) -> usize {
+        self.0.remaining_bytes()
+    }
+}
+
+impl<W: AsyncWrite> AsyncSerialize<W> for SerArcBytes<W> {
+    type Serialized = Arc<[u8]>;
+
+    fn from_val(writer: W, val: Arc<[u8]>) -> Self {
+        let body = if prefix_fits(val.len()) {
+            Some(val)
+        } else {
+            None
+        };
+        SerArcBytes(TryWriteLenPrefixed::new(writer, body, "buffer is too long"))
+    }
+}
+
+impl<W: AsyncWrite> AsyncSerializeLen<W> for SerArcBytes<W> {
+    fn total_bytes(val: &Arc<[u8]>) -> usize {
+        if prefix_fits(val.len()) {
+            4 + val.len()
+        } else {
+            0
+        }
+    }
+}
+
+/// Deserializes an `Arc<[u8]>`.
+///
+/// The bytes are read into a `Vec<u8>` that grows as data arrives (so a bogus length can not make
+/// this allocate a huge buffer up front), which is then converted into the `Arc<[u8]>`.
+pub struct DeserArcBytes<R>(ReadLenPrefixed<R>);
+
+impl<R: AsyncRead> Future for DeserArcBytes<R> {
+    type Item = (R, Arc<[u8]>, usize);
+    type Error = (R, DeserializeError<ArcBytesError>);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        match self.0.poll(cx) {
+            Ok(Async::Ready((reader, bytes, read))) => {
+                Ok(Async::Ready((reader, Arc::from(bytes), read)))
+            }
+            Ok(Async::Pending) => Ok(Async::Pending),
+            Err((reader, err)) => Err((reader, DeserializeError::ReaderError(err))),
+        }
+    }
+}
+
+impl<R: AsyncRead> AsyncDeserialize<R, Arc<[u8]>, ArcBytesError> for DeserArcBytes<R> {
+    fn from_reader(reader: R) -> Self {
+        DeserArcBytes(ReadLenPrefixed::new(reader))
+    }
+
+    fn already_read(&self) -> usize {
+        self.0.already_read()
+    }
+}
+
+impl<R> Restorable<R> for DeserArcBytes<R> {
+    fn restore(self) -> Option<R> {
+        self.0.restore()
+    }
+}
+
+impl<R> PayloadRemaining for DeserArcBytes<R> {
+    fn payload_remaining(&self) -> Option<usize> {
+        self.0.payload_remaining()
+    }
+}
+
+/// The data error of a `DeserArcBytes`. Any sequence of bytes is valid, so this has no values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArcBytesError {}
+
+impl Display for ArcBytesError {
+    fn fmt(&self, _: &mut Formatter) -> Result<(), fmt::Error> {
+        match *self {}
+    }
+}
+
+impl Error for ArcBytesError {}
@@ -0,0 +1,87 @@
+//! Deserialization of single characters encoded as one byte, e.g. the command bytes or status
+//! codes of text-based protocols.
+
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+
+use futures_core::{Async, Future, Poll};
+use futures_core::task::Context;
+use futures_io::AsyncRead;
+
+use {AsyncDeserialize, DeserializeError, Restorable};
+use util::ReadExact;
+
+/// Deserializes a `char` from a single byte.
+///
+/// By default, only ASCII bytes (`0..=127`) are accepted. A deserializer created via
+/// `allow_extended` accepts every byte and interprets it as Latin-1, i.e. as the code point of
+/// the same value.
+pub struct DeserAsciiChar<R> {
+    inner: ReadExact<R, [u8; 1]>,
+    extended: bool,
+}
+
+impl<R: AsyncRead> DeserAsciiChar<R> {
+    /// Create a new `DeserAsciiChar` that accepts any byte, not only ASCII.
+    pub fn allow_extended(reader: R) -> DeserAsciiChar<R> {
+        DeserAsciiChar {
+            inner: ReadExact::new(reader, [0; 1]),
+            extended: true,
+        }
+    }
+}
+
+impl<R: AsyncRead> Future for DeserAsciiChar<R> {
+    type Item = (R, char, usize);
+    type Error = (R, DeserializeError<AsciiCharError>);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        let (reader, [byte], read) = match self.inner.poll(cx) {
+            Ok(Async::Ready(done)) => done,
+            Ok(Async::Pending) => return Ok(Async::Pending),
+            Err((reader, err)) => return Err((reader, DeserializeError::ReaderError(err))),
+        };
+
+        if byte.is_ascii() || self.extended {
+            Ok(Async::Ready((reader, char::from(byte), read)))
+        } else {
+            Err((reader, DeserializeError::DataError(AsciiCharError::NonAscii(byte))))
+        }
+    }
+}
+
+impl<R: AsyncRead> AsyncDeserialize<R, char, AsciiCharError> for DeserAsciiChar<R> {
+    fn from_reader(reader: R) -> Self {
+        DeserAsciiChar {
+            inner: ReadExact::new(reader, [0; 1]),
+            extended: false,
+        }
+    }
+
+    fn already_read(&self) -> usize {
+        self.inner.already_read()
+    }
+}
+
+impl<R> Restorable<R> for DeserAsciiChar<R> {
+    fn restore(self) -> Option<R> {
+        self.inner.restore()
+    }
+}
+
+/// Everything that can go wrong when deserializing a character with a `DeserAsciiChar`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AsciiCharError {
+    /// A byte with the high bit set, which is not an ASCII character.
+    NonAscii(u8),
+}
+
+impl Display for AsciiCharError {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            AsciiCharError::NonAscii(byte) => write!(f, "Non-ASCII byte {:#04x}", byte),
+        }
+    }
+}
+
+impl Error for AsciiCharError {}
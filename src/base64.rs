@@ -0,0 +1,371 @@
+//! Serialization of binary data as base64 ASCII (RFC 4648).
+//!
+//! Every three bytes are encoded as four characters of either the standard or the URL-safe
+//! alphabet. With padding, the last group is filled up to four characters with `=`, without
+//! padding it is two or three characters long. There is no length prefix: the deserializer either
+//! reads a given number of characters or reads up to a terminator byte.
+//!
+//! The deserializer only accepts canonical encodings, i.e. the bits of the last character that do
+//! not belong to any byte must be zero, and padding must be present exactly if it is configured.
+//! This module is only available with the `base64` feature.
+
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::marker::PhantomData;
+
+use futures_core::{Async, Future, Poll};
+use futures_core::task::Context;
+use futures_io::{AsyncRead, AsyncWrite, Error as FutIoErr, ErrorKind};
+
+use {AsyncSerializeRef, AsyncSerializeRefLen, AsyncWriterFuture, AsyncWriterFutureLen,
+     DeserializeError, Restorable};
+use util::WriteAll;
+
+const STANDARD: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+const URL_SAFE: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+/// The characters that encode the 64 values of six bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Alphabet {
+    /// The standard alphabet, ending in `+` and `/`.
+    Standard,
+    /// The URL and filename safe alphabet, ending in `-` and `_`.
+    UrlSafe,
+}
+
+/// Whether the last group of characters is padded with `=`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Padding {
+    /// Always write and expect complete groups of four characters.
+    Padded,
+    /// Omit the padding characters, and reject them when reading.
+    Unpadded,
+}
+
+/// The encoding used by a `SerBase64` or `DeserBase64`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Base64Config {
+    alphabet: Alphabet,
+    padding: Padding,
+}
+
+impl Base64Config {
+    /// The standard alphabet with padding, as used by `from_ref`.
+    pub const STANDARD: Base64Config = Base64Config::new(Alphabet::Standard, Padding::Padded);
+
+    /// The URL-safe alphabet without padding, as commonly used in URLs and tokens.
+    pub const URL_SAFE: Base64Config = Base64Config::new(Alphabet::UrlSafe, Padding::Unpadded);
+
+    /// Create a new `Base64Config`.
+    pub const fn new(alphabet: Alphabet, padding: Padding) -> Base64Config {
+        Base64Config { alphabet, padding }
+    }
+
+    /// Return the alphabet of this encoding.
+    pub fn alphabet(&self) -> Alphabet {
+        self.alphabet
+    }
+
+    /// Return whether this encoding pads the last group.
+    pub fn padding(&self) -> Padding {
+        self.padding
+    }
+
+    /// Return the number of characters that encode `len` bytes.
+    pub fn encoded_len(&self, len: usize) -> usize {
+        match self.padding {
+            Padding::Padded => 4 * len.div_ceil(3),
+            Padding::Unpadded => (4 * len).div_ceil(3),
+        }
+    }
+
+    fn symbols(&self) -> &'static [u8; 64] {
+        match self.alphabet {
+            Alphabet::Standard => STANDARD,
+            Alphabet::UrlSafe => URL_SAFE,
+        }
+    }
+
+    fn decode_symbol(&self, c: u8) -> Option<u32> {
+        self.symbols()
+            .iter()
+            .position(|symbol| *symbol == c)
+            .map(|value| value as u32)
+    }
+
+    fn encode(&self, bytes: &[u8]) -> Vec<u8> {
+        let symbols = self.symbols();
+        let mut out = Vec::with_capacity(self.encoded_len(bytes.len()));
+
+        for chunk in bytes.chunks(3) {
+            let mut group = [0; 3];
+            group[..chunk.len()].copy_from_slice(chunk);
+            let bits = (u32::from(group[0]) << 16) | (u32::from(group[1]) << 8) |
+                       u32::from(group[2]);
+
+            for i in 0..chunk.len() + 1 {
+                out.push(symbols[((bits >> (18 - 6 * i)) & 0x3f) as usize]);
+            }
+            if self.padding == Padding::Padded {
+                out.extend_from_slice(&b"=="[..3 - chunk.len()]);
+            }
+        }
+        out
+    }
+}
+
+impl Default for Base64Config {
+    fn default() -> Base64Config {
+        Base64Config::STANDARD
+    }
+}
+
+/// Serializes a byte slice as base64 ASCII.
+///
+/// The encoding is computed up front, so the slice is not borrowed while writing. `from_ref` and
+/// `total_bytes` use `Base64Config::STANDARD`.
+pub struct SerBase64<'val, W> {
+    inner: WriteAll<W, Vec<u8>>,
+    _val: PhantomData<&'val [u8]>,
+}
+
+impl<'val, W: AsyncWrite> SerBase64<'val, W> {
+    /// Create a new `SerBase64`, writing `val` into `writer` in the given encoding.
+    pub fn new(writer: W, config: Base64Config, val: &'val [u8]) -> SerBase64<'val, W> {
+        SerBase64 {
+            inner: WriteAll::new(writer, config.encode(val)),
+            _val: PhantomData,
+        }
+    }
+}
+
+impl<'val, W: AsyncWrite> Future for SerBase64<'val, W> {
+    type Item = (W, usize);
+    type Error = (W, FutIoErr);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        self.inner.poll(cx)
+    }
+}
+
+impl<'val, W: AsyncWrite> AsyncWriterFuture<W> for SerBase64<'val, W> {
+    fn already_written(&self) -> usize {
+        self.inner.already_written()
+    }
+}
+
+impl<'val, W: AsyncWrite> AsyncWriterFutureLen<W> for SerBase64<'val, W> {
+    fn remaining_bytes(&self) -> usize {
+        self.inner.remaining_bytes()
+    }
+}
+
+impl<'val, W: AsyncWrite> AsyncSerializeRef<'val, W> for SerBase64<'val, W> {
+    type Serialized = [u8];
+
+    fn from_ref(writer: W, val: &'val [u8]) -> Self {
+        SerBase64::new(writer, Base64Config::STANDARD, val)
+    }
+}
+
+impl<'val, W: AsyncWrite> AsyncSerializeRefLen<'val, W> for SerBase64<'val, W> {
+    fn total_bytes(val: &[u8]) -> usize {
+        Base64Config::STANDARD.encoded_len(val.len())
+    }
+}
+
+/// Where the characters read by a `DeserBase64` end.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Base64End {
+    /// Read exactly this many characters.
+    Len(usize),
+    /// Read up to and including a terminator byte.
+    Terminator {
+        /// The byte that ends the encoded data.
+        terminator: u8,
+        /// The maximum number of characters before the terminator.
+        limit: usize,
+    },
+}
+
+/// Deserializes base64 ASCII into a `Vec<u8>`.
+pub struct DeserBase64<R> {
+    reader: Option<R>,
+    config: Base64Config,
+    end: Base64End,
+    buf: Vec<u8>,
+    group: [u32; 4],
+    group_len: usize,
+    padding: usize,
+    read: usize,
+}
+
+impl<R: AsyncRead> DeserBase64<R> {
+    /// Create a new `DeserBase64`, reading characters of the given encoding up to `end`.
+    pub fn new(reader: R, config: Base64Config, end: Base64End) -> DeserBase64<R> {
+        DeserBase64 {
+            reader: Some(reader),
+            config,
+            end,
+            buf: Vec::new(),
+            group: [0; 4],
+            group_len: 0,
+            padding: 0,
+            read: 0,
+        }
+    }
+
+    /// Return how many bytes have already been read.
+    pub fn already_read(&self) -> usize {
+        self.read
+    }
+
+    fn invalid(&self) -> Base64Error {
+        Base64Error::InvalidBase64 { offset: self.read.saturating_sub(1) }
+    }
+
+    // Append the bytes of the current group to the output, checking that unused bits are zero.
+    fn flush_group(&mut self) -> Result<(), Base64Error> {
+        let len = match self.group_len {
+            0 => return Ok(()),
+            1 => return Err(self.invalid()),
+            len => len - 1,
+        };
+        let bits = (self.group[0] << 18) | (self.group[1] << 12) | (self.group[2] << 6) |
+                   self.group[3];
+        if bits & (0xff_ffff >> (8 * len)) != 0 {
+            return Err(self.invalid());
+        }
+
+        for i in 0..len {
+            self.buf.push((bits >> (16 - 8 * i)) as u8);
+        }
+        self.group = [0; 4];
+        self.group_len = 0;
+        Ok(())
+    }
+
+    fn push(&mut self, c: u8) -> Result<(), Base64Error> {
+        if c == b'=' && self.config.padding == Padding::Padded && self.group_len >= 2 &&
+           self.group_len + self.padding < 4 {
+            self.padding += 1;
+            if self.group_len + self.padding == 4 {
+                self.flush_group()?;
+            }
+            return Ok(());
+        }
+
+        // Nothing may follow the padding of a group.
+        if self.padding > 0 {
+            return Err(self.invalid());
+        }
+        match self.config.decode_symbol(c) {
+            Some(value) => {
+                self.group[self.group_len] = value;
+                self.group_len += 1;
+                if self.group_len == 4 {
+                    self.flush_group()?;
+                }
+                Ok(())
+            }
+            None => Err(self.invalid()),
+        }
+    }
+
+    // Finish decoding after `len` characters of data.
+    fn finish(&mut self, len: usize) -> Result<Vec<u8>, Base64Error> {
+        if self.config.padding == Padding::Padded && self.group_len != 0 {
+            return Err(Base64Error::InvalidBase64 { offset: len });
+        }
+        self.flush_group()
+            .map_err(|_| Base64Error::InvalidBase64 { offset: len })?;
+        Ok(::std::mem::take(&mut self.buf))
+    }
+}
+
+impl<R> Restorable<R> for DeserBase64<R> {
+    fn restore(self) -> Option<R> {
+        self.reader
+    }
+}
+
+impl<R: AsyncRead> Future for DeserBase64<R> {
+    type Item = (R, Vec<u8>, usize);
+    type Error = (R, DeserializeError<Base64Error>);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        let mut reader = or_pending!(self.reader.take());
+
+        loop {
+            if self.end == Base64End::Len(self.read) {
+                return match self.finish(self.read) {
+                    Ok(buf) => Ok(Async::Ready((reader, buf, self.read))),
+                    Err(err) => Err((reader, DeserializeError::DataError(err))),
+                };
+            }
+
+            let mut byte = [0u8; 1];
+            match reader.poll_read(cx, &mut byte) {
+                Ok(Async::Ready(0)) => {
+                    let err = FutIoErr::new(ErrorKind::UnexpectedEof,
+                                            "unexpected end of base64 data");
+                    return Err((reader, DeserializeError::ReaderError(err)));
+                }
+                Ok(Async::Ready(_)) => {
+                    self.read += 1;
+                    let res = match self.end {
+                        Base64End::Terminator { terminator, .. } if byte[0] == terminator => {
+                            return match self.finish(self.read - 1) {
+                                Ok(buf) => Ok(Async::Ready((reader, buf, self.read))),
+                                Err(err) => Err((reader, DeserializeError::DataError(err))),
+                            };
+                        }
+                        Base64End::Terminator { limit, .. } if self.read > limit => {
+                            Err(Base64Error::TooLong { limit })
+                        }
+                        _ => self.push(byte[0]),
+                    };
+                    if let Err(err) = res {
+                        return Err((reader, DeserializeError::DataError(err)));
+                    }
+                }
+                Ok(Async::Pending) => {
+                    self.reader = Some(reader);
+                    return Ok(Async::Pending);
+                }
+                Err(err) => return Err((reader, DeserializeError::ReaderError(err))),
+            }
+        }
+    }
+}
+
+/// Everything that can go wrong when deserializing base64 ASCII.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Base64Error {
+    /// The data is not a canonical encoding of the configured alphabet and padding.
+    InvalidBase64 {
+        /// The offset of the character at which the data was found to be invalid, or the number
+        /// of characters if the data ends in the middle of a group.
+        offset: usize,
+    },
+    /// No terminator was found within the limit.
+    TooLong {
+        /// The maximum number of characters before the terminator.
+        limit: usize,
+    },
+}
+
+impl Display for Base64Error {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            Base64Error::InvalidBase64 { offset } => {
+                write!(f, "Invalid base64 data at offset {}", offset)
+            }
+            Base64Error::TooLong { limit } => {
+                write!(f, "Base64 data exceeds the limit of {} characters", limit)
+            }
+        }
+    }
+}
+
+impl Error for Base64Error {}
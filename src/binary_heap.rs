@@ -0,0 +1,251 @@
+//! Serialization of binary heaps.
+//!
+//! A heap is encoded as the number of elements as a big-endian `u32`, followed by the elements in
+//! ascending order, so the encoding of a heap does not depend on its internal layout. This is the
+//! same format as a `linked_list` of the sorted elements. Heaps with more than `u32::MAX`
+//! elements can not be serialized, the serializer fails with an `ErrorKind::InvalidInput` error
+//! without writing anything.
+//!
+//! Deserializing a heap only preserves its elements, not the internal layout of the original
+//! heap, so e.g. the order in which `iter` visits the elements may differ.
+
+use std::collections::BinaryHeap;
+use std::marker::PhantomData;
+use std::mem;
+use std::vec::IntoIter;
+
+use futures_core::{Async, Future, Poll};
+use futures_core::task::Context;
+use futures_io::{AsyncRead, AsyncWrite, Error as FutIoErr, ErrorKind};
+
+use {AsyncDeserialize, AsyncSerialize, AsyncSerializeLen, AsyncWriterFuture, AsyncWriterFutureLen,
+     DeserializeError, Restorable, ValidationError};
+use util::{prefix_fits, validate_prefix, ReadExact, WriteAll};
+
+/// Serializes a `BinaryHeap`, serializing the elements via `S` in ascending order.
+pub struct SerBinaryHeap<S, W>
+    where S: AsyncSerialize<W>,
+          W: AsyncWrite
+{
+    state: SerState<S, W>,
+    elements: IntoIter<S::Serialized>,
+    written: usize,
+}
+
+enum SerState<S, W> {
+    Count(WriteAll<W, [u8; 4]>),
+    Element(S),
+    Invalid(Option<W>),
+    Done,
+}
+
+impl<S, W> Future for SerBinaryHeap<S, W>
+    where S: AsyncSerialize<W>,
+          W: AsyncWrite
+{
+    type Item = (W, usize);
+    type Error = (W, FutIoErr);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let (writer, written) = match self.state {
+                SerState::Count(ref mut inner) => try_ready!(inner.poll(cx)),
+                SerState::Element(ref mut inner) => try_ready!(inner.poll(cx)),
+                SerState::Invalid(ref mut writer) => {
+                    let err = FutIoErr::new(ErrorKind::InvalidInput, "heap is too large");
+                    let writer = or_pending!(writer.take());
+                    return Err((writer, err));
+                }
+                SerState::Done => return Ok(Async::Pending),
+            };
+            self.written += written;
+
+            match self.elements.next() {
+                Some(element) => {
+                    self.state = SerState::Element(S::from_val(writer, element));
+                }
+                None => {
+                    self.state = SerState::Done;
+                    return Ok(Async::Ready((writer, self.written)));
+                }
+            }
+        }
+    }
+}
+
+impl<S, W> AsyncWriterFuture<W> for SerBinaryHeap<S, W>
+    where S: AsyncSerialize<W>,
+          W: AsyncWrite
+{
+    fn already_written(&self) -> usize {
+        self.written +
+        match self.state {
+            SerState::Count(ref inner) => inner.already_written(),
+            SerState::Element(ref inner) => inner.already_written(),
+            SerState::Invalid(_) | SerState::Done => 0,
+        }
+    }
+}
+
+impl<S, W> AsyncWriterFutureLen<W> for SerBinaryHeap<S, W>
+    where S: AsyncSerializeLen<W>,
+          W: AsyncWrite
+{
+    fn remaining_bytes(&self) -> usize {
+        let current = match self.state {
+            SerState::Count(ref inner) => inner.remaining_bytes(),
+            SerState::Element(ref inner) => inner.remaining_bytes(),
+            SerState::Invalid(_) | SerState::Done => return 0,
+        };
+
+        current + self.elements.as_slice().iter().map(S::total_bytes).sum::<usize>()
+    }
+}
+
+impl<S, W> SerBinaryHeap<S, W>
+    where S: AsyncSerialize<W>,
+          S::Serialized: Ord,
+          W: AsyncWrite
+{
+    /// Check whether `val` can be serialized, i.e. whether it has at most `u32::MAX` elements.
+    ///
+    /// This does not validate the elements themselves.
+    pub fn validate(val: &BinaryHeap<S::Serialized>) -> Result<(), ValidationError> {
+        validate_prefix(val.len())
+    }
+}
+
+impl<S, W> AsyncSerialize<W> for SerBinaryHeap<S, W>
+    where S: AsyncSerialize<W>,
+          S::Serialized: Ord,
+          W: AsyncWrite
+{
+    type Serialized = BinaryHeap<S::Serialized>;
+
+    fn from_val(writer: W, val: BinaryHeap<S::Serialized>) -> Self {
+        let state = if prefix_fits(val.len()) {
+            SerState::Count(WriteAll::new(writer, (val.len() as u32).to_be_bytes()))
+        } else {
+            SerState::Invalid(Some(writer))
+        };
+
+        SerBinaryHeap {
+            state,
+            elements: val.into_sorted_vec().into_iter(),
+            written: 0,
+        }
+    }
+}
+
+impl<S, W> AsyncSerializeLen<W> for SerBinaryHeap<S, W>
+    where S: AsyncSerializeLen<W>,
+          S::Serialized: Ord,
+          W: AsyncWrite
+{
+    /// Returns zero for heaps that can not be serialized.
+    fn total_bytes(val: &BinaryHeap<S::Serialized>) -> usize {
+        if prefix_fits(val.len()) {
+            4 + val.iter().map(S::total_bytes).sum::<usize>()
+        } else {
+            0
+        }
+    }
+}
+
+/// Deserializes a `BinaryHeap`, deserializing the elements via `D`.
+///
+/// The elements may appear in any order, they do not have to be sorted.
+pub struct DeserBinaryHeap<D, R, T, E> {
+    state: DeserState<D, R>,
+    remaining: u32,
+    read: usize,
+    heap: BinaryHeap<T>,
+    _error: PhantomData<E>,
+}
+
+enum DeserState<D, R> {
+    Count(ReadExact<R, [u8; 4]>),
+    Element(D),
+    Done,
+}
+
+impl<D, R, T, E> Future for DeserBinaryHeap<D, R, T, E>
+    where D: AsyncDeserialize<R, T, E>,
+          R: AsyncRead,
+          T: Ord
+{
+    type Item = (R, BinaryHeap<T>, usize);
+    type Error = (R, DeserializeError<E>);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let reader = match self.state {
+                DeserState::Count(ref mut inner) => {
+                    let (reader, count, read) = match inner.poll(cx) {
+                        Ok(Async::Ready(done)) => done,
+                        Ok(Async::Pending) => return Ok(Async::Pending),
+                        Err((reader, err)) => {
+                            return Err((reader, DeserializeError::ReaderError(err)))
+                        }
+                    };
+                    self.remaining = u32::from_be_bytes(count);
+                    self.read += read;
+                    reader
+                }
+
+                DeserState::Element(ref mut inner) => {
+                    let (reader, element, read) = try_ready!(inner.poll(cx));
+                    self.heap.push(element);
+                    self.remaining -= 1;
+                    self.read += read;
+                    reader
+                }
+
+                DeserState::Done => return Ok(Async::Pending),
+            };
+
+            if self.remaining == 0 {
+                self.state = DeserState::Done;
+                return Ok(Async::Ready((reader, mem::take(&mut self.heap), self.read)));
+            }
+            self.state = DeserState::Element(D::from_reader(reader));
+        }
+    }
+}
+
+impl<D, R, T, E> AsyncDeserialize<R, BinaryHeap<T>, E> for DeserBinaryHeap<D, R, T, E>
+    where D: AsyncDeserialize<R, T, E>,
+          R: AsyncRead,
+          T: Ord
+{
+    fn from_reader(reader: R) -> Self {
+        DeserBinaryHeap {
+            state: DeserState::Count(ReadExact::new(reader, [0; 4])),
+            remaining: 0,
+            read: 0,
+            heap: BinaryHeap::new(),
+            _error: PhantomData,
+        }
+    }
+
+    fn already_read(&self) -> usize {
+        self.read +
+        match self.state {
+            DeserState::Count(ref inner) => inner.already_read(),
+            DeserState::Element(ref inner) => inner.already_read(),
+            DeserState::Done => 0,
+        }
+    }
+}
+
+impl<D, R, T, E> Restorable<R> for DeserBinaryHeap<D, R, T, E>
+    where D: Restorable<R>
+{
+    fn restore(self) -> Option<R> {
+        match self.state {
+            DeserState::Count(count) => count.restore(),
+            DeserState::Element(element) => element.restore(),
+            DeserState::Done => None,
+        }
+    }
+}
@@ -0,0 +1,285 @@
+//! Serialization of sequences of booleans as packed bitsets.
+//!
+//! A sequence of `n` booleans is encoded as `n` as a [varint](../varint/index.html), followed by
+//! `ceil(n / 8)` bytes holding eight booleans each. Bits are assigned LSB-first: the boolean at
+//! index `i` is bit `i % 8` (where bit 0 is the least significant one) of byte `i / 8`. Unused
+//! bits of the last byte are zero.
+//!
+//! So `[true, false, false, true, true, false, false, false, false, true]` encodes as
+//! `[0x0a, 0x19, 0x02]`.
+
+use std::cmp::min;
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::mem;
+
+use futures_core::{Async, Future, Poll};
+use futures_core::task::Context;
+use futures_io::{AsyncRead, AsyncWrite, Error as FutIoErr, ErrorKind};
+
+use {AsyncDeserialize, AsyncSerializeRef, AsyncSerializeRefLen, AsyncWriterFuture,
+     AsyncWriterFutureLen, DeserializeError, Restorable};
+use util::poll_write_buf;
+use varint::{varint_len, ReadVarint, VarintBuf, VarintError};
+
+const CHUNK: usize = 64;
+
+fn packed_len(n: usize) -> usize {
+    n.div_ceil(8)
+}
+
+/// Serializes a slice of booleans as a packed bitset.
+///
+/// The booleans are packed into a small fixed-size buffer as writing progresses, so this does not
+/// allocate.
+pub struct WriteBitset<'val, W> {
+    writer: Option<W>,
+    bits: &'val [bool],
+    prefix: VarintBuf,
+    prefix_written: usize,
+    chunk: [u8; CHUNK],
+    chunk_len: usize,
+    chunk_written: usize,
+    packed: usize,
+    bytes_written: usize,
+}
+
+impl<'val, W> WriteBitset<'val, W> {
+    // Pack the next chunk of booleans into the chunk buffer.
+    fn refill(&mut self) {
+        let len = min(CHUNK, packed_len(self.bits.len()) - self.packed);
+
+        for (i, byte) in self.chunk[..len].iter_mut().enumerate() {
+            let start = (self.packed + i) * 8;
+            let end = min(start + 8, self.bits.len());
+            *byte = self.bits[start..end]
+                .iter()
+                .enumerate()
+                .fold(0, |acc, (bit, set)| if *set { acc | (1 << bit) } else { acc });
+        }
+
+        self.packed += len;
+        self.chunk_len = len;
+        self.chunk_written = 0;
+    }
+}
+
+impl<'val, W: AsyncWrite> Future for WriteBitset<'val, W> {
+    type Item = (W, usize);
+    type Error = (W, FutIoErr);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        let mut writer = or_pending!(self.writer.take());
+
+        loop {
+            let res = if self.prefix_written < self.prefix.as_ref().len() {
+                poll_write_buf(&mut writer, cx, self.prefix.as_ref(), &mut self.prefix_written)
+            } else if self.chunk_written < self.chunk_len {
+                let before = self.chunk_written;
+                let res = poll_write_buf(&mut writer,
+                                         cx,
+                                         &self.chunk[..self.chunk_len],
+                                         &mut self.chunk_written);
+                self.bytes_written += self.chunk_written - before;
+                res
+            } else if self.packed < packed_len(self.bits.len()) {
+                self.refill();
+                continue;
+            } else {
+                let written = self.already_written();
+                return Ok(Async::Ready((writer, written)));
+            };
+
+            match res {
+                Ok(Async::Ready(())) => {}
+                Ok(Async::Pending) => {
+                    self.writer = Some(writer);
+                    return Ok(Async::Pending);
+                }
+                Err(err) => return Err((writer, err)),
+            }
+        }
+    }
+}
+
+impl<'val, W: AsyncWrite> AsyncWriterFuture<W> for WriteBitset<'val, W> {
+    fn already_written(&self) -> usize {
+        self.prefix_written + self.bytes_written
+    }
+}
+
+impl<'val, W: AsyncWrite> AsyncWriterFutureLen<W> for WriteBitset<'val, W> {
+    fn remaining_bytes(&self) -> usize {
+        self.prefix.as_ref().len() + packed_len(self.bits.len()) - self.already_written()
+    }
+}
+
+impl<'val, W: AsyncWrite> AsyncSerializeRef<'val, W> for WriteBitset<'val, W> {
+    type Serialized = [bool];
+
+    fn from_ref(writer: W, val: &'val [bool]) -> Self {
+        WriteBitset {
+            writer: Some(writer),
+            bits: val,
+            prefix: VarintBuf::new(val.len() as u64),
+            prefix_written: 0,
+            chunk: [0; CHUNK],
+            chunk_len: 0,
+            chunk_written: 0,
+            packed: 0,
+            bytes_written: 0,
+        }
+    }
+}
+
+impl<'val, W: AsyncWrite> AsyncSerializeRefLen<'val, W> for WriteBitset<'val, W> {
+    fn total_bytes(val: &[bool]) -> usize {
+        varint_len(val.len() as u64) + packed_len(val.len())
+    }
+}
+
+/// Deserializes a packed bitset into a `Vec<bool>`.
+pub struct ReadBitset<R>(ReadState<R>);
+
+enum ReadState<R> {
+    Length(ReadVarint<R>),
+    Bits {
+        reader: Option<R>,
+        bits: Vec<bool>,
+        len: usize,
+        prefix_len: usize,
+        bytes_read: usize,
+    },
+}
+
+impl<R: AsyncRead> Future for ReadBitset<R> {
+    type Item = (R, Vec<bool>, usize);
+    type Error = (R, DeserializeError<BitsetError>);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let (reader, len, prefix_len) = match self.0 {
+                ReadState::Length(ref mut inner) => {
+                    match inner.poll(cx) {
+                        Ok(Async::Ready(done)) => done,
+                        Ok(Async::Pending) => return Ok(Async::Pending),
+                        Err((reader, DeserializeError::ReaderError(err))) => {
+                            return Err((reader, DeserializeError::ReaderError(err)))
+                        }
+                        Err((reader, DeserializeError::DataError(VarintError::Overflow))) => {
+                            return Err((reader,
+                                        DeserializeError::DataError(BitsetError::VarintOverflow)))
+                        }
+                    }
+                }
+
+                ReadState::Bits {
+                    ref mut reader,
+                    ref mut bits,
+                    len,
+                    prefix_len,
+                    ref mut bytes_read,
+                } => {
+                    let mut r = or_pending!(reader.take());
+
+                    while bits.len() < len {
+                        let mut chunk = [0u8; CHUNK];
+                        let chunk_len = min(CHUNK, packed_len(len - bits.len()));
+
+                        let filled = match r.poll_read(cx, &mut chunk[..chunk_len]) {
+                            Ok(Async::Ready(0)) => {
+                                let err = FutIoErr::new(ErrorKind::UnexpectedEof,
+                                                        "unexpected end of bitset");
+                                return Err((r, DeserializeError::ReaderError(err)));
+                            }
+                            Ok(Async::Ready(read)) => read,
+                            Ok(Async::Pending) => {
+                                *reader = Some(r);
+                                return Ok(Async::Pending);
+                            }
+                            Err(err) => return Err((r, DeserializeError::ReaderError(err))),
+                        };
+
+                        *bytes_read += filled;
+                        for byte in &chunk[..filled] {
+                            let n = min(8, len - bits.len());
+                            if n < 8 && byte >> n != 0 {
+                                return Err((r,
+                                            DeserializeError::DataError(BitsetError::NonZeroPadding)));
+                            }
+                            bits.extend((0..n).map(|bit| byte & (1 << bit) != 0));
+                        }
+                    }
+
+                    let read = prefix_len + *bytes_read;
+                    return Ok(Async::Ready((r, mem::take(bits), read)));
+                }
+            };
+
+            let len = match usize::try_from(len) {
+                Ok(len) => len,
+                Err(_) => {
+                    return Err((reader, DeserializeError::DataError(BitsetError::LengthOverflow)))
+                }
+            };
+
+            self.0 = ReadState::Bits {
+                reader: Some(reader),
+                bits: Vec::new(),
+                len,
+                prefix_len,
+                bytes_read: 0,
+            };
+        }
+    }
+}
+
+impl<R: AsyncRead> AsyncDeserialize<R, Vec<bool>, BitsetError> for ReadBitset<R> {
+    fn from_reader(reader: R) -> Self {
+        ReadBitset(ReadState::Length(ReadVarint::from_reader(reader)))
+    }
+
+    fn already_read(&self) -> usize {
+        match self.0 {
+            ReadState::Length(ref inner) => inner.already_read(),
+            ReadState::Bits {
+                prefix_len,
+                bytes_read,
+                ..
+            } => prefix_len + bytes_read,
+        }
+    }
+}
+
+impl<R> Restorable<R> for ReadBitset<R> {
+    fn restore(self) -> Option<R> {
+        match self.0 {
+            ReadState::Length(inner) => inner.restore(),
+            ReadState::Bits { reader, .. } => reader,
+        }
+    }
+}
+
+/// Everything that can go wrong when deserializing a bitset.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BitsetError {
+    /// The length varint does not fit into a `u64`.
+    VarintOverflow,
+    /// The length does not fit into a `usize`.
+    LengthOverflow,
+    /// An unused bit of the last byte is set.
+    NonZeroPadding,
+}
+
+impl Display for BitsetError {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            BitsetError::VarintOverflow => write!(f, "Varint overflows a u64"),
+            BitsetError::LengthOverflow => write!(f, "Length overflows a usize"),
+            BitsetError::NonZeroPadding => write!(f, "Padding bits are not zero"),
+        }
+    }
+}
+
+impl Error for BitsetError {}
@@ -0,0 +1,177 @@
+//! Retry deserialization after transient reader errors without losing the bytes that have
+//! already been consumed.
+
+use std::cmp::min;
+use std::io::ErrorKind;
+
+use futures_core::{Async, Future, Poll};
+use futures_core::task::Context;
+use futures_io::{AsyncRead, Error as FutIoErr};
+
+use {AsyncDeserialize, DeserializeError, Restorable};
+
+/// Wraps an `AsyncRead` and keeps a copy of all bytes read from it, so that they can be read
+/// again after a `rewind`.
+///
+/// After rewinding, reads are served from the copy until it is exhausted, and only then from the
+/// wrapped reader. The wrapped reader can be exchanged via `replace`, e.g. for a fresh connection
+/// that continues where the failed one stopped.
+#[derive(Debug)]
+pub struct Replay<R> {
+    inner: R,
+    buf: Vec<u8>,
+    position: usize,
+}
+
+impl<R> Replay<R> {
+    /// Create a new `Replay`, reading from `inner`.
+    pub fn new(inner: R) -> Replay<R> {
+        Replay {
+            inner,
+            buf: Vec::new(),
+            position: 0,
+        }
+    }
+
+    /// Return all bytes read from the wrapped reader so far.
+    pub fn recorded(&self) -> &[u8] {
+        &self.buf
+    }
+
+    /// Return how many of the recorded bytes have not been replayed since the last `rewind`.
+    pub fn pending_replay(&self) -> usize {
+        self.buf.len() - self.position
+    }
+
+    /// Serve the next reads from the start of the recorded bytes again.
+    pub fn rewind(&mut self) {
+        self.position = 0;
+    }
+
+    /// Replace the wrapped reader with `inner`, returning the old one. The recorded bytes are
+    /// kept.
+    pub fn replace(&mut self, inner: R) -> R {
+        ::std::mem::replace(&mut self.inner, inner)
+    }
+
+    /// Get a reference to the wrapped reader.
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// Get a mutable reference to the wrapped reader.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    /// Consume this `Replay`, returning the wrapped reader. Recorded bytes that have not been
+    /// replayed yet are lost.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: AsyncRead> AsyncRead for Replay<R> {
+    fn poll_read(&mut self, cx: &mut Context, buf: &mut [u8]) -> Poll<usize, FutIoErr> {
+        if self.position < self.buf.len() {
+            let len = min(buf.len(), self.buf.len() - self.position);
+            buf[..len].copy_from_slice(&self.buf[self.position..self.position + len]);
+            self.position += len;
+            return Ok(Async::Ready(len));
+        }
+
+        let read = try_ready!(self.inner.poll_read(cx, buf));
+        self.buf.extend_from_slice(&buf[..read]);
+        self.position = self.buf.len();
+        Ok(Async::Ready(read))
+    }
+}
+
+/// Returns whether a reader error is transient, i.e. whether reading again may succeed.
+pub fn is_retryable(err: &FutIoErr) -> bool {
+    matches!(err.kind(), ErrorKind::Interrupted | ErrorKind::WouldBlock)
+}
+
+/// Deserializes via `D` from a `Replay`, starting over from the recorded bytes whenever `D` fails
+/// with a transient reader error (see `is_retryable`).
+///
+/// Before starting over, this yields to the executor once, so that a reader that keeps failing
+/// does not block the thread. Other errors are returned together with the `Replay`; its reader
+/// can then be exchanged via `Replay::replace`, and `Buffered::retry` starts over again with the
+/// already consumed bytes.
+///
+/// On success, the `Replay` is returned rather than the wrapped reader, since a deserializer that
+/// does not read the same bytes on every attempt might leave recorded bytes unconsumed.
+pub struct Buffered<D, R> {
+    inner: Option<D>,
+    replay: Option<Replay<R>>,
+}
+
+impl<D, R: AsyncRead> Buffered<D, R> {
+    /// Create a new `Buffered`, deserializing from `reader`.
+    pub fn new<T, E>(reader: R) -> Buffered<D, R>
+        where D: AsyncDeserialize<Replay<R>, T, E>
+    {
+        Buffered::retry(Replay::new(reader))
+    }
+
+    /// Start deserializing again from the start of the bytes recorded by `replay`.
+    pub fn retry<T, E>(mut replay: Replay<R>) -> Buffered<D, R>
+        where D: AsyncDeserialize<Replay<R>, T, E>
+    {
+        replay.rewind();
+        Buffered {
+            inner: Some(D::from_reader(replay)),
+            replay: None,
+        }
+    }
+}
+
+impl<D, R, T, E> Future for Buffered<D, R>
+    where D: AsyncDeserialize<Replay<R>, T, E>,
+          D: Future<Item = (Replay<R>, T, usize), Error = (Replay<R>, DeserializeError<E>)>,
+          R: AsyncRead
+{
+    type Item = (Replay<R>, T, usize);
+    type Error = (Replay<R>, DeserializeError<E>);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        if let Some(mut replay) = self.replay.take() {
+            replay.rewind();
+            self.inner = Some(D::from_reader(replay));
+        }
+
+        let res = or_pending!(self.inner.as_mut()).poll(cx);
+
+        match res {
+            Ok(Async::Ready(done)) => {
+                self.inner = None;
+                Ok(Async::Ready(done))
+            }
+            Ok(Async::Pending) => Ok(Async::Pending),
+            Err((replay, DeserializeError::ReaderError(ref err))) if is_retryable(err) => {
+                self.inner = None;
+                self.replay = Some(replay);
+                cx.waker().wake();
+                Ok(Async::Pending)
+            }
+            Err(err) => {
+                self.inner = None;
+                Err(err)
+            }
+        }
+    }
+}
+
+/// Restores the `Replay` rather than the wrapped reader, so that the bytes recorded so far are
+/// not lost.
+impl<D, R> Restorable<Replay<R>> for Buffered<D, R>
+    where D: Restorable<Replay<R>>
+{
+    fn restore(self) -> Option<Replay<R>> {
+        match self.inner {
+            Some(inner) => inner.restore(),
+            None => self.replay,
+        }
+    }
+}
@@ -0,0 +1,179 @@
+//! Cooperatively abort serialization and deserialization.
+
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use futures_core::{Async, Poll};
+use futures_core::task::{Context, Waker};
+use futures_io::{AsyncRead, AsyncWrite, Error as FutIoErr};
+
+/// Wraps an `AsyncRead` or `AsyncWrite` and fails all reads or writes once a shared cancellation
+/// flag has been set.
+///
+/// Any serializer or deserializer over a `Cancellable` stops at its next byte operation after the
+/// flag has been set, failing with an `ErrorKind::Other` error that wraps a `Cancelled`, and
+/// returns the `Cancellable` as usual. The flag is only checked before forwarding a read or write,
+/// so a single read or write is never interrupted halfway: the bytes that have been handed to the
+/// wrapped writer are exactly those reported by the serializer's `already_written`.
+///
+/// Flushing and closing are still forwarded after cancellation, so that the stream can be shut
+/// down cleanly.
+///
+/// A plain flag is only checked when the task is polled anyway. With a `CancellationToken`, a
+/// read or write that is waiting for the wrapped reader or writer registers the task with the
+/// token, so cancelling wakes it up and it fails immediately.
+#[derive(Debug)]
+pub struct Cancellable<T> {
+    inner: T,
+    token: Flag,
+}
+
+#[derive(Debug)]
+enum Flag {
+    Plain(Arc<AtomicBool>),
+    Token(CancellationToken),
+}
+
+impl<T> Cancellable<T> {
+    /// Create a new `Cancellable`, which is cancelled once `token` is set to `true`.
+    pub fn new(inner: T, token: Arc<AtomicBool>) -> Cancellable<T> {
+        Cancellable {
+            inner,
+            token: Flag::Plain(token),
+        }
+    }
+
+    /// Create a new `Cancellable`, which is cancelled once `token` is cancelled.
+    pub fn with_token(inner: T, token: CancellationToken) -> Cancellable<T> {
+        Cancellable {
+            inner,
+            token: Flag::Token(token),
+        }
+    }
+
+    /// Return whether this has been cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        match self.token {
+            Flag::Plain(ref flag) => flag.load(Ordering::SeqCst),
+            Flag::Token(ref token) => token.is_cancelled(),
+        }
+    }
+
+    /// Get a reference to the wrapped reader or writer.
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    /// Get a mutable reference to the wrapped reader or writer.
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    /// Consume this `Cancellable`, returning the wrapped reader or writer.
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+
+    fn check(&self) -> Result<(), FutIoErr> {
+        if self.is_cancelled() {
+            Err(FutIoErr::other(Cancelled))
+        } else {
+            Ok(())
+        }
+    }
+
+    // Make sure a pending operation is woken up by cancellation, and does not miss a cancellation
+    // that happened while it was being polled.
+    fn register<V>(&self, cx: &mut Context, res: Poll<V, FutIoErr>) -> Poll<V, FutIoErr> {
+        if let (&Ok(Async::Pending), Flag::Token(ref token)) = (&res, &self.token) {
+            token.register(cx.waker());
+            self.check()?;
+        }
+        res
+    }
+}
+
+impl<R: AsyncRead> AsyncRead for Cancellable<R> {
+    fn poll_read(&mut self, cx: &mut Context, buf: &mut [u8]) -> Poll<usize, FutIoErr> {
+        self.check()?;
+        let res = self.inner.poll_read(cx, buf);
+        self.register(cx, res)
+    }
+}
+
+impl<W: AsyncWrite> AsyncWrite for Cancellable<W> {
+    fn poll_write(&mut self, cx: &mut Context, buf: &[u8]) -> Poll<usize, FutIoErr> {
+        self.check()?;
+        let res = self.inner.poll_write(cx, buf);
+        self.register(cx, res)
+    }
+
+    fn poll_flush(&mut self, cx: &mut Context) -> Poll<(), FutIoErr> {
+        self.inner.poll_flush(cx)
+    }
+
+    fn poll_close(&mut self, cx: &mut Context) -> Poll<(), FutIoErr> {
+        self.inner.poll_close(cx)
+    }
+}
+
+/// A cancellation flag that wakes up the tasks waiting on a `Cancellable` when it is set.
+///
+/// Clones share the same flag.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<TokenState>);
+
+#[derive(Debug, Default)]
+struct TokenState {
+    cancelled: AtomicBool,
+    wakers: Mutex<Vec<Waker>>,
+}
+
+impl CancellationToken {
+    /// Create a new token that has not been cancelled.
+    pub fn new() -> CancellationToken {
+        CancellationToken::default()
+    }
+
+    /// Cancel all `Cancellable`s using this token, waking up the tasks that wait on them.
+    pub fn cancel(&self) {
+        self.0.cancelled.store(true, Ordering::SeqCst);
+        let wakers = ::std::mem::take(&mut *self.0.wakers.lock().unwrap());
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+
+    /// Return whether this token has been cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.0.cancelled.load(Ordering::SeqCst)
+    }
+
+    fn register(&self, waker: &Waker) {
+        let mut wakers = self.0.wakers.lock().unwrap();
+        if !wakers.iter().any(|registered| registered.will_wake(waker)) {
+            wakers.push(waker.clone());
+        }
+    }
+}
+
+/// The error wrapped by the io errors of a cancelled `Cancellable`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cancelled;
+
+impl Cancelled {
+    /// Return whether the given io error was caused by cancellation.
+    pub fn is_cancelled(err: &FutIoErr) -> bool {
+        err.get_ref().map(|inner| inner.is::<Cancelled>()).unwrap_or(false)
+    }
+}
+
+impl Display for Cancelled {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        write!(f, "Cancelled")
+    }
+}
+
+impl Error for Cancelled {}
@@ -0,0 +1,273 @@
+//! Ensure that deserialized data is in canonical form, i.e. that serializing the deserialized
+//! value yields exactly the bytes it was deserialized from.
+
+use std::cmp::min;
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::mem;
+
+use futures_core::{Async, Future, Poll};
+use futures_core::task::Context;
+use futures_io::{AsyncRead, AsyncWrite, Error as FutIoErr, ErrorKind};
+
+use {AsyncDeserialize, AsyncSerialize, DeserializeError, Restorable};
+
+/// Wraps an `AsyncRead` and records all bytes that are read from it.
+#[derive(Debug)]
+pub struct Recording<R> {
+    inner: R,
+    recorded: Vec<u8>,
+}
+
+impl<R> Recording<R> {
+    /// Get a reference to the wrapped reader.
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// Get a mutable reference to the wrapped reader.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    /// Return the bytes read so far.
+    pub fn recorded(&self) -> &[u8] {
+        &self.recorded
+    }
+}
+
+impl<R: AsyncRead> AsyncRead for Recording<R> {
+    fn poll_read(&mut self, cx: &mut Context, buf: &mut [u8]) -> Poll<usize, FutIoErr> {
+        let read = try_ready!(self.inner.poll_read(cx, buf));
+        self.recorded.extend_from_slice(&buf[..read]);
+        Ok(Async::Ready(read))
+    }
+}
+
+/// An `AsyncWrite` that compares everything written to it against some expected bytes, failing
+/// at the first difference.
+#[derive(Debug)]
+pub struct Comparison {
+    expected: Vec<u8>,
+    offset: usize,
+}
+
+impl Comparison {
+    /// Return the offset of the first byte that differs from the expected bytes, if any.
+    fn mismatch(&self) -> Option<usize> {
+        if self.offset == self.expected.len() {
+            None
+        } else {
+            Some(self.offset)
+        }
+    }
+}
+
+impl AsyncWrite for Comparison {
+    fn poll_write(&mut self, _: &mut Context, buf: &[u8]) -> Poll<usize, FutIoErr> {
+        let expected = &self.expected[self.offset..];
+        let len = min(buf.len(), expected.len());
+        match buf[..len].iter().zip(expected).position(|(a, b)| a != b) {
+            Some(pos) => {
+                self.offset += pos;
+                Err(FutIoErr::new(ErrorKind::InvalidData, "serialization is not canonical"))
+            }
+            None if len < buf.len() => {
+                self.offset += len;
+                Err(FutIoErr::new(ErrorKind::InvalidData, "serialization is too long"))
+            }
+            None => {
+                self.offset += len;
+                Ok(Async::Ready(len))
+            }
+        }
+    }
+
+    fn poll_flush(&mut self, _: &mut Context) -> Poll<(), FutIoErr> {
+        Ok(Async::Ready(()))
+    }
+
+    fn poll_close(&mut self, _: &mut Context) -> Poll<(), FutIoErr> {
+        Ok(Async::Ready(()))
+    }
+}
+
+/// Deserializes a value with a `D`, then serializes it again with an `S` and fails with
+/// `CanonicalError::NonCanonical` if that does not yield exactly the bytes that were read.
+///
+/// This is useful when the encoding of a value must be unique, e.g. for content-addressed
+/// storage. It costs a second pass over the data and buffers all bytes the inner deserializer
+/// reads, so wrapping the reader in a [`TakeReader`](../take_reader/struct.TakeReader.html) is
+/// advisable for values of unbounded size. The buffer can be reused across values via
+/// `with_buffer` and `take_buffer`.
+pub struct Canonical<D, S, R, T> {
+    state: State<D, S, R, T>,
+    buffer: Vec<u8>,
+}
+
+enum State<D, S, R, T> {
+    Deserialize(D),
+    Serialize(S, (R, T, usize)),
+    Done,
+}
+
+impl<D, S, R, T> Canonical<D, S, R, T> {
+    /// Create a new `Canonical`, recording the read bytes into `buffer`.
+    ///
+    /// The buffer is cleared first, so this only reuses its allocation.
+    pub fn with_buffer<E>(reader: R, mut buffer: Vec<u8>) -> Canonical<D, S, R, T>
+        where D: AsyncDeserialize<Recording<R>, T, E>,
+              R: AsyncRead
+    {
+        buffer.clear();
+        Canonical {
+            state: State::Deserialize(D::from_reader(Recording {
+                                                         inner: reader,
+                                                         recorded: buffer,
+                                                     })),
+            buffer: Vec::new(),
+        }
+    }
+
+    /// Take the buffer the read bytes were recorded into, after this has completed.
+    ///
+    /// Returns an empty buffer while this is still in progress.
+    pub fn take_buffer(&mut self) -> Vec<u8> {
+        mem::take(&mut self.buffer)
+    }
+}
+
+impl<D, S, R, T, E> Future for Canonical<D, S, R, T>
+    where D: Future<Item = (Recording<R>, T, usize),
+                    Error = (Recording<R>, DeserializeError<E>)>,
+          S: AsyncSerialize<Comparison, Serialized = T>,
+          R: AsyncRead,
+          T: Clone
+{
+    type Item = (R, T, usize);
+    type Error = (R, DeserializeError<CanonicalError<E>>);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        loop {
+            match mem::replace(&mut self.state, State::Done) {
+                State::Deserialize(mut inner) => {
+                    match inner.poll(cx) {
+                        Ok(Async::Ready((recording, val, read))) => {
+                            let comparison = Comparison {
+                                expected: recording.recorded,
+                                offset: 0,
+                            };
+                            let ser = S::from_val(comparison, val.clone());
+                            self.state = State::Serialize(ser, (recording.inner, val, read));
+                        }
+                        Ok(Async::Pending) => {
+                            self.state = State::Deserialize(inner);
+                            return Ok(Async::Pending);
+                        }
+                        Err((recording, err)) => {
+                            self.buffer = recording.recorded;
+                            let err = match err {
+                                DeserializeError::ReaderError(err) => {
+                                    DeserializeError::ReaderError(err)
+                                }
+                                DeserializeError::DataError(err) => {
+                                    DeserializeError::DataError(CanonicalError::Inner(err))
+                                }
+                            };
+                            return Err((recording.inner, err));
+                        }
+                    }
+                }
+
+                State::Serialize(mut ser, done) => {
+                    let (comparison, mismatch) = match ser.poll(cx) {
+                        Ok(Async::Ready((comparison, _))) => {
+                            let mismatch = comparison.mismatch();
+                            (comparison, mismatch)
+                        }
+                        Ok(Async::Pending) => {
+                            self.state = State::Serialize(ser, done);
+                            return Ok(Async::Pending);
+                        }
+                        Err((comparison, _)) => {
+                            let offset = comparison.offset;
+                            (comparison, Some(offset))
+                        }
+                    };
+
+                    self.buffer = comparison.expected;
+                    let (reader, val, read) = done;
+                    return match mismatch {
+                        None => Ok(Async::Ready((reader, val, read))),
+                        Some(first_mismatch_offset) => {
+                            let err = CanonicalError::NonCanonical { first_mismatch_offset };
+                            Err((reader, DeserializeError::DataError(err)))
+                        }
+                    };
+                }
+
+                State::Done => return Ok(Async::Pending),
+            }
+        }
+    }
+}
+
+impl<D, S, R, T, E> AsyncDeserialize<R, T, CanonicalError<E>> for Canonical<D, S, R, T>
+    where D: AsyncDeserialize<Recording<R>, T, E>,
+          S: AsyncSerialize<Comparison, Serialized = T>,
+          R: AsyncRead,
+          T: Clone
+{
+    fn from_reader(reader: R) -> Self {
+        Canonical::with_buffer(reader, Vec::new())
+    }
+
+    fn already_read(&self) -> usize {
+        match self.state {
+            State::Deserialize(ref inner) => inner.already_read(),
+            State::Serialize(_, ref done) => done.2,
+            State::Done => 0,
+        }
+    }
+}
+
+/// Once the inner deserializer has completed, this restores the reader positioned after the value,
+/// also while the value is still being compared against the read bytes.
+impl<D, S, R, T> Restorable<R> for Canonical<D, S, R, T>
+    where D: Restorable<Recording<R>>
+{
+    fn restore(self) -> Option<R> {
+        match self.state {
+            State::Deserialize(inner) => inner.restore().map(|recording| recording.inner),
+            State::Serialize(_, (reader, _, _)) => Some(reader),
+            State::Done => None,
+        }
+    }
+}
+
+/// The data error of a `Canonical`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CanonicalError<E> {
+    /// The inner deserializer failed.
+    Inner(E),
+    /// Serializing the deserialized value does not yield the bytes that were read.
+    NonCanonical {
+        /// The offset of the first byte in which the serialization differs from the read bytes.
+        /// If the serialization is a prefix of the read bytes or vice versa, this is the length
+        /// of the shorter one.
+        first_mismatch_offset: usize,
+    },
+}
+
+impl<E: Display> Display for CanonicalError<E> {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            CanonicalError::Inner(ref err) => write!(f, "{}", err),
+            CanonicalError::NonCanonical { first_mismatch_offset } => {
+                write!(f, "Encoding is not canonical at offset {}", first_mismatch_offset)
+            }
+        }
+    }
+}
+
+impl<E: Error> Error for CanonicalError<E> {}
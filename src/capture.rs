@@ -0,0 +1,156 @@
+//! Capture the bytes that lead to a deserialization failure, for debugging.
+
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+
+use futures_core::{Async, Future, Poll};
+use futures_core::task::Context;
+use futures_io::{AsyncRead, Error as FutIoErr};
+
+use {AsyncDeserialize, DeserializeError, Restorable};
+
+/// Wraps an `AsyncRead` and keeps a copy of the last `limit` bytes that were read from it.
+///
+/// Since this works at the reader level, the capture contains all bytes consumed by a
+/// deserializer, including those read by any nested deserializers.
+#[derive(Debug)]
+pub struct Capture<R> {
+    inner: R,
+    limit: usize,
+    captured: VecDeque<u8>,
+    position: u64,
+}
+
+impl<R> Capture<R> {
+    /// Create a new `Capture`, keeping the last `limit` bytes read from `inner`.
+    pub fn new(inner: R, limit: usize) -> Capture<R> {
+        Capture {
+            inner,
+            limit,
+            captured: VecDeque::with_capacity(limit),
+            position: 0,
+        }
+    }
+
+    /// Return the captured bytes, i.e. the last (up to) `limit` bytes that have been read.
+    pub fn captured(&self) -> Vec<u8> {
+        self.captured.iter().cloned().collect()
+    }
+
+    /// Return how many bytes have been read in total.
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Get a reference to the wrapped reader.
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// Get a mutable reference to the wrapped reader.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    /// Consume this `Capture`, returning the wrapped reader.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: AsyncRead> AsyncRead for Capture<R> {
+    fn poll_read(&mut self, cx: &mut Context, buf: &mut [u8]) -> Poll<usize, FutIoErr> {
+        let read = try_ready!(self.inner.poll_read(cx, buf));
+        self.position += read as u64;
+
+        let start = read.saturating_sub(self.limit);
+        self.captured.extend(&buf[start..read]);
+        let excess = self.captured.len().saturating_sub(self.limit);
+        self.captured.drain(..excess);
+
+        Ok(Async::Ready(read))
+    }
+}
+
+/// Run a deserializer over a `Capture` of `reader`, attaching the last `limit` bytes that were
+/// read to the data error if deserialization fails.
+pub fn capture_on_error<D, R, S, E>(reader: R, limit: usize) -> CaptureOnError<D>
+    where D: AsyncDeserialize<Capture<R>, S, E>,
+          R: AsyncRead
+{
+    CaptureOnError(D::from_reader(Capture::new(reader, limit)))
+}
+
+/// The future returned by `capture_on_error`.
+pub struct CaptureOnError<D>(D);
+
+impl<D> CaptureOnError<D> {
+    /// Get a reference to the wrapped deserializer.
+    pub fn get_ref(&self) -> &D {
+        &self.0
+    }
+}
+
+impl<D, R, S, E> Future for CaptureOnError<D>
+    where D: Future<Item = (Capture<R>, S, usize), Error = (Capture<R>, DeserializeError<E>)>
+{
+    type Item = (R, S, usize);
+    type Error = (R, DeserializeError<Captured<E>>);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        match self.0.poll(cx) {
+            Ok(Async::Ready((capture, val, read))) => {
+                Ok(Async::Ready((capture.into_inner(), val, read)))
+            }
+            Ok(Async::Pending) => Ok(Async::Pending),
+            Err((capture, DeserializeError::ReaderError(err))) => {
+                Err((capture.into_inner(), DeserializeError::ReaderError(err)))
+            }
+            Err((capture, DeserializeError::DataError(err))) => {
+                let captured = Captured {
+                    error: err,
+                    bytes: capture.captured(),
+                    position: capture.position(),
+                };
+                Err((capture.into_inner(), DeserializeError::DataError(captured)))
+            }
+        }
+    }
+}
+
+impl<D, R> Restorable<R> for CaptureOnError<D>
+    where D: Restorable<Capture<R>>
+{
+    fn restore(self) -> Option<R> {
+        self.0.restore().map(Capture::into_inner)
+    }
+}
+
+/// A data error together with the bytes that were read before it occurred.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Captured<E> {
+    /// The data error of the deserializer.
+    pub error: E,
+    /// The last bytes that were read before the error occurred, so the failure happened at the
+    /// end of these bytes.
+    pub bytes: Vec<u8>,
+    /// The offset in the stream at which the error occurred, i.e. how many bytes were read in
+    /// total.
+    pub position: u64,
+}
+
+impl<E: Display> Display for Captured<E> {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        write!(f, "{} at offset {}, preceded by [", self.error, self.position)?;
+        for (i, byte) in self.bytes.iter().enumerate() {
+            if i > 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "{:02x}", byte)?;
+        }
+        write!(f, "]")
+    }
+}
+
+impl<E: Error> Error for Captured<E> {}
@@ -0,0 +1,178 @@
+//! Serialize two values one after the other.
+//!
+//! To serialize more than two values, e.g. the fields of a struct, a `SerializeBatch` or the
+//! `batch_serialize!` macro build the nested chain without spelling out its type.
+
+use futures_core::{Async, Future, Poll};
+use futures_core::task::Context;
+use futures_io::{AsyncWrite, Error as FutIoErr};
+
+use {AsyncSerialize, AsyncSerializeLen, AsyncWriterFuture, AsyncWriterFutureLen};
+use message::NoParts;
+
+/// Serializes a pair of values by first serializing the first value via `A` and then the second
+/// value via `B`.
+///
+/// Chains can be nested to serialize more than two values, e.g.
+/// `Chain<A, Chain<B, C, W>, W>` serializes values of type `(A::Serialized, (B::Serialized,
+/// C::Serialized))`.
+pub struct Chain<A, B, W>
+    where B: AsyncSerialize<W>,
+          W: AsyncWrite
+{
+    state: State<A, B, W>,
+    first_written: usize,
+}
+
+enum State<A, B, W>
+    where B: AsyncSerialize<W>,
+          W: AsyncWrite
+{
+    First(A, Option<B::Serialized>),
+    Second(B),
+}
+
+impl<A, B, W> Future for Chain<A, B, W>
+    where A: AsyncSerialize<W>,
+          B: AsyncSerialize<W>,
+          W: AsyncWrite
+{
+    type Item = (W, usize);
+    type Error = (W, FutIoErr);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let second = match self.state {
+                State::First(ref mut first, ref mut second_val) => {
+                    let (writer, written) = match first.poll(cx) {
+                        Ok(Async::Ready(done)) => done,
+                        Ok(Async::Pending) => return Ok(Async::Pending),
+                        Err(err) => return Err(err),
+                    };
+                    self.first_written = written;
+
+                    let second_val = or_pending!(second_val.take());
+                    B::from_val(writer, second_val)
+                }
+
+                State::Second(ref mut second) => {
+                    return match second.poll(cx) {
+                               Ok(Async::Ready((writer, written))) => {
+                                   Ok(Async::Ready((writer, self.first_written + written)))
+                               }
+                               Ok(Async::Pending) => Ok(Async::Pending),
+                               Err(err) => Err(err),
+                           };
+                }
+            };
+
+            self.state = State::Second(second);
+        }
+    }
+}
+
+impl<A, B, W> AsyncWriterFuture<W> for Chain<A, B, W>
+    where A: AsyncSerialize<W>,
+          B: AsyncSerialize<W>,
+          W: AsyncWrite
+{
+    fn already_written(&self) -> usize {
+        match self.state {
+            State::First(ref first, _) => first.already_written(),
+            State::Second(ref second) => self.first_written + second.already_written(),
+        }
+    }
+}
+
+impl<A, B, W> AsyncWriterFutureLen<W> for Chain<A, B, W>
+    where A: AsyncSerializeLen<W>,
+          B: AsyncSerializeLen<W>,
+          W: AsyncWrite
+{
+    fn remaining_bytes(&self) -> usize {
+        match self.state {
+            State::First(ref first, ref second_val) => {
+                first.remaining_bytes() + second_val.as_ref().map(B::total_bytes).unwrap_or(0)
+            }
+            State::Second(ref second) => second.remaining_bytes(),
+        }
+    }
+}
+
+impl<A, B, W> AsyncSerialize<W> for Chain<A, B, W>
+    where A: AsyncSerialize<W>,
+          B: AsyncSerialize<W>,
+          W: AsyncWrite
+{
+    type Serialized = (A::Serialized, B::Serialized);
+
+    fn from_val(writer: W, val: Self::Serialized) -> Self {
+        Chain {
+            state: State::First(A::from_val(writer, val.0), Some(val.1)),
+            first_written: 0,
+        }
+    }
+}
+
+impl<A, B, W> AsyncSerializeLen<W> for Chain<A, B, W>
+    where A: AsyncSerializeLen<W>,
+          B: AsyncSerializeLen<W>,
+          W: AsyncWrite
+{
+    fn total_bytes(val: &Self::Serialized) -> usize {
+        A::total_bytes(&val.0) + B::total_bytes(&val.1)
+    }
+}
+
+/// Collects values together with their serializers, to serialize them one after the other into a
+/// single writer.
+///
+/// Create one via `new`, add values with `then`, and turn it into a future with `execute`. The
+/// future is a left-nested `Chain` starting with `NoParts`, which yields the writer and the total
+/// number of written bytes.
+pub struct SerializeBatch<P, W>
+    where P: AsyncSerialize<W>,
+          W: AsyncWrite
+{
+    writer: W,
+    val: P::Serialized,
+}
+
+impl<W: AsyncWrite> SerializeBatch<NoParts<W>, W> {
+    /// Create a `SerializeBatch` without any values, that will write into `writer`.
+    pub fn new(writer: W) -> SerializeBatch<NoParts<W>, W> {
+        SerializeBatch { writer, val: () }
+    }
+}
+
+impl<P, W> SerializeBatch<P, W>
+    where P: AsyncSerialize<W>,
+          W: AsyncWrite
+{
+    /// Append a value that is serialized via `S`.
+    pub fn then<S>(self, val: S::Serialized) -> SerializeBatch<Chain<P, S, W>, W>
+        where S: AsyncSerialize<W>
+    {
+        SerializeBatch {
+            writer: self.writer,
+            val: (self.val, val),
+        }
+    }
+
+    /// Create the future that serializes all values in the order they were added.
+    pub fn execute(self) -> P {
+        P::from_val(self.writer, self.val)
+    }
+}
+
+/// Serialize several values one after the other into a writer, each via its own serializer.
+///
+/// `batch_serialize!(writer, (SerA, a), (SerB, b))` is short for
+/// `SerializeBatch::new(writer).then::<SerA>(a).then::<SerB>(b).execute()`, a future that yields
+/// the writer and the total number of written bytes.
+#[macro_export]
+macro_rules! batch_serialize {
+    ($writer:expr $(, ($ser:ty, $val:expr))* $(,)*) => {
+        $crate::chain::SerializeBatch::new($writer)$(.then::<$ser>($val))*.execute()
+    }
+}
@@ -0,0 +1,189 @@
+//! Serialize and deserialize structs field by field, without writing the state machines by hand.
+//!
+//! The `compose_serialize!` macro generates a serializer that writes the fields of a struct one
+//! after the other, each via its own serializer. The `compose_deserialize!` macro generates the
+//! matching deserializer, which reads the fields in the same order and then constructs the struct.
+//! The encoding is just the concatenation of the fields, without any framing.
+//!
+//! Both macros take the name of the generated type and its writer or reader parameter, since
+//! `macro_rules!` can not derive a name such as `SerPoint` from `Point`.
+
+#[doc(hidden)]
+pub mod __private {
+    pub use futures_core::{Async, Future, Poll};
+    pub use futures_core::task::Context;
+    pub use futures_io::{AsyncRead, AsyncWrite, Error as FutIoErr};
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __compose_chain {
+    ($w:ident; $acc:ty;) => { $acc };
+    ($w:ident; $acc:ty; $ser:ty $(, $rest:ty)*) => {
+        $crate::__compose_chain!($w; $crate::chain::Chain<$acc, $ser, $w>; $($rest),*)
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __compose_value {
+    ($acc:expr;) => { $acc };
+    ($acc:expr; $field:ident $(, $rest:ident)*) => {
+        $crate::__compose_value!(($acc, $field); $($rest),*)
+    };
+}
+
+/// Generate a serializer for a struct that serializes its fields one after the other.
+///
+/// The serializer implements `AsyncSerialize<W>` and `AsyncSerializeLen<W>`, with `Serialized`
+/// being the struct, so all field serializers must implement `AsyncSerializeLen<W>`. The fields
+/// are given as `name: Serializer` pairs, in the order in which they are written. Every field of
+/// the struct must be listed.
+///
+/// ```rust,ignore
+/// compose_serialize! {
+///     /// Serializes a `Point`.
+///     pub struct WritePoint<W>: Point {
+///         x: WriteVarint<W>,
+///         y: WriteVarint<W>,
+///     }
+/// }
+/// ```
+#[macro_export]
+macro_rules! compose_serialize {
+    (
+        $(#[$attr:meta])*
+        $vis:vis struct $name:ident<$w:ident>: $target:ident {
+            $($field:ident: $ser:ty),* $(,)*
+        }
+    ) => {
+        $(#[$attr])*
+        $vis struct $name<$w>(
+            $crate::__compose_chain!($w; $crate::message::NoParts<$w>; $($ser),*)
+        ) where $w: $crate::compose::__private::AsyncWrite;
+
+        impl<$w> $crate::compose::__private::Future for $name<$w>
+            where $w: $crate::compose::__private::AsyncWrite
+        {
+            type Item = ($w, usize);
+            type Error = ($w, $crate::compose::__private::FutIoErr);
+
+            fn poll(&mut self,
+                    cx: &mut $crate::compose::__private::Context)
+                    -> $crate::compose::__private::Poll<Self::Item, Self::Error> {
+                $crate::compose::__private::Future::poll(&mut self.0, cx)
+            }
+        }
+
+        impl<$w> $crate::AsyncWriterFuture<$w> for $name<$w>
+            where $w: $crate::compose::__private::AsyncWrite
+        {
+            fn already_written(&self) -> usize {
+                $crate::AsyncWriterFuture::already_written(&self.0)
+            }
+        }
+
+        impl<$w> $crate::AsyncWriterFutureLen<$w> for $name<$w>
+            where $w: $crate::compose::__private::AsyncWrite
+        {
+            fn remaining_bytes(&self) -> usize {
+                $crate::AsyncWriterFutureLen::remaining_bytes(&self.0)
+            }
+        }
+
+        impl<$w> $crate::AsyncSerialize<$w> for $name<$w>
+            where $w: $crate::compose::__private::AsyncWrite
+        {
+            type Serialized = $target;
+
+            fn from_val(writer: $w, val: $target) -> Self {
+                let $target { $($field),* } = val;
+                $name($crate::AsyncSerialize::from_val(writer,
+                                                        $crate::__compose_value!((); $($field),*)))
+            }
+        }
+
+        impl<$w> $crate::AsyncSerializeLen<$w> for $name<$w>
+            where $w: $crate::compose::__private::AsyncWrite
+        {
+            fn total_bytes(val: &$target) -> usize {
+                0 $(+ <$ser as $crate::AsyncSerializeLen<$w>>::total_bytes(&val.$field))*
+            }
+        }
+    }
+}
+
+/// Generate a deserializer for a struct that deserializes its fields one after the other.
+///
+/// The deserializer implements `AsyncDeserialize<R, T, E>`, where `T` is the struct and `E` the
+/// data error type shared by all fields, into which the data errors of the field deserializers
+/// are converted via `Into`. It implements `Restorable<R>` if all field deserializers do. The
+/// fields are given as `name: Deserializer` pairs, in the order in which they are read.
+///
+/// ```rust,ignore
+/// compose_deserialize! {
+///     /// Deserializes a `Point`.
+///     pub struct ReadPoint<R>: Point {
+///         error: VarintError,
+///         x: ReadVarint<R>,
+///         y: ReadVarint<R>,
+///     }
+/// }
+/// ```
+#[macro_export]
+macro_rules! compose_deserialize {
+    (
+        $(#[$attr:meta])*
+        $vis:vis struct $name:ident<$r:ident>: $target:ident {
+            error: $err:ty,
+            $($field:ident: $de:ty),* $(,)*
+        }
+    ) => {
+        $(#[$attr])*
+        $vis struct $name<$r>($crate::__deserialize_enum_fields!($r, $err; $($de),*))
+            where $r: $crate::compose::__private::AsyncRead;
+
+        impl<$r> $crate::compose::__private::Future for $name<$r>
+            where $r: $crate::compose::__private::AsyncRead
+        {
+            type Item = ($r, $target, usize);
+            type Error = ($r, $crate::DeserializeError<$err>);
+
+            fn poll(&mut self,
+                    cx: &mut $crate::compose::__private::Context)
+                    -> $crate::compose::__private::Poll<Self::Item, Self::Error> {
+                use $crate::compose::__private::{Async, Future};
+
+                let (reader, fields, read) = match self.0.poll(cx) {
+                    Ok(Async::Ready(done)) => done,
+                    Ok(Async::Pending) => return Ok(Async::Pending),
+                    Err(err) => return Err(err),
+                };
+
+                let $crate::__deserialize_enum_pattern!($($field),*) = fields;
+                Ok(Async::Ready((reader, $target { $($field),* }, read)))
+            }
+        }
+
+        impl<$r> $crate::AsyncDeserialize<$r, $target, $err> for $name<$r>
+            where $r: $crate::compose::__private::AsyncRead
+        {
+            fn from_reader(reader: $r) -> Self {
+                $name($crate::AsyncDeserialize::from_reader(reader))
+            }
+
+            fn already_read(&self) -> usize {
+                $crate::AsyncDeserialize::already_read(&self.0)
+            }
+        }
+
+        impl<$r> $crate::Restorable<$r> for $name<$r>
+            where $r: $crate::compose::__private::AsyncRead,
+                  $crate::__deserialize_enum_fields!($r, $err; $($de),*): $crate::Restorable<$r>
+        {
+            fn restore(self) -> Option<$r> {
+                $crate::Restorable::restore(self.0)
+            }
+        }
+    }
+}
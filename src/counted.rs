@@ -0,0 +1,269 @@
+//! Serialize the elements of an iterator of unknown length behind a count.
+//!
+//! A sequence is encoded as the number of elements as a big-endian `u32`, followed by the elements
+//! in order, the same encoding as used by the `linked_list` and `fold` modules. Since the count
+//! has to be written before the elements, `SerializeCounted` needs a `Strategy` for learning it.
+//! The caller always picks the strategy, `SerializeCounted` never switches to another one on its
+//! own.
+
+use std::collections::VecDeque;
+
+use futures_core::{Async, Future, Poll};
+use futures_core::task::Context;
+use futures_io::{AsyncWrite, Error as FutIoErr, ErrorKind};
+
+use {AsyncSerialize, AsyncWriterFuture};
+use util::{prefix_fits, WriteAll};
+
+/// How a `SerializeCounted` learns the count of the elements before writing them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Strategy {
+    /// Take up to `max` elements from the iterator, then write their count and serialize them.
+    ///
+    /// If the iterator has more elements, this fails with an `ErrorKind::InvalidInput` error
+    /// without writing anything.
+    Buffer {
+        /// The largest number of elements to hold in memory.
+        max: usize,
+    },
+    /// Serialize all elements into memory behind a reserved count, fill in the count once all
+    /// elements have been serialized, and then write everything, like a `ReserveAndFill`.
+    ///
+    /// This produces the same bytes as `Buffer`, but holds the serialized elements in memory
+    /// instead of the elements themselves, and works for any number of elements.
+    Backpatch,
+    /// Take up to `chunk` elements at a time from the iterator, and write each such chunk like
+    /// `Buffer` would, followed by an empty chunk as the terminator.
+    ///
+    /// The stream is a sequence of counted sequences, so it is not compatible with the other
+    /// strategies, but each chunk can be read like a whole sequence written by them, e.g. via a
+    /// `ReadFold`, until one of them is empty.
+    Chunked {
+        /// The largest number of elements to hold in memory, at least one.
+        chunk: u32,
+    },
+}
+
+/// The writer into which the element serializers of a `SerializeCounted` write.
+///
+/// It forwards to the wrapped writer, except while a `Strategy::Backpatch` is serializing the
+/// elements, during which it appends everything to an in-memory buffer.
+#[derive(Debug)]
+pub struct Spool<W> {
+    inner: W,
+    buf: Option<Vec<u8>>,
+}
+
+impl<W> Spool<W> {
+    /// Get a reference to the wrapped writer.
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+}
+
+impl<W: AsyncWrite> AsyncWrite for Spool<W> {
+    fn poll_write(&mut self, cx: &mut Context, buf: &[u8]) -> Poll<usize, FutIoErr> {
+        match self.buf {
+            Some(ref mut spooled) => {
+                spooled.extend_from_slice(buf);
+                Ok(Async::Ready(buf.len()))
+            }
+            None => self.inner.poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(&mut self, cx: &mut Context) -> Poll<(), FutIoErr> {
+        self.inner.poll_flush(cx)
+    }
+
+    fn poll_close(&mut self, cx: &mut Context) -> Poll<(), FutIoErr> {
+        self.inner.poll_close(cx)
+    }
+}
+
+/// Serializes the elements of an iterator `I` via `S` behind their count, learning the count as
+/// chosen by a `Strategy`.
+///
+/// The elements are serialized into a `Spool` wrapping `W`, so `S` is e.g. a
+/// `WriteVarint<Spool<W>>`. Yields the wrapped writer and the number of bytes written into it.
+/// If writing fails, the wrapped writer is emitted together with the error, and the elements
+/// spooled by a `Strategy::Backpatch` are lost.
+pub struct SerializeCounted<W, S, I>
+    where S: AsyncSerialize<Spool<W>>,
+          W: AsyncWrite
+{
+    state: State<W, S>,
+    elements: I,
+    strategy: Strategy,
+    batch: VecDeque<S::Serialized>,
+    count: u32,
+    terminated: bool,
+    written: usize,
+}
+
+enum State<W, S> {
+    Count(WriteAll<Spool<W>, [u8; 4]>),
+    Element(S),
+    Flush(WriteAll<Spool<W>, Vec<u8>>),
+    Invalid(Option<Spool<W>>, &'static str),
+    Done(Option<Spool<W>>),
+}
+
+impl<W, S, I> SerializeCounted<W, S, I>
+    where S: AsyncSerialize<Spool<W>>,
+          W: AsyncWrite,
+          I: Iterator<Item = S::Serialized>
+{
+    /// Create a new `SerializeCounted`, serializing `elements` into `writer` via `strategy`.
+    ///
+    /// Panics if `strategy` is a `Strategy::Chunked` with a `chunk` of zero.
+    pub fn new(writer: W, elements: I, strategy: Strategy) -> SerializeCounted<W, S, I> {
+        let mut counted = SerializeCounted {
+            state: State::Done(None),
+            elements,
+            strategy,
+            batch: VecDeque::new(),
+            count: 0,
+            terminated: false,
+            written: 0,
+        };
+
+        let writer = Spool {
+            inner: writer,
+            buf: None,
+        };
+        counted.state = match strategy {
+            Strategy::Buffer { max } => {
+                counted.batch.extend(counted.elements.by_ref().take(max));
+                let len = counted.batch.len();
+                if counted.elements.next().is_some() {
+                    State::Invalid(Some(writer), "sequence has more elements than can be buffered")
+                } else if !prefix_fits(len) {
+                    State::Invalid(Some(writer), "sequence is too long")
+                } else {
+                    State::Count(WriteAll::new(writer, (len as u32).to_be_bytes()))
+                }
+            }
+            Strategy::Backpatch => {
+                let mut writer = writer;
+                writer.buf = Some(vec![0; 4]);
+                counted.next(writer)
+            }
+            Strategy::Chunked { chunk } => {
+                assert!(chunk > 0, "Chunked needs a positive chunk");
+                counted.next(writer)
+            }
+        };
+        counted
+    }
+
+    // Decide what to do after the previous step, which gave back `writer`.
+    fn next(&mut self, mut writer: Spool<W>) -> State<W, S> {
+        match self.strategy {
+            Strategy::Buffer { .. } => {
+                match self.batch.pop_front() {
+                    Some(element) => State::Element(S::from_val(writer, element)),
+                    None => State::Done(Some(writer)),
+                }
+            }
+
+            Strategy::Backpatch => {
+                if self.terminated {
+                    return State::Done(Some(writer));
+                }
+                match self.elements.next() {
+                    Some(element) => {
+                        if self.count == u32::MAX {
+                            writer.buf = None;
+                            return State::Invalid(Some(writer), "sequence is too long");
+                        }
+                        self.count += 1;
+                        State::Element(S::from_val(writer, element))
+                    }
+                    None => {
+                        let mut buf = writer.buf.take().unwrap_or_else(|| vec![0; 4]);
+                        buf[..4].copy_from_slice(&self.count.to_be_bytes());
+                        self.terminated = true;
+                        State::Flush(WriteAll::new(writer, buf))
+                    }
+                }
+            }
+
+            Strategy::Chunked { chunk } => {
+                if let Some(element) = self.batch.pop_front() {
+                    return State::Element(S::from_val(writer, element));
+                }
+                if self.terminated {
+                    return State::Done(Some(writer));
+                }
+                self.batch.extend(self.elements.by_ref().take(chunk as usize));
+                self.terminated = self.batch.is_empty();
+                State::Count(WriteAll::new(writer, (self.batch.len() as u32).to_be_bytes()))
+            }
+        }
+    }
+}
+
+impl<W, S, I> Future for SerializeCounted<W, S, I>
+    where S: AsyncSerialize<Spool<W>>,
+          W: AsyncWrite,
+          I: Iterator<Item = S::Serialized>
+{
+    type Item = (W, usize);
+    type Error = (W, FutIoErr);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let res = match self.state {
+                State::Count(ref mut inner) => inner.poll(cx),
+                State::Element(ref mut inner) => inner.poll(cx),
+                State::Flush(ref mut inner) => inner.poll(cx),
+                State::Invalid(ref mut writer, reason) => {
+                    let writer = or_pending!(writer.take());
+                    let err = FutIoErr::new(ErrorKind::InvalidInput, reason);
+                    return Err((writer.inner, err));
+                }
+                State::Done(ref mut writer) => {
+                    let writer = or_pending!(writer.take());
+                    return Ok(Async::Ready((writer.inner, self.written)));
+                }
+            };
+
+            let (writer, written) = match res {
+                Ok(Async::Ready(done)) => done,
+                Ok(Async::Pending) => return Ok(Async::Pending),
+                Err((writer, err)) => return Err((writer.inner, err)),
+            };
+            if writer.buf.is_none() {
+                self.written += written;
+            }
+
+            let next = self.next(writer);
+            self.state = next;
+        }
+    }
+}
+
+impl<W, S, I> AsyncWriterFuture<W> for SerializeCounted<W, S, I>
+    where S: AsyncSerialize<Spool<W>>,
+          W: AsyncWrite,
+          I: Iterator<Item = S::Serialized>
+{
+    /// Return how many bytes have already been written into the wrapped writer. This stays zero
+    /// while a `Strategy::Backpatch` is still serializing the elements into memory.
+    fn already_written(&self) -> usize {
+        self.written +
+        match self.state {
+            State::Count(ref inner) => inner.already_written(),
+            State::Element(ref inner) => {
+                if self.strategy == Strategy::Backpatch {
+                    0
+                } else {
+                    inner.already_written()
+                }
+            }
+            State::Flush(ref inner) => inner.already_written(),
+            State::Invalid(..) | State::Done(_) => 0,
+        }
+    }
+}
@@ -0,0 +1,203 @@
+//! Serialization of possibly borrowed byte strings and strings, by reference (`SerCowBytes`,
+//! `SerCowStr`) or by value (`WriteCowBytes`, `WriteCowStr`).
+//!
+//! Both are encoded as their length in bytes as a big-endian `u32`, followed by the bytes, the
+//! same as an [`Arc<[u8]>`](../arc_bytes/index.html). Values longer than `u32::MAX` bytes can not
+//! be serialized, the serializers fail with an `ErrorKind::InvalidInput` error without writing
+//! anything.
+
+use std::borrow::Cow;
+
+use futures_core::{Future, Poll};
+use futures_core::task::Context;
+use futures_io::{AsyncWrite, Error as FutIoErr};
+
+use {AsyncSerialize, AsyncSerializeLen, AsyncSerializeRef, AsyncSerializeRefLen, AsyncWriterFuture,
+     AsyncWriterFutureLen};
+use util::{prefix_fits, TryWriteLenPrefixed};
+
+fn checked(bytes: &[u8]) -> Option<&[u8]> {
+    if prefix_fits(bytes.len()) {
+        Some(bytes)
+    } else {
+        None
+    }
+}
+
+fn encoded_len(bytes: &[u8]) -> usize {
+    checked(bytes).map(|bytes| 4 + bytes.len()).unwrap_or(0)
+}
+
+/// Serializes a `Cow<[u8]>` by reference, without cloning it regardless of whether it is borrowed
+/// or owned.
+///
+/// For values that can not be serialized, `total_bytes` returns zero.
+pub struct SerCowBytes<'val, W>(TryWriteLenPrefixed<W, &'val [u8]>);
+
+impl<'val, W: AsyncWrite> Future for SerCowBytes<'val, W> {
+    type Item = (W, usize);
+    type Error = (W, FutIoErr);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        self.0.poll(cx)
+    }
+}
+
+impl<'val, W: AsyncWrite> AsyncWriterFuture<W> for SerCowBytes<'val, W> {
+    fn already_written(&self) -> usize {
+        self.0.already_written()
+    }
+}
+
+impl<'val, W: AsyncWrite> AsyncWriterFutureLen<W> for SerCowBytes<'val, W> {
+    fn remaining_bytes(&self) -> usize {
+        self.0.remaining_bytes()
+    }
+}
+
+impl<'val, W: AsyncWrite> AsyncSerializeRef<'val, W> for SerCowBytes<'val, W> {
+    type Serialized = Cow<'val, [u8]>;
+
+    fn from_ref(writer: W, val: &'val Cow<'val, [u8]>) -> Self {
+        SerCowBytes(TryWriteLenPrefixed::new(writer, checked(val), "buffer is too long"))
+    }
+}
+
+impl<'val, W: AsyncWrite> AsyncSerializeRefLen<'val, W> for SerCowBytes<'val, W> {
+    fn total_bytes(val: &Cow<'val, [u8]>) -> usize {
+        encoded_len(val)
+    }
+}
+
+/// Serializes a `Cow<str>` by reference, without cloning it regardless of whether it is borrowed
+/// or owned.
+///
+/// For values that can not be serialized, `total_bytes` returns zero.
+pub struct SerCowStr<'val, W>(TryWriteLenPrefixed<W, &'val [u8]>);
+
+impl<'val, W: AsyncWrite> Future for SerCowStr<'val, W> {
+    type Item = (W, usize);
+    type Error = (W, FutIoErr);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        self.0.poll(cx)
+    }
+}
+
+impl<'val, W: AsyncWrite> AsyncWriterFuture<W> for SerCowStr<'val, W> {
+    fn already_written(&self) -> usize {
+        self.0.already_written()
+    }
+}
+
+impl<'val, W: AsyncWrite> AsyncWriterFutureLen<W> for SerCowStr<'val, W> {
+    fn remaining_bytes(&self) -> usize {
+        self.0.remaining_bytes()
+    }
+}
+
+impl<'val, W: AsyncWrite> AsyncSerializeRef<'val, W> for SerCowStr<'val, W> {
+    type Serialized = Cow<'val, str>;
+
+    fn from_ref(writer: W, val: &'val Cow<'val, str>) -> Self {
+        SerCowStr(TryWriteLenPrefixed::new(writer, checked(val.as_bytes()), "string is too long"))
+    }
+}
+
+impl<'val, W: AsyncWrite> AsyncSerializeRefLen<'val, W> for SerCowStr<'val, W> {
+    fn total_bytes(val: &Cow<'val, str>) -> usize {
+        encoded_len(val.as_bytes())
+    }
+}
+
+/// Serializes a `Cow<[u8]>` by value. Borrowed data is written directly from the borrowed slice,
+/// owned data from the owned buffer, so neither case copies.
+///
+/// For values that can not be serialized, `total_bytes` returns zero.
+pub struct WriteCowBytes<'a, W>(TryWriteLenPrefixed<W, Cow<'a, [u8]>>);
+
+impl<'a, W: AsyncWrite> Future for WriteCowBytes<'a, W> {
+    type Item = (W, usize);
+    type Error = (W, FutIoErr);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        self.0.poll(cx)
+    }
+}
+
+impl<'a, W: AsyncWrite> AsyncWriterFuture<W> for WriteCowBytes<'a, W> {
+    fn already_written(&self) -> usize {
+        self.0.already_written()
+    }
+}
+
+impl<'a, W: AsyncWrite> AsyncWriterFutureLen<W> for WriteCowBytes<'a, W> {
+    fn remaining_bytes(&self) -> usize {
+        self.0.remaining_bytes()
+    }
+}
+
+impl<'a, W: AsyncWrite> AsyncSerialize<W> for WriteCowBytes<'a, W> {
+    type Serialized = Cow<'a, [u8]>;
+
+    fn from_val(writer: W, val: Cow<'a, [u8]>) -> Self {
+        let body = if prefix_fits(val.len()) { Some(val) } else { None };
+        WriteCowBytes(TryWriteLenPrefixed::new(writer, body, "buffer is too long"))
+    }
+}
+
+impl<'a, W: AsyncWrite> AsyncSerializeLen<W> for WriteCowBytes<'a, W> {
+    fn total_bytes(val: &Cow<'a, [u8]>) -> usize {
+        encoded_len(val)
+    }
+}
+
+/// Serializes a `Cow<str>` by value. Borrowed data is written directly from the borrowed string,
+/// owned data from the owned string, so neither case copies.
+///
+/// For values that can not be serialized, `total_bytes` returns zero.
+pub struct WriteCowStr<'a, W>(WriteCowBytes<'a, W>);
+
+impl<'a, W: AsyncWrite> Future for WriteCowStr<'a, W> {
+    type Item = (W, usize);
+    type Error = (W, FutIoErr);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        self.0.poll(cx)
+    }
+}
+
+impl<'a, W: AsyncWrite> AsyncWriterFuture<W> for WriteCowStr<'a, W> {
+    fn already_written(&self) -> usize {
+        self.0.already_written()
+    }
+}
+
+impl<'a, W: AsyncWrite> AsyncWriterFutureLen<W> for WriteCowStr<'a, W> {
+    fn remaining_bytes(&self) -> usize {
+        self.0.remaining_bytes()
+    }
+}
+
+impl<'a, W: AsyncWrite> AsyncSerialize<W> for WriteCowStr<'a, W> {
+    type Serialized = Cow<'a, str>;
+
+    fn from_val(writer: W, val: Cow<'a, str>) -> Self {
+        let bytes = match val {
+            Cow::Borrowed(string) => Cow::Borrowed(string.as_bytes()),
+            Cow::Owned(string) => Cow::Owned(string.into_bytes()),
+        };
+
+        if prefix_fits(bytes.len()) {
+            WriteCowStr(WriteCowBytes::from_val(writer, bytes))
+        } else {
+            WriteCowStr(WriteCowBytes(TryWriteLenPrefixed::new(writer, None, "string is too long")))
+        }
+    }
+}
+
+impl<'a, W: AsyncWrite> AsyncSerializeLen<W> for WriteCowStr<'a, W> {
+    fn total_bytes(val: &Cow<'a, str>) -> usize {
+        encoded_len(val.as_bytes())
+    }
+}
@@ -0,0 +1,186 @@
+//! Serialize a header and flush it before serializing the payload.
+
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+
+use futures_core::{Async, Future, Poll};
+use futures_core::task::Context;
+use futures_io::{AsyncWrite, Error as FutIoErr};
+
+use {AsyncSerialize, AsyncSerializeLen, AsyncWriterFuture, AsyncWriterFutureLen};
+
+/// Serializes a pair of a header and a payload like a [`Chain`](../chain/struct.Chain.html), but
+/// flushes the writer after the header, so that the receiver can process the header while the
+/// payload is still being written.
+///
+/// The header can contain the length of the payload without any buffering, since the
+/// `total_bytes` of the payload serializer can be used to construct the header value.
+///
+/// Errors that happen while writing or flushing the header are wrapped into an io error of the
+/// same kind that holds a `HeaderError`, errors of the payload are passed through unchanged.
+///
+/// `from_val` always flushes, use `new` for writers where flushing is expensive.
+pub struct EagerHeader<W, H, P>
+    where P: AsyncSerialize<W>,
+          W: AsyncWrite
+{
+    state: State<W, H, P>,
+    flush: bool,
+    header_written: usize,
+}
+
+enum State<W, H, P>
+    where P: AsyncSerialize<W>,
+          W: AsyncWrite
+{
+    Header(H, Option<P::Serialized>),
+    Flush(Option<W>, Option<P::Serialized>),
+    Payload(P),
+}
+
+impl<W, H, P> EagerHeader<W, H, P>
+    where H: AsyncSerialize<W>,
+          P: AsyncSerialize<W>,
+          W: AsyncWrite
+{
+    /// Create a new `EagerHeader`, flushing after the header only if `flush` is `true`.
+    pub fn new(writer: W,
+               val: (H::Serialized, P::Serialized),
+               flush: bool)
+               -> EagerHeader<W, H, P> {
+        EagerHeader {
+            state: State::Header(H::from_val(writer, val.0), Some(val.1)),
+            flush,
+            header_written: 0,
+        }
+    }
+}
+
+fn header_error(err: FutIoErr) -> FutIoErr {
+    FutIoErr::new(err.kind(), HeaderError(err))
+}
+
+impl<W, H, P> Future for EagerHeader<W, H, P>
+    where H: AsyncSerialize<W>,
+          P: AsyncSerialize<W>,
+          W: AsyncWrite
+{
+    type Item = (W, usize);
+    type Error = (W, FutIoErr);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let next = match self.state {
+                State::Header(ref mut header, ref mut payload_val) => {
+                    let (writer, written) = match header.poll(cx) {
+                        Ok(Async::Ready(done)) => done,
+                        Ok(Async::Pending) => return Ok(Async::Pending),
+                        Err((writer, err)) => return Err((writer, header_error(err))),
+                    };
+                    self.header_written = written;
+
+                    let payload_val = payload_val.take();
+                    if self.flush {
+                        State::Flush(Some(writer), payload_val)
+                    } else {
+                        let payload_val = or_pending!(payload_val);
+                        State::Payload(P::from_val(writer, payload_val))
+                    }
+                }
+
+                State::Flush(ref mut writer, ref mut payload_val) => {
+                    let mut w = or_pending!(writer.take());
+                    match w.poll_flush(cx) {
+                        Ok(Async::Ready(())) => {}
+                        Ok(Async::Pending) => {
+                            *writer = Some(w);
+                            return Ok(Async::Pending);
+                        }
+                        Err(err) => return Err((w, header_error(err))),
+                    }
+
+                    let payload_val = or_pending!(payload_val.take());
+                    State::Payload(P::from_val(w, payload_val))
+                }
+
+                State::Payload(ref mut payload) => {
+                    let (writer, written) = try_ready!(payload.poll(cx));
+                    return Ok(Async::Ready((writer, self.header_written + written)));
+                }
+            };
+
+            self.state = next;
+        }
+    }
+}
+
+impl<W, H, P> AsyncWriterFuture<W> for EagerHeader<W, H, P>
+    where H: AsyncSerialize<W>,
+          P: AsyncSerialize<W>,
+          W: AsyncWrite
+{
+    fn already_written(&self) -> usize {
+        match self.state {
+            State::Header(ref header, _) => header.already_written(),
+            State::Flush(..) => self.header_written,
+            State::Payload(ref payload) => self.header_written + payload.already_written(),
+        }
+    }
+}
+
+impl<W, H, P> AsyncWriterFutureLen<W> for EagerHeader<W, H, P>
+    where H: AsyncSerializeLen<W>,
+          P: AsyncSerializeLen<W>,
+          W: AsyncWrite
+{
+    fn remaining_bytes(&self) -> usize {
+        match self.state {
+            State::Header(ref header, ref payload_val) => {
+                header.remaining_bytes() +
+                P::total_bytes(payload_val.as_ref().expect("Used EagerHeader after completion"))
+            }
+            State::Flush(_, ref payload_val) => {
+                P::total_bytes(payload_val.as_ref().expect("Used EagerHeader after completion"))
+            }
+            State::Payload(ref payload) => payload.remaining_bytes(),
+        }
+    }
+}
+
+impl<W, H, P> AsyncSerialize<W> for EagerHeader<W, H, P>
+    where H: AsyncSerialize<W>,
+          P: AsyncSerialize<W>,
+          W: AsyncWrite
+{
+    type Serialized = (H::Serialized, P::Serialized);
+
+    fn from_val(writer: W, val: Self::Serialized) -> Self {
+        EagerHeader::new(writer, val, true)
+    }
+}
+
+impl<W, H, P> AsyncSerializeLen<W> for EagerHeader<W, H, P>
+    where H: AsyncSerializeLen<W>,
+          P: AsyncSerializeLen<W>,
+          W: AsyncWrite
+{
+    fn total_bytes(val: &Self::Serialized) -> usize {
+        H::total_bytes(&val.0) + P::total_bytes(&val.1)
+    }
+}
+
+/// Wraps an error that happened while writing or flushing the header of an `EagerHeader`.
+#[derive(Debug)]
+pub struct HeaderError(pub FutIoErr);
+
+impl Display for HeaderError {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        write!(f, "Failed to write header: {}", self.0)
+    }
+}
+
+impl Error for HeaderError {
+    fn cause(&self) -> Option<&dyn Error> {
+        Some(&self.0)
+    }
+}
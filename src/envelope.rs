@@ -0,0 +1,455 @@
+//! A self-describing envelope for values stored in files.
+//!
+//! An envelope consists of a 4-byte magic number, a 2-byte format version, the length of the body
+//! as an 8-byte integer, the body, and the CRC-32 (IEEE) of the body as a 4-byte integer. All
+//! integers are big-endian.
+//!
+//! The magic number and versions are const parameters, so that the envelope of a particular file
+//! format is a single type, e.g. `type WriteMyFile<W> = WriteEnvelope<F, W, 0x4d594649, 3>`.
+
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+
+use futures_core::{Async, Future, Poll};
+use futures_core::task::Context;
+use futures_io::{AsyncRead, AsyncWrite, Error as FutIoErr};
+
+use {AsyncDeserialize, AsyncSerialize, AsyncSerializeLen, AsyncWriterFuture,
+     AsyncWriterFutureLen, DeserializeError, Restorable};
+use take_reader::TakeReader;
+use util::{ReadExact, WriteAll};
+
+const HEADER_LEN: usize = 4 + 2 + 8;
+const TRAILER_LEN: usize = 4;
+
+/// Update a CRC-32 (IEEE) checksum with some bytes. Start with a checksum of zero.
+pub fn crc32(crc: u32, bytes: &[u8]) -> u32 {
+    let mut crc = !crc;
+    for &byte in bytes {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+/// Wraps an `AsyncWrite` and computes the CRC-32 of all bytes written to it.
+#[derive(Debug)]
+pub struct CrcWriter<W> {
+    inner: W,
+    crc: u32,
+}
+
+impl<W> CrcWriter<W> {
+    /// Create a new `CrcWriter` wrapping `inner`.
+    pub fn new(inner: W) -> CrcWriter<W> {
+        CrcWriter { inner, crc: 0 }
+    }
+
+    /// Return the CRC-32 of the bytes written so far.
+    pub fn crc(&self) -> u32 {
+        self.crc
+    }
+
+    /// Get a reference to the wrapped writer.
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Get a mutable reference to the wrapped writer.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// Consume this `CrcWriter`, returning the wrapped writer.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: AsyncWrite> AsyncWrite for CrcWriter<W> {
+    fn poll_write(&mut self, cx: &mut Context, buf: &[u8]) -> Poll<usize, FutIoErr> {
+        let written = try_ready!(self.inner.poll_write(cx, buf));
+        self.crc = crc32(self.crc, &buf[..written]);
+        Ok(Async::Ready(written))
+    }
+
+    fn poll_flush(&mut self, cx: &mut Context) -> Poll<(), FutIoErr> {
+        self.inner.poll_flush(cx)
+    }
+
+    fn poll_close(&mut self, cx: &mut Context) -> Poll<(), FutIoErr> {
+        self.inner.poll_close(cx)
+    }
+}
+
+/// Wraps an `AsyncRead` and computes the CRC-32 of all bytes read from it.
+#[derive(Debug)]
+pub struct CrcReader<R> {
+    inner: R,
+    crc: u32,
+}
+
+impl<R> CrcReader<R> {
+    /// Create a new `CrcReader` wrapping `inner`.
+    pub fn new(inner: R) -> CrcReader<R> {
+        CrcReader { inner, crc: 0 }
+    }
+
+    /// Return the CRC-32 of the bytes read so far.
+    pub fn crc(&self) -> u32 {
+        self.crc
+    }
+
+    /// Get a reference to the wrapped reader.
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// Get a mutable reference to the wrapped reader.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    /// Consume this `CrcReader`, returning the wrapped reader.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: AsyncRead> AsyncRead for CrcReader<R> {
+    fn poll_read(&mut self, cx: &mut Context, buf: &mut [u8]) -> Poll<usize, FutIoErr> {
+        let read = try_ready!(self.inner.poll_read(cx, buf));
+        self.crc = crc32(self.crc, &buf[..read]);
+        Ok(Async::Ready(read))
+    }
+}
+
+/// Serializes a value via `F` into an envelope with the given magic number and version.
+pub struct WriteEnvelope<F, W, const MAGIC: u32, const VERSION: u16>
+    where F: AsyncSerialize<CrcWriter<W>>,
+          W: AsyncWrite
+{
+    state: WriteState<F, W>,
+    written: usize,
+}
+
+enum WriteState<F, W>
+    where F: AsyncSerialize<CrcWriter<W>>,
+          W: AsyncWrite
+{
+    Header(WriteAll<W, [u8; HEADER_LEN]>, Option<F::Serialized>, usize),
+    Body(F),
+    Trailer(WriteAll<W, [u8; TRAILER_LEN]>),
+}
+
+impl<F, W, const MAGIC: u32, const VERSION: u16> Future for WriteEnvelope<F, W, MAGIC, VERSION>
+    where F: AsyncSerialize<CrcWriter<W>>,
+          W: AsyncWrite
+{
+    type Item = (W, usize);
+    type Error = (W, FutIoErr);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let next = match self.state {
+                WriteState::Header(ref mut header, ref mut val, _) => {
+                    let (writer, written) = try_ready!(header.poll(cx));
+                    self.written += written;
+                    let val = or_pending!(val.take());
+                    WriteState::Body(F::from_val(CrcWriter::new(writer), val))
+                }
+                WriteState::Body(ref mut body) => {
+                    let (writer, written) = match body.poll(cx) {
+                        Ok(Async::Ready(done)) => done,
+                        Ok(Async::Pending) => return Ok(Async::Pending),
+                        Err((writer, err)) => return Err((writer.into_inner(), err)),
+                    };
+                    self.written += written;
+                    let crc = writer.crc();
+                    WriteState::Trailer(WriteAll::new(writer.into_inner(), crc.to_be_bytes()))
+                }
+                WriteState::Trailer(ref mut trailer) => {
+                    let (writer, written) = try_ready!(trailer.poll(cx));
+                    self.written += written;
+                    return Ok(Async::Ready((writer, self.written)));
+                }
+            };
+            self.state = next;
+        }
+    }
+}
+
+impl<F, W, const MAGIC: u32, const VERSION: u16> AsyncWriterFuture<W>
+    for WriteEnvelope<F, W, MAGIC, VERSION>
+    where F: AsyncSerialize<CrcWriter<W>>,
+          W: AsyncWrite
+{
+    fn already_written(&self) -> usize {
+        self.written +
+        match self.state {
+            WriteState::Header(ref header, _, _) => header.already_written(),
+            WriteState::Body(ref body) => body.already_written(),
+            WriteState::Trailer(ref trailer) => trailer.already_written(),
+        }
+    }
+}
+
+impl<F, W, const MAGIC: u32, const VERSION: u16> AsyncWriterFutureLen<W>
+    for WriteEnvelope<F, W, MAGIC, VERSION>
+    where F: AsyncSerializeLen<CrcWriter<W>>,
+          W: AsyncWrite
+{
+    fn remaining_bytes(&self) -> usize {
+        match self.state {
+            WriteState::Header(ref header, _, body_len) => {
+                header.remaining_bytes() + body_len + TRAILER_LEN
+            }
+            WriteState::Body(ref body) => body.remaining_bytes() + TRAILER_LEN,
+            WriteState::Trailer(ref trailer) => trailer.remaining_bytes(),
+        }
+    }
+}
+
+impl<F, W, const MAGIC: u32, const VERSION: u16> AsyncSerialize<W>
+    for WriteEnvelope<F, W, MAGIC, VERSION>
+    where F: AsyncSerializeLen<CrcWriter<W>>,
+          W: AsyncWrite
+{
+    type Serialized = F::Serialized;
+
+    fn from_val(writer: W, val: F::Serialized) -> Self {
+        let body_len = F::total_bytes(&val);
+
+        let mut header = [0; HEADER_LEN];
+        header[..4].copy_from_slice(&MAGIC.to_be_bytes());
+        header[4..6].copy_from_slice(&VERSION.to_be_bytes());
+        header[6..].copy_from_slice(&(body_len as u64).to_be_bytes());
+
+        WriteEnvelope {
+            state: WriteState::Header(WriteAll::new(writer, header), Some(val), body_len),
+            written: 0,
+        }
+    }
+}
+
+impl<F, W, const MAGIC: u32, const VERSION: u16> AsyncSerializeLen<W>
+    for WriteEnvelope<F, W, MAGIC, VERSION>
+    where F: AsyncSerializeLen<CrcWriter<W>>,
+          W: AsyncWrite
+{
+    fn total_bytes(val: &F::Serialized) -> usize {
+        HEADER_LEN + F::total_bytes(val) + TRAILER_LEN
+    }
+}
+
+/// Deserializes the body of an envelope via `D`, yielding the version of the envelope together
+/// with the body.
+///
+/// Envelopes with a magic number other than `MAGIC` or a version outside of `MIN_VERSION` to
+/// `MAX_VERSION` (inclusive) are rejected before the body is read. The body is read through a
+/// `TakeReader`, so `D` can not read past its end, and must consume it completely.
+pub struct ReadEnvelope<D, R, T, const MAGIC: u32, const MIN_VERSION: u16, const MAX_VERSION: u16> {
+    state: ReadState<D, R, T>,
+    read: usize,
+}
+
+enum ReadState<D, R, T> {
+    Header(ReadExact<R, [u8; HEADER_LEN]>),
+    Body(D, u16, u64),
+    Trailer(ReadExact<R, [u8; TRAILER_LEN]>, u16, u32, Option<T>),
+}
+
+impl<D, R, T, E, const MAGIC: u32, const MIN_VERSION: u16, const MAX_VERSION: u16> Future
+    for ReadEnvelope<D, R, T, MAGIC, MIN_VERSION, MAX_VERSION>
+    where D: AsyncDeserialize<CrcReader<TakeReader<R>>, T, E>,
+          D: Future<Item = (CrcReader<TakeReader<R>>, T, usize),
+                    Error = (CrcReader<TakeReader<R>>, DeserializeError<E>)>,
+          R: AsyncRead
+{
+    type Item = (R, (u16, T), usize);
+    type Error = (R, DeserializeError<EnvelopeError<E>>);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let next = match self.state {
+                ReadState::Header(ref mut header) => {
+                    let (reader, header, read) = match header.poll(cx) {
+                        Ok(Async::Ready(done)) => done,
+                        Ok(Async::Pending) => return Ok(Async::Pending),
+                        Err((reader, err)) => {
+                            return Err((reader, DeserializeError::ReaderError(err)))
+                        }
+                    };
+                    self.read += read;
+
+                    let magic = u32::from_be_bytes([header[0], header[1], header[2], header[3]]);
+                    if magic != MAGIC {
+                        let err = EnvelopeError::BadMagic { found: magic };
+                        return Err((reader, DeserializeError::DataError(err)));
+                    }
+
+                    let version = u16::from_be_bytes([header[4], header[5]]);
+                    if version < MIN_VERSION || version > MAX_VERSION {
+                        let err = EnvelopeError::UnsupportedVersion { version };
+                        return Err((reader, DeserializeError::DataError(err)));
+                    }
+
+                    let mut len = [0; 8];
+                    len.copy_from_slice(&header[6..]);
+                    let len = u64::from_be_bytes(len);
+
+                    let body = CrcReader::new(TakeReader::new(reader, len));
+                    ReadState::Body(D::from_reader(body), version, len)
+                }
+
+                ReadState::Body(ref mut body, version, len) => {
+                    let (body, val, _) = match body.poll(cx) {
+                        Ok(Async::Ready(done)) => done,
+                        Ok(Async::Pending) => return Ok(Async::Pending),
+                        Err((body, DeserializeError::ReaderError(err))) => {
+                            return Err((body.into_inner().into_inner(),
+                                        DeserializeError::ReaderError(err)))
+                        }
+                        Err((body, DeserializeError::DataError(err))) => {
+                            return Err((body.into_inner().into_inner(),
+                                        DeserializeError::DataError(EnvelopeError::Inner(err))))
+                        }
+                    };
+
+                    let crc = body.crc();
+                    let take = body.into_inner();
+                    let consumed = len - take.limit();
+                    self.read += consumed as usize;
+                    if consumed != len {
+                        let err = EnvelopeError::LengthMismatch {
+                            declared: len,
+                            consumed,
+                        };
+                        return Err((take.into_inner(), DeserializeError::DataError(err)));
+                    }
+
+                    ReadState::Trailer(ReadExact::new(take.into_inner(), [0; TRAILER_LEN]),
+                                       version,
+                                       crc,
+                                       Some(val))
+                }
+
+                ReadState::Trailer(ref mut trailer, version, actual, ref mut val) => {
+                    let (reader, expected, read) = match trailer.poll(cx) {
+                        Ok(Async::Ready(done)) => done,
+                        Ok(Async::Pending) => return Ok(Async::Pending),
+                        Err((reader, err)) => {
+                            return Err((reader, DeserializeError::ReaderError(err)))
+                        }
+                    };
+                    self.read += read;
+
+                    let expected = u32::from_be_bytes(expected);
+                    if expected != actual {
+                        let err = EnvelopeError::CrcMismatch { expected, actual };
+                        return Err((reader, DeserializeError::DataError(err)));
+                    }
+
+                    let val = or_pending!(val.take());
+                    return Ok(Async::Ready((reader, (version, val), self.read)));
+                }
+            };
+            self.state = next;
+        }
+    }
+}
+
+impl<D, R, T, E, const MAGIC: u32, const MIN_VERSION: u16, const MAX_VERSION: u16>
+    AsyncDeserialize<R, (u16, T), EnvelopeError<E>>
+    for ReadEnvelope<D, R, T, MAGIC, MIN_VERSION, MAX_VERSION>
+    where D: AsyncDeserialize<CrcReader<TakeReader<R>>, T, E>,
+          R: AsyncRead
+{
+    fn from_reader(reader: R) -> Self {
+        ReadEnvelope {
+            state: ReadState::Header(ReadExact::new(reader, [0; HEADER_LEN])),
+            read: 0,
+        }
+    }
+
+    fn already_read(&self) -> usize {
+        self.read +
+        match self.state {
+            ReadState::Header(ref header) => header.already_read(),
+            ReadState::Body(ref body, _, _) => body.already_read(),
+            ReadState::Trailer(ref trailer, _, _, _) => trailer.already_read(),
+        }
+    }
+}
+
+impl<D, R, T, const MAGIC: u32, const MIN_VERSION: u16, const MAX_VERSION: u16> Restorable<R>
+    for ReadEnvelope<D, R, T, MAGIC, MIN_VERSION, MAX_VERSION>
+    where D: Restorable<CrcReader<TakeReader<R>>>
+{
+    fn restore(self) -> Option<R> {
+        match self.state {
+            ReadState::Header(inner) => inner.restore(),
+            ReadState::Body(inner, ..) => {
+                inner.restore().map(|body| body.into_inner().into_inner())
+            }
+            ReadState::Trailer(inner, ..) => inner.restore(),
+        }
+    }
+}
+
+/// Everything that can go wrong when reading an envelope.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EnvelopeError<E> {
+    /// The envelope starts with the wrong magic number.
+    BadMagic {
+        /// The magic number that was found.
+        found: u32,
+    },
+    /// The version of the envelope is not supported.
+    UnsupportedVersion {
+        /// The version of the envelope.
+        version: u16,
+    },
+    /// The body could not be deserialized.
+    Inner(E),
+    /// The body was deserialized without consuming all of its bytes.
+    LengthMismatch {
+        /// The length of the body according to the header.
+        declared: u64,
+        /// How many bytes the body deserializer consumed.
+        consumed: u64,
+    },
+    /// The checksum of the body does not match.
+    CrcMismatch {
+        /// The checksum stored in the envelope.
+        expected: u32,
+        /// The checksum of the body that was read.
+        actual: u32,
+    },
+}
+
+impl<E: Display> Display for EnvelopeError<E> {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            EnvelopeError::BadMagic { found } => write!(f, "Unknown magic number {:#010x}", found),
+            EnvelopeError::UnsupportedVersion { version } => {
+                write!(f, "Unsupported format version {}", version)
+            }
+            EnvelopeError::Inner(ref err) => write!(f, "Invalid body: {}", err),
+            EnvelopeError::LengthMismatch { declared, consumed } => {
+                write!(f, "Body has {} bytes but only {} were consumed", declared, consumed)
+            }
+            EnvelopeError::CrcMismatch { expected, actual } => {
+                write!(f, "Body checksum {:#010x} does not match {:#010x}", actual, expected)
+            }
+        }
+    }
+}
+
+impl<E: Error> Error for EnvelopeError<E> {}
@@ -0,0 +1,246 @@
+//! Serialization of fixed-point decimals, for data such as monetary amounts that must not suffer
+//! from the rounding of floating point numbers.
+//!
+//! A decimal is a signed integer mantissa together with a scale, its value is
+//! `mantissa * 10^(-scale)`. It is encoded as the mantissa as a zigzag-encoded
+//! [varint](../varint/index.html), followed by the scale as a single byte.
+
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+
+use futures_core::{Async, Future, Poll};
+use futures_core::task::Context;
+use futures_io::{AsyncRead, AsyncWrite, Error as FutIoErr};
+
+use {AsyncDeserialize, AsyncSerialize, AsyncSerializeLen, AsyncWriterFuture,
+     AsyncWriterFutureLen, DeserializeError, Restorable};
+use protobuf_wire::{decode_zigzag, encode_zigzag};
+use util::{ReadExact, WriteAll};
+use varint::{varint_len, ReadVarint, VarintBuf, VarintError, MAX_VARINT_LEN};
+
+/// The largest scale accepted by `ReadFixedPoint::from_reader`. Every `i64` mantissa has at most
+/// this many decimal digits after the first one.
+pub const MAX_SCALE: u8 = 18;
+
+/// A decimal number with a fixed number of digits after the decimal point.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FixedPoint {
+    /// The digits of the number.
+    pub mantissa: i64,
+    /// How many of the digits of the mantissa come after the decimal point.
+    pub scale: u8,
+}
+
+impl FixedPoint {
+    /// Create a new `FixedPoint` with the value `mantissa * 10^(-scale)`.
+    pub fn new(mantissa: i64, scale: u8) -> FixedPoint {
+        FixedPoint { mantissa, scale }
+    }
+}
+
+impl Display for FixedPoint {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        if self.scale == 0 {
+            return write!(f, "{}", self.mantissa);
+        }
+
+        let digits = self.mantissa.unsigned_abs().to_string();
+        let scale = self.scale as usize;
+        let sign = if self.mantissa < 0 { "-" } else { "" };
+        if digits.len() > scale {
+            let (int, frac) = digits.split_at(digits.len() - scale);
+            write!(f, "{}{}.{}", sign, int, frac)
+        } else {
+            write!(f, "{}0.{:0>width$}", sign, digits, width = scale)
+        }
+    }
+}
+
+struct FixedPointBuf {
+    bytes: [u8; MAX_VARINT_LEN + 1],
+    len: usize,
+}
+
+impl FixedPointBuf {
+    fn new(val: FixedPoint) -> FixedPointBuf {
+        let mantissa = VarintBuf::new(encode_zigzag(val.mantissa));
+        let mantissa = mantissa.as_ref();
+
+        let mut bytes = [0; MAX_VARINT_LEN + 1];
+        bytes[..mantissa.len()].copy_from_slice(mantissa);
+        bytes[mantissa.len()] = val.scale;
+
+        FixedPointBuf {
+            bytes,
+            len: mantissa.len() + 1,
+        }
+    }
+}
+
+impl AsRef<[u8]> for FixedPointBuf {
+    fn as_ref(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
+}
+
+/// Serializes a `FixedPoint`.
+pub struct WriteFixedPoint<W>(WriteAll<W, FixedPointBuf>);
+
+impl<W: AsyncWrite> Future for WriteFixedPoint<W> {
+    type Item = (W, usize);
+    type Error = (W, FutIoErr);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        self.0.poll(cx)
+    }
+}
+
+impl<W: AsyncWrite> AsyncWriterFuture<W> for WriteFixedPoint<W> {
+    fn already_written(&self) -> usize {
+        self.0.already_written()
+    }
+}
+
+impl<W: AsyncWrite> AsyncWriterFutureLen<W> for WriteFixedPoint<W> {
+    fn remaining_bytes(&self) -> usize {
+        self.0.remaining_bytes()
+    }
+}
+
+impl<W: AsyncWrite> AsyncSerialize<W> for WriteFixedPoint<W> {
+    type Serialized = FixedPoint;
+
+    fn from_val(writer: W, val: FixedPoint) -> Self {
+        WriteFixedPoint(WriteAll::new(writer, FixedPointBuf::new(val)))
+    }
+}
+
+impl<W: AsyncWrite> AsyncSerializeLen<W> for WriteFixedPoint<W> {
+    fn total_bytes(val: &FixedPoint) -> usize {
+        varint_len(encode_zigzag(val.mantissa)) + 1
+    }
+}
+
+/// Deserializes a `FixedPoint`, rejecting scales above a maximum with
+/// `FixedPointError::InvalidScale`.
+///
+/// `from_reader` accepts scales up to `MAX_SCALE`, use `new` to choose a different maximum.
+pub struct ReadFixedPoint<R> {
+    state: State<R>,
+    max_scale: u8,
+    read: usize,
+}
+
+enum State<R> {
+    Mantissa(ReadVarint<R>),
+    Scale(ReadExact<R, [u8; 1]>, i64),
+}
+
+impl<R: AsyncRead> ReadFixedPoint<R> {
+    /// Create a new `ReadFixedPoint`, accepting scales up to `max_scale`.
+    pub fn new(reader: R, max_scale: u8) -> ReadFixedPoint<R> {
+        ReadFixedPoint {
+            state: State::Mantissa(ReadVarint::from_reader(reader)),
+            max_scale,
+            read: 0,
+        }
+    }
+}
+
+impl<R: AsyncRead> Future for ReadFixedPoint<R> {
+    type Item = (R, FixedPoint, usize);
+    type Error = (R, DeserializeError<FixedPointError>);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let next = match self.state {
+                State::Mantissa(ref mut inner) => {
+                    let (reader, mantissa, read) = match inner.poll(cx) {
+                        Ok(Async::Ready(done)) => done,
+                        Ok(Async::Pending) => return Ok(Async::Pending),
+                        Err((reader, DeserializeError::ReaderError(err))) => {
+                            return Err((reader, DeserializeError::ReaderError(err)))
+                        }
+                        Err((reader, DeserializeError::DataError(VarintError::Overflow))) => {
+                            let err = FixedPointError::VarintOverflow;
+                            return Err((reader, DeserializeError::DataError(err)));
+                        }
+                    };
+                    self.read = read;
+                    State::Scale(ReadExact::new(reader, [0]), decode_zigzag(mantissa))
+                }
+
+                State::Scale(ref mut inner, mantissa) => {
+                    let (reader, scale, read) = match inner.poll(cx) {
+                        Ok(Async::Ready(done)) => done,
+                        Ok(Async::Pending) => return Ok(Async::Pending),
+                        Err((reader, err)) => {
+                            return Err((reader, DeserializeError::ReaderError(err)))
+                        }
+                    };
+                    let read = self.read + read;
+
+                    let scale = scale[0];
+                    if scale > self.max_scale {
+                        let err = FixedPointError::InvalidScale {
+                            scale,
+                            max: self.max_scale,
+                        };
+                        return Err((reader, DeserializeError::DataError(err)));
+                    }
+                    return Ok(Async::Ready((reader, FixedPoint::new(mantissa, scale), read)));
+                }
+            };
+            self.state = next;
+        }
+    }
+}
+
+impl<R: AsyncRead> AsyncDeserialize<R, FixedPoint, FixedPointError> for ReadFixedPoint<R> {
+    fn from_reader(reader: R) -> Self {
+        ReadFixedPoint::new(reader, MAX_SCALE)
+    }
+
+    fn already_read(&self) -> usize {
+        match self.state {
+            State::Mantissa(ref inner) => inner.already_read(),
+            State::Scale(ref inner, _) => self.read + inner.already_read(),
+        }
+    }
+}
+
+impl<R> Restorable<R> for ReadFixedPoint<R> {
+    fn restore(self) -> Option<R> {
+        match self.state {
+            State::Mantissa(inner) => inner.restore(),
+            State::Scale(inner, _) => inner.restore(),
+        }
+    }
+}
+
+/// Everything that can go wrong when deserializing a `FixedPoint`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FixedPointError {
+    /// The mantissa varint does not fit into a `u64`.
+    VarintOverflow,
+    /// The scale exceeds the maximum accepted by the deserializer.
+    InvalidScale {
+        /// The scale that was read.
+        scale: u8,
+        /// The largest accepted scale.
+        max: u8,
+    },
+}
+
+impl Display for FixedPointError {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            FixedPointError::VarintOverflow => write!(f, "Varint overflows a u64"),
+            FixedPointError::InvalidScale { scale, max } => {
+                write!(f, "Invalid scale {}, at most {} is allowed", scale, max)
+            }
+        }
+    }
+}
+
+impl Error for FixedPointError {}
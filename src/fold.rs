@@ -0,0 +1,150 @@
+//! Aggregate the elements of a sequence while deserializing it, without collecting them.
+//!
+//! A sequence is encoded as the number of elements as a big-endian `u32`, followed by the elements
+//! in order, the same encoding as used by the `linked_list` module.
+
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+
+use futures_core::{Async, Future, Poll};
+use futures_core::task::Context;
+use futures_io::AsyncRead;
+
+use {AsyncDeserialize, DeserializeError, Restorable};
+use util::ReadExact;
+
+/// Deserializes a sequence via `D` and folds each element into an accumulator via `F`, yielding
+/// the final accumulator.
+///
+/// Only the accumulator and a single element are held in memory at any time. The count of the
+/// sequence is checked against a maximum before any element is read, so a hostile count can not
+/// make this run for longer than intended.
+pub struct ReadFold<D, R, Acc, F> {
+    state: State<D, R>,
+    remaining: u32,
+    max_count: u32,
+    read: usize,
+    acc: Option<Acc>,
+    f: F,
+}
+
+enum State<D, R> {
+    Count(ReadExact<R, [u8; 4]>),
+    Element(D),
+}
+
+impl<D, R: AsyncRead, Acc, F> ReadFold<D, R, Acc, F> {
+    /// Create a new `ReadFold`, reading a sequence of at most `max_count` elements from `reader`
+    /// and folding them into `init` via `f`.
+    pub fn new(reader: R, max_count: u32, init: Acc, f: F) -> ReadFold<D, R, Acc, F> {
+        ReadFold {
+            state: State::Count(ReadExact::new(reader, [0; 4])),
+            remaining: 0,
+            max_count,
+            read: 0,
+            acc: Some(init),
+            f,
+        }
+    }
+}
+
+impl<D, R, Acc, F, T, E> Future for ReadFold<D, R, Acc, F>
+    where D: AsyncDeserialize<R, T, E>,
+          D: Future<Item = (R, T, usize), Error = (R, DeserializeError<E>)>,
+          F: FnMut(Acc, T) -> Acc,
+          R: AsyncRead
+{
+    type Item = (R, Acc, usize);
+    type Error = (R, DeserializeError<FoldError<E>>);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let reader = match self.state {
+                State::Count(ref mut inner) => {
+                    let (reader, count, read) = match inner.poll(cx) {
+                        Ok(Async::Ready(done)) => done,
+                        Ok(Async::Pending) => return Ok(Async::Pending),
+                        Err((reader, err)) => {
+                            return Err((reader, DeserializeError::ReaderError(err)))
+                        }
+                    };
+                    self.read += read;
+
+                    let count = u32::from_be_bytes(count);
+                    if count > self.max_count {
+                        let err = FoldError::TooMany {
+                            count,
+                            max: self.max_count,
+                        };
+                        return Err((reader, DeserializeError::DataError(err)));
+                    }
+                    self.remaining = count;
+                    reader
+                }
+
+                State::Element(ref mut inner) => {
+                    let (reader, element, read) = match inner.poll(cx) {
+                        Ok(Async::Ready(done)) => done,
+                        Ok(Async::Pending) => return Ok(Async::Pending),
+                        Err((reader, DeserializeError::ReaderError(err))) => {
+                            return Err((reader, DeserializeError::ReaderError(err)))
+                        }
+                        Err((reader, DeserializeError::DataError(err))) => {
+                            return Err((reader,
+                                        DeserializeError::DataError(FoldError::Element(err))))
+                        }
+                    };
+                    let acc = or_pending!(self.acc.take());
+                    self.acc = Some((self.f)(acc, element));
+                    self.remaining -= 1;
+                    self.read += read;
+                    reader
+                }
+            };
+
+            if self.remaining == 0 {
+                let acc = or_pending!(self.acc.take());
+                return Ok(Async::Ready((reader, acc, self.read)));
+            }
+            self.state = State::Element(D::from_reader(reader));
+        }
+    }
+}
+
+impl<D, R, Acc, F> Restorable<R> for ReadFold<D, R, Acc, F>
+    where D: Restorable<R>
+{
+    fn restore(self) -> Option<R> {
+        match self.state {
+            State::Count(inner) => inner.restore(),
+            State::Element(inner) => inner.restore(),
+        }
+    }
+}
+
+/// Everything that can go wrong when folding a sequence, apart from reader errors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FoldError<E> {
+    /// The sequence has more elements than allowed.
+    TooMany {
+        /// The number of elements of the sequence.
+        count: u32,
+        /// The maximum number of elements.
+        max: u32,
+    },
+    /// An element could not be deserialized.
+    Element(E),
+}
+
+impl<E: Display> Display for FoldError<E> {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            FoldError::TooMany { count, max } => {
+                write!(f, "Sequence of {} elements exceeds the maximum of {}", count, max)
+            }
+            FoldError::Element(ref err) => write!(f, "Invalid element: {}", err),
+        }
+    }
+}
+
+impl<E: Error> Error for FoldError<E> {}
@@ -0,0 +1,563 @@
+//! Deserialization of length-prefixed frames that is robust against misbehaving inner
+//! deserializers.
+//!
+//! A frame is encoded as a varint length, followed by that many bytes, the same as the elements of
+//! a [lenient sequence](../lenient_seq/index.html).
+//!
+//! Protocols that negotiate the encoding of the length at runtime can choose a different
+//! `LengthWidth` via `ReadFramed::with_width`, and write their frames via `Message::with_width`.
+//! Like the width of a [tag](../tagged/index.html), the width is not part of the encoding, so
+//! both sides must use the same one.
+//!
+//! `Framed` bundles both directions for a duplex stream.
+
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::io::ErrorKind;
+
+use futures_core::{Async, Future, Poll};
+use futures_core::task::Context;
+use futures_io::{AsyncRead, AsyncWrite, Error as FutIoErr};
+
+use {AsyncDeserialize, AsyncSerialize, AsyncSerializeLen, AsyncWriterFuture,
+     AsyncWriterFutureLen, DeserializeError, Restorable, ValidationError};
+use message::Message;
+use progress::PayloadRemaining;
+use take_reader::TakeReader;
+use util::{poll_skip, ReadExact, WriteAll};
+use varint::{varint_len, ReadVarint, VarintBuf, VarintError};
+
+/// How the length of a frame is encoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LengthWidth {
+    /// A single byte, for frames of up to 255 bytes.
+    U8,
+    /// A big-endian `u16`, for frames of up to 65535 bytes.
+    U16,
+    /// A big-endian `u32`, for frames of up to `u32::MAX` bytes.
+    U32,
+    /// A big-endian `u64`, for frames of arbitrary length.
+    U64,
+    /// A varint, for frames of arbitrary length. This is the default.
+    Varint,
+}
+
+impl LengthWidth {
+    /// Return the largest frame length that can be encoded with this width.
+    pub fn max_len(self) -> u64 {
+        match self {
+            LengthWidth::U8 => u64::from(u8::MAX),
+            LengthWidth::U16 => u64::from(u16::MAX),
+            LengthWidth::U32 => u64::from(u32::MAX),
+            LengthWidth::U64 | LengthWidth::Varint => u64::MAX,
+        }
+    }
+
+    /// Return how many bytes the length prefix of a frame of `len` bytes takes, or `None` if
+    /// `len` exceeds `max_len`.
+    pub fn prefix_len(self, len: u64) -> Option<usize> {
+        match self {
+            _ if len > self.max_len() => None,
+            LengthWidth::U8 => Some(1),
+            LengthWidth::U16 => Some(2),
+            LengthWidth::U32 => Some(4),
+            LengthWidth::U64 => Some(8),
+            LengthWidth::Varint => Some(varint_len(len)),
+        }
+    }
+}
+
+// The bytes of a fixed-width length prefix, big-endian.
+struct FixedBuf {
+    bytes: [u8; 8],
+    len: usize,
+}
+
+impl AsRef<[u8]> for FixedBuf {
+    fn as_ref(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
+}
+
+impl AsMut<[u8]> for FixedBuf {
+    fn as_mut(&mut self) -> &mut [u8] {
+        &mut self.bytes[..self.len]
+    }
+}
+
+enum LengthBuf {
+    Fixed(FixedBuf),
+    Varint(VarintBuf),
+}
+
+impl AsRef<[u8]> for LengthBuf {
+    fn as_ref(&self) -> &[u8] {
+        match *self {
+            LengthBuf::Fixed(ref buf) => buf.as_ref(),
+            LengthBuf::Varint(ref buf) => buf.as_ref(),
+        }
+    }
+}
+
+/// Serializes the length prefix of a frame with a `LengthWidth` chosen at runtime.
+///
+/// If the length exceeds the `max_len` of the width, this fails with an
+/// `ErrorKind::InvalidInput` error without writing anything.
+pub struct WriteLength<W> {
+    state: WriteLengthState<W>,
+}
+
+enum WriteLengthState<W> {
+    Length(WriteAll<W, LengthBuf>),
+    Invalid(Option<W>),
+}
+
+impl<W: AsyncWrite> Future for WriteLength<W> {
+    type Item = (W, usize);
+    type Error = (W, FutIoErr);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        match self.state {
+            WriteLengthState::Length(ref mut inner) => inner.poll(cx),
+            WriteLengthState::Invalid(ref mut writer) => {
+                let err = FutIoErr::new(ErrorKind::InvalidInput,
+                                        "frame is too long for the width of its length");
+                let writer = or_pending!(writer.take());
+                Err((writer, err))
+            }
+        }
+    }
+}
+
+impl<W: AsyncWrite> AsyncWriterFuture<W> for WriteLength<W> {
+    fn already_written(&self) -> usize {
+        match self.state {
+            WriteLengthState::Length(ref inner) => inner.already_written(),
+            WriteLengthState::Invalid(_) => 0,
+        }
+    }
+}
+
+impl<W: AsyncWrite> AsyncWriterFutureLen<W> for WriteLength<W> {
+    fn remaining_bytes(&self) -> usize {
+        match self.state {
+            WriteLengthState::Length(ref inner) => inner.remaining_bytes(),
+            WriteLengthState::Invalid(_) => 0,
+        }
+    }
+}
+
+impl<W: AsyncWrite> WriteLength<W> {
+    /// Check whether a length can be serialized, i.e. whether it does not exceed the `max_len` of
+    /// the width.
+    pub fn validate(&(width, len): &(LengthWidth, u64)) -> Result<(), ValidationError> {
+        match width.prefix_len(len) {
+            Some(_) => Ok(()),
+            None => {
+                Err(ValidationError::TooLong {
+                        len,
+                        max: width.max_len(),
+                    })
+            }
+        }
+    }
+}
+
+impl<W: AsyncWrite> AsyncSerialize<W> for WriteLength<W> {
+    type Serialized = (LengthWidth, u64);
+
+    fn from_val(writer: W, (width, len): (LengthWidth, u64)) -> Self {
+        let state = match width.prefix_len(len) {
+            None => WriteLengthState::Invalid(Some(writer)),
+            Some(_) if width == LengthWidth::Varint => {
+                let buf = LengthBuf::Varint(VarintBuf::new(len));
+                WriteLengthState::Length(WriteAll::new(writer, buf))
+            }
+            Some(prefix_len) => {
+                let mut bytes = [0; 8];
+                bytes[..prefix_len].copy_from_slice(&len.to_be_bytes()[8 - prefix_len..]);
+                let buf = LengthBuf::Fixed(FixedBuf {
+                                               bytes,
+                                               len: prefix_len,
+                                           });
+                WriteLengthState::Length(WriteAll::new(writer, buf))
+            }
+        };
+
+        WriteLength { state }
+    }
+}
+
+impl<W: AsyncWrite> AsyncSerializeLen<W> for WriteLength<W> {
+    /// Returns zero for lengths that can not be serialized.
+    fn total_bytes(&(width, len): &(LengthWidth, u64)) -> usize {
+        width.prefix_len(len).unwrap_or(0)
+    }
+}
+
+// Reads the length prefix of a frame.
+enum ReadLength<R> {
+    Fixed(ReadExact<R, FixedBuf>),
+    Varint(ReadVarint<R>),
+}
+
+impl<R: AsyncRead> ReadLength<R> {
+    fn new(reader: R, width: LengthWidth) -> ReadLength<R> {
+        let len = match width {
+            LengthWidth::U8 => 1,
+            LengthWidth::U16 => 2,
+            LengthWidth::U32 => 4,
+            LengthWidth::U64 => 8,
+            LengthWidth::Varint => return ReadLength::Varint(ReadVarint::from_reader(reader)),
+        };
+        ReadLength::Fixed(ReadExact::new(reader, FixedBuf { bytes: [0; 8], len }))
+    }
+
+    fn already_read(&self) -> usize {
+        match *self {
+            ReadLength::Fixed(ref inner) => inner.already_read(),
+            ReadLength::Varint(ref inner) => inner.already_read(),
+        }
+    }
+}
+
+impl<R> Restorable<R> for ReadLength<R> {
+    fn restore(self) -> Option<R> {
+        match self {
+            ReadLength::Fixed(inner) => inner.restore(),
+            ReadLength::Varint(inner) => inner.restore(),
+        }
+    }
+}
+
+impl<R: AsyncRead> Future for ReadLength<R> {
+    type Item = (R, u64, usize);
+    type Error = (R, DeserializeError<VarintError>);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        match *self {
+            ReadLength::Fixed(ref mut inner) => {
+                match inner.poll(cx) {
+                    Ok(Async::Ready((reader, buf, read))) => {
+                        let len = buf.as_ref()
+                            .iter()
+                            .fold(0, |len, byte| (len << 8) | u64::from(*byte));
+                        Ok(Async::Ready((reader, len, read)))
+                    }
+                    Ok(Async::Pending) => Ok(Async::Pending),
+                    Err((reader, err)) => Err((reader, DeserializeError::ReaderError(err))),
+                }
+            }
+            ReadLength::Varint(ref mut inner) => inner.poll(cx),
+        }
+    }
+}
+
+/// What to do if the inner deserializer of a frame does not consume the whole frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trailing {
+    /// Skip the remainder of the frame, then fail with `FramedError::TrailingBytes`.
+    Reject,
+    /// Skip the remainder of the frame and succeed.
+    Skip,
+}
+
+/// Deserializes a length-prefixed frame with a `D`, making sure the reader ends up at the end of
+/// the frame no matter how `D` behaves.
+///
+/// `D` reads through a `TakeReader`, so it physically can not read past the end of the frame; an
+/// attempt to do so fails with `FramedError::OverRead`. If `D` consumes less than the whole frame,
+/// the remainder is skipped and, depending on the `Trailing` mode, the frame is rejected with
+/// `FramedError::TrailingBytes` or accepted. When `D` fails with a `DataError`, the remainder is
+/// skipped as well before reporting the error.
+///
+/// So for every `DataError`, the returned reader is positioned directly after the frame. Only
+/// errors of the underlying reader and a malformed length prefix leave it somewhere within the
+/// frame, since there is no way of recovering from those.
+///
+/// `from_reader` uses `Trailing::Reject` and a varint length, use `new` to choose a different mode
+/// and `with_width` to choose a different `LengthWidth` as well.
+pub struct ReadFramed<D, R, S, E> {
+    state: State<D, R, S, E>,
+    trailing: Trailing,
+    prefix_len: usize,
+}
+
+enum State<D, R, S, E> {
+    Length(ReadLength<R>),
+    Frame(D, u64),
+    Skip(Option<TakeReader<R>>, u64, Option<Result<S, FramedError<E>>>),
+}
+
+impl<D, R, S, E> ReadFramed<D, R, S, E>
+    where D: AsyncDeserialize<TakeReader<R>, S, E>,
+          R: AsyncRead
+{
+    /// Create a new `ReadFramed`, handling unconsumed bytes of the frame according to `trailing`.
+    pub fn new(reader: R, trailing: Trailing) -> ReadFramed<D, R, S, E> {
+        ReadFramed::with_width(reader, LengthWidth::Varint, trailing)
+    }
+
+    /// Create a new `ReadFramed` for frames whose length is encoded with `width`, handling
+    /// unconsumed bytes of the frame according to `trailing`.
+    pub fn with_width(reader: R, width: LengthWidth, trailing: Trailing) -> ReadFramed<D, R, S, E> {
+        ReadFramed {
+            state: State::Length(ReadLength::new(reader, width)),
+            trailing,
+            prefix_len: 0,
+        }
+    }
+}
+
+impl<D, R, S, E> Future for ReadFramed<D, R, S, E>
+    where D: AsyncDeserialize<TakeReader<R>, S, E>,
+          R: AsyncRead
+{
+    type Item = (R, S, usize);
+    type Error = (R, DeserializeError<FramedError<E>>);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let (take, len, outcome) = match self.state {
+                State::Length(ref mut inner) => {
+                    match inner.poll(cx) {
+                        Ok(Async::Ready((reader, len, read))) => {
+                            // Past this check, the whole frame is countable in a `usize`, so
+                            // casting the length or parts of it to a `usize` is lossless.
+                            match usize::try_from(len) {
+                                Ok(len) if len <= usize::MAX - read => {}
+                                _ => {
+                                    let err = FramedError::LengthOverflow;
+                                    return Err((reader, DeserializeError::DataError(err)));
+                                }
+                            }
+                            self.prefix_len = read;
+                            let inner = D::from_reader(TakeReader::new(reader, len));
+                            self.state = State::Frame(inner, len);
+                            continue;
+                        }
+                        Ok(Async::Pending) => return Ok(Async::Pending),
+                        Err((reader, DeserializeError::ReaderError(err))) => {
+                            return Err((reader, DeserializeError::ReaderError(err)))
+                        }
+                        Err((reader, DeserializeError::DataError(VarintError::Overflow))) => {
+                            return Err((reader,
+                                        DeserializeError::DataError(FramedError::VarintOverflow)))
+                        }
+                    }
+                }
+
+                State::Frame(ref mut inner, len) => {
+                    match inner.poll(cx) {
+                        Ok(Async::Ready((take, val, _))) => {
+                            if take.limit() == 0 || self.trailing == Trailing::Skip {
+                                (take, len, Ok(val))
+                            } else {
+                                let err = FramedError::TrailingBytes {
+                                    declared: len,
+                                    consumed: len - take.limit(),
+                                };
+                                (take, len, Err(err))
+                            }
+                        }
+                        Ok(Async::Pending) => return Ok(Async::Pending),
+                        Err((take, DeserializeError::DataError(err))) => {
+                            (take, len, Err(FramedError::Inner(err)))
+                        }
+                        Err((take, DeserializeError::ReaderError(err))) => {
+                            if take.limit() == 0 && err.kind() == ErrorKind::UnexpectedEof {
+                                (take, len, Err(FramedError::OverRead { declared: len }))
+                            } else {
+                                return Err((take.into_inner(),
+                                            DeserializeError::ReaderError(err)));
+                            }
+                        }
+                    }
+                }
+
+                State::Skip(ref mut take, len, ref mut outcome) => {
+                    let mut t = or_pending!(take.take());
+                    let mut remaining = t.limit();
+                    match poll_skip(&mut t, cx, &mut remaining) {
+                        Ok(Async::Ready(())) => {}
+                        Ok(Async::Pending) => {
+                            *take = Some(t);
+                            return Ok(Async::Pending);
+                        }
+                        Err(err) => return Err((t.into_inner(), DeserializeError::ReaderError(err))),
+                    }
+
+                    let read = self.prefix_len + len as usize;
+                    return match or_pending!(outcome.take()) {
+                        Ok(val) => Ok(Async::Ready((t.into_inner(), val, read))),
+                        Err(err) => Err((t.into_inner(), DeserializeError::DataError(err))),
+                    };
+                }
+            };
+
+            self.state = State::Skip(Some(take), len, Some(outcome));
+        }
+    }
+}
+
+impl<D, R, S, E> AsyncDeserialize<R, S, FramedError<E>> for ReadFramed<D, R, S, E>
+    where D: AsyncDeserialize<TakeReader<R>, S, E>,
+          R: AsyncRead
+{
+    fn from_reader(reader: R) -> Self {
+        ReadFramed::new(reader, Trailing::Reject)
+    }
+
+    fn already_read(&self) -> usize {
+        match self.state {
+            State::Length(ref inner) => inner.already_read(),
+            State::Frame(ref inner, _) => self.prefix_len + inner.already_read(),
+            State::Skip(ref take, len, _) => {
+                self.prefix_len + (len - take.as_ref().map(|t| t.limit()).unwrap_or(0)) as usize
+            }
+        }
+    }
+}
+
+impl<D, R, S, E> Restorable<R> for ReadFramed<D, R, S, E>
+    where D: Restorable<TakeReader<R>>
+{
+    fn restore(self) -> Option<R> {
+        match self.state {
+            State::Length(inner) => inner.restore(),
+            State::Frame(inner, _) => inner.restore().map(TakeReader::into_inner),
+            State::Skip(take, ..) => take.map(TakeReader::into_inner),
+        }
+    }
+}
+
+impl<D, R, S, E> PayloadRemaining for ReadFramed<D, R, S, E>
+    where D: AsyncDeserialize<TakeReader<R>, S, E>,
+          R: AsyncRead
+{
+    fn payload_remaining(&self) -> Option<usize> {
+        match self.state {
+            State::Length(_) => None,
+            State::Frame(ref inner, len) => {
+                Some((len as usize).saturating_sub(inner.already_read()))
+            }
+            State::Skip(ref take, _, _) => {
+                Some(take.as_ref().map(|take| take.limit()).unwrap_or(0) as usize)
+            }
+        }
+    }
+}
+
+/// A duplex stream over which values are exchanged as frames.
+///
+/// `write_frame` writes a value as a `Message`, and `read_frame` reads one via a `ReadFramed`,
+/// both with the `LengthWidth` and `Trailing` mode of the `Framed`. The futures borrow the stream,
+/// so only one frame can be in flight at a time.
+#[derive(Debug)]
+pub struct Framed<S> {
+    stream: S,
+    width: LengthWidth,
+    trailing: Trailing,
+}
+
+impl<S> Framed<S> {
+    /// Create a new `Framed` over `stream`, with varint lengths and `Trailing::Reject`.
+    pub fn new(stream: S) -> Framed<S> {
+        Framed::with_width(stream, LengthWidth::Varint, Trailing::Reject)
+    }
+
+    /// Create a new `Framed` over `stream`, encoding lengths with `width` and handling
+    /// unconsumed bytes of read frames according to `trailing`.
+    pub fn with_width(stream: S, width: LengthWidth, trailing: Trailing) -> Framed<S> {
+        Framed {
+            stream,
+            width,
+            trailing,
+        }
+    }
+
+    /// Return the width with which lengths are encoded.
+    pub fn width(&self) -> LengthWidth {
+        self.width
+    }
+
+    /// Get a reference to the wrapped stream.
+    pub fn get_ref(&self) -> &S {
+        &self.stream
+    }
+
+    /// Get a mutable reference to the wrapped stream.
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.stream
+    }
+
+    /// Consume this `Framed`, returning the wrapped stream.
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+}
+
+impl<S: AsyncWrite> Framed<S> {
+    /// Write `val` as a single frame via `P`, yielding the number of written bytes including the
+    /// length prefix.
+    pub fn write_frame<'a, P>(&'a mut self, val: P::Serialized) -> Message<P, &'a mut S>
+        where P: AsyncSerializeLen<&'a mut S>
+    {
+        Message::with_width(&mut self.stream, self.width, val)
+    }
+}
+
+impl<S: AsyncRead> Framed<S> {
+    /// Read a single frame, deserializing its content via `D`.
+    pub fn read_frame<'a, D, T, E>(&'a mut self) -> ReadFramed<D, &'a mut S, T, E>
+        where D: AsyncDeserialize<TakeReader<&'a mut S>, T, E>
+    {
+        ReadFramed::with_width(&mut self.stream, self.width, self.trailing)
+    }
+}
+
+/// Everything that can go wrong when deserializing a frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FramedError<E> {
+    /// The length is a varint that does not fit into a `u64`.
+    VarintOverflow,
+    /// The length of the frame including its prefix does not fit into a `usize`.
+    LengthOverflow,
+    /// The inner deserializer failed.
+    Inner(E),
+    /// The inner deserializer tried to read past the end of the frame.
+    OverRead {
+        /// The length of the frame.
+        declared: u64,
+    },
+    /// The inner deserializer finished without consuming the whole frame, and the frame was read
+    /// with `Trailing::Reject`.
+    TrailingBytes {
+        /// The length of the frame.
+        declared: u64,
+        /// How many bytes of the frame the inner deserializer consumed.
+        consumed: u64,
+    },
+}
+
+impl<E: Display> Display for FramedError<E> {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            FramedError::VarintOverflow => write!(f, "Varint overflows a u64"),
+            FramedError::LengthOverflow => write!(f, "Length overflows a usize"),
+            FramedError::Inner(ref err) => write!(f, "{}", err),
+            FramedError::OverRead { declared } => {
+                write!(f, "Tried to read past the end of a frame of {} bytes", declared)
+            }
+            FramedError::TrailingBytes { declared, consumed } => {
+                write!(f,
+                       "Consumed only {} bytes of a frame of {} bytes",
+                       consumed,
+                       declared)
+            }
+        }
+    }
+}
+
+impl<E: Error> Error for FramedError<E> {}
@@ -0,0 +1,386 @@
+//! Serialization of sorted sequences of strings with front coding, e.g. for dictionaries of keys.
+//!
+//! Neighbouring strings of a sorted sequence tend to share long prefixes, so every string is
+//! stored as the number of leading bytes it shares with its predecessor, followed by the rest of
+//! the string. The sequence is encoded as the number of strings as a
+//! [varint](../varint/index.html), followed by one entry per string: the length of the shared
+//! prefix in bytes as a varint, the length of the remaining suffix in bytes as a varint, and the
+//! bytes of the suffix. The first string shares nothing, its shared prefix length is always zero.
+//!
+//! So `["ab", "abc", "b"]` encodes as `[0x03, 0x00, 0x02, 0x61, 0x62, 0x02, 0x01, 0x63, 0x00,
+//! 0x01, 0x62]`.
+//!
+//! Prefixes are shared byte-wise, so a shared prefix may end in the middle of a multi-byte
+//! character. Unsorted sequences can be serialized as well, they just compress worse.
+
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::mem;
+use std::str::Utf8Error;
+
+use futures_core::{Async, Future, Poll};
+use futures_core::task::Context;
+use futures_io::{AsyncRead, AsyncWrite, Error as FutIoErr};
+
+use {AsyncDeserialize, AsyncSerializeRef, AsyncSerializeRefLen, AsyncWriterFuture,
+     AsyncWriterFutureLen, DeserializeError, Restorable};
+use util::{poll_read_vec, poll_write_buf};
+use varint::{varint_len, ReadVarint, VarintBuf, VarintError, MAX_VARINT_LEN};
+
+// Return the number of leading bytes `string` shares with `prev`.
+fn shared_prefix(prev: &str, string: &str) -> usize {
+    prev.bytes().zip(string.bytes()).take_while(|&(a, b)| a == b).count()
+}
+
+// Return the shared prefix length and the suffix of the string at `index`.
+fn entry<'val>(strings: &[&'val str], index: usize) -> (usize, &'val [u8]) {
+    let string = strings[index];
+    let shared = if index == 0 {
+        0
+    } else {
+        shared_prefix(strings[index - 1], string)
+    };
+    (shared, &string.as_bytes()[shared..])
+}
+
+// The varints preceding the suffix of an entry, or the count of strings.
+struct Header {
+    bytes: [u8; 2 * MAX_VARINT_LEN],
+    len: usize,
+}
+
+impl Header {
+    fn new(vals: &[u64]) -> Header {
+        let mut bytes = [0; 2 * MAX_VARINT_LEN];
+        let mut len = 0;
+        for val in vals {
+            let varint = VarintBuf::new(*val);
+            let varint = varint.as_ref();
+            bytes[len..len + varint.len()].copy_from_slice(varint);
+            len += varint.len();
+        }
+        Header { bytes, len }
+    }
+}
+
+impl AsRef<[u8]> for Header {
+    fn as_ref(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
+}
+
+/// Serializes a slice of strings with front coding.
+pub struct WriteFrontCoded<'val, W> {
+    writer: Option<W>,
+    strings: &'val [&'val str],
+    next: usize,
+    header: Header,
+    header_written: usize,
+    suffix: &'val [u8],
+    suffix_written: usize,
+    written: usize,
+}
+
+impl<'val, W: AsyncWrite> Future for WriteFrontCoded<'val, W> {
+    type Item = (W, usize);
+    type Error = (W, FutIoErr);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        let mut writer = or_pending!(self.writer.take());
+
+        loop {
+            let res = if self.header_written < self.header.len {
+                poll_write_buf(&mut writer, cx, self.header.as_ref(), &mut self.header_written)
+            } else if self.suffix_written < self.suffix.len() {
+                poll_write_buf(&mut writer, cx, self.suffix, &mut self.suffix_written)
+            } else if self.next < self.strings.len() {
+                self.written += self.header_written + self.suffix_written;
+                let (shared, suffix) = entry(self.strings, self.next);
+                self.header = Header::new(&[shared as u64, suffix.len() as u64]);
+                self.header_written = 0;
+                self.suffix = suffix;
+                self.suffix_written = 0;
+                self.next += 1;
+                continue;
+            } else {
+                let written = self.already_written();
+                return Ok(Async::Ready((writer, written)));
+            };
+
+            match res {
+                Ok(Async::Ready(())) => {}
+                Ok(Async::Pending) => {
+                    self.writer = Some(writer);
+                    return Ok(Async::Pending);
+                }
+                Err(err) => return Err((writer, err)),
+            }
+        }
+    }
+}
+
+impl<'val, W: AsyncWrite> AsyncWriterFuture<W> for WriteFrontCoded<'val, W> {
+    fn already_written(&self) -> usize {
+        self.written + self.header_written + self.suffix_written
+    }
+}
+
+impl<'val, W: AsyncWrite> AsyncWriterFutureLen<W> for WriteFrontCoded<'val, W> {
+    fn remaining_bytes(&self) -> usize {
+        let current = self.header.len + self.suffix.len() -
+                      (self.header_written + self.suffix_written);
+        let rest: usize = (self.next..self.strings.len())
+            .map(|index| {
+                     let (shared, suffix) = entry(self.strings, index);
+                     varint_len(shared as u64) + varint_len(suffix.len() as u64) + suffix.len()
+                 })
+            .sum();
+        current + rest
+    }
+}
+
+impl<'val, W: AsyncWrite> AsyncSerializeRef<'val, W> for WriteFrontCoded<'val, W> {
+    type Serialized = [&'val str];
+
+    fn from_ref(writer: W, val: &'val [&'val str]) -> Self {
+        WriteFrontCoded {
+            writer: Some(writer),
+            strings: val,
+            next: 0,
+            header: Header::new(&[val.len() as u64]),
+            header_written: 0,
+            suffix: &[],
+            suffix_written: 0,
+            written: 0,
+        }
+    }
+}
+
+impl<'val, W: AsyncWrite> AsyncSerializeRefLen<'val, W> for WriteFrontCoded<'val, W> {
+    fn total_bytes(val: &[&'val str]) -> usize {
+        varint_len(val.len() as u64) +
+        (0..val.len())
+            .map(|index| {
+                     let (shared, suffix) = entry(val, index);
+                     varint_len(shared as u64) + varint_len(suffix.len() as u64) + suffix.len()
+                 })
+            .sum::<usize>()
+    }
+}
+
+/// Deserializes a front-coded sequence of strings into a `Vec<String>`.
+///
+/// Fails with `FrontCodedError::SharedPrefixTooLong` if an entry claims to share more bytes than
+/// its predecessor has.
+pub struct ReadFrontCoded<R> {
+    state: ReadState<R>,
+    remaining: u64,
+    strings: Vec<String>,
+    read: usize,
+}
+
+enum ReadState<R> {
+    Count(ReadVarint<R>),
+    Shared(ReadVarint<R>),
+    SuffixLen(ReadVarint<R>, usize),
+    Suffix {
+        reader: Option<R>,
+        string: Vec<u8>,
+        filled: usize,
+        shared: usize,
+        len: usize,
+    },
+}
+
+// Poll a varint of the header of an entry, converting it to a `usize`.
+fn poll_len<R: AsyncRead>(inner: &mut ReadVarint<R>,
+                          cx: &mut Context)
+                          -> Poll<(R, usize, usize), (R, DeserializeError<FrontCodedError>)> {
+    match inner.poll(cx) {
+        Ok(Async::Ready((reader, val, read))) => {
+            match usize::try_from(val) {
+                Ok(val) => Ok(Async::Ready((reader, val, read))),
+                Err(_) => {
+                    Err((reader, DeserializeError::DataError(FrontCodedError::LengthOverflow)))
+                }
+            }
+        }
+        Ok(Async::Pending) => Ok(Async::Pending),
+        Err((reader, DeserializeError::ReaderError(err))) => {
+            Err((reader, DeserializeError::ReaderError(err)))
+        }
+        Err((reader, DeserializeError::DataError(VarintError::Overflow))) => {
+            Err((reader, DeserializeError::DataError(FrontCodedError::VarintOverflow)))
+        }
+    }
+}
+
+impl<R: AsyncRead> Future for ReadFrontCoded<R> {
+    type Item = (R, Vec<String>, usize);
+    type Error = (R, DeserializeError<FrontCodedError>);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        loop {
+            // The reader after the count or a complete entry, for reading the next entry.
+            let reader = match self.state {
+                ReadState::Count(ref mut inner) => {
+                    let (reader, count, read) = match inner.poll(cx) {
+                        Ok(Async::Ready(done)) => done,
+                        Ok(Async::Pending) => return Ok(Async::Pending),
+                        Err((reader, DeserializeError::ReaderError(err))) => {
+                            return Err((reader, DeserializeError::ReaderError(err)))
+                        }
+                        Err((reader, DeserializeError::DataError(VarintError::Overflow))) => {
+                            let err = FrontCodedError::VarintOverflow;
+                            return Err((reader, DeserializeError::DataError(err)));
+                        }
+                    };
+                    self.read = read;
+                    self.remaining = count;
+                    reader
+                }
+
+                ReadState::Shared(ref mut inner) => {
+                    let (reader, shared, read) = try_ready!(poll_len(inner, cx));
+                    self.read += read;
+
+                    let previous = self.strings.last().map(String::len).unwrap_or(0);
+                    if shared > previous {
+                        let err = FrontCodedError::SharedPrefixTooLong { shared, previous };
+                        return Err((reader, DeserializeError::DataError(err)));
+                    }
+                    self.state = ReadState::SuffixLen(ReadVarint::from_reader(reader), shared);
+                    continue;
+                }
+
+                ReadState::SuffixLen(ref mut inner, shared) => {
+                    let (reader, len, read) = try_ready!(poll_len(inner, cx));
+                    self.read += read;
+
+                    let len = match shared.checked_add(len) {
+                        Some(len) => len,
+                        None => {
+                            let err = FrontCodedError::LengthOverflow;
+                            return Err((reader, DeserializeError::DataError(err)));
+                        }
+                    };
+                    let string = match self.strings.last() {
+                        Some(prev) => prev.as_bytes()[..shared].to_vec(),
+                        None => Vec::new(),
+                    };
+                    self.state = ReadState::Suffix {
+                        reader: Some(reader),
+                        string,
+                        filled: shared,
+                        shared,
+                        len,
+                    };
+                    continue;
+                }
+
+                ReadState::Suffix {
+                    ref mut reader,
+                    ref mut string,
+                    ref mut filled,
+                    shared,
+                    len,
+                } => {
+                    let mut r = or_pending!(reader.take());
+                    match poll_read_vec(&mut r, cx, string, filled, len) {
+                        Ok(Async::Ready(())) => {}
+                        Ok(Async::Pending) => {
+                            *reader = Some(r);
+                            return Ok(Async::Pending);
+                        }
+                        Err(err) => return Err((r, DeserializeError::ReaderError(err))),
+                    }
+                    self.read += len - shared;
+
+                    match String::from_utf8(mem::take(string)) {
+                        Ok(string) => self.strings.push(string),
+                        Err(err) => {
+                            let err = FrontCodedError::InvalidUtf8(err.utf8_error());
+                            return Err((r, DeserializeError::DataError(err)));
+                        }
+                    }
+                    self.remaining -= 1;
+                    r
+                }
+            };
+
+            if self.remaining == 0 {
+                let strings = mem::take(&mut self.strings);
+                return Ok(Async::Ready((reader, strings, self.read)));
+            }
+            self.state = ReadState::Shared(ReadVarint::from_reader(reader));
+        }
+    }
+}
+
+impl<R: AsyncRead> AsyncDeserialize<R, Vec<String>, FrontCodedError> for ReadFrontCoded<R> {
+    fn from_reader(reader: R) -> Self {
+        ReadFrontCoded {
+            state: ReadState::Count(ReadVarint::from_reader(reader)),
+            remaining: 0,
+            strings: Vec::new(),
+            read: 0,
+        }
+    }
+
+    fn already_read(&self) -> usize {
+        match self.state {
+            ReadState::Count(ref inner) => inner.already_read(),
+            ReadState::Shared(ref inner) |
+            ReadState::SuffixLen(ref inner, _) => self.read + inner.already_read(),
+            ReadState::Suffix { filled, shared, .. } => self.read + filled - shared,
+        }
+    }
+}
+
+impl<R> Restorable<R> for ReadFrontCoded<R> {
+    fn restore(self) -> Option<R> {
+        match self.state {
+            ReadState::Count(inner) |
+            ReadState::Shared(inner) |
+            ReadState::SuffixLen(inner, _) => inner.restore(),
+            ReadState::Suffix { reader, .. } => reader,
+        }
+    }
+}
+
+/// Everything that can go wrong when deserializing a front-coded sequence of strings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrontCodedError {
+    /// A varint does not fit into a `u64`.
+    VarintOverflow,
+    /// A length does not fit into a `usize`.
+    LengthOverflow,
+    /// An entry shares more bytes with its predecessor than the predecessor has.
+    SharedPrefixTooLong {
+        /// The length of the shared prefix claimed by the entry.
+        shared: usize,
+        /// The length of the predecessor, zero for the first entry.
+        previous: usize,
+    },
+    /// A string is not valid utf8.
+    InvalidUtf8(Utf8Error),
+}
+
+impl Display for FrontCodedError {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            FrontCodedError::VarintOverflow => write!(f, "Varint overflows a u64"),
+            FrontCodedError::LengthOverflow => write!(f, "Length overflows a usize"),
+            FrontCodedError::SharedPrefixTooLong { shared, previous } => {
+                write!(f,
+                       "Shared prefix of {} bytes exceeds the previous string of {} bytes",
+                       shared,
+                       previous)
+            }
+            FrontCodedError::InvalidUtf8(ref err) => write!(f, "Invalid utf8: {}", err),
+        }
+    }
+}
+
+impl Error for FrontCodedError {}
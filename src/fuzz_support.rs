@@ -0,0 +1,120 @@
+//! Entry points for fuzzing deserializers, available with the `fuzz_support` feature.
+//!
+//! `drive_from_bytes` runs a deserializer synchronously over a fuzz input. The reader splits the
+//! input into chunks and interleaves pending reads as determined by the input itself, so a fuzzer
+//! that explores inputs also explores the code paths that resume after partial and pending reads.
+//! See the `fuzz` directory of the repository for targets that use this with `cargo fuzz`.
+
+use std::cmp::min;
+use std::sync::Arc;
+
+use futures_core::{Async, Poll};
+use futures_core::task::{Context, LocalMap, Wake, Waker};
+use futures_io::{AsyncRead, Error as FutIoErr};
+
+use {AsyncDeserialize, DeserializeError};
+
+struct NoopWake;
+
+impl Wake for NoopWake {
+    fn wake(_: &Arc<NoopWake>) {}
+}
+
+/// An `AsyncRead` over a byte slice that delivers it in chunks of pseudo-random sizes, and that is
+/// not ready before some of them.
+///
+/// The chunk sizes and pending reads are derived from a hash of the whole slice, so they are the
+/// same for every run over the same input.
+#[derive(Debug)]
+pub struct FuzzReader<'a> {
+    data: &'a [u8],
+    position: usize,
+    state: u64,
+    was_pending: bool,
+}
+
+impl<'a> FuzzReader<'a> {
+    /// Create a new `FuzzReader` over `data`.
+    pub fn new(data: &'a [u8]) -> FuzzReader<'a> {
+        // FNV-1a, forced to be nonzero since xorshift gets stuck at zero.
+        let hash = data.iter().fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| {
+            (hash ^ u64::from(*byte)).wrapping_mul(0x0000_0100_0000_01b3)
+        });
+
+        FuzzReader {
+            data,
+            position: 0,
+            state: hash | 1,
+            was_pending: false,
+        }
+    }
+
+    /// Return how many bytes have been read so far.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    // xorshift64
+    fn next(&mut self) -> u64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.state
+    }
+}
+
+impl<'a> AsyncRead for FuzzReader<'a> {
+    fn poll_read(&mut self, cx: &mut Context, buf: &mut [u8]) -> Poll<usize, FutIoErr> {
+        let rand = self.next();
+
+        // Never pending twice in a row, so that every read eventually makes progress.
+        if !self.was_pending && rand.is_multiple_of(4) {
+            self.was_pending = true;
+            cx.waker().wake();
+            return Ok(Async::Pending);
+        }
+
+        self.was_pending = false;
+        let chunk = 1 + (rand >> 2) as usize % 16;
+        let len = min(min(buf.len(), chunk), self.data.len() - self.position);
+        buf[..len].copy_from_slice(&self.data[self.position..self.position + len]);
+        self.position += len;
+        Ok(Async::Ready(len))
+    }
+}
+
+/// Deserialize a value from `input` via `D`, yielding the value and the number of bytes `D`
+/// reports to have read.
+///
+/// This does not panic for any input, as long as `D` behaves. It does panic if `D` violates the
+/// contract of `AsyncDeserialize`, so that the fuzzer reports it:
+///
+/// - if the number of bytes that `D` reports differs from how many bytes it actually read, or
+/// - if `D` is still pending after many more polls than the input has bytes.
+pub fn drive_from_bytes<'a, D, T, E>(input: &'a [u8]) -> Result<(T, usize), DeserializeError<E>>
+    where D: AsyncDeserialize<FuzzReader<'a>, T, E>
+{
+    let waker = Waker::from(Arc::new(NoopWake));
+    let mut map = LocalMap::new();
+    let mut cx = Context::without_spawn(&mut map, &waker);
+
+    // Every byte takes at most two reads, and a deserializer needs at most one more read to
+    // notice the end of the input, plus some slack for deserializers that yield voluntarily.
+    let max_polls = 4 * input.len() + 64;
+    let mut fut = D::from_reader(FuzzReader::new(input));
+
+    for _ in 0..max_polls {
+        match fut.poll(&mut cx) {
+            Ok(Async::Ready((reader, val, read))) => {
+                assert_eq!(read,
+                           reader.position(),
+                           "deserializer reports a wrong number of read bytes");
+                return Ok((val, read));
+            }
+            Ok(Async::Pending) => {}
+            Err((_, err)) => return Err(err),
+        }
+    }
+
+    panic!("deserializer still pending after {} polls", max_polls);
+}
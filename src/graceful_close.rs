@@ -0,0 +1,217 @@
+//! Shut down a connection by exchanging goodbye messages before closing it.
+
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::mem;
+use std::time::Duration;
+
+use futures_core::{Async, Future, Poll};
+use futures_core::task::Context;
+use futures_io::{AsyncRead, AsyncWrite, Error as FutIoErr};
+
+use {AsyncDeserialize, AsyncSerialize, DeserializeError, Restorable};
+use timeout::Delay;
+
+/// Sends a goodbye message via `S`, flushes, then reads messages of the peer via `D` until one of
+/// them is its goodbye, and finally closes the writer.
+///
+/// Messages of the peer that arrive before its goodbye are either collected or discarded. Waiting
+/// for the goodbye of the peer is bounded by a single timeout for all of its messages, measured by
+/// the time source `C`.
+///
+/// Yields `(writer, reader, bytes_written, bytes_read, stragglers)`, where the byte counts include
+/// the goodbye messages and `stragglers` holds the collected messages that arrived before the
+/// goodbye of the peer. If any phase fails, the writer and reader are given back together with a
+/// `GracefulCloseError` that tells which phase it was. When the goodbye of the peer does not
+/// arrive in time, the reader is taken back via the `Restorable` implementation of `D`, and is
+/// `None` if that fails.
+pub struct GracefulClose<W, R, S, D, T, C: Delay> {
+    state: State<W, R, S, D, C>,
+    is_goodbye: fn(&T) -> bool,
+    collect: bool,
+    clock: C,
+    timeout: Duration,
+    written: usize,
+    read: usize,
+    stragglers: Vec<T>,
+}
+
+enum State<W, R, S, D, C: Delay> {
+    Send(S, Option<R>),
+    Flush(Option<W>, Option<R>),
+    Receive(Option<W>, D, Option<C::Delay>),
+    Close(Option<W>, Option<R>),
+    Done,
+}
+
+impl<W, R, S, D, T, C> GracefulClose<W, R, S, D, T, C>
+    where S: AsyncSerialize<W>,
+          W: AsyncWrite,
+          R: AsyncRead,
+          C: Delay
+{
+    /// Create a new `GracefulClose`, sending `goodbye` and waiting at most `timeout` for a message
+    /// of the peer for which `is_goodbye` returns `true`. Earlier messages of the peer are kept if
+    /// `collect` is `true`, and discarded otherwise.
+    pub fn new(writer: W,
+               reader: R,
+               goodbye: S::Serialized,
+               is_goodbye: fn(&T) -> bool,
+               collect: bool,
+               clock: C,
+               timeout: Duration)
+               -> GracefulClose<W, R, S, D, T, C> {
+        GracefulClose {
+            state: State::Send(S::from_val(writer, goodbye), Some(reader)),
+            is_goodbye,
+            collect,
+            clock,
+            timeout,
+            written: 0,
+            read: 0,
+            stragglers: Vec::new(),
+        }
+    }
+}
+
+impl<W, R, S, D, T, C> GracefulClose<W, R, S, D, T, C>
+    where D: Restorable<R>,
+          C: Delay
+{
+    // Give up waiting for the goodbye of the peer, giving back the writer and the reader.
+    fn time_out<E>(&mut self) -> Option<(W, Option<R>, GracefulCloseError<E>)> {
+        match mem::replace(&mut self.state, State::Done) {
+            State::Receive(Some(writer), receive, _) => {
+                Some((writer, receive.restore(), GracefulCloseError::Timeout))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl<W, R, S, D, T, E, C> Future for GracefulClose<W, R, S, D, T, C>
+    where S: Future<Item = (W, usize), Error = (W, FutIoErr)>,
+          D: AsyncDeserialize<R, T, E> + Restorable<R>,
+          D: Future<Item = (R, T, usize), Error = (R, DeserializeError<E>)>,
+          W: AsyncWrite,
+          R: AsyncRead,
+          C: Delay
+{
+    type Item = (W, R, usize, usize, Vec<T>);
+    type Error = (W, Option<R>, GracefulCloseError<E>);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let next = match self.state {
+                State::Send(ref mut send, ref mut reader) => {
+                    let (writer, written) = match send.poll(cx) {
+                        Ok(Async::Ready(done)) => done,
+                        Ok(Async::Pending) => return Ok(Async::Pending),
+                        Err((writer, err)) => {
+                            let reader = Some(or_pending!(reader.take()));
+                            return Err((writer, reader, GracefulCloseError::Send(err)));
+                        }
+                    };
+                    self.written = written;
+                    State::Flush(Some(writer), reader.take())
+                }
+
+                State::Flush(ref mut writer, ref mut reader) => {
+                    match or_pending!(writer.as_mut()).poll_flush(cx) {
+                        Ok(Async::Ready(())) => {}
+                        Ok(Async::Pending) => return Ok(Async::Pending),
+                        Err(err) => {
+                            let (writer, reader) = (or_pending!(writer.take()),
+                                                    or_pending!(reader.take()));
+                            return Err((writer, Some(reader), GracefulCloseError::Flush(err)));
+                        }
+                    }
+                    let reader = or_pending!(reader.take());
+                    State::Receive(writer.take(), D::from_reader(reader), None)
+                }
+
+                State::Receive(ref mut writer, ref mut receive, ref mut timer) => {
+                    match receive.poll(cx) {
+                        Ok(Async::Ready((reader, msg, read))) => {
+                            self.read += read;
+                            if (self.is_goodbye)(&msg) {
+                                State::Close(writer.take(), Some(reader))
+                            } else {
+                                if self.collect {
+                                    self.stragglers.push(msg);
+                                }
+                                *receive = D::from_reader(reader);
+                                continue;
+                            }
+                        }
+                        Ok(Async::Pending) => {
+                            let clock = &self.clock;
+                            let timeout = self.timeout;
+                            match timer.get_or_insert_with(|| clock.delay(timeout)).poll(cx) {
+                                Ok(Async::Ready(())) => return Err(or_pending!(self.time_out())),
+                                Ok(Async::Pending) => return Ok(Async::Pending),
+                                Err(never) => match never {},
+                            }
+                        }
+                        Err((reader, err)) => {
+                            let writer = or_pending!(writer.take());
+                            return Err((writer, Some(reader), GracefulCloseError::Receive(err)));
+                        }
+                    }
+                }
+
+                State::Close(ref mut writer, ref mut reader) => {
+                    let res = or_pending!(writer.as_mut()).poll_close(cx);
+                    let (writer, reader) = match res {
+                        Ok(Async::Pending) => return Ok(Async::Pending),
+                        _ => (or_pending!(writer.take()), or_pending!(reader.take())),
+                    };
+                    return match res {
+                        Ok(_) => {
+                            Ok(Async::Ready((writer,
+                                             reader,
+                                             self.written,
+                                             self.read,
+                                             mem::take(&mut self.stragglers))))
+                        }
+                        Err(err) => Err((writer, Some(reader), GracefulCloseError::Close(err))),
+                    };
+                }
+
+                State::Done => return Ok(Async::Pending),
+            };
+            self.state = next;
+        }
+    }
+}
+
+/// Everything that can go wrong during a `GracefulClose`, by phase.
+#[derive(Debug)]
+pub enum GracefulCloseError<E> {
+    /// Sending the own goodbye failed.
+    Send(FutIoErr),
+    /// Flushing after the own goodbye failed.
+    Flush(FutIoErr),
+    /// Receiving a message of the peer failed.
+    Receive(DeserializeError<E>),
+    /// The goodbye of the peer did not arrive in time.
+    Timeout,
+    /// Closing the writer failed.
+    Close(FutIoErr),
+}
+
+impl<E: Display> Display for GracefulCloseError<E> {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            GracefulCloseError::Send(ref err) => write!(f, "Failed to send goodbye: {}", err),
+            GracefulCloseError::Flush(ref err) => write!(f, "Failed to flush goodbye: {}", err),
+            GracefulCloseError::Receive(ref err) => {
+                write!(f, "Failed to receive goodbye: {}", err)
+            }
+            GracefulCloseError::Timeout => write!(f, "Timed out waiting for goodbye"),
+            GracefulCloseError::Close(ref err) => write!(f, "Failed to close: {}", err),
+        }
+    }
+}
+
+impl<E: Error> Error for GracefulCloseError<E> {}
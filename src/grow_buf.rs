@@ -0,0 +1,109 @@
+//! A byte buffer for values whose length is not known in advance.
+
+use std::cmp::{max, min};
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::mem;
+
+/// A buffer that starts with a small capacity and doubles it whenever it is full, up to a hard
+/// limit on its length.
+///
+/// The buffer never shrinks while it is being filled. To reuse its allocation for the next value,
+/// hand the vector returned by `into_vec` (or any other vector) back to `seeded`, or call `take`
+/// and `recycle`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GrowBuf {
+    buf: Vec<u8>,
+    initial: usize,
+    limit: usize,
+}
+
+impl GrowBuf {
+    /// Create a new, empty `GrowBuf`, allocating `initial` bytes on the first push and holding at
+    /// most `limit` bytes.
+    pub fn new(initial: usize, limit: usize) -> GrowBuf {
+        GrowBuf::seeded(Vec::new(), initial, limit)
+    }
+
+    /// Create a new, empty `GrowBuf` that reuses the allocation of `buf`.
+    pub fn seeded(mut buf: Vec<u8>, initial: usize, limit: usize) -> GrowBuf {
+        buf.clear();
+        GrowBuf {
+            buf,
+            initial: max(initial, 1),
+            limit,
+        }
+    }
+
+    /// Append a byte, growing the buffer if necessary.
+    ///
+    /// Fails without modifying the buffer if it already holds `limit` bytes.
+    pub fn push(&mut self, byte: u8) -> Result<(), LimitExceeded> {
+        if self.buf.len() >= self.limit {
+            return Err(LimitExceeded { limit: self.limit });
+        }
+
+        if self.buf.len() == self.buf.capacity() {
+            let new_capacity = min(self.limit, max(self.initial, self.buf.capacity() * 2));
+            self.buf.reserve_exact(new_capacity - self.buf.len());
+        }
+        self.buf.push(byte);
+        Ok(())
+    }
+
+    /// Return the bytes in the buffer.
+    pub fn as_slice(&self) -> &[u8] {
+        &self.buf
+    }
+
+    /// Return how many bytes the buffer holds.
+    pub fn len(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// Return whether the buffer holds no bytes.
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    /// Return how many bytes the buffer can hold without allocating.
+    pub fn capacity(&self) -> usize {
+        self.buf.capacity()
+    }
+
+    /// Return the maximum number of bytes the buffer can hold.
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// Take the bytes out of the buffer, leaving it empty and without an allocation.
+    pub fn take(&mut self) -> Vec<u8> {
+        mem::take(&mut self.buf)
+    }
+
+    /// Reuse the allocation of `buf` for the next value, discarding the current contents.
+    pub fn recycle(&mut self, mut buf: Vec<u8>) {
+        buf.clear();
+        self.buf = buf;
+    }
+
+    /// Consume this `GrowBuf`, returning the bytes it holds.
+    pub fn into_vec(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+/// A value is longer than the limit of the buffer it is read into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LimitExceeded {
+    /// The maximum number of bytes the buffer can hold.
+    pub limit: usize,
+}
+
+impl Display for LimitExceeded {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        write!(f, "Value exceeds the limit of {} bytes", self.limit)
+    }
+}
+
+impl Error for LimitExceeded {}
@@ -0,0 +1,123 @@
+//! Exchange a hello message with a peer, sending and receiving at the same time.
+
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::marker::PhantomData;
+
+use futures_core::{Async, Future, Poll};
+use futures_core::task::Context;
+use futures_io::{AsyncRead, AsyncWrite, Error as FutIoErr};
+
+use {AsyncDeserialize, AsyncSerialize, DeserializeError};
+
+/// Serializes a hello message via `S` into a writer while deserializing the hello of the peer via
+/// `D` from a reader, yielding `(writer, reader, bytes_written, peer_hello, bytes_read)`.
+///
+/// Both directions make progress independently: each poll drives the direction that is not done
+/// yet, so a peer that only starts reading after it has written its own hello can not deadlock
+/// the exchange, no matter how small the underlying buffers are.
+///
+/// If either direction fails, the other one is still driven until it is done as well, and then
+/// the handshake fails with the writer, the reader and a `HandshakeError` telling which direction
+/// failed. If both fail, the error of sending is reported.
+pub struct Handshake<W, R, S, D>
+    where S: Future,
+          D: Future
+{
+    send: Half<S>,
+    receive: Half<D>,
+    _halves: PhantomData<(W, R)>,
+}
+
+enum Half<F: Future> {
+    Running(F),
+    Done(Option<Result<F::Item, F::Error>>),
+}
+
+impl<F: Future> Half<F> {
+    fn poll(&mut self, cx: &mut Context) {
+        let res = match *self {
+            Half::Running(ref mut fut) => {
+                match fut.poll(cx) {
+                    Ok(Async::Ready(done)) => Ok(done),
+                    Ok(Async::Pending) => return,
+                    Err(err) => Err(err),
+                }
+            }
+            Half::Done(_) => return,
+        };
+        *self = Half::Done(Some(res));
+    }
+}
+
+impl<W, R, S, D> Handshake<W, R, S, D>
+    where S: AsyncSerialize<W>,
+          D: Future,
+          W: AsyncWrite,
+          R: AsyncRead
+{
+    /// Create a new `Handshake`, sending `hello` into `writer` and reading the hello of the peer
+    /// from `reader`.
+    pub fn new<T, E>(writer: W, reader: R, hello: S::Serialized) -> Handshake<W, R, S, D>
+        where D: AsyncDeserialize<R, T, E>
+    {
+        Handshake {
+            send: Half::Running(S::from_val(writer, hello)),
+            receive: Half::Running(D::from_reader(reader)),
+            _halves: PhantomData,
+        }
+    }
+}
+
+impl<W, R, S, D, T, E> Future for Handshake<W, R, S, D>
+    where S: Future<Item = (W, usize), Error = (W, FutIoErr)>,
+          D: Future<Item = (R, T, usize), Error = (R, DeserializeError<E>)>
+{
+    type Item = (W, R, usize, T, usize);
+    type Error = (W, R, HandshakeError<E>);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        self.send.poll(cx);
+        self.receive.poll(cx);
+
+        let (sent, received) = match (&mut self.send, &mut self.receive) {
+            (&mut Half::Done(ref mut sent), &mut Half::Done(ref mut received)) => {
+                (or_pending!(sent.take()), or_pending!(received.take()))
+            }
+            _ => return Ok(Async::Pending),
+        };
+
+        match (sent, received) {
+            (Ok((writer, written)), Ok((reader, hello, read))) => {
+                Ok(Async::Ready((writer, reader, written, hello, read)))
+            }
+            (Err((writer, err)), Ok((reader, _, _))) |
+            (Err((writer, err)), Err((reader, _))) => {
+                Err((writer, reader, HandshakeError::Send(err)))
+            }
+            (Ok((writer, _)), Err((reader, err))) => {
+                Err((writer, reader, HandshakeError::Receive(err)))
+            }
+        }
+    }
+}
+
+/// Everything that can go wrong during a `Handshake`.
+#[derive(Debug)]
+pub enum HandshakeError<E> {
+    /// Sending the own hello failed.
+    Send(FutIoErr),
+    /// Receiving the hello of the peer failed.
+    Receive(DeserializeError<E>),
+}
+
+impl<E: Display> Display for HandshakeError<E> {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            HandshakeError::Send(ref err) => write!(f, "Failed to send hello: {}", err),
+            HandshakeError::Receive(ref err) => write!(f, "Failed to receive hello: {}", err),
+        }
+    }
+}
+
+impl<E: Error> Error for HandshakeError<E> {}
@@ -0,0 +1,135 @@
+//! Keep an otherwise slow connection alive by injecting heartbeats between serialized values.
+//!
+//! The heartbeats are inserted into the byte stream between two values, so the format must let
+//! the peer recognize and drop them, e.g. a sentinel that can not start a value of the format. A
+//! heartbeat is never injected into the middle of a value, even if its serializer splits it into
+//! several writes, so the bytes of every value stay contiguous. To keep a connection alive while
+//! sending a huge body, split it into several values, e.g. chunks.
+
+use std::time::Duration;
+
+use futures_core::{Async, Future, Poll};
+use futures_core::task::Context;
+use futures_io::{AsyncWrite, Error as FutIoErr};
+
+use AsyncSerialize;
+use timeout::Delay;
+use util::poll_write_buf;
+
+/// Serializes the values of an iterator `I` one after the other via `F`, and writes a heartbeat
+/// before the next value whenever `interval` has passed since the last heartbeat (or since the
+/// creation of the `WithHeartbeat`).
+///
+/// The time is measured by the time source `C`. Heartbeats are only written between values, so
+/// they are delayed while a value is being written, and none is written after the last value.
+///
+/// Yields `(writer, bytes_written, heartbeat_bytes_written)`, where `bytes_written` only counts
+/// the bytes of the values.
+pub struct WithHeartbeat<F, W, C: Delay, I> {
+    state: State<F, W>,
+    values: I,
+    clock: C,
+    interval: Duration,
+    timer: C::Delay,
+    heartbeat: Vec<u8>,
+    written: usize,
+    sent: usize,
+}
+
+enum State<F, W> {
+    // Between two values, deciding whether to write a heartbeat or the next value.
+    Next(Option<W>),
+    // Writing a heartbeat, of which the given number of bytes has been written.
+    Heartbeat(Option<W>, usize),
+    Value(F),
+}
+
+impl<F, W, C, I> WithHeartbeat<F, W, C, I>
+    where F: AsyncSerialize<W>,
+          W: AsyncWrite,
+          C: Delay,
+          I: Iterator<Item = F::Serialized>
+{
+    /// Create a new `WithHeartbeat`, serializing `values` into `writer` and writing `heartbeat`
+    /// between them every `interval` as measured by `clock`.
+    pub fn new(writer: W,
+               values: I,
+               heartbeat: Vec<u8>,
+               clock: C,
+               interval: Duration)
+               -> WithHeartbeat<F, W, C, I> {
+        WithHeartbeat {
+            state: State::Next(Some(writer)),
+            values,
+            timer: clock.delay(interval),
+            clock,
+            interval,
+            heartbeat,
+            written: 0,
+            sent: 0,
+        }
+    }
+
+    /// Return how many heartbeat bytes have been written so far.
+    pub fn heartbeat_bytes(&self) -> usize {
+        self.sent
+    }
+}
+
+impl<F, W, C, I> Future for WithHeartbeat<F, W, C, I>
+    where F: AsyncSerialize<W>,
+          W: AsyncWrite,
+          C: Delay,
+          I: Iterator<Item = F::Serialized>
+{
+    type Item = (W, usize, usize);
+    type Error = (W, FutIoErr);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let next = match self.state {
+                State::Next(ref mut writer) => {
+                    let writer = or_pending!(writer.take());
+                    let due = match self.timer.poll(cx) {
+                        Ok(Async::Ready(())) => true,
+                        Ok(Async::Pending) => false,
+                        Err(never) => match never {},
+                    };
+
+                    if due {
+                        State::Heartbeat(Some(writer), 0)
+                    } else {
+                        match self.values.next() {
+                            Some(val) => State::Value(F::from_val(writer, val)),
+                            None => return Ok(Async::Ready((writer, self.written, self.sent))),
+                        }
+                    }
+                }
+
+                State::Heartbeat(ref mut writer, ref mut offset) => {
+                    let mut w = or_pending!(writer.take());
+                    let before = *offset;
+                    let res = poll_write_buf(&mut w, cx, &self.heartbeat, offset);
+                    self.sent += *offset - before;
+                    match res {
+                        Ok(Async::Ready(())) => {}
+                        Ok(Async::Pending) => {
+                            *writer = Some(w);
+                            return Ok(Async::Pending);
+                        }
+                        Err(err) => return Err((w, err)),
+                    }
+                    self.timer = self.clock.delay(self.interval);
+                    State::Next(Some(w))
+                }
+
+                State::Value(ref mut inner) => {
+                    let (writer, written) = try_ready!(inner.poll(cx));
+                    self.written += written;
+                    State::Next(Some(writer))
+                }
+            };
+            self.state = next;
+        }
+    }
+}
@@ -0,0 +1,174 @@
+//! Serialization of binary data as hexadecimal ASCII.
+//!
+//! A buffer is encoded as two lowercase hexadecimal digits per byte, most significant digit
+//! first, without a length prefix. The deserializer accepts both lowercase and uppercase digits,
+//! and must be told how many bytes to decode.
+
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+
+use futures_core::{Async, Future, Poll};
+use futures_core::task::Context;
+use futures_io::{AsyncRead, AsyncWrite, Error as FutIoErr};
+
+use {AsyncSerialize, AsyncSerializeLen, AsyncWriterFuture, AsyncWriterFutureLen,
+     DeserializeError, Restorable};
+use util::{ReadExact, WriteAll};
+
+const DIGITS: &[u8; 16] = b"0123456789abcdef";
+
+fn decode_digit(digit: u8) -> Result<u8, HexError> {
+    match digit {
+        b'0'..=b'9' => Ok(digit - b'0'),
+        b'a'..=b'f' => Ok(digit - b'a' + 10),
+        b'A'..=b'F' => Ok(digit - b'A' + 10),
+        _ => Err(HexError::InvalidHexChar(char::from(digit))),
+    }
+}
+
+/// Serializes an owned buffer as hexadecimal ASCII.
+pub struct SerHexStr<W>(WriteAll<W, Vec<u8>>);
+
+impl<W: AsyncWrite> Future for SerHexStr<W> {
+    type Item = (W, usize);
+    type Error = (W, FutIoErr);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        self.0.poll(cx)
+    }
+}
+
+impl<W: AsyncWrite> AsyncWriterFuture<W> for SerHexStr<W> {
+    fn already_written(&self) -> usize {
+        self.0.already_written()
+    }
+}
+
+impl<W: AsyncWrite> AsyncWriterFutureLen<W> for SerHexStr<W> {
+    fn remaining_bytes(&self) -> usize {
+        self.0.remaining_bytes()
+    }
+}
+
+impl<W: AsyncWrite> AsyncSerialize<W> for SerHexStr<W> {
+    type Serialized = Vec<u8>;
+
+    fn from_val(writer: W, val: Vec<u8>) -> Self {
+        let mut hex = Vec::with_capacity(2 * val.len());
+        for byte in val {
+            hex.push(DIGITS[usize::from(byte >> 4)]);
+            hex.push(DIGITS[usize::from(byte & 0xf)]);
+        }
+        SerHexStr(WriteAll::new(writer, hex))
+    }
+}
+
+impl<W: AsyncWrite> AsyncSerializeLen<W> for SerHexStr<W> {
+    fn total_bytes(val: &Vec<u8>) -> usize {
+        2 * val.len()
+    }
+}
+
+/// Deserializes a buffer of a given length from hexadecimal ASCII.
+///
+/// Each pair of digits is validated as soon as it has been read, so this fails on the first
+/// invalid digit without reading the remaining input.
+pub struct DeserHexStr<R> {
+    state: State<R>,
+    buf: Vec<u8>,
+    len: usize,
+}
+
+enum State<R> {
+    Digits(ReadExact<R, [u8; 2]>),
+    Empty(Option<R>),
+}
+
+impl<R: AsyncRead> DeserHexStr<R> {
+    /// Create a new `DeserHexStr`, decoding `len` bytes (i.e. reading `2 * len` digits).
+    pub fn new(reader: R, len: usize) -> DeserHexStr<R> {
+        let state = if len == 0 {
+            State::Empty(Some(reader))
+        } else {
+            State::Digits(ReadExact::new(reader, [0; 2]))
+        };
+        DeserHexStr {
+            state,
+            buf: Vec::with_capacity(len),
+            len,
+        }
+    }
+
+    /// Return how many bytes have already been read.
+    pub fn already_read(&self) -> usize {
+        2 * self.buf.len() +
+        match self.state {
+            State::Digits(ref digits) => digits.already_read(),
+            State::Empty(_) => 0,
+        }
+    }
+}
+
+impl<R> Restorable<R> for DeserHexStr<R> {
+    fn restore(self) -> Option<R> {
+        match self.state {
+            State::Digits(digits) => digits.restore(),
+            State::Empty(reader) => reader,
+        }
+    }
+}
+
+impl<R: AsyncRead> Future for DeserHexStr<R> {
+    type Item = (R, Vec<u8>, usize);
+    type Error = (R, DeserializeError<HexError>);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let (reader, digits, _) = match self.state {
+                State::Digits(ref mut digits) => {
+                    match digits.poll(cx) {
+                        Ok(Async::Ready(done)) => done,
+                        Ok(Async::Pending) => return Ok(Async::Pending),
+                        Err((reader, err)) => {
+                            return Err((reader, DeserializeError::ReaderError(err)))
+                        }
+                    }
+                }
+                State::Empty(ref mut reader) => {
+                    let reader = or_pending!(reader.take());
+                    return Ok(Async::Ready((reader, Vec::new(), 0)));
+                }
+            };
+
+            let byte = decode_digit(digits[0])
+                .and_then(|high| decode_digit(digits[1]).map(|low| (high << 4) | low));
+            match byte {
+                Ok(byte) => self.buf.push(byte),
+                Err(err) => return Err((reader, DeserializeError::DataError(err))),
+            }
+
+            if self.buf.len() == self.len {
+                let buf = ::std::mem::take(&mut self.buf);
+                return Ok(Async::Ready((reader, buf, 2 * self.len)));
+            }
+            self.state = State::Digits(ReadExact::new(reader, [0; 2]));
+        }
+    }
+}
+
+/// Everything that can go wrong when deserializing hexadecimal ASCII.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HexError {
+    /// A character that is not a hexadecimal digit.
+    InvalidHexChar(char),
+}
+
+impl Display for HexError {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            HexError::InvalidHexChar(c) => write!(f, "Invalid hexadecimal digit {:?}", c),
+        }
+    }
+}
+
+impl Error for HexError {}
@@ -0,0 +1,174 @@
+//! Serialize directly into a `Vec<u8>` and deserialize directly from a byte slice.
+//!
+//! This is the escape hatch for code that just wants the bytes. It defeats the whole point of
+//! this crate: the serialized data has to be in memory at once, and serializing into a `Vec`
+//! copies it once more than writing into the final destination would. Prefer serializing into the
+//! actual writer whenever there is one.
+//!
+//! Serializers of text formats can write into a `StringWriter` instead, which saves the final
+//! UTF-8 validation of the bytes.
+
+use std::io::Cursor;
+use std::mem;
+use std::str;
+use std::sync::Arc;
+
+use futures_core::{Async, Future, Poll};
+use futures_core::task::{Context, LocalMap, Wake, Waker};
+use futures_io::{AsyncWrite, Error as FutIoErr, ErrorKind};
+
+use {AsyncDeserialize, AsyncSerialize, DeserializeError};
+
+struct NoopWake;
+
+impl Wake for NoopWake {
+    fn wake(_: &Arc<NoopWake>) {}
+}
+
+// Poll a future until it is done. Only used with in-memory readers and writers, which are always
+// ready, so this never spins for longer than the future yields voluntarily.
+fn run<F: Future>(mut fut: F) -> Result<F::Item, F::Error> {
+    let waker = Waker::from(Arc::new(NoopWake));
+    let mut map = LocalMap::new();
+    let mut cx = Context::without_spawn(&mut map, &waker);
+
+    loop {
+        if let Async::Ready(item) = fut.poll(&mut cx)? {
+            return Ok(item);
+        }
+    }
+}
+
+/// Serialize `val` via `S` into a new `Vec<u8>`.
+///
+/// Writing into memory does not fail, so an error can only be raised by the serializer itself,
+/// e.g. for a value that is too large for its format.
+pub fn to_vec<S>(val: S::Serialized) -> Result<Vec<u8>, FutIoErr>
+    where S: AsyncSerialize<Cursor<Vec<u8>>>
+{
+    match run(S::from_val(Cursor::new(Vec::new()), val)) {
+        Ok((writer, _)) => Ok(writer.into_inner()),
+        Err((_, err)) => Err(err),
+    }
+}
+
+/// Serialize `val` via `S` into a new `String`.
+///
+/// Fails with an `ErrorKind::InvalidData` error if the serializer writes bytes that are not valid
+/// UTF-8, including an incomplete character at the end.
+pub fn to_string<S>(val: S::Serialized) -> Result<String, FutIoErr>
+    where S: AsyncSerialize<StringWriter>
+{
+    match run(S::from_val(StringWriter::new(), val)) {
+        Ok((writer, _)) => {
+            if writer.incomplete().is_empty() {
+                Ok(writer.into_inner())
+            } else {
+                Err(incomplete_err())
+            }
+        }
+        Err((_, err)) => Err(err),
+    }
+}
+
+/// Deserialize a value via `D` from the start of `bytes`.
+///
+/// Any bytes after the value are ignored. Reaching the end of `bytes` before the value is complete
+/// is a reader error, for the deserializers of this crate one of kind `UnexpectedEof`.
+pub fn from_slice<'a, D, T, E>(bytes: &'a [u8]) -> Result<T, DeserializeError<E>>
+    where D: AsyncDeserialize<&'a [u8], T, E>
+{
+    match run(D::from_reader(bytes)) {
+        Ok((_, val, _)) => Ok(val),
+        Err((_, err)) => Err(err),
+    }
+}
+
+/// An `AsyncWrite` that appends everything to a `String`, failing with an `ErrorKind::InvalidData`
+/// error for bytes that are not valid UTF-8.
+///
+/// A write may end in the middle of a character, its first bytes are then held back until the
+/// next write completes it. A write that fails does not append anything.
+#[derive(Debug, Default)]
+pub struct StringWriter {
+    string: String,
+    incomplete: Vec<u8>,
+}
+
+impl StringWriter {
+    /// Create a new, empty `StringWriter`.
+    pub fn new() -> StringWriter {
+        StringWriter::default()
+    }
+
+    /// Return the text written so far, without an incomplete character at the end.
+    pub fn as_str(&self) -> &str {
+        &self.string
+    }
+
+    /// Return the bytes of an incomplete character at the end of the written bytes, which are not
+    /// part of the string (yet).
+    pub fn incomplete(&self) -> &[u8] {
+        &self.incomplete
+    }
+
+    /// Consume this `StringWriter`, returning the text written to it.
+    ///
+    /// The bytes of an incomplete character at the end are dropped, check `incomplete` first to
+    /// detect them.
+    pub fn into_inner(self) -> String {
+        self.string
+    }
+
+    fn push(&mut self, bytes: &[u8]) -> Result<(), FutIoErr> {
+        match str::from_utf8(bytes) {
+            Ok(text) => self.string.push_str(text),
+            Err(err) => {
+                if err.error_len().is_some() {
+                    return Err(FutIoErr::new(ErrorKind::InvalidData,
+                                             "wrote invalid UTF-8 into a StringWriter"));
+                }
+                let (valid, incomplete) = bytes.split_at(err.valid_up_to());
+                // `valid` is exactly the prefix that `from_utf8` has validated.
+                self.string.push_str(str::from_utf8(valid).unwrap());
+                self.incomplete.extend_from_slice(incomplete);
+            }
+        }
+        Ok(())
+    }
+}
+
+fn incomplete_err() -> FutIoErr {
+    FutIoErr::new(ErrorKind::InvalidData, "wrote an incomplete character into a StringWriter")
+}
+
+impl AsyncWrite for StringWriter {
+    fn poll_write(&mut self, _: &mut Context, buf: &[u8]) -> Poll<usize, FutIoErr> {
+        if self.incomplete.is_empty() {
+            self.push(buf)?;
+        } else {
+            let mut bytes = mem::take(&mut self.incomplete);
+            let held_back = bytes.len();
+            bytes.extend_from_slice(buf);
+            if let Err(err) = self.push(&bytes) {
+                bytes.truncate(held_back);
+                self.incomplete = bytes;
+                return Err(err);
+            }
+        }
+        Ok(Async::Ready(buf.len()))
+    }
+
+    fn poll_flush(&mut self, _: &mut Context) -> Poll<(), FutIoErr> {
+        Ok(Async::Ready(()))
+    }
+
+    /// Fails if the written bytes end in the middle of a character.
+    fn poll_close(&mut self, _: &mut Context) -> Poll<(), FutIoErr> {
+        if self.incomplete.is_empty() {
+            Ok(Async::Ready(()))
+        } else {
+            Err(incomplete_err())
+        }
+    }
+}
@@ -0,0 +1,188 @@
+//! Serialization of `IpAddr`s.
+//!
+//! An `IpAddr` is encoded as a family byte (`V4` or `V6`) followed by the four or sixteen bytes of
+//! the address in network byte order.
+
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use futures_core::{Async, Future, Poll};
+use futures_core::task::Context;
+use futures_io::{AsyncRead, AsyncWrite, Error as FutIoErr};
+
+use {AsyncDeserialize, AsyncSerialize, AsyncSerializeLen, AsyncWriterFuture,
+     AsyncWriterFutureLen, DeserializeError, Restorable};
+use util::{ReadExact, WriteAll};
+
+/// The family byte of IPv4 addresses.
+pub const V4: u8 = 0x04;
+/// The family byte of IPv6 addresses.
+pub const V6: u8 = 0x06;
+
+// The family byte and the bytes of an address.
+struct Encoded {
+    bytes: [u8; 17],
+    len: usize,
+}
+
+impl AsRef<[u8]> for Encoded {
+    fn as_ref(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
+}
+
+/// Serializes an `IpAddr`.
+pub struct SerIpAddr<W>(WriteAll<W, Encoded>);
+
+impl<W: AsyncWrite> Future for SerIpAddr<W> {
+    type Item = (W, usize);
+    type Error = (W, FutIoErr);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        self.0.poll(cx)
+    }
+}
+
+impl<W: AsyncWrite> AsyncWriterFuture<W> for SerIpAddr<W> {
+    fn already_written(&self) -> usize {
+        self.0.already_written()
+    }
+}
+
+impl<W: AsyncWrite> AsyncWriterFutureLen<W> for SerIpAddr<W> {
+    fn remaining_bytes(&self) -> usize {
+        self.0.remaining_bytes()
+    }
+}
+
+impl<W: AsyncWrite> AsyncSerialize<W> for SerIpAddr<W> {
+    type Serialized = IpAddr;
+
+    fn from_val(writer: W, val: IpAddr) -> Self {
+        let mut bytes = [0; 17];
+        let len = match val {
+            IpAddr::V4(addr) => {
+                bytes[0] = V4;
+                bytes[1..5].copy_from_slice(&addr.octets());
+                5
+            }
+            IpAddr::V6(addr) => {
+                bytes[0] = V6;
+                bytes[1..].copy_from_slice(&addr.octets());
+                17
+            }
+        };
+        SerIpAddr(WriteAll::new(writer, Encoded { bytes, len }))
+    }
+}
+
+impl<W: AsyncWrite> AsyncSerializeLen<W> for SerIpAddr<W> {
+    fn total_bytes(val: &IpAddr) -> usize {
+        match *val {
+            IpAddr::V4(_) => 5,
+            IpAddr::V6(_) => 17,
+        }
+    }
+}
+
+/// Deserializes an `IpAddr`.
+pub struct DeserIpAddr<R>(DeserState<R>);
+
+enum DeserState<R> {
+    Family(ReadExact<R, [u8; 1]>),
+    V4(ReadExact<R, [u8; 4]>),
+    V6(ReadExact<R, [u8; 16]>),
+}
+
+impl<R: AsyncRead> Future for DeserIpAddr<R> {
+    type Item = (R, IpAddr, usize);
+    type Error = (R, DeserializeError<IpAddrError>);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let res = match self.0 {
+                DeserState::Family(ref mut family) => {
+                    let (reader, family, _) = match family.poll(cx) {
+                        Ok(Async::Ready(done)) => done,
+                        Ok(Async::Pending) => return Ok(Async::Pending),
+                        Err((reader, err)) => {
+                            return Err((reader, DeserializeError::ReaderError(err)))
+                        }
+                    };
+
+                    self.0 = match family[0] {
+                        V4 => DeserState::V4(ReadExact::new(reader, [0; 4])),
+                        V6 => DeserState::V6(ReadExact::new(reader, [0; 16])),
+                        family => {
+                            let err = IpAddrError::UnknownAddressFamily(family);
+                            return Err((reader, DeserializeError::DataError(err)));
+                        }
+                    };
+                    continue;
+                }
+
+                DeserState::V4(ref mut addr) => {
+                    addr.poll(cx).map(|done| {
+                        done.map(|(reader, octets, read)| {
+                            (reader, IpAddr::V4(Ipv4Addr::from(octets)), 1 + read)
+                        })
+                    })
+                }
+
+                DeserState::V6(ref mut addr) => {
+                    addr.poll(cx).map(|done| {
+                        done.map(|(reader, octets, read)| {
+                            (reader, IpAddr::V6(Ipv6Addr::from(octets)), 1 + read)
+                        })
+                    })
+                }
+            };
+
+            return res.map_err(|(reader, err)| (reader, DeserializeError::ReaderError(err)));
+        }
+    }
+}
+
+impl<R: AsyncRead> AsyncDeserialize<R, IpAddr, IpAddrError> for DeserIpAddr<R> {
+    fn from_reader(reader: R) -> Self {
+        DeserIpAddr(DeserState::Family(ReadExact::new(reader, [0])))
+    }
+
+    fn already_read(&self) -> usize {
+        match self.0 {
+            DeserState::Family(ref family) => family.already_read(),
+            DeserState::V4(ref addr) => 1 + addr.already_read(),
+            DeserState::V6(ref addr) => 1 + addr.already_read(),
+        }
+    }
+}
+
+impl<R> Restorable<R> for DeserIpAddr<R> {
+    fn restore(self) -> Option<R> {
+        match self.0 {
+            DeserState::Family(family) => family.restore(),
+            DeserState::V4(addr) => addr.restore(),
+            DeserState::V6(addr) => addr.restore(),
+        }
+    }
+}
+
+/// Everything that can go wrong when deserializing an `IpAddr`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IpAddrError {
+    /// The family byte is neither `V4` nor `V6`.
+    UnknownAddressFamily(u8),
+}
+
+impl Display for IpAddrError {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            IpAddrError::UnknownAddressFamily(family) => {
+                write!(f, "Unknown address family {:#04x}", family)
+            }
+        }
+    }
+}
+
+impl Error for IpAddrError {}
@@ -0,0 +1,369 @@
+//! Frames that are compatible with the `LengthDelimitedCodec` of `tokio-util`.
+//!
+//! A frame is encoded as the length of its payload in a header of one, two or four bytes, in
+//! big-endian or little-endian byte order, followed by the payload. A `LengthDelimitedCodec`
+//! configures this the same way as its namesake in `tokio-util` (without length adjustment or
+//! offsets), and its defaults are the same as well: a four-byte big-endian header and frames of at
+//! most 8 MiB. So peers that use `tokio-util` can exchange frames with peers that use this
+//! module.
+//!
+//! Rather than a `Codec`, this provides futures that write a frame via an `AsyncSerialize` and
+//! read one via an `AsyncDeserialize`, see `LengthDelimitedCodec::write_frame` and
+//! `LengthDelimitedCodec::read_frame`. This module is only available with the `tokio-compat`
+//! feature.
+
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+
+use futures_core::{Async, Future, Poll};
+use futures_core::task::Context;
+use futures_io::{AsyncRead, AsyncWrite, Error as FutIoErr, ErrorKind};
+
+use {AsyncDeserialize, AsyncSerialize, AsyncSerializeLen, AsyncWriterFuture,
+     AsyncWriterFutureLen, DeserializeError, Restorable};
+use take_reader::TakeReader;
+use util::{ReadExact, WriteAll};
+
+/// The byte order of the length header of a frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteOrder {
+    /// Most significant byte first. This is the default.
+    BigEndian,
+    /// Least significant byte first.
+    LittleEndian,
+}
+
+/// The configuration of length-delimited frames.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LengthDelimitedCodec {
+    length_field_length: usize,
+    byte_order: ByteOrder,
+    max_frame_length: usize,
+}
+
+impl Default for LengthDelimitedCodec {
+    fn default() -> LengthDelimitedCodec {
+        LengthDelimitedCodec::new()
+    }
+}
+
+impl LengthDelimitedCodec {
+    /// Create a new `LengthDelimitedCodec` with a four-byte big-endian header and a maximum frame
+    /// length of 8 MiB.
+    pub fn new() -> LengthDelimitedCodec {
+        LengthDelimitedCodec {
+            length_field_length: 4,
+            byte_order: ByteOrder::BigEndian,
+            max_frame_length: 8 * 1024 * 1024,
+        }
+    }
+
+    /// Use a header of `len` bytes.
+    ///
+    /// Panics unless `len` is one, two or four.
+    pub fn length_field_length(self, len: usize) -> LengthDelimitedCodec {
+        assert!(len == 1 || len == 2 || len == 4,
+                "LengthDelimitedCodec needs a header of 1, 2 or 4 bytes");
+        LengthDelimitedCodec { length_field_length: len, ..self }
+    }
+
+    /// Encode the header with the given byte order.
+    pub fn byte_order(self, byte_order: ByteOrder) -> LengthDelimitedCodec {
+        LengthDelimitedCodec { byte_order, ..self }
+    }
+
+    /// Reject frames whose payload is longer than `max` bytes.
+    pub fn max_frame_length(self, max: usize) -> LengthDelimitedCodec {
+        LengthDelimitedCodec {
+            max_frame_length: max,
+            ..self
+        }
+    }
+
+    /// Return the largest payload length that can be written and read, taking both the maximum
+    /// frame length and the size of the header into account.
+    pub fn max_len(&self) -> u64 {
+        let header_max = (1u64 << (8 * self.length_field_length as u32)) - 1;
+        header_max.min(self.max_frame_length as u64)
+    }
+
+    fn header(&self, len: u64) -> Option<Header> {
+        if len > self.max_len() {
+            return None;
+        }
+
+        let mut bytes = [0; 4];
+        let n = self.length_field_length;
+        match self.byte_order {
+            ByteOrder::BigEndian => bytes[..n].copy_from_slice(&len.to_be_bytes()[8 - n..]),
+            ByteOrder::LittleEndian => bytes[..n].copy_from_slice(&len.to_le_bytes()[..n]),
+        }
+        Some(Header { bytes, len: n })
+    }
+
+    fn decode_header(&self, header: &Header) -> u64 {
+        let bytes = header.as_ref();
+        match self.byte_order {
+            ByteOrder::BigEndian => bytes.iter().fold(0, |len, byte| (len << 8) | u64::from(*byte)),
+            ByteOrder::LittleEndian => {
+                bytes.iter().rev().fold(0, |len, byte| (len << 8) | u64::from(*byte))
+            }
+        }
+    }
+
+    /// Write `val` as a single frame via `P`, yielding the number of written bytes including the
+    /// header.
+    ///
+    /// If the payload is longer than `max_len`, this fails with an `ErrorKind::InvalidInput` error
+    /// without writing anything.
+    pub fn write_frame<P, W>(&self, writer: W, val: P::Serialized) -> WriteDelimited<P, W>
+        where P: AsyncSerializeLen<W>,
+              W: AsyncWrite
+    {
+        let state = match self.header(P::total_bytes(&val) as u64) {
+            Some(header) => WriteState::Header(WriteAll::new(writer, header), Some(val)),
+            None => WriteState::Invalid(Some(writer)),
+        };
+        WriteDelimited {
+            state,
+            header_len: self.length_field_length,
+        }
+    }
+
+    /// Read a single frame, deserializing its payload via `D`.
+    ///
+    /// `D` reads through a `TakeReader`, so it can not read past the end of the frame, and it
+    /// must consume the frame completely.
+    pub fn read_frame<D, R, T, E>(&self, reader: R) -> ReadDelimited<D, R, T, E>
+        where D: AsyncDeserialize<TakeReader<R>, T, E>,
+              R: AsyncRead
+    {
+        let header = Header {
+            bytes: [0; 4],
+            len: self.length_field_length,
+        };
+        ReadDelimited {
+            state: ReadState::Header(ReadExact::new(reader, header)),
+            codec: *self,
+            _types: ::std::marker::PhantomData,
+        }
+    }
+}
+
+// The bytes of a length header.
+struct Header {
+    bytes: [u8; 4],
+    len: usize,
+}
+
+impl AsRef<[u8]> for Header {
+    fn as_ref(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
+}
+
+impl AsMut<[u8]> for Header {
+    fn as_mut(&mut self) -> &mut [u8] {
+        &mut self.bytes[..self.len]
+    }
+}
+
+/// Serializes a single frame via `P`, created via `LengthDelimitedCodec::write_frame`.
+pub struct WriteDelimited<P, W>
+    where P: AsyncSerialize<W>,
+          W: AsyncWrite
+{
+    state: WriteState<P, W>,
+    header_len: usize,
+}
+
+enum WriteState<P, W>
+    where P: AsyncSerialize<W>,
+          W: AsyncWrite
+{
+    Header(WriteAll<W, Header>, Option<P::Serialized>),
+    Payload(P),
+    Invalid(Option<W>),
+}
+
+impl<P, W> Future for WriteDelimited<P, W>
+    where P: AsyncSerialize<W>,
+          W: AsyncWrite
+{
+    type Item = (W, usize);
+    type Error = (W, FutIoErr);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let payload = match self.state {
+                WriteState::Header(ref mut header, ref mut val) => {
+                    let (writer, _) = try_ready!(header.poll(cx));
+                    let val = or_pending!(val.take());
+                    P::from_val(writer, val)
+                }
+
+                WriteState::Payload(ref mut payload) => {
+                    let (writer, written) = try_ready!(payload.poll(cx));
+                    return Ok(Async::Ready((writer, self.header_len + written)));
+                }
+
+                WriteState::Invalid(ref mut writer) => {
+                    let err = FutIoErr::new(ErrorKind::InvalidInput, "frame is too long");
+                    let writer = or_pending!(writer.take());
+                    return Err((writer, err));
+                }
+            };
+            self.state = WriteState::Payload(payload);
+        }
+    }
+}
+
+impl<P, W> AsyncWriterFuture<W> for WriteDelimited<P, W>
+    where P: AsyncSerialize<W>,
+          W: AsyncWrite
+{
+    fn already_written(&self) -> usize {
+        match self.state {
+            WriteState::Header(ref header, _) => header.already_written(),
+            WriteState::Payload(ref payload) => self.header_len + payload.already_written(),
+            WriteState::Invalid(_) => 0,
+        }
+    }
+}
+
+impl<P, W> AsyncWriterFutureLen<W> for WriteDelimited<P, W>
+    where P: AsyncSerializeLen<W>,
+          W: AsyncWrite
+{
+    fn remaining_bytes(&self) -> usize {
+        match self.state {
+            WriteState::Header(ref header, ref val) => {
+                header.remaining_bytes() + val.as_ref().map(P::total_bytes).unwrap_or(0)
+            }
+            WriteState::Payload(ref payload) => payload.remaining_bytes(),
+            WriteState::Invalid(_) => 0,
+        }
+    }
+}
+
+/// Deserializes a single frame via `D`, created via `LengthDelimitedCodec::read_frame`.
+pub struct ReadDelimited<D, R, T, E> {
+    state: ReadState<D, R>,
+    codec: LengthDelimitedCodec,
+    _types: ::std::marker::PhantomData<(T, E)>,
+}
+
+enum ReadState<D, R> {
+    Header(ReadExact<R, Header>),
+    Payload(D, u64),
+}
+
+impl<D, R, T, E> Future for ReadDelimited<D, R, T, E>
+    where D: AsyncDeserialize<TakeReader<R>, T, E>,
+          D: Future<Item = (TakeReader<R>, T, usize),
+                    Error = (TakeReader<R>, DeserializeError<E>)>,
+          R: AsyncRead
+{
+    type Item = (R, T, usize);
+    type Error = (R, DeserializeError<DelimitedError<E>>);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let next = match self.state {
+                ReadState::Header(ref mut header) => {
+                    let (reader, header, _) = match header.poll(cx) {
+                        Ok(Async::Ready(done)) => done,
+                        Ok(Async::Pending) => return Ok(Async::Pending),
+                        Err((reader, err)) => {
+                            return Err((reader, DeserializeError::ReaderError(err)))
+                        }
+                    };
+
+                    let len = self.codec.decode_header(&header);
+                    if len > self.codec.max_len() {
+                        let err = DelimitedError::FrameTooLong {
+                            len,
+                            max: self.codec.max_len(),
+                        };
+                        return Err((reader, DeserializeError::DataError(err)));
+                    }
+                    ReadState::Payload(D::from_reader(TakeReader::new(reader, len)), len)
+                }
+
+                ReadState::Payload(ref mut payload, len) => {
+                    let (take, val, _) = match payload.poll(cx) {
+                        Ok(Async::Ready(done)) => done,
+                        Ok(Async::Pending) => return Ok(Async::Pending),
+                        Err((take, DeserializeError::ReaderError(err))) => {
+                            return Err((take.into_inner(), DeserializeError::ReaderError(err)))
+                        }
+                        Err((take, DeserializeError::DataError(err))) => {
+                            return Err((take.into_inner(),
+                                        DeserializeError::DataError(DelimitedError::Inner(err))))
+                        }
+                    };
+
+                    let consumed = len - take.limit();
+                    if consumed != len {
+                        let err = DelimitedError::LengthMismatch {
+                            declared: len,
+                            consumed,
+                        };
+                        return Err((take.into_inner(), DeserializeError::DataError(err)));
+                    }
+                    let read = self.codec.length_field_length + len as usize;
+                    return Ok(Async::Ready((take.into_inner(), val, read)));
+                }
+            };
+            self.state = next;
+        }
+    }
+}
+
+impl<D, R, T, E> Restorable<R> for ReadDelimited<D, R, T, E>
+    where D: Restorable<TakeReader<R>>
+{
+    fn restore(self) -> Option<R> {
+        match self.state {
+            ReadState::Header(inner) => inner.restore(),
+            ReadState::Payload(inner, _) => inner.restore().map(TakeReader::into_inner),
+        }
+    }
+}
+
+/// Everything that can go wrong when reading a length-delimited frame, apart from reader errors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DelimitedError<E> {
+    /// The frame is longer than the maximum length of the codec.
+    FrameTooLong {
+        /// The length of the frame according to its header.
+        len: u64,
+        /// The maximum length.
+        max: u64,
+    },
+    /// The payload could not be deserialized.
+    Inner(E),
+    /// The payload was deserialized without consuming all of its bytes.
+    LengthMismatch {
+        /// The length of the frame according to its header.
+        declared: u64,
+        /// How many bytes the payload deserializer consumed.
+        consumed: u64,
+    },
+}
+
+impl<E: Display> Display for DelimitedError<E> {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            DelimitedError::FrameTooLong { len, max } => {
+                write!(f, "Frame of {} bytes exceeds the maximum of {}", len, max)
+            }
+            DelimitedError::Inner(ref err) => write!(f, "{}", err),
+            DelimitedError::LengthMismatch { declared, consumed } => {
+                write!(f, "Frame has {} bytes but only {} were consumed", declared, consumed)
+            }
+        }
+    }
+}
+
+impl<E: Error> Error for DelimitedError<E> {}
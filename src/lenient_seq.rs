@@ -0,0 +1,190 @@
+//! Best-effort deserialization of sequences whose elements may be malformed.
+
+use std::io::ErrorKind;
+use std::mem;
+
+use futures_core::{Async, Future, Poll};
+use futures_core::task::Context;
+use futures_io::AsyncRead;
+
+use {AsyncDeserialize, DeserializeError, Restorable};
+use take_reader::TakeReader;
+use util::poll_skip;
+use varint::{ReadVarint, VarintError};
+
+/// The result of leniently deserializing a sequence.
+#[derive(Debug)]
+pub struct LenientSeq<S, E> {
+    /// The elements that could be deserialized, in order.
+    pub values: Vec<S>,
+    /// The elements that could not be deserialized, as pairs of their index in the sequence and
+    /// the reason they were rejected.
+    ///
+    /// A `ReaderError` in here means the element tried to read past the end of its frame.
+    pub errors: Vec<(usize, DeserializeError<E>)>,
+}
+
+/// Deserializes a sequence of independently framed elements, collecting the elements that fail to
+/// deserialize instead of aborting.
+///
+/// The encoding is a varint count, followed by that many elements which are each encoded as a
+/// varint length followed by that many bytes. Each element is deserialized by a `D` that can only
+/// read within the frame of the element; whatever it leaves unread is skipped. When `D` fails with
+/// a `DataError` (or by reading past its frame), the error is recorded, the rest of the frame is
+/// skipped and deserialization continues with the next element.
+///
+/// An error of the underlying reader, or a malformed count or length, still aborts the whole
+/// sequence, since there is no way to resynchronize after those.
+pub struct ReadLenientSeq<D, R, S, E> {
+    state: State<D, R>,
+    remaining: u64,
+    index: usize,
+    read: usize,
+    values: Vec<S>,
+    errors: Vec<(usize, DeserializeError<E>)>,
+}
+
+enum State<D, R> {
+    Count(ReadVarint<R>),
+    Length(ReadVarint<R>),
+    Element(D, u64),
+    Skip(Option<TakeReader<R>>, u64),
+}
+
+impl<D, R, S, E> ReadLenientSeq<D, R, S, E>
+    where D: AsyncDeserialize<TakeReader<R>, S, E>,
+          R: AsyncRead
+{
+    fn next_element(&mut self, reader: R) -> Result<State<D, R>, R> {
+        if self.remaining == 0 {
+            Err(reader)
+        } else {
+            Ok(State::Length(ReadVarint::from_reader(reader)))
+        }
+    }
+}
+
+impl<D, R, S, E> Future for ReadLenientSeq<D, R, S, E>
+    where D: AsyncDeserialize<TakeReader<R>, S, E>,
+          R: AsyncRead
+{
+    type Item = (R, LenientSeq<S, E>, usize);
+    type Error = (R, DeserializeError<VarintError>);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let reader = match self.state {
+                State::Count(ref mut inner) => {
+                    let (reader, count, read) = try_ready!(inner.poll(cx));
+                    self.read += read;
+                    self.remaining = count;
+                    reader
+                }
+
+                State::Length(ref mut inner) => {
+                    let (reader, len, read) = try_ready!(inner.poll(cx));
+                    self.read += read;
+                    self.state = State::Element(D::from_reader(TakeReader::new(reader, len)), len);
+                    continue;
+                }
+
+                State::Element(ref mut inner, len) => {
+                    let take = match inner.poll(cx) {
+                        Ok(Async::Ready((take, val, _))) => {
+                            self.values.push(val);
+                            take
+                        }
+                        Ok(Async::Pending) => return Ok(Async::Pending),
+                        Err((take, DeserializeError::DataError(err))) => {
+                            self.errors
+                                .push((self.index, DeserializeError::DataError(err)));
+                            take
+                        }
+                        Err((take, DeserializeError::ReaderError(err))) => {
+                            if take.limit() == 0 && err.kind() == ErrorKind::UnexpectedEof {
+                                self.errors
+                                    .push((self.index, DeserializeError::ReaderError(err)));
+                                take
+                            } else {
+                                return Err((take.into_inner(),
+                                            DeserializeError::ReaderError(err)));
+                            }
+                        }
+                    };
+                    self.state = State::Skip(Some(take), len);
+                    continue;
+                }
+
+                State::Skip(ref mut take, len) => {
+                    let mut t = or_pending!(take.take());
+                    let mut remaining = t.limit();
+                    match poll_skip(&mut t, cx, &mut remaining) {
+                        Ok(Async::Ready(())) => {}
+                        Ok(Async::Pending) => {
+                            *take = Some(t);
+                            return Ok(Async::Pending);
+                        }
+                        Err(err) => return Err((t.into_inner(), DeserializeError::ReaderError(err))),
+                    }
+
+                    self.read += len as usize;
+                    self.index += 1;
+                    self.remaining -= 1;
+                    t.into_inner()
+                }
+            };
+
+            match self.next_element(reader) {
+                Ok(state) => self.state = state,
+                Err(reader) => {
+                    let seq = LenientSeq {
+                        values: mem::take(&mut self.values),
+                        errors: mem::take(&mut self.errors),
+                    };
+                    return Ok(Async::Ready((reader, seq, self.read)));
+                }
+            }
+        }
+    }
+}
+
+impl<D, R, S, E> AsyncDeserialize<R, LenientSeq<S, E>, VarintError> for ReadLenientSeq<D, R, S, E>
+    where D: AsyncDeserialize<TakeReader<R>, S, E>,
+          R: AsyncRead
+{
+    fn from_reader(reader: R) -> Self {
+        ReadLenientSeq {
+            state: State::Count(ReadVarint::from_reader(reader)),
+            remaining: 0,
+            index: 0,
+            read: 0,
+            values: Vec::new(),
+            errors: Vec::new(),
+        }
+    }
+
+    fn already_read(&self) -> usize {
+        self.read +
+        match self.state {
+            State::Count(ref inner) |
+            State::Length(ref inner) => inner.already_read(),
+            State::Element(ref inner, _) => inner.already_read(),
+            State::Skip(ref take, len) => {
+                (len - take.as_ref().map(|t| t.limit()).unwrap_or(0)) as usize
+            }
+        }
+    }
+}
+
+impl<D, R, S, E> Restorable<R> for ReadLenientSeq<D, R, S, E>
+    where D: Restorable<TakeReader<R>>
+{
+    fn restore(self) -> Option<R> {
+        match self.state {
+            State::Count(inner) |
+            State::Length(inner) => inner.restore(),
+            State::Element(inner, _) => inner.restore().map(TakeReader::into_inner),
+            State::Skip(take, _) => take.map(TakeReader::into_inner),
+        }
+    }
+}
@@ -5,19 +5,102 @@
 
 #![deprecated="This was a failed attempt at finding a suitable abstraction. The async-codec crate might be what you need instead."]
 
+extern crate either;
+#[macro_use]
 extern crate futures_core;
 extern crate futures_io;
 
 use std::error::Error;
-use std::fmt::{self, Display, Formatter};
+use std::fmt::{self, Debug, Display, Formatter};
 
 use futures_core::Future;
-use futures_io::{AsyncRead, AsyncWrite, Error as FutIoErr};
+use futures_io::{AsyncRead, AsyncWrite, Error as FutIoErr, ErrorKind};
+
+#[macro_use]
+mod util;
+
+pub mod arc_bytes;
+pub mod ascii_char;
+#[cfg(feature = "base64")]
+pub mod base64;
+pub mod binary_heap;
+pub mod bitset;
+pub mod buffered;
+pub mod cancellable;
+pub mod canonical;
+pub mod capture;
+pub mod chain;
+pub mod compose;
+pub mod counted;
+pub mod cow;
+pub mod eager_header;
+pub mod envelope;
+pub mod fixed_point;
+pub mod fold;
+pub mod framed;
+pub mod front_coded;
+#[cfg(feature = "fuzz_support")]
+pub mod fuzz_support;
+pub mod grow_buf;
+pub mod graceful_close;
+pub mod handshake;
+pub mod heartbeat;
+pub mod hex_str;
+pub mod in_memory;
+pub mod ip_addr;
+#[cfg(feature = "tokio-compat")]
+pub mod length_delimited;
+pub mod lenient_seq;
+pub mod linked_list;
+pub mod log_record;
+pub mod merge;
+pub mod message;
+pub mod min_write_size;
+pub mod named;
+pub mod offset_reader;
+pub mod option;
+pub mod os_string;
+pub mod pair;
+pub mod parity;
+pub mod partial;
+pub mod path;
+pub mod pipeline;
+pub mod poll_budget;
+pub mod progress;
+pub mod protobuf_wire;
+pub mod quota;
+pub mod range;
+pub mod redundant;
+pub mod reserve_and_fill;
+pub mod ring_writer;
+pub mod run_length;
+pub mod saturating;
+pub mod schema_id;
+pub mod session;
+pub mod short_circuit;
+pub mod sized;
+pub mod sparse;
+pub mod stateful;
+pub mod streaming_utf8;
+pub mod tagged;
+pub mod tagged_enum;
+pub mod take_reader;
+#[cfg(feature = "telemetry")]
+pub mod telemetry;
+pub mod terminated;
+#[cfg(feature = "testing")]
+pub mod testing;
+pub mod throttle;
+pub mod timeout;
+pub mod tlv;
+pub mod validated;
+pub mod varint;
 
 /// Base trait for futures that write things into `AsyncWrite`s.
 ///
 /// The future must yield a previously wrapped `AsyncWrite`, and the number of written bytes.
 /// If there's an error upon writing, the wrapped `AsyncWrite` is emitted together with the error.
+/// Once the future has resolved, polling it again returns `Async::Pending` rather than panicking.
 pub trait AsyncWriterFuture<W: AsyncWrite>
     : Future<Item = (W, usize), Error = (W, FutIoErr)> {
     /// Return how many bytes have already been written.
@@ -51,9 +134,13 @@ pub trait AsyncSerializeLen<W: AsyncWrite>
 }
 
 /// A future that asynchronously serializes something by reference into a wrapped AsyncWrite.
+///
+/// `Serialized` may be unsized, so that e.g. a `Path` or a `[bool]` can be serialized without an
+/// owned wrapper. Generic code that uses `S::Serialized` by value, e.g. in an
+/// `Option<S::Serialized>` or a `Vec<S::Serialized>`, must require `S::Serialized: Sized`.
 pub trait AsyncSerializeRef<'val, W: AsyncWrite>: AsyncWriterFuture<W> {
-    /// The type of values serialized.
-    type Serialized;
+    /// The type of values serialized. This may be unsized, e.g. `str` or `[u8]`.
+    type Serialized: ?Sized;
 
     /// Create a new instance, taking a reference to the value to serialize and wrapping the
     /// `AsyncWrite` to serialize into.
@@ -70,6 +157,8 @@ pub trait AsyncSerializeRefLen<'val, W: AsyncWrite>
 
 /// A future that asynchronously serializes something from a wrapped AsyncRead and then returns
 /// the wrapped AsyncRead, the deserialized value, and how many bytes were read.
+///
+/// Once the future has resolved, polling it again returns `Async::Pending` rather than panicking.
 pub trait AsyncDeserialize<R: AsyncRead, S, E>
     : Future<Item = (R, S, usize), Error = (R, DeserializeError<E>)> {
     /// Consume a reader to create an `AsyncDeserialize`.
@@ -79,8 +168,19 @@ pub trait AsyncDeserialize<R: AsyncRead, S, E>
     fn already_read(&self) -> usize;
 }
 
+/// A deserializer that can give back its reader if it is abandoned before resolving, e.g. because
+/// a timeout fired first.
+///
+/// Whatever has been read so far is lost, so the reader is usually left in the middle of a value.
+/// This is meant for cleanup code that still needs the reader, e.g. to close a connection.
+pub trait Restorable<R> {
+    /// Consume the deserializer and return its reader.
+    ///
+    /// Returns `None` if the deserializer has already resolved, as it has then emitted the reader.
+    fn restore(self) -> Option<R>;
+}
+
 /// An error that occured during deserialization.
-#[derive(Debug)]
 pub enum DeserializeError<E> {
     /// An error propagated from the underlying reader.
     ReaderError(FutIoErr),
@@ -88,6 +188,56 @@ pub enum DeserializeError<E> {
     DataError(E),
 }
 
+impl<E: Display> DeserializeError<E> {
+    /// Consume the error and return its `Display` representation.
+    pub fn into_string(self) -> String {
+        self.to_string()
+    }
+}
+
+impl<E> DeserializeError<DeserializeError<E>> {
+    /// Collapse a nested error into a single level: a data error wrapping a reader error becomes
+    /// a reader error, a data error wrapping a data error becomes that data error.
+    pub fn flatten(self) -> DeserializeError<E> {
+        match self {
+            DeserializeError::ReaderError(err) |
+            DeserializeError::DataError(DeserializeError::ReaderError(err)) => {
+                DeserializeError::ReaderError(err)
+            }
+            DeserializeError::DataError(DeserializeError::DataError(err)) => {
+                DeserializeError::DataError(err)
+            }
+        }
+    }
+}
+
+impl<E: Debug> Debug for DeserializeError<E> {
+    /// Shows the kind and the message of reader errors rather than the internal representation of
+    /// the io error.
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            DeserializeError::ReaderError(ref err) => {
+                f.debug_struct("ReaderError")
+                    .field("kind", &err.kind())
+                    .field("message", &format_args!("{}", err))
+                    .finish()
+            }
+            DeserializeError::DataError(ref err) => f.debug_tuple("DataError").field(err).finish(),
+        }
+    }
+}
+
+/// Data errors are compared via `E`. `FutIoErr` does not implement `PartialEq`, so a reader error
+/// is never equal to any error, not even to itself. Compare the `kind` of reader errors instead.
+impl<E: PartialEq> PartialEq for DeserializeError<E> {
+    fn eq(&self, other: &DeserializeError<E>) -> bool {
+        match (self, other) {
+            (DeserializeError::DataError(a), DeserializeError::DataError(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
 impl<E: Display> Display for DeserializeError<E> {
     fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
         match *self {
@@ -100,6 +250,7 @@ impl<E: Display> Display for DeserializeError<E> {
 }
 
 impl<E: Error> Error for DeserializeError<E> {
+    #[allow(deprecated)]
     fn description(&self) -> &str {
         match *self {
             DeserializeError::ReaderError(ref err) => err.description(),
@@ -107,7 +258,7 @@ impl<E: Error> Error for DeserializeError<E> {
         }
     }
 
-    fn cause(&self) -> Option<&Error> {
+    fn cause(&self) -> Option<&dyn Error> {
         match *self {
             DeserializeError::ReaderError(ref err) => Some(err),
             DeserializeError::DataError(ref err) => Some(err),
@@ -115,8 +266,45 @@ impl<E: Error> Error for DeserializeError<E> {
     }
 }
 
+/// `futures_io::Error` is a re-export of `std::io::Error`, so this also converts `std::io::Error`s,
+/// e.g. via `?`.
 impl<E> From<FutIoErr> for DeserializeError<E> {
     fn from(err: FutIoErr) -> DeserializeError<E> {
         DeserializeError::ReaderError(err)
     }
 }
+
+/// Why a value can not be serialized, as reported by the `validate` functions of the serializers
+/// that reject some values.
+///
+/// Validating a value does not touch any writer, so a value can be rejected before anything is
+/// written. Converting this into a `FutIoErr` yields an error of kind `ErrorKind::InvalidInput`,
+/// the same kind with which the serializer itself would fail.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValidationError {
+    /// A length exceeds the maximum that the format can encode.
+    TooLong {
+        /// The length of the value.
+        len: u64,
+        /// The largest length the format can encode.
+        max: u64,
+    },
+}
+
+impl Display for ValidationError {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            ValidationError::TooLong { len, max } => {
+                write!(f, "Length {} exceeds the maximum of {}", len, max)
+            }
+        }
+    }
+}
+
+impl Error for ValidationError {}
+
+impl From<ValidationError> for FutIoErr {
+    fn from(err: ValidationError) -> FutIoErr {
+        FutIoErr::new(ErrorKind::InvalidInput, err)
+    }
+}
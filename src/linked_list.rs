@@ -0,0 +1,297 @@
+//! Serialization of linked lists.
+//!
+//! A list is encoded as the number of elements as a big-endian `u32`, followed by the elements in
+//! order. Lists with more than `u32::MAX` elements can not be serialized, the serializer fails
+//! with an `ErrorKind::InvalidInput` error without writing anything.
+
+use std::collections::LinkedList;
+use std::collections::linked_list::Iter;
+use std::marker::PhantomData;
+use std::mem;
+
+use futures_core::{Async, Future, Poll};
+use futures_core::task::Context;
+use futures_io::{AsyncRead, AsyncWrite, Error as FutIoErr, ErrorKind};
+
+use {AsyncDeserialize, AsyncSerializeRef, AsyncSerializeRefLen, AsyncWriterFuture,
+     AsyncWriterFutureLen, DeserializeError, Restorable, ValidationError};
+use partial::PartialResult;
+use util::{prefix_fits, validate_prefix, ReadExact, WriteAll};
+
+/// Serializes a `LinkedList` by reference, serializing the elements via `S`.
+pub struct SerLinkedList<'val, S, W>
+    where S: AsyncSerializeRef<'val, W>,
+          S::Serialized: Sized + 'val,
+          W: AsyncWrite
+{
+    state: SerState<S, W>,
+    elements: Iter<'val, S::Serialized>,
+    written: usize,
+}
+
+enum SerState<S, W> {
+    Count(WriteAll<W, [u8; 4]>),
+    Element(S),
+    Invalid(Option<W>),
+    Done,
+}
+
+impl<'val, S, W> Future for SerLinkedList<'val, S, W>
+    where S: AsyncSerializeRef<'val, W>,
+          S::Serialized: Sized + 'val,
+          W: AsyncWrite
+{
+    type Item = (W, usize);
+    type Error = (W, FutIoErr);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let (writer, written) = match self.state {
+                SerState::Count(ref mut inner) => try_ready!(inner.poll(cx)),
+                SerState::Element(ref mut inner) => try_ready!(inner.poll(cx)),
+                SerState::Invalid(ref mut writer) => {
+                    let err = FutIoErr::new(ErrorKind::InvalidInput, "list is too long");
+                    let writer = or_pending!(writer.take());
+                    return Err((writer, err));
+                }
+                SerState::Done => return Ok(Async::Pending),
+            };
+            self.written += written;
+
+            match self.elements.next() {
+                Some(element) => {
+                    self.state = SerState::Element(S::from_ref(writer, element));
+                }
+                None => {
+                    self.state = SerState::Done;
+                    return Ok(Async::Ready((writer, self.written)));
+                }
+            }
+        }
+    }
+}
+
+impl<'val, S, W> AsyncWriterFuture<W> for SerLinkedList<'val, S, W>
+    where S: AsyncSerializeRef<'val, W>,
+          S::Serialized: Sized + 'val,
+          W: AsyncWrite
+{
+    fn already_written(&self) -> usize {
+        self.written +
+        match self.state {
+            SerState::Count(ref inner) => inner.already_written(),
+            SerState::Element(ref inner) => inner.already_written(),
+            SerState::Invalid(_) | SerState::Done => 0,
+        }
+    }
+}
+
+impl<'val, S, W> AsyncWriterFutureLen<W> for SerLinkedList<'val, S, W>
+    where S: AsyncSerializeRefLen<'val, W>,
+          S::Serialized: Sized + 'val,
+          W: AsyncWrite
+{
+    fn remaining_bytes(&self) -> usize {
+        let current = match self.state {
+            SerState::Count(ref inner) => inner.remaining_bytes(),
+            SerState::Element(ref inner) => inner.remaining_bytes(),
+            SerState::Invalid(_) | SerState::Done => return 0,
+        };
+
+        current + self.elements.clone().map(S::total_bytes).sum::<usize>()
+    }
+}
+
+impl<'val, S, W> SerLinkedList<'val, S, W>
+    where S: AsyncSerializeRef<'val, W>,
+          S::Serialized: Sized + 'val,
+          W: AsyncWrite
+{
+    /// Check whether `val` can be serialized, i.e. whether it has at most `u32::MAX` elements.
+    ///
+    /// This does not validate the elements themselves.
+    pub fn validate(val: &LinkedList<S::Serialized>) -> Result<(), ValidationError> {
+        validate_prefix(val.len())
+    }
+}
+
+impl<'val, S, W> AsyncSerializeRef<'val, W> for SerLinkedList<'val, S, W>
+    where S: AsyncSerializeRef<'val, W>,
+          S::Serialized: Sized + 'val,
+          W: AsyncWrite
+{
+    type Serialized = LinkedList<S::Serialized>;
+
+    fn from_ref(writer: W, val: &'val LinkedList<S::Serialized>) -> Self {
+        let state = if prefix_fits(val.len()) {
+            SerState::Count(WriteAll::new(writer, (val.len() as u32).to_be_bytes()))
+        } else {
+            SerState::Invalid(Some(writer))
+        };
+
+        SerLinkedList {
+            state,
+            elements: val.iter(),
+            written: 0,
+        }
+    }
+}
+
+impl<'val, S, W> AsyncSerializeRefLen<'val, W> for SerLinkedList<'val, S, W>
+    where S: AsyncSerializeRefLen<'val, W>,
+          S::Serialized: Sized + 'val,
+          W: AsyncWrite
+{
+    /// Returns zero for lists that can not be serialized.
+    fn total_bytes(val: &LinkedList<S::Serialized>) -> usize {
+        if prefix_fits(val.len()) {
+            4 + val.iter().map(S::total_bytes).sum::<usize>()
+        } else {
+            0
+        }
+    }
+}
+
+/// Deserializes a `LinkedList`, deserializing the elements via `D`.
+///
+/// Use `from_reader_partial` to recover the elements before a malformed one.
+pub struct DeserLinkedList<D, R, T, E> {
+    state: DeserState<D, R>,
+    remaining: u32,
+    read: usize,
+    list: LinkedList<T>,
+    _error: PhantomData<E>,
+}
+
+enum DeserState<D, R> {
+    Count(ReadExact<R, [u8; 4]>),
+    Element(D),
+    Done,
+}
+
+impl<D, R, T, E> Future for DeserLinkedList<D, R, T, E>
+    where D: AsyncDeserialize<R, T, E>,
+          R: AsyncRead
+{
+    type Item = (R, LinkedList<T>, usize);
+    type Error = (R, DeserializeError<E>);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let reader = match self.state {
+                DeserState::Count(ref mut inner) => {
+                    let (reader, count, read) = match inner.poll(cx) {
+                        Ok(Async::Ready(done)) => done,
+                        Ok(Async::Pending) => return Ok(Async::Pending),
+                        Err((reader, err)) => {
+                            return Err((reader, DeserializeError::ReaderError(err)))
+                        }
+                    };
+                    self.remaining = u32::from_be_bytes(count);
+                    self.read += read;
+                    reader
+                }
+
+                DeserState::Element(ref mut inner) => {
+                    let (reader, element, read) = try_ready!(inner.poll(cx));
+                    self.list.push_back(element);
+                    self.remaining -= 1;
+                    self.read += read;
+                    reader
+                }
+
+                DeserState::Done => return Ok(Async::Pending),
+            };
+
+            if self.remaining == 0 {
+                self.state = DeserState::Done;
+                return Ok(Async::Ready((reader, mem::take(&mut self.list), self.read)));
+            }
+            self.state = DeserState::Element(D::from_reader(reader));
+        }
+    }
+}
+
+impl<D, R, T, E> AsyncDeserialize<R, LinkedList<T>, E> for DeserLinkedList<D, R, T, E>
+    where D: AsyncDeserialize<R, T, E>,
+          R: AsyncRead
+{
+    fn from_reader(reader: R) -> Self {
+        DeserLinkedList {
+            state: DeserState::Count(ReadExact::new(reader, [0; 4])),
+            remaining: 0,
+            read: 0,
+            list: LinkedList::new(),
+            _error: PhantomData,
+        }
+    }
+
+    fn already_read(&self) -> usize {
+        self.read +
+        match self.state {
+            DeserState::Count(ref inner) => inner.already_read(),
+            DeserState::Element(ref inner) => inner.already_read(),
+            DeserState::Done => 0,
+        }
+    }
+}
+
+impl<D, R, T, E> Restorable<R> for DeserLinkedList<D, R, T, E>
+    where D: Restorable<R>
+{
+    fn restore(self) -> Option<R> {
+        match self.state {
+            DeserState::Count(count) => count.restore(),
+            DeserState::Element(element) => element.restore(),
+            DeserState::Done => None,
+        }
+    }
+}
+
+impl<D, R, T, E> DeserLinkedList<D, R, T, E>
+    where D: AsyncDeserialize<R, T, E>,
+          R: AsyncRead
+{
+    /// Create a deserializer that does not fail if an element can not be deserialized, but
+    /// resolves to a `PartialResult` with the elements before it.
+    ///
+    /// The reader is then left in the middle of the list, see the `partial` module.
+    pub fn from_reader_partial(reader: R) -> PartialLinkedList<D, R, T, E> {
+        PartialLinkedList(DeserLinkedList::from_reader(reader))
+    }
+}
+
+/// Deserializes a `LinkedList` like `DeserLinkedList`, but recovers the elements before the first
+/// one that fails with a `DataError`, created via `DeserLinkedList::from_reader_partial`.
+///
+/// The partial result holds the complete elements and the error of the failed one, and the number
+/// of read bytes includes those that the failed element deserializer reports to have read.
+pub struct PartialLinkedList<D, R, T, E>(DeserLinkedList<D, R, T, E>);
+
+impl<D, R, T, E> Future for PartialLinkedList<D, R, T, E>
+    where D: AsyncDeserialize<R, T, E>,
+          R: AsyncRead
+{
+    type Item = (R, PartialResult<LinkedList<T>, LinkedList<T>, E>, usize);
+    type Error = (R, DeserializeError<E>);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        match self.0.poll(cx) {
+            Ok(Async::Ready((reader, list, read))) => {
+                Ok(Async::Ready((reader, PartialResult::Complete(list), read)))
+            }
+            Ok(Async::Pending) => Ok(Async::Pending),
+            Err((reader, DeserializeError::DataError(error))) => {
+                let partial = PartialResult::Partial {
+                    parsed: mem::take(&mut self.0.list),
+                    error,
+                    offset: self.0.read,
+                };
+                Ok(Async::Ready((reader, partial, self.0.already_read())))
+            }
+            Err((reader, DeserializeError::ReaderError(err))) => {
+                Err((reader, DeserializeError::ReaderError(err)))
+            }
+        }
+    }
+}
@@ -0,0 +1,319 @@
+//! Records of append-only logs that survive a crash in the middle of an append.
+//!
+//! A record consists of its body followed by a footer: the length of the body and the CRC-32
+//! (IEEE) of the body, both as big-endian `u32`s. The footer is written last and the record is
+//! flushed before `WriteLoggedRecord` completes, so a record whose footer is missing or does not
+//! match its body was never completely appended.
+//!
+//! When recovering a log, `ReadLoggedRecord` yields `None` if the log ends cleanly before the next
+//! record. Any data error means that the remainder of the log starting at this record is a
+//! corrupt or partial tail, which the caller should truncate.
+
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+
+use futures_core::{Async, Future, Poll};
+use futures_core::task::Context;
+use futures_io::{AsyncRead, AsyncWrite, Error as FutIoErr, ErrorKind};
+
+use {AsyncDeserialize, AsyncSerialize, AsyncSerializeLen, AsyncWriterFuture,
+     AsyncWriterFutureLen, DeserializeError, Restorable};
+use envelope::{CrcReader, CrcWriter};
+use offset_reader::OffsetReader;
+use util::{prefix_fits, ReadExact, WriteAll};
+
+const FOOTER_LEN: usize = 4 + 4;
+
+/// Serializes a value via `F` as a log record, then flushes the writer.
+///
+/// Bodies longer than `u32::MAX` bytes can not be described by the footer, the future then fails
+/// with an `ErrorKind::InvalidInput` error after writing the body but not the footer.
+pub struct WriteLoggedRecord<F, W>
+    where F: AsyncSerialize<CrcWriter<W>>,
+          W: AsyncWrite
+{
+    state: WriteState<F, W>,
+    written: usize,
+}
+
+enum WriteState<F, W> {
+    Body(F),
+    Footer(WriteAll<W, [u8; FOOTER_LEN]>),
+    Flush(Option<W>),
+}
+
+impl<F, W> Future for WriteLoggedRecord<F, W>
+    where F: AsyncSerialize<CrcWriter<W>>,
+          W: AsyncWrite
+{
+    type Item = (W, usize);
+    type Error = (W, FutIoErr);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let next = match self.state {
+                WriteState::Body(ref mut body) => {
+                    let (writer, written) = match body.poll(cx) {
+                        Ok(Async::Ready(done)) => done,
+                        Ok(Async::Pending) => return Ok(Async::Pending),
+                        Err((writer, err)) => return Err((writer.into_inner(), err)),
+                    };
+                    self.written = written;
+
+                    let crc = writer.crc();
+                    let writer = writer.into_inner();
+                    if !prefix_fits(written) {
+                        let err = FutIoErr::new(ErrorKind::InvalidInput, "record body is too long");
+                        return Err((writer, err));
+                    }
+
+                    let mut footer = [0; FOOTER_LEN];
+                    footer[..4].copy_from_slice(&(written as u32).to_be_bytes());
+                    footer[4..].copy_from_slice(&crc.to_be_bytes());
+                    WriteState::Footer(WriteAll::new(writer, footer))
+                }
+
+                WriteState::Footer(ref mut footer) => {
+                    let (writer, written) = try_ready!(footer.poll(cx));
+                    self.written += written;
+                    WriteState::Flush(Some(writer))
+                }
+
+                WriteState::Flush(ref mut writer) => {
+                    let mut w = or_pending!(writer.take());
+                    return match w.poll_flush(cx) {
+                        Ok(Async::Ready(())) => Ok(Async::Ready((w, self.written))),
+                        Ok(Async::Pending) => {
+                            *writer = Some(w);
+                            Ok(Async::Pending)
+                        }
+                        Err(err) => Err((w, err)),
+                    };
+                }
+            };
+            self.state = next;
+        }
+    }
+}
+
+impl<F, W> AsyncWriterFuture<W> for WriteLoggedRecord<F, W>
+    where F: AsyncSerialize<CrcWriter<W>>,
+          W: AsyncWrite
+{
+    fn already_written(&self) -> usize {
+        match self.state {
+            WriteState::Body(ref body) => body.already_written(),
+            WriteState::Footer(ref footer) => self.written + footer.already_written(),
+            WriteState::Flush(_) => self.written,
+        }
+    }
+}
+
+impl<F, W> AsyncWriterFutureLen<W> for WriteLoggedRecord<F, W>
+    where F: AsyncSerializeLen<CrcWriter<W>>,
+          W: AsyncWrite
+{
+    fn remaining_bytes(&self) -> usize {
+        match self.state {
+            WriteState::Body(ref body) => body.remaining_bytes() + FOOTER_LEN,
+            WriteState::Footer(ref footer) => footer.remaining_bytes(),
+            WriteState::Flush(_) => 0,
+        }
+    }
+}
+
+impl<F, W> AsyncSerialize<W> for WriteLoggedRecord<F, W>
+    where F: AsyncSerialize<CrcWriter<W>>,
+          W: AsyncWrite
+{
+    type Serialized = F::Serialized;
+
+    fn from_val(writer: W, val: F::Serialized) -> Self {
+        WriteLoggedRecord {
+            state: WriteState::Body(F::from_val(CrcWriter::new(writer), val)),
+            written: 0,
+        }
+    }
+}
+
+impl<F, W> AsyncSerializeLen<W> for WriteLoggedRecord<F, W>
+    where F: AsyncSerializeLen<CrcWriter<W>>,
+          W: AsyncWrite
+{
+    fn total_bytes(val: &F::Serialized) -> usize {
+        F::total_bytes(val) + FOOTER_LEN
+    }
+}
+
+/// Deserializes the body of a log record via `D`, yielding `None` if the reader is at the end of
+/// the log instead.
+///
+/// If the reader ends in the middle of a record, this fails with `LogRecordError::Truncated`
+/// rather than an `ErrorKind::UnexpectedEof` reader error.
+pub struct ReadLoggedRecord<D, R, T> {
+    state: ReadState<D, R, T>,
+}
+
+enum ReadState<D, R, T> {
+    Body(D),
+    Footer(ReadExact<R, [u8; FOOTER_LEN]>, u64, u32, Option<T>),
+}
+
+fn is_eof(err: &FutIoErr) -> bool {
+    err.kind() == ErrorKind::UnexpectedEof
+}
+
+impl<D, R, T, E> Future for ReadLoggedRecord<D, R, T>
+    where D: AsyncDeserialize<CrcReader<OffsetReader<R>>, T, E>,
+          D: Future<Item = (CrcReader<OffsetReader<R>>, T, usize),
+                    Error = (CrcReader<OffsetReader<R>>, DeserializeError<E>)>,
+          R: AsyncRead
+{
+    type Item = (R, Option<T>, usize);
+    type Error = (R, DeserializeError<LogRecordError<E>>);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let next = match self.state {
+                ReadState::Body(ref mut body) => {
+                    let (body, val, _) = match body.poll(cx) {
+                        Ok(Async::Ready(done)) => done,
+                        Ok(Async::Pending) => return Ok(Async::Pending),
+                        Err((body, DeserializeError::ReaderError(err))) => {
+                            let (reader, read) = body.into_inner().into_inner();
+                            return if !is_eof(&err) {
+                                Err((reader, DeserializeError::ReaderError(err)))
+                            } else if read == 0 {
+                                Ok(Async::Ready((reader, None, 0)))
+                            } else {
+                                let err = LogRecordError::Truncated { read };
+                                Err((reader, DeserializeError::DataError(err)))
+                            };
+                        }
+                        Err((body, DeserializeError::DataError(err))) => {
+                            let (reader, _) = body.into_inner().into_inner();
+                            return Err((reader,
+                                        DeserializeError::DataError(LogRecordError::Inner(err))));
+                        }
+                    };
+
+                    let crc = body.crc();
+                    let (reader, read) = body.into_inner().into_inner();
+                    ReadState::Footer(ReadExact::new(reader, [0; FOOTER_LEN]), read, crc, Some(val))
+                }
+
+                ReadState::Footer(ref mut footer, body_len, actual, ref mut val) => {
+                    let (reader, footer, _) = match footer.poll(cx) {
+                        Ok(Async::Ready(done)) => done,
+                        Ok(Async::Pending) => return Ok(Async::Pending),
+                        Err((reader, err)) => {
+                            if !is_eof(&err) {
+                                return Err((reader, DeserializeError::ReaderError(err)));
+                            }
+                            let read = body_len + footer.already_read() as u64;
+                            let err = LogRecordError::Truncated { read };
+                            return Err((reader, DeserializeError::DataError(err)));
+                        }
+                    };
+
+                    let declared = u32::from_be_bytes([footer[0], footer[1], footer[2], footer[3]]);
+                    if u64::from(declared) != body_len {
+                        let err = LogRecordError::LengthMismatch {
+                            declared,
+                            consumed: body_len,
+                        };
+                        return Err((reader, DeserializeError::DataError(err)));
+                    }
+
+                    let expected = u32::from_be_bytes([footer[4], footer[5], footer[6], footer[7]]);
+                    if expected != actual {
+                        let err = LogRecordError::CrcMismatch { expected, actual };
+                        return Err((reader, DeserializeError::DataError(err)));
+                    }
+
+                    let val = or_pending!(val.take());
+                    let read = body_len as usize + FOOTER_LEN;
+                    return Ok(Async::Ready((reader, Some(val), read)));
+                }
+            };
+            self.state = next;
+        }
+    }
+}
+
+impl<D, R, T, E> AsyncDeserialize<R, Option<T>, LogRecordError<E>> for ReadLoggedRecord<D, R, T>
+    where D: AsyncDeserialize<CrcReader<OffsetReader<R>>, T, E>,
+          R: AsyncRead
+{
+    fn from_reader(reader: R) -> Self {
+        let body = CrcReader::new(OffsetReader::new(reader));
+        ReadLoggedRecord { state: ReadState::Body(D::from_reader(body)) }
+    }
+
+    fn already_read(&self) -> usize {
+        match self.state {
+            ReadState::Body(ref body) => body.already_read(),
+            ReadState::Footer(ref footer, body_len, _, _) => {
+                body_len as usize + footer.already_read()
+            }
+        }
+    }
+}
+
+impl<D, R, T> Restorable<R> for ReadLoggedRecord<D, R, T>
+    where D: Restorable<CrcReader<OffsetReader<R>>>
+{
+    fn restore(self) -> Option<R> {
+        match self.state {
+            ReadState::Body(body) => body.restore().map(|body| body.into_inner().into_inner().0),
+            ReadState::Footer(footer, ..) => footer.restore(),
+        }
+    }
+}
+
+/// Everything that can go wrong when reading a log record, apart from reader errors.
+///
+/// Each of these means that the log has a corrupt or partial tail starting at the record.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogRecordError<E> {
+    /// The log ends in the middle of the record.
+    Truncated {
+        /// How many bytes of the record were read.
+        read: u64,
+    },
+    /// The body could not be deserialized.
+    Inner(E),
+    /// The length in the footer differs from the number of bytes the body deserializer consumed.
+    LengthMismatch {
+        /// The length of the body according to the footer.
+        declared: u32,
+        /// How many bytes the body deserializer consumed.
+        consumed: u64,
+    },
+    /// The checksum of the body does not match.
+    CrcMismatch {
+        /// The checksum stored in the footer.
+        expected: u32,
+        /// The checksum of the body that was read.
+        actual: u32,
+    },
+}
+
+impl<E: Display> Display for LogRecordError<E> {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            LogRecordError::Truncated { read } => {
+                write!(f, "Log ends in the middle of a record after {} bytes", read)
+            }
+            LogRecordError::Inner(ref err) => write!(f, "Invalid record body: {}", err),
+            LogRecordError::LengthMismatch { declared, consumed } => {
+                write!(f, "Record has {} bytes but {} were consumed", declared, consumed)
+            }
+            LogRecordError::CrcMismatch { expected, actual } => {
+                write!(f, "Record checksum {:#010x} does not match {:#010x}", actual, expected)
+            }
+        }
+    }
+}
+
+impl<E: Error> Error for LogRecordError<E> {}
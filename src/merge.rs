@@ -0,0 +1,206 @@
+//! Merge two sorted sequences while deserializing them, without collecting either of them.
+//!
+//! Both sequences are encoded as the number of elements as a big-endian `u32`, followed by the
+//! elements in order, the same encoding as used by the `linked_list` module. This is the building
+//! block of an external merge sort: sorted runs written to separate files can be merged into one
+//! sorted stream while holding only one element per run in memory.
+
+use std::cmp::Ordering;
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+
+use futures_core::{Async, Future, Poll, Stream};
+use futures_core::task::Context;
+use futures_io::AsyncRead;
+
+use {AsyncDeserialize, DeserializeError};
+use util::ReadExact;
+
+// One of the two sequences, holding at most one element that has been read but not yielded.
+struct Side<D, R, T> {
+    state: SideState<D, R>,
+    remaining: u32,
+    head: Option<T>,
+}
+
+enum SideState<D, R> {
+    Count(ReadExact<R, [u8; 4]>),
+    Element(D),
+    Idle(Option<R>),
+    // Reading failed, so the side must not be read any further.
+    Done(Option<R>),
+}
+
+impl<D, R, T, E> Side<D, R, T>
+    where D: AsyncDeserialize<R, T, E>,
+          D: Future<Item = (R, T, usize), Error = (R, DeserializeError<E>)>,
+          R: AsyncRead
+{
+    fn new(reader: R) -> Side<D, R, T> {
+        Side {
+            state: SideState::Count(ReadExact::new(reader, [0; 4])),
+            remaining: 0,
+            head: None,
+        }
+    }
+
+    // Resolves once `head` holds the next element, or the sequence has been read completely.
+    fn poll_head(&mut self, cx: &mut Context) -> Poll<(), DeserializeError<E>> {
+        while self.head.is_none() {
+            let next = match self.state {
+                SideState::Count(ref mut inner) => {
+                    match inner.poll(cx) {
+                        Ok(Async::Ready((reader, count, _))) => {
+                            self.remaining = u32::from_be_bytes(count);
+                            SideState::Idle(Some(reader))
+                        }
+                        Ok(Async::Pending) => return Ok(Async::Pending),
+                        Err((reader, err)) => {
+                            self.state = SideState::Done(Some(reader));
+                            return Err(DeserializeError::ReaderError(err));
+                        }
+                    }
+                }
+
+                SideState::Element(ref mut inner) => {
+                    match inner.poll(cx) {
+                        Ok(Async::Ready((reader, element, _))) => {
+                            self.head = Some(element);
+                            self.remaining -= 1;
+                            SideState::Idle(Some(reader))
+                        }
+                        Ok(Async::Pending) => return Ok(Async::Pending),
+                        Err((reader, err)) => {
+                            self.state = SideState::Done(Some(reader));
+                            return Err(err);
+                        }
+                    }
+                }
+
+                SideState::Idle(ref mut reader) => {
+                    if self.remaining == 0 {
+                        break;
+                    }
+                    SideState::Element(D::from_reader(or_pending!(reader.take())))
+                }
+
+                SideState::Done(_) => return Ok(Async::Pending),
+            };
+            self.state = next;
+        }
+
+        Ok(Async::Ready(()))
+    }
+
+    fn is_done(&self) -> bool {
+        matches!(self.state, SideState::Done(_))
+    }
+
+    fn into_reader(self) -> Option<R> {
+        match self.state {
+            SideState::Idle(reader) | SideState::Done(reader) => reader,
+            _ => None,
+        }
+    }
+}
+
+/// A stream of the elements of two sorted sequences, deserialized via `DA` from `RA` and via `DB`
+/// from `RB`, in the order given by a comparator.
+///
+/// Of two equal elements, the one of the first sequence is yielded first. Neither sequence is
+/// checked for actually being sorted, unsorted input simply leads to unsorted output. Once the
+/// stream has yielded an error, polling it again returns `Async::Pending`.
+pub struct MergeReader<DA, DB, RA, RB, T, F> {
+    a: Side<DA, RA, T>,
+    b: Side<DB, RB, T>,
+    compare: F,
+}
+
+impl<DA, DB, RA, RB, T, F, EA, EB> MergeReader<DA, DB, RA, RB, T, F>
+    where DA: AsyncDeserialize<RA, T, EA>,
+          DA: Future<Item = (RA, T, usize), Error = (RA, DeserializeError<EA>)>,
+          DB: AsyncDeserialize<RB, T, EB>,
+          DB: Future<Item = (RB, T, usize), Error = (RB, DeserializeError<EB>)>,
+          RA: AsyncRead,
+          RB: AsyncRead
+{
+    /// Create a new `MergeReader`, merging the sequences read from `a` and `b` in the order given
+    /// by `compare`.
+    pub fn new(a: RA, b: RB, compare: F) -> MergeReader<DA, DB, RA, RB, T, F> {
+        MergeReader {
+            a: Side::new(a),
+            b: Side::new(b),
+            compare,
+        }
+    }
+
+    /// Consume this `MergeReader`, returning the two wrapped readers.
+    ///
+    /// Returns `None` if either reader is in the middle of reading an element.
+    pub fn into_inner(self) -> Option<(RA, RB)> {
+        match (self.a.into_reader(), self.b.into_reader()) {
+            (Some(a), Some(b)) => Some((a, b)),
+            _ => None,
+        }
+    }
+}
+
+impl<DA, DB, RA, RB, T, F, EA, EB> Stream for MergeReader<DA, DB, RA, RB, T, F>
+    where DA: AsyncDeserialize<RA, T, EA>,
+          DA: Future<Item = (RA, T, usize), Error = (RA, DeserializeError<EA>)>,
+          DB: AsyncDeserialize<RB, T, EB>,
+          DB: Future<Item = (RB, T, usize), Error = (RB, DeserializeError<EB>)>,
+          RA: AsyncRead,
+          RB: AsyncRead,
+          F: FnMut(&T, &T) -> Ordering
+{
+    type Item = T;
+    type Error = MergeError<EA, EB>;
+
+    fn poll_next(&mut self, cx: &mut Context) -> Poll<Option<T>, MergeError<EA, EB>> {
+        if self.a.is_done() || self.b.is_done() {
+            return Ok(Async::Pending);
+        }
+
+        // Poll both sides before giving up, so that both make progress concurrently.
+        let a = self.a.poll_head(cx).map_err(MergeError::A)?;
+        let b = self.b.poll_head(cx).map_err(MergeError::B)?;
+        if a.is_pending() || b.is_pending() {
+            return Ok(Async::Pending);
+        }
+
+        let take_a = match (self.a.head.as_ref(), self.b.head.as_ref()) {
+            (None, None) => return Ok(Async::Ready(None)),
+            (Some(_), None) => true,
+            (None, Some(_)) => false,
+            (Some(a), Some(b)) => (self.compare)(a, b) != Ordering::Greater,
+        };
+
+        if take_a {
+            Ok(Async::Ready(self.a.head.take()))
+        } else {
+            Ok(Async::Ready(self.b.head.take()))
+        }
+    }
+}
+
+/// Everything that can go wrong when merging two sequences, tagged with the sequence in which it
+/// happened.
+#[derive(Debug)]
+pub enum MergeError<EA, EB> {
+    /// Reading the first sequence failed.
+    A(DeserializeError<EA>),
+    /// Reading the second sequence failed.
+    B(DeserializeError<EB>),
+}
+
+impl<EA: Display, EB: Display> Display for MergeError<EA, EB> {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            MergeError::A(ref err) => write!(f, "First sequence: {}", err),
+            MergeError::B(ref err) => write!(f, "Second sequence: {}", err),
+        }
+    }
+}
+
+impl<EA: Error, EB: Error> Error for MergeError<EA, EB> {}
@@ -0,0 +1,193 @@
+//! Serialize a composite of several parts as a single length-prefixed message.
+//!
+//! A message is encoded as the total length of its parts as a [varint](../varint/index.html),
+//! followed by the parts in order. This is the same encoding as a [frame](../framed/index.html),
+//! so messages can be read with a `ReadFramed`. Messages created via `Message::with_width` encode
+//! the length with a different `LengthWidth` instead, and can be read via `ReadFramed::with_width`.
+
+use std::marker::PhantomData;
+
+use futures_core::{Async, Future, Poll};
+use futures_core::task::Context;
+use futures_io::{AsyncWrite, Error as FutIoErr};
+
+use {AsyncSerialize, AsyncSerializeLen, AsyncWriterFuture, AsyncWriterFutureLen, ValidationError};
+use chain::Chain;
+use framed::{LengthWidth, WriteLength};
+use varint::varint_len;
+
+/// Serializes the parts of a message via `P`, prefixed by their total length.
+///
+/// `P` is usually a left-nested `Chain` starting with `NoParts`, which is exactly what a
+/// `MessageBuilder` produces, but any `AsyncSerializeLen` works.
+pub struct Message<P, W>(Chain<WriteLength<W>, P, W>)
+    where P: AsyncSerialize<W>,
+          W: AsyncWrite;
+
+impl<W: AsyncWrite> Message<NoParts<W>, W> {
+    /// Create a `MessageBuilder` without any parts.
+    pub fn builder() -> MessageBuilder<NoParts<W>, W> {
+        MessageBuilder {
+            val: (),
+            _writer: PhantomData,
+        }
+    }
+}
+
+impl<P, W> Message<P, W>
+    where P: AsyncSerializeLen<W>,
+          W: AsyncWrite
+{
+    /// Create a new `Message` whose length is encoded with `width` rather than as a varint.
+    ///
+    /// If the length of the parts exceeds the `max_len` of the width, this fails with an
+    /// `ErrorKind::InvalidInput` error without writing anything. `total_bytes` assumes a varint
+    /// length, the prefix of such a message takes `width.prefix_len(len)` bytes instead.
+    pub fn with_width(writer: W, width: LengthWidth, val: P::Serialized) -> Message<P, W> {
+        let len = P::total_bytes(&val) as u64;
+        Message(Chain::from_val(writer, ((width, len), val)))
+    }
+
+    /// Check whether the length of the parts of `val` can be encoded with `width`.
+    ///
+    /// This does not validate the parts themselves. Messages created via `from_val` encode their
+    /// length as a varint, which fits every length.
+    pub fn validate_with_width(width: LengthWidth,
+                               val: &P::Serialized)
+                               -> Result<(), ValidationError> {
+        WriteLength::<W>::validate(&(width, P::total_bytes(val) as u64))
+    }
+}
+
+impl<P, W> Future for Message<P, W>
+    where P: AsyncSerialize<W>,
+          W: AsyncWrite
+{
+    type Item = (W, usize);
+    type Error = (W, FutIoErr);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        self.0.poll(cx)
+    }
+}
+
+impl<P, W> AsyncWriterFuture<W> for Message<P, W>
+    where P: AsyncSerialize<W>,
+          W: AsyncWrite
+{
+    fn already_written(&self) -> usize {
+        self.0.already_written()
+    }
+}
+
+impl<P, W> AsyncWriterFutureLen<W> for Message<P, W>
+    where P: AsyncSerializeLen<W>,
+          W: AsyncWrite
+{
+    fn remaining_bytes(&self) -> usize {
+        self.0.remaining_bytes()
+    }
+}
+
+impl<P, W> AsyncSerialize<W> for Message<P, W>
+    where P: AsyncSerializeLen<W>,
+          W: AsyncWrite
+{
+    type Serialized = P::Serialized;
+
+    fn from_val(writer: W, val: Self::Serialized) -> Self {
+        Message::with_width(writer, LengthWidth::Varint, val)
+    }
+}
+
+impl<P, W> AsyncSerializeLen<W> for Message<P, W>
+    where P: AsyncSerializeLen<W>,
+          W: AsyncWrite
+{
+    fn total_bytes(val: &Self::Serialized) -> usize {
+        let len = P::total_bytes(val);
+        varint_len(len as u64) + len
+    }
+}
+
+/// Collects the parts of a message, remembering the serializer of each part in its type.
+///
+/// Create one via `Message::builder`, add parts with `part`, and turn it into a `Message` with
+/// `build`.
+pub struct MessageBuilder<P, W>
+    where P: AsyncSerialize<W>,
+          W: AsyncWrite
+{
+    val: P::Serialized,
+    _writer: PhantomData<W>,
+}
+
+impl<P, W> MessageBuilder<P, W>
+    where P: AsyncSerializeLen<W>,
+          W: AsyncWrite
+{
+    /// Append a part that is serialized via `S`.
+    pub fn part<S>(self, val: S::Serialized) -> MessageBuilder<Chain<P, S, W>, W>
+        where S: AsyncSerializeLen<W>
+    {
+        MessageBuilder {
+            val: (self.val, val),
+            _writer: PhantomData,
+        }
+    }
+
+    /// Return the number of bytes the message will take up, including its length prefix.
+    pub fn total_bytes(&self) -> usize {
+        Message::<P, W>::total_bytes(&self.val)
+    }
+
+    /// Create a `Message` that writes the collected parts into `writer`.
+    pub fn build(self, writer: W) -> Message<P, W> {
+        Message::from_val(writer, self.val)
+    }
+
+    /// Consume the builder, returning the values of the parts.
+    pub fn into_val(self) -> P::Serialized {
+        self.val
+    }
+}
+
+/// Serializes the empty tuple by writing nothing. This is the first part of messages created by a
+/// `MessageBuilder`.
+pub struct NoParts<W>(Option<W>);
+
+impl<W: AsyncWrite> Future for NoParts<W> {
+    type Item = (W, usize);
+    type Error = (W, FutIoErr);
+
+    fn poll(&mut self, _: &mut Context) -> Poll<Self::Item, Self::Error> {
+        let writer = or_pending!(self.0.take());
+        Ok(Async::Ready((writer, 0)))
+    }
+}
+
+impl<W: AsyncWrite> AsyncWriterFuture<W> for NoParts<W> {
+    fn already_written(&self) -> usize {
+        0
+    }
+}
+
+impl<W: AsyncWrite> AsyncWriterFutureLen<W> for NoParts<W> {
+    fn remaining_bytes(&self) -> usize {
+        0
+    }
+}
+
+impl<W: AsyncWrite> AsyncSerialize<W> for NoParts<W> {
+    type Serialized = ();
+
+    fn from_val(writer: W, _: ()) -> Self {
+        NoParts(Some(writer))
+    }
+}
+
+impl<W: AsyncWrite> AsyncSerializeLen<W> for NoParts<W> {
+    fn total_bytes(_: &()) -> usize {
+        0
+    }
+}
@@ -0,0 +1,236 @@
+//! Coalesce small writes into writes of some minimum size.
+
+use std::cmp::min;
+use std::collections::VecDeque;
+
+use futures_core::{Async, Poll};
+use futures_core::task::Context;
+use futures_io::{AsyncWrite, Error as FutIoErr, ErrorKind};
+
+/// Wraps an `AsyncWrite` and buffers written data until at least `min` bytes are pending, before
+/// forwarding them to the wrapped writer in a single write.
+///
+/// Unlike a regular buffered writer, this guarantees a *minimum* size for the writes that reach
+/// the wrapped writer, which helps with backends for which tiny writes are expensive. The only
+/// exception are flushing and closing: `poll_flush` and `poll_close` first forward all pending
+/// bytes, however few there are. Writes that are at least `min` bytes long are forwarded directly
+/// if nothing is pending.
+///
+/// The wrapped writer may still accept fewer bytes than were passed to it, in which case the
+/// remainder is forwarded by a later write.
+///
+/// A `MinWriteSize` created via `adaptive` adjusts `min` at runtime to the number of bytes the
+/// wrapped writer accepts per write, see `Adaptive`. One created via `new` never changes `min`.
+#[derive(Debug)]
+pub struct MinWriteSize<W> {
+    inner: W,
+    min: usize,
+    buf: Vec<u8>,
+    offset: usize,
+    tuning: Option<Tuning>,
+}
+
+/// Configuration for a `MinWriteSize` that adjusts its minimum write size to the wrapped writer.
+///
+/// A write for which the wrapped writer accepts fewer bytes than it was given measures how much
+/// the wrapped writer accepts at once. A write of at least the minimum write size that is accepted
+/// completely only tells that the wrapped writer might accept more.
+///
+/// The `MinWriteSize` remembers the last `window` writes of either kind. After every `interval`
+/// of them, it sets the minimum write size to the average of the remembered measurements, or
+/// doubles it if all remembered writes were accepted completely. The result is clamped to
+/// `min..=max`. A writer that accepts few bytes per write thus gets small writes, reducing
+/// latency, and a writer that accepts many gets large ones, reducing the number of writes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Adaptive {
+    /// The smallest minimum write size to use, at least one.
+    pub min: usize,
+    /// The largest minimum write size to use, at least `min`.
+    pub max: usize,
+    /// How many writes of the wrapped writer to remember, at least one.
+    pub window: usize,
+    /// After how many writes of the wrapped writer to adjust the minimum write size, at least one.
+    pub interval: usize,
+}
+
+#[derive(Debug)]
+struct Tuning {
+    config: Adaptive,
+    // The remembered writes, with `None` for writes that were accepted completely.
+    samples: VecDeque<Option<usize>>,
+    sum: usize,
+    measured: usize,
+    since_adjust: usize,
+}
+
+impl Tuning {
+    // Record a write of `offered` bytes of which the wrapped writer accepted `written`, returning
+    // the new minimum write size if it is time to adjust `current`.
+    fn record(&mut self, offered: usize, written: usize, current: usize) -> Option<usize> {
+        let sample = if written < offered {
+            Some(written)
+        } else if offered >= current {
+            None
+        } else {
+            return None;
+        };
+
+        if self.samples.len() == self.config.window {
+            if let Some(Some(old)) = self.samples.pop_front() {
+                self.sum -= old;
+                self.measured -= 1;
+            }
+        }
+        if let Some(written) = sample {
+            self.sum += written;
+            self.measured += 1;
+        }
+        self.samples.push_back(sample);
+
+        self.since_adjust += 1;
+        if self.since_adjust < self.config.interval {
+            return None;
+        }
+        self.since_adjust = 0;
+
+        let adjusted = self.sum
+            .checked_div(self.measured)
+            .unwrap_or_else(|| current.saturating_mul(2));
+        Some(adjusted.max(self.config.min).min(self.config.max))
+    }
+}
+
+impl<W> MinWriteSize<W> {
+    /// Create a new `MinWriteSize`, forwarding writes of at least `min` bytes to `inner`.
+    pub fn new(inner: W, min: usize) -> MinWriteSize<W> {
+        MinWriteSize {
+            inner,
+            min,
+            buf: Vec::with_capacity(min),
+            offset: 0,
+            tuning: None,
+        }
+    }
+
+    /// Create a new `MinWriteSize` that starts out forwarding writes of at least `initial` bytes
+    /// to `inner`, and then adjusts that size as configured by `config`.
+    ///
+    /// `initial` is clamped to `config.min..=config.max`. Panics if `config` violates any of the
+    /// bounds documented on `Adaptive`.
+    pub fn adaptive(inner: W, initial: usize, config: Adaptive) -> MinWriteSize<W> {
+        assert!(config.min > 0, "Adaptive needs a positive min");
+        assert!(config.max >= config.min, "Adaptive needs a max of at least min");
+        assert!(config.window > 0, "Adaptive needs a positive window");
+        assert!(config.interval > 0, "Adaptive needs a positive interval");
+
+        let min = initial.max(config.min).min(config.max);
+        MinWriteSize {
+            inner,
+            min,
+            buf: Vec::with_capacity(min),
+            offset: 0,
+            tuning: Some(Tuning {
+                config,
+                samples: VecDeque::with_capacity(config.window),
+                sum: 0,
+                measured: 0,
+                since_adjust: 0,
+            }),
+        }
+    }
+
+    /// Return the current minimum write size. This only ever changes for a `MinWriteSize`
+    /// created via `adaptive`.
+    pub fn threshold(&self) -> usize {
+        self.min
+    }
+
+    /// Return the number of bytes that have been written but not yet forwarded to the wrapped
+    /// writer.
+    pub fn pending(&self) -> usize {
+        self.buf.len() - self.offset
+    }
+
+    /// Get a reference to the wrapped writer.
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Get a mutable reference to the wrapped writer.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// Consume this `MinWriteSize`, returning the wrapped writer.
+    ///
+    /// Pending bytes are lost, so this should only be called after flushing.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+// Write to the wrapped writer, feeding the result to the tuning, if any.
+fn poll_forward<W: AsyncWrite>(inner: &mut W,
+                               tuning: &mut Option<Tuning>,
+                               min: &mut usize,
+                               cx: &mut Context,
+                               buf: &[u8])
+                               -> Poll<usize, FutIoErr> {
+    let written = try_ready!(inner.poll_write(cx, buf));
+    if written > 0 {
+        if let Some(tuning) = tuning.as_mut() {
+            if let Some(adjusted) = tuning.record(buf.len(), written, *min) {
+                *min = adjusted;
+            }
+        }
+    }
+    Ok(Async::Ready(written))
+}
+
+impl<W: AsyncWrite> MinWriteSize<W> {
+    fn poll_drain(&mut self, cx: &mut Context) -> Poll<(), FutIoErr> {
+        while self.offset < self.buf.len() {
+            let written = try_ready!(poll_forward(&mut self.inner,
+                                                  &mut self.tuning,
+                                                  &mut self.min,
+                                                  cx,
+                                                  &self.buf[self.offset..]));
+            if written == 0 {
+                return Err(FutIoErr::new(ErrorKind::WriteZero, "failed to write whole buffer"));
+            }
+            self.offset += written;
+        }
+        self.buf.clear();
+        self.offset = 0;
+        Ok(Async::Ready(()))
+    }
+}
+
+impl<W: AsyncWrite> AsyncWrite for MinWriteSize<W> {
+    fn poll_write(&mut self, cx: &mut Context, buf: &[u8]) -> Poll<usize, FutIoErr> {
+        if self.pending() == 0 && buf.len() >= self.min {
+            return poll_forward(&mut self.inner, &mut self.tuning, &mut self.min, cx, buf);
+        }
+
+        if self.pending() >= self.min {
+            try_ready!(self.poll_drain(cx));
+            if buf.len() >= self.min {
+                return poll_forward(&mut self.inner, &mut self.tuning, &mut self.min, cx, buf);
+            }
+        }
+
+        let accepted = min(buf.len(), self.min - self.pending());
+        self.buf.extend_from_slice(&buf[..accepted]);
+        Ok(Async::Ready(accepted))
+    }
+
+    fn poll_flush(&mut self, cx: &mut Context) -> Poll<(), FutIoErr> {
+        try_ready!(self.poll_drain(cx));
+        self.inner.poll_flush(cx)
+    }
+
+    fn poll_close(&mut self, cx: &mut Context) -> Poll<(), FutIoErr> {
+        try_ready!(self.poll_drain(cx));
+        self.inner.poll_close(cx)
+    }
+}
@@ -0,0 +1,80 @@
+//! Attach names to in-flight futures, to find out which part of a composed (de)serializer is
+//! stuck.
+
+use futures_core::{Future, Poll};
+use futures_core::task::Context;
+use futures_io::AsyncWrite;
+
+use {AsyncWriterFuture, AsyncWriterFutureLen};
+
+/// Wraps a future and annotates it with a name, e.g. the name of the field it (de)serializes.
+///
+/// The name is only stored if the `debug_names` feature is enabled. Otherwise, `Named` does not
+/// add anything to the wrapped future and `phase_name` always returns `None`.
+pub struct Named<F> {
+    inner: F,
+    #[cfg(feature = "debug_names")]
+    name: &'static str,
+}
+
+impl<F> Named<F> {
+    /// Create a new `Named`, annotating `inner` with `name`.
+    #[cfg(feature = "debug_names")]
+    pub fn new(name: &'static str, inner: F) -> Named<F> {
+        Named { inner, name }
+    }
+
+    /// Create a new `Named`, annotating `inner` with `name`.
+    #[cfg(not(feature = "debug_names"))]
+    pub fn new(_name: &'static str, inner: F) -> Named<F> {
+        Named { inner }
+    }
+
+    /// Return the name of the wrapped future, or `None` if the `debug_names` feature is disabled.
+    #[cfg(feature = "debug_names")]
+    pub fn phase_name(&self) -> Option<&'static str> {
+        Some(self.name)
+    }
+
+    /// Return the name of the wrapped future, or `None` if the `debug_names` feature is disabled.
+    #[cfg(not(feature = "debug_names"))]
+    pub fn phase_name(&self) -> Option<&'static str> {
+        None
+    }
+
+    /// Get a reference to the wrapped future.
+    pub fn get_ref(&self) -> &F {
+        &self.inner
+    }
+
+    /// Get a mutable reference to the wrapped future.
+    pub fn get_mut(&mut self) -> &mut F {
+        &mut self.inner
+    }
+
+    /// Consume this `Named`, returning the wrapped future.
+    pub fn into_inner(self) -> F {
+        self.inner
+    }
+}
+
+impl<F: Future> Future for Named<F> {
+    type Item = F::Item;
+    type Error = F::Error;
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        self.inner.poll(cx)
+    }
+}
+
+impl<W: AsyncWrite, F: AsyncWriterFuture<W>> AsyncWriterFuture<W> for Named<F> {
+    fn already_written(&self) -> usize {
+        self.inner.already_written()
+    }
+}
+
+impl<W: AsyncWrite, F: AsyncWriterFutureLen<W>> AsyncWriterFutureLen<W> for Named<F> {
+    fn remaining_bytes(&self) -> usize {
+        self.inner.remaining_bytes()
+    }
+}
@@ -0,0 +1,59 @@
+//! Track the absolute byte offset into a stream, e.g. to report where a malformed value starts.
+
+use futures_core::{Async, Poll};
+use futures_core::task::Context;
+use futures_io::{AsyncRead, Error as FutIoErr};
+
+/// Wraps an `AsyncRead` and counts the bytes read from it.
+///
+/// Wrap the reader once at the outermost deserialization call, the wrapped deserializers can then
+/// query the `offset` of the reader they are given, e.g. from the error returned by a failed
+/// deserializer.
+#[derive(Debug)]
+pub struct OffsetReader<R> {
+    inner: R,
+    offset: u64,
+}
+
+impl<R> OffsetReader<R> {
+    /// Create a new `OffsetReader`, counting from zero.
+    pub fn new(inner: R) -> OffsetReader<R> {
+        OffsetReader::starting_at(inner, 0)
+    }
+
+    /// Create a new `OffsetReader`, counting from `offset`, e.g. for a stream whose first bytes
+    /// have already been consumed elsewhere.
+    pub fn starting_at(inner: R, offset: u64) -> OffsetReader<R> {
+        OffsetReader { inner, offset }
+    }
+
+    /// Return the offset of the next byte to be read.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Get a reference to the wrapped reader.
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// Get a mutable reference to the wrapped reader.
+    ///
+    /// Reading from it directly does not advance the offset.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    /// Consume this `OffsetReader`, returning the wrapped reader and the final offset.
+    pub fn into_inner(self) -> (R, u64) {
+        (self.inner, self.offset)
+    }
+}
+
+impl<R: AsyncRead> AsyncRead for OffsetReader<R> {
+    fn poll_read(&mut self, cx: &mut Context, buf: &mut [u8]) -> Poll<usize, FutIoErr> {
+        let read = try_ready!(self.inner.poll_read(cx, buf));
+        self.offset += read as u64;
+        Ok(Async::Ready(read))
+    }
+}
@@ -0,0 +1,230 @@
+//! Serialization of `Option`s.
+//!
+//! An `Option` is encoded as a tag byte, `0` for `None` and `1` for `Some`, followed by the
+//! encoding of the contained value if there is one.
+
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::marker::PhantomData;
+
+use futures_core::{Async, Future, Poll};
+use futures_core::task::Context;
+use futures_io::{AsyncRead, AsyncWrite, Error as FutIoErr};
+
+use {AsyncDeserialize, AsyncSerializeRef, AsyncSerializeRefLen, AsyncWriterFuture,
+     AsyncWriterFutureLen, DeserializeError, Restorable};
+use util::{ReadExact, WriteAll};
+
+/// The tag of `None`.
+pub const NONE: u8 = 0;
+/// The tag of `Some`.
+pub const SOME: u8 = 1;
+
+/// Serializes an `Option` by reference, serializing the contained value via `S`.
+///
+/// The contained value is passed to `S::from_ref`, so it is never cloned.
+pub struct SerOptionRef<'val, S, W>
+    where S: AsyncSerializeRef<'val, W>,
+          S::Serialized: Sized + 'val,
+          W: AsyncWrite
+{
+    state: SerState<'val, S, W>,
+}
+
+enum SerState<'val, S, W>
+    where S: AsyncSerializeRef<'val, W>,
+          S::Serialized: Sized + 'val,
+          W: AsyncWrite
+{
+    Tag(WriteAll<W, [u8; 1]>, Option<&'val S::Serialized>),
+    Value(S),
+}
+
+impl<'val, S, W> Future for SerOptionRef<'val, S, W>
+    where S: AsyncSerializeRef<'val, W>,
+          S::Serialized: Sized + 'val,
+          W: AsyncWrite
+{
+    type Item = (W, usize);
+    type Error = (W, FutIoErr);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let value = match self.state {
+                SerState::Tag(ref mut tag, val) => {
+                    let (writer, written) = try_ready!(tag.poll(cx));
+                    match val {
+                        Some(val) => S::from_ref(writer, val),
+                        None => return Ok(Async::Ready((writer, written))),
+                    }
+                }
+                SerState::Value(ref mut value) => {
+                    let (writer, written) = try_ready!(value.poll(cx));
+                    return Ok(Async::Ready((writer, 1 + written)));
+                }
+            };
+            self.state = SerState::Value(value);
+        }
+    }
+}
+
+impl<'val, S, W> AsyncWriterFuture<W> for SerOptionRef<'val, S, W>
+    where S: AsyncSerializeRef<'val, W>,
+          S::Serialized: Sized + 'val,
+          W: AsyncWrite
+{
+    fn already_written(&self) -> usize {
+        match self.state {
+            SerState::Tag(ref tag, _) => tag.already_written(),
+            SerState::Value(ref value) => 1 + value.already_written(),
+        }
+    }
+}
+
+impl<'val, S, W> AsyncWriterFutureLen<W> for SerOptionRef<'val, S, W>
+    where S: AsyncSerializeRefLen<'val, W>,
+          S::Serialized: Sized + 'val,
+          W: AsyncWrite
+{
+    fn remaining_bytes(&self) -> usize {
+        match self.state {
+            SerState::Tag(ref tag, val) => {
+                tag.remaining_bytes() + val.map(S::total_bytes).unwrap_or(0)
+            }
+            SerState::Value(ref value) => value.remaining_bytes(),
+        }
+    }
+}
+
+impl<'val, S, W> AsyncSerializeRef<'val, W> for SerOptionRef<'val, S, W>
+    where S: AsyncSerializeRef<'val, W>,
+          S::Serialized: Sized + 'val,
+          W: AsyncWrite
+{
+    type Serialized = Option<S::Serialized>;
+
+    fn from_ref(writer: W, val: &'val Option<S::Serialized>) -> Self {
+        let tag = if val.is_some() { SOME } else { NONE };
+        SerOptionRef { state: SerState::Tag(WriteAll::new(writer, [tag]), val.as_ref()) }
+    }
+}
+
+impl<'val, S, W> AsyncSerializeRefLen<'val, W> for SerOptionRef<'val, S, W>
+    where S: AsyncSerializeRefLen<'val, W>,
+          S::Serialized: Sized + 'val,
+          W: AsyncWrite
+{
+    fn total_bytes(val: &Option<S::Serialized>) -> usize {
+        1 + val.as_ref().map(S::total_bytes).unwrap_or(0)
+    }
+}
+
+/// Deserializes an `Option`, deserializing the contained value via `D`.
+pub struct DeserOption<D, R, T, E> {
+    state: DeserState<D, R>,
+    _types: PhantomData<(T, E)>,
+}
+
+enum DeserState<D, R> {
+    Tag(ReadExact<R, [u8; 1]>),
+    Value(D),
+}
+
+impl<D, R, T, E> Future for DeserOption<D, R, T, E>
+    where D: AsyncDeserialize<R, T, E>,
+          R: AsyncRead
+{
+    type Item = (R, Option<T>, usize);
+    type Error = (R, DeserializeError<OptionError<E>>);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let value = match self.state {
+                DeserState::Tag(ref mut tag) => {
+                    let (reader, tag, read) = match tag.poll(cx) {
+                        Ok(Async::Ready(done)) => done,
+                        Ok(Async::Pending) => return Ok(Async::Pending),
+                        Err((reader, err)) => {
+                            return Err((reader, DeserializeError::ReaderError(err)))
+                        }
+                    };
+
+                    match tag[0] {
+                        NONE => return Ok(Async::Ready((reader, None, read))),
+                        SOME => D::from_reader(reader),
+                        tag => {
+                            let err = OptionError::InvalidTag(tag);
+                            return Err((reader, DeserializeError::DataError(err)));
+                        }
+                    }
+                }
+
+                DeserState::Value(ref mut value) => {
+                    return match value.poll(cx) {
+                        Ok(Async::Ready((reader, val, read))) => {
+                            Ok(Async::Ready((reader, Some(val), 1 + read)))
+                        }
+                        Ok(Async::Pending) => Ok(Async::Pending),
+                        Err((reader, DeserializeError::ReaderError(err))) => {
+                            Err((reader, DeserializeError::ReaderError(err)))
+                        }
+                        Err((reader, DeserializeError::DataError(err))) => {
+                            Err((reader, DeserializeError::DataError(OptionError::Inner(err))))
+                        }
+                    };
+                }
+            };
+            self.state = DeserState::Value(value);
+        }
+    }
+}
+
+impl<D, R, T, E> AsyncDeserialize<R, Option<T>, OptionError<E>> for DeserOption<D, R, T, E>
+    where D: AsyncDeserialize<R, T, E>,
+          R: AsyncRead
+{
+    fn from_reader(reader: R) -> Self {
+        DeserOption {
+            state: DeserState::Tag(ReadExact::new(reader, [0])),
+            _types: PhantomData,
+        }
+    }
+
+    fn already_read(&self) -> usize {
+        match self.state {
+            DeserState::Tag(ref tag) => tag.already_read(),
+            DeserState::Value(ref value) => 1 + value.already_read(),
+        }
+    }
+}
+
+impl<D, R, T, E> Restorable<R> for DeserOption<D, R, T, E>
+    where D: Restorable<R>
+{
+    fn restore(self) -> Option<R> {
+        match self.state {
+            DeserState::Tag(tag) => tag.restore(),
+            DeserState::Value(value) => value.restore(),
+        }
+    }
+}
+
+/// Everything that can go wrong when deserializing an `Option`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OptionError<E> {
+    /// The tag is neither `NONE` nor `SOME`.
+    InvalidTag(u8),
+    /// The contained value could not be deserialized.
+    Inner(E),
+}
+
+impl<E: Display> Display for OptionError<E> {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            OptionError::InvalidTag(tag) => write!(f, "Invalid option tag {}", tag),
+            OptionError::Inner(ref err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl<E: Error> Error for OptionError<E> {}
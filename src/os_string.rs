@@ -0,0 +1,287 @@
+//! Serialization of `OsString`s in their native representation.
+//!
+//! An `OsString` is encoded as a tag byte naming the platform whose representation follows, then
+//! the length of the representation in bytes as a big-endian `u32`, then the representation
+//! itself. On Unix, that is the raw bytes of the string (tag `UNIX`), on Windows, its UTF-16 code
+//! units as big-endian `u16`s (tag `WINDOWS`). On other platforms, strings are encoded like on
+//! Unix, and only strings that are valid unicode can be serialized.
+//!
+//! Deserializing a string of another platform fails with `OsStringError::ForeignPlatform` before
+//! its representation is read, rather than reinterpreting it. Strings whose representation is
+//! longer than `u32::MAX` bytes can not be serialized, the serializer fails with an
+//! `ErrorKind::InvalidInput` error without writing anything.
+
+use std::error::Error;
+use std::ffi::OsString;
+use std::fmt::{self, Display, Formatter};
+
+use futures_core::{Async, Future, Poll};
+use futures_core::task::Context;
+use futures_io::{AsyncRead, AsyncWrite, Error as FutIoErr, ErrorKind};
+
+use {AsyncDeserialize, AsyncSerialize, AsyncSerializeLen, AsyncWriterFuture,
+     AsyncWriterFutureLen, DeserializeError, Restorable};
+use util::{prefix_fits, ReadExact, ReadLenPrefixed, WriteAll};
+
+/// The tag of strings in the Unix representation.
+pub const UNIX: u8 = 0;
+/// The tag of strings in the Windows representation.
+pub const WINDOWS: u8 = 1;
+
+#[cfg(unix)]
+const NATIVE: u8 = UNIX;
+#[cfg(windows)]
+const NATIVE: u8 = WINDOWS;
+#[cfg(not(any(unix, windows)))]
+const NATIVE: u8 = UNIX;
+
+#[cfg(unix)]
+fn encode(val: OsString) -> Option<Vec<u8>> {
+    use std::os::unix::ffi::OsStringExt;
+    Some(val.into_vec())
+}
+
+#[cfg(windows)]
+fn encode(val: OsString) -> Option<Vec<u8>> {
+    use std::os::windows::ffi::OsStrExt;
+    Some(val.encode_wide().flat_map(u16::to_be_bytes).collect())
+}
+
+#[cfg(not(any(unix, windows)))]
+fn encode(val: OsString) -> Option<Vec<u8>> {
+    val.into_string().ok().map(String::into_bytes)
+}
+
+#[cfg(unix)]
+fn decode(bytes: Vec<u8>) -> Option<OsString> {
+    use std::os::unix::ffi::OsStringExt;
+    Some(OsString::from_vec(bytes))
+}
+
+#[cfg(windows)]
+fn decode(bytes: Vec<u8>) -> Option<OsString> {
+    use std::os::windows::ffi::OsStringExt;
+    if bytes.len() % 2 != 0 {
+        return None;
+    }
+    let wide: Vec<u16> = bytes
+        .chunks(2)
+        .map(|unit| u16::from_be_bytes([unit[0], unit[1]]))
+        .collect();
+    Some(OsString::from_wide(&wide))
+}
+
+#[cfg(not(any(unix, windows)))]
+fn decode(bytes: Vec<u8>) -> Option<OsString> {
+    String::from_utf8(bytes).ok().map(OsString::from)
+}
+
+#[cfg(unix)]
+fn encoded_len(val: &OsString) -> Option<usize> {
+    Some(val.len())
+}
+
+#[cfg(windows)]
+fn encoded_len(val: &OsString) -> Option<usize> {
+    use std::os::windows::ffi::OsStrExt;
+    Some(2 * val.encode_wide().count())
+}
+
+#[cfg(not(any(unix, windows)))]
+fn encoded_len(val: &OsString) -> Option<usize> {
+    val.to_str().map(str::len)
+}
+
+const INVALID: &str = "string is not representable or too long";
+
+/// Serializes an owned `OsString` in the representation of the current platform.
+///
+/// For strings that can not be serialized, `total_bytes` returns zero.
+pub struct SerOsString<W>(SerState<W>);
+
+// The tag and the length are written together, so that the header of an empty string takes a
+// single write.
+enum SerState<W> {
+    Header(WriteAll<W, [u8; 5]>, Option<Vec<u8>>),
+    Body(WriteAll<W, Vec<u8>>),
+    Invalid(Option<W>),
+}
+
+fn header(tag: u8, len: usize) -> [u8; 5] {
+    let mut header = [tag, 0, 0, 0, 0];
+    header[1..].copy_from_slice(&(len as u32).to_be_bytes());
+    header
+}
+
+impl<W: AsyncWrite> Future for SerOsString<W> {
+    type Item = (W, usize);
+    type Error = (W, FutIoErr);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let body = match self.0 {
+                SerState::Header(ref mut header, ref mut body) => {
+                    let (writer, _) = try_ready!(header.poll(cx));
+                    WriteAll::new(writer, or_pending!(body.take()))
+                }
+                SerState::Body(ref mut body) => {
+                    let (writer, written) = try_ready!(body.poll(cx));
+                    return Ok(Async::Ready((writer, 5 + written)));
+                }
+                SerState::Invalid(ref mut writer) => {
+                    let err = FutIoErr::new(ErrorKind::InvalidInput, INVALID);
+                    return Err((or_pending!(writer.take()), err));
+                }
+            };
+            self.0 = SerState::Body(body);
+        }
+    }
+}
+
+impl<W: AsyncWrite> AsyncWriterFuture<W> for SerOsString<W> {
+    fn already_written(&self) -> usize {
+        match self.0 {
+            SerState::Header(ref header, _) => header.already_written(),
+            SerState::Body(ref body) => 5 + body.already_written(),
+            SerState::Invalid(_) => 0,
+        }
+    }
+}
+
+impl<W: AsyncWrite> AsyncWriterFutureLen<W> for SerOsString<W> {
+    fn remaining_bytes(&self) -> usize {
+        match self.0 {
+            SerState::Header(ref header, ref body) => {
+                header.remaining_bytes() + body.as_ref().map(Vec::len).unwrap_or(0)
+            }
+            SerState::Body(ref body) => body.remaining_bytes(),
+            SerState::Invalid(_) => 0,
+        }
+    }
+}
+
+impl<W: AsyncWrite> AsyncSerialize<W> for SerOsString<W> {
+    type Serialized = OsString;
+
+    fn from_val(writer: W, val: OsString) -> Self {
+        match encode(val).filter(|body| prefix_fits(body.len())) {
+            Some(body) => {
+                let header = WriteAll::new(writer, header(NATIVE, body.len()));
+                SerOsString(SerState::Header(header, Some(body)))
+            }
+            None => SerOsString(SerState::Invalid(Some(writer))),
+        }
+    }
+}
+
+impl<W: AsyncWrite> AsyncSerializeLen<W> for SerOsString<W> {
+    fn total_bytes(val: &OsString) -> usize {
+        encoded_len(val)
+            .filter(|len| prefix_fits(*len))
+            .map(|len| 5 + len)
+            .unwrap_or(0)
+    }
+}
+
+/// Deserializes an `OsString` in the representation of the current platform.
+pub struct DeserOsString<R>(DeserState<R>);
+
+enum DeserState<R> {
+    Header(ReadExact<R, [u8; 5]>),
+    Body(ReadLenPrefixed<R>),
+}
+
+impl<R: AsyncRead> Future for DeserOsString<R> {
+    type Item = (R, OsString, usize);
+    type Error = (R, DeserializeError<OsStringError>);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let body = match self.0 {
+                DeserState::Header(ref mut header) => {
+                    let (reader, header, _) = match header.poll(cx) {
+                        Ok(Async::Ready(done)) => done,
+                        Ok(Async::Pending) => return Ok(Async::Pending),
+                        Err((reader, err)) => {
+                            return Err((reader, DeserializeError::ReaderError(err)))
+                        }
+                    };
+
+                    let err = match header[0] {
+                        tag if tag == NATIVE => {
+                            let prefix = [header[1], header[2], header[3], header[4]];
+                            self.0 = DeserState::Body(ReadLenPrefixed::with_prefix(reader, prefix));
+                            continue;
+                        }
+                        UNIX | WINDOWS => OsStringError::ForeignPlatform(header[0]),
+                        tag => OsStringError::UnknownPlatform(tag),
+                    };
+                    return Err((reader, DeserializeError::DataError(err)));
+                }
+
+                DeserState::Body(ref mut body) => {
+                    match body.poll(cx) {
+                        Ok(Async::Ready(done)) => done,
+                        Ok(Async::Pending) => return Ok(Async::Pending),
+                        Err((reader, err)) => {
+                            return Err((reader, DeserializeError::ReaderError(err)))
+                        }
+                    }
+                }
+            };
+
+            let (reader, bytes, read) = body;
+            return match decode(bytes) {
+                Some(val) => Ok(Async::Ready((reader, val, 1 + read))),
+                None => Err((reader, DeserializeError::DataError(OsStringError::Malformed))),
+            };
+        }
+    }
+}
+
+impl<R: AsyncRead> AsyncDeserialize<R, OsString, OsStringError> for DeserOsString<R> {
+    fn from_reader(reader: R) -> Self {
+        DeserOsString(DeserState::Header(ReadExact::new(reader, [0; 5])))
+    }
+
+    fn already_read(&self) -> usize {
+        match self.0 {
+            DeserState::Header(ref header) => header.already_read(),
+            DeserState::Body(ref body) => 1 + body.already_read(),
+        }
+    }
+}
+
+impl<R> Restorable<R> for DeserOsString<R> {
+    fn restore(self) -> Option<R> {
+        match self.0 {
+            DeserState::Header(header) => header.restore(),
+            DeserState::Body(body) => body.restore(),
+        }
+    }
+}
+
+/// Everything that can go wrong when deserializing an `OsString`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OsStringError {
+    /// The tag does not name any known platform.
+    UnknownPlatform(u8),
+    /// The string is in the representation of another platform.
+    ForeignPlatform(u8),
+    /// The representation is not valid for the current platform.
+    Malformed,
+}
+
+impl Display for OsStringError {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            OsStringError::UnknownPlatform(tag) => write!(f, "Unknown platform tag {}", tag),
+            OsStringError::ForeignPlatform(tag) => {
+                write!(f, "String of platform {} can not be represented here", tag)
+            }
+            OsStringError::Malformed => write!(f, "Invalid string representation"),
+        }
+    }
+}
+
+impl Error for OsStringError {}
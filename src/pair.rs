@@ -0,0 +1,187 @@
+//! Deserialization of key-value pairs.
+//!
+//! A pair is encoded as the key followed by the value, without any framing, so it can be a
+//! building block for maps and records.
+
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::marker::PhantomData;
+
+use futures_core::{Async, Future, Poll};
+use futures_core::task::Context;
+use futures_io::AsyncRead;
+
+use {AsyncDeserialize, DeserializeError, Restorable};
+use partial::PartialResult;
+
+/// Deserializes a `(K, V)` pair, first the key via `DK`, then the value via `DV`.
+///
+/// Use `from_reader_partial` to recover the key if the value is malformed.
+pub struct DeserPair<DK, DV, R, K, V, EK, EV> {
+    state: State<DK, DV, K>,
+    read: usize,
+    _types: PhantomData<(R, V, EK, EV)>,
+}
+
+enum State<DK, DV, K> {
+    Key(DK),
+    Value(DV, Option<K>),
+}
+
+impl<DK, DV, R, K, V, EK, EV> Future for DeserPair<DK, DV, R, K, V, EK, EV>
+    where DK: AsyncDeserialize<R, K, EK>,
+          DV: AsyncDeserialize<R, V, EV>,
+          R: AsyncRead
+{
+    type Item = (R, (K, V), usize);
+    type Error = (R, DeserializeError<PairError<EK, EV>>);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let next = match self.state {
+                State::Key(ref mut key) => {
+                    let (reader, key, read) = match key.poll(cx) {
+                        Ok(Async::Ready(done)) => done,
+                        Ok(Async::Pending) => return Ok(Async::Pending),
+                        Err((reader, DeserializeError::ReaderError(err))) => {
+                            return Err((reader, DeserializeError::ReaderError(err)))
+                        }
+                        Err((reader, DeserializeError::DataError(err))) => {
+                            return Err((reader, DeserializeError::DataError(PairError::Key(err))))
+                        }
+                    };
+                    self.read += read;
+                    State::Value(DV::from_reader(reader), Some(key))
+                }
+
+                State::Value(ref mut value, ref mut key) => {
+                    let (reader, value, read) = match value.poll(cx) {
+                        Ok(Async::Ready(done)) => done,
+                        Ok(Async::Pending) => return Ok(Async::Pending),
+                        Err((reader, DeserializeError::ReaderError(err))) => {
+                            return Err((reader, DeserializeError::ReaderError(err)))
+                        }
+                        Err((reader, DeserializeError::DataError(err))) => {
+                            return Err((reader,
+                                        DeserializeError::DataError(PairError::Value(err))))
+                        }
+                    };
+                    self.read += read;
+                    let key = or_pending!(key.take());
+                    return Ok(Async::Ready((reader, (key, value), self.read)));
+                }
+            };
+            self.state = next;
+        }
+    }
+}
+
+impl<DK, DV, R, K, V, EK, EV> AsyncDeserialize<R, (K, V), PairError<EK, EV>>
+    for DeserPair<DK, DV, R, K, V, EK, EV>
+    where DK: AsyncDeserialize<R, K, EK>,
+          DV: AsyncDeserialize<R, V, EV>,
+          R: AsyncRead
+{
+    fn from_reader(reader: R) -> Self {
+        DeserPair {
+            state: State::Key(DK::from_reader(reader)),
+            read: 0,
+            _types: PhantomData,
+        }
+    }
+
+    fn already_read(&self) -> usize {
+        self.read +
+        match self.state {
+            State::Key(ref inner) => inner.already_read(),
+            State::Value(ref inner, _) => inner.already_read(),
+        }
+    }
+}
+
+impl<DK, DV, R, K, V, EK, EV> Restorable<R> for DeserPair<DK, DV, R, K, V, EK, EV>
+    where DK: Restorable<R>,
+          DV: Restorable<R>
+{
+    fn restore(self) -> Option<R> {
+        match self.state {
+            State::Key(inner) => inner.restore(),
+            State::Value(inner, _) => inner.restore(),
+        }
+    }
+}
+
+impl<DK, DV, R, K, V, EK, EV> DeserPair<DK, DV, R, K, V, EK, EV>
+    where DK: AsyncDeserialize<R, K, EK>,
+          DV: AsyncDeserialize<R, V, EV>,
+          R: AsyncRead
+{
+    /// Create a deserializer that does not fail if the key or value can not be deserialized, but
+    /// resolves to a `PartialResult` with the key if it was deserialized.
+    ///
+    /// The reader is then left in the middle of the pair, see the `partial` module.
+    pub fn from_reader_partial(reader: R) -> PartialPair<DK, DV, R, K, V, EK, EV> {
+        PartialPair(DeserPair::from_reader(reader))
+    }
+}
+
+/// Deserializes a `(K, V)` pair like `DeserPair`, but recovers the key if the value fails with a
+/// `DataError`, created via `DeserPair::from_reader_partial`.
+///
+/// The partial result holds the key, or `None` if the key itself failed, and the number of read
+/// bytes includes those that the failed deserializer reports to have read.
+pub struct PartialPair<DK, DV, R, K, V, EK, EV>(DeserPair<DK, DV, R, K, V, EK, EV>);
+
+impl<DK, DV, R, K, V, EK, EV> Future for PartialPair<DK, DV, R, K, V, EK, EV>
+    where DK: AsyncDeserialize<R, K, EK>,
+          DV: AsyncDeserialize<R, V, EV>,
+          R: AsyncRead
+{
+    type Item = (R, PartialResult<(K, V), Option<K>, PairError<EK, EV>>, usize);
+    type Error = (R, DeserializeError<PairError<EK, EV>>);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        match self.0.poll(cx) {
+            Ok(Async::Ready((reader, pair, read))) => {
+                Ok(Async::Ready((reader, PartialResult::Complete(pair), read)))
+            }
+            Ok(Async::Pending) => Ok(Async::Pending),
+            Err((reader, DeserializeError::DataError(error))) => {
+                let read = self.0.already_read();
+                let (parsed, offset) = match self.0.state {
+                    State::Key(_) => (None, 0),
+                    State::Value(_, ref mut key) => (key.take(), self.0.read),
+                };
+                let partial = PartialResult::Partial {
+                    parsed,
+                    error,
+                    offset,
+                };
+                Ok(Async::Ready((reader, partial, read)))
+            }
+            Err((reader, DeserializeError::ReaderError(err))) => {
+                Err((reader, DeserializeError::ReaderError(err)))
+            }
+        }
+    }
+}
+
+/// Everything that can go wrong when deserializing a pair, apart from reader errors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PairError<EK, EV> {
+    /// The key could not be deserialized.
+    Key(EK),
+    /// The value could not be deserialized.
+    Value(EV),
+}
+
+impl<EK: Display, EV: Display> Display for PairError<EK, EV> {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            PairError::Key(ref err) => write!(f, "Invalid key: {}", err),
+            PairError::Value(ref err) => write!(f, "Invalid value: {}", err),
+        }
+    }
+}
+
+impl<EK: Error, EV: Error> Error for PairError<EK, EV> {}
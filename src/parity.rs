@@ -0,0 +1,468 @@
+//! Interleave the bytes of a value with XOR parity bytes, as required by some legacy device
+//! protocols.
+//!
+//! The bytes are split into blocks of a fixed size, and every block is followed by the XOR of its
+//! bytes. The last block may be shorter than the others, it is followed by its parity just the
+//! same. An empty value is encoded without any parity byte.
+//!
+//! So `[1, 2, 3, 4]` with a block size of three encodes as `[1, 2, 3, 0, 4, 4]`.
+
+use std::cmp::min;
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+
+use futures_core::{Async, Future, Poll};
+use futures_core::task::Context;
+use futures_io::{AsyncRead, AsyncWrite, Error as FutIoErr, ErrorKind};
+
+use {AsyncDeserialize, AsyncSerialize, AsyncSerializeLen, AsyncWriterFuture, AsyncWriterFutureLen,
+     DeserializeError, Restorable};
+
+/// Wraps an `AsyncWrite` and inserts a parity byte after every `block_size` bytes written to it.
+///
+/// The parity byte of a full block is only written once the next block is started, or when the
+/// writer is flushed, closed or finished via `poll_finish`. The parity byte of a final partial
+/// block is written by `poll_finish` and `poll_close` only. The numbers of written bytes reported
+/// by `poll_write` do not include the parity bytes.
+#[derive(Debug)]
+pub struct ParityWriter<W> {
+    inner: W,
+    block_size: usize,
+    filled: usize,
+    parity: u8,
+    parity_bytes: usize,
+}
+
+impl<W> ParityWriter<W> {
+    /// Create a new `ParityWriter`, writing a parity byte after every `block_size` bytes.
+    ///
+    /// Panics if `block_size` is zero.
+    pub fn new(inner: W, block_size: usize) -> ParityWriter<W> {
+        assert!(block_size > 0, "ParityWriter needs a positive block size");
+        ParityWriter {
+            inner,
+            block_size,
+            filled: 0,
+            parity: 0,
+            parity_bytes: 0,
+        }
+    }
+
+    /// Return how many parity bytes have been written to the wrapped writer so far.
+    pub fn parity_bytes(&self) -> usize {
+        self.parity_bytes
+    }
+
+    /// Get a reference to the wrapped writer.
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Get a mutable reference to the wrapped writer.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// Consume this `ParityWriter`, returning the wrapped writer.
+    ///
+    /// Any parity byte that has not been written yet is lost, use `poll_finish` first.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: AsyncWrite> ParityWriter<W> {
+    /// Write the parity byte of the current block, even if the block is not full. Does nothing if
+    /// there is no current block.
+    pub fn poll_finish(&mut self, cx: &mut Context) -> Poll<(), FutIoErr> {
+        if self.filled == 0 {
+            return Ok(Async::Ready(()));
+        }
+
+        if try_ready!(self.inner.poll_write(cx, &[self.parity])) == 0 {
+            return Err(FutIoErr::new(ErrorKind::WriteZero, "could not write parity byte"));
+        }
+        self.filled = 0;
+        self.parity = 0;
+        self.parity_bytes += 1;
+        Ok(Async::Ready(()))
+    }
+
+    // Write the parity byte of the current block if it is full.
+    fn poll_full_block(&mut self, cx: &mut Context) -> Poll<(), FutIoErr> {
+        if self.filled == self.block_size {
+            self.poll_finish(cx)
+        } else {
+            Ok(Async::Ready(()))
+        }
+    }
+}
+
+impl<W: AsyncWrite> AsyncWrite for ParityWriter<W> {
+    fn poll_write(&mut self, cx: &mut Context, buf: &[u8]) -> Poll<usize, FutIoErr> {
+        try_ready!(self.poll_full_block(cx));
+
+        let len = min(buf.len(), self.block_size - self.filled);
+        let written = try_ready!(self.inner.poll_write(cx, &buf[..len]));
+        self.parity = buf[..written].iter().fold(self.parity, |parity, byte| parity ^ byte);
+        self.filled += written;
+        Ok(Async::Ready(written))
+    }
+
+    fn poll_flush(&mut self, cx: &mut Context) -> Poll<(), FutIoErr> {
+        try_ready!(self.poll_full_block(cx));
+        self.inner.poll_flush(cx)
+    }
+
+    fn poll_close(&mut self, cx: &mut Context) -> Poll<(), FutIoErr> {
+        try_ready!(self.poll_finish(cx));
+        self.inner.poll_close(cx)
+    }
+}
+
+/// Wraps an `AsyncRead`, strips the parity byte after every `block_size` bytes and verifies it.
+///
+/// A parity byte is read and verified once the bytes after it are read. If it does not match,
+/// reading fails with an `ErrorKind::InvalidData` error. The parity byte of the final block is
+/// only read and verified by `poll_finish`.
+#[derive(Debug)]
+pub struct ParityReader<R> {
+    inner: R,
+    block_size: usize,
+    filled: usize,
+    parity: u8,
+    parity_bytes: usize,
+    mismatch: Option<(u8, u8)>,
+}
+
+impl<R> ParityReader<R> {
+    /// Create a new `ParityReader`, expecting a parity byte after every `block_size` bytes.
+    ///
+    /// Panics if `block_size` is zero.
+    pub fn new(inner: R, block_size: usize) -> ParityReader<R> {
+        assert!(block_size > 0, "ParityReader needs a positive block size");
+        ParityReader {
+            inner,
+            block_size,
+            filled: 0,
+            parity: 0,
+            parity_bytes: 0,
+            mismatch: None,
+        }
+    }
+
+    /// Return how many parity bytes have been read from the wrapped reader so far.
+    pub fn parity_bytes(&self) -> usize {
+        self.parity_bytes
+    }
+
+    /// Get a reference to the wrapped reader.
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// Get a mutable reference to the wrapped reader.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    /// Consume this `ParityReader`, returning the wrapped reader.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: AsyncRead> ParityReader<R> {
+    /// Read and verify the parity byte of the current block, even if the block is not full. Does
+    /// nothing if there is no current block.
+    pub fn poll_finish(&mut self, cx: &mut Context) -> Poll<(), FutIoErr> {
+        if self.filled == 0 {
+            return Ok(Async::Ready(()));
+        }
+
+        let mut expected = [0];
+        if try_ready!(self.inner.poll_read(cx, &mut expected)) == 0 {
+            return Err(FutIoErr::new(ErrorKind::UnexpectedEof, "missing parity byte"));
+        }
+        self.parity_bytes += 1;
+
+        if expected[0] != self.parity {
+            self.mismatch = Some((expected[0], self.parity));
+            return Err(FutIoErr::new(ErrorKind::InvalidData, "parity mismatch"));
+        }
+        self.filled = 0;
+        self.parity = 0;
+        Ok(Async::Ready(()))
+    }
+}
+
+impl<R: AsyncRead> AsyncRead for ParityReader<R> {
+    fn poll_read(&mut self, cx: &mut Context, buf: &mut [u8]) -> Poll<usize, FutIoErr> {
+        if self.filled == self.block_size {
+            try_ready!(self.poll_finish(cx));
+        }
+
+        let len = min(buf.len(), self.block_size - self.filled);
+        let read = try_ready!(self.inner.poll_read(cx, &mut buf[..len]));
+        self.parity = buf[..read].iter().fold(self.parity, |parity, byte| parity ^ byte);
+        self.filled += read;
+        Ok(Async::Ready(read))
+    }
+}
+
+/// Serializes a value via `F` through a `ParityWriter`, writing the parity byte of the final
+/// block once `F` is done.
+///
+/// Resolves to the wrapped writer and the number of written bytes, including the parity bytes.
+/// While `F` is running, `already_written` counts the parity bytes of the blocks before the
+/// current one, even if the parity byte of a full block has not been written yet.
+pub struct WriteWithParity<F, W> {
+    state: WriteState<F, W>,
+    block_size: usize,
+}
+
+enum WriteState<F, W> {
+    Body(F),
+    Finish(Option<ParityWriter<W>>, usize),
+}
+
+impl<F, W> WriteWithParity<F, W>
+    where F: AsyncSerialize<ParityWriter<W>>,
+          W: AsyncWrite
+{
+    /// Create a new `WriteWithParity`, serializing `val` with a parity byte after every
+    /// `block_size` bytes.
+    ///
+    /// Panics if `block_size` is zero.
+    pub fn new(writer: W, block_size: usize, val: F::Serialized) -> WriteWithParity<F, W> {
+        WriteWithParity {
+            state: WriteState::Body(F::from_val(ParityWriter::new(writer, block_size), val)),
+            block_size,
+        }
+    }
+}
+
+impl<F, W> WriteWithParity<F, W>
+    where F: AsyncSerializeLen<ParityWriter<W>>,
+          W: AsyncWrite
+{
+    /// Compute the exact number of bytes that would be written in total if `val` was serialized
+    /// with the given block size.
+    pub fn total_bytes(block_size: usize, val: &F::Serialized) -> usize {
+        let len = F::total_bytes(val);
+        len + len.div_ceil(block_size)
+    }
+}
+
+impl<F, W> Future for WriteWithParity<F, W>
+    where F: AsyncSerialize<ParityWriter<W>>,
+          W: AsyncWrite
+{
+    type Item = (W, usize);
+    type Error = (W, FutIoErr);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let next = match self.state {
+                WriteState::Body(ref mut body) => {
+                    match body.poll(cx) {
+                        Ok(Async::Ready((writer, written))) => {
+                            WriteState::Finish(Some(writer), written)
+                        }
+                        Ok(Async::Pending) => return Ok(Async::Pending),
+                        Err((writer, err)) => return Err((writer.into_inner(), err)),
+                    }
+                }
+
+                WriteState::Finish(ref mut writer, written) => {
+                    let result = or_pending!(writer.as_mut()).poll_finish(cx);
+                    let done = match result {
+                        Ok(Async::Ready(())) => Ok(()),
+                        Ok(Async::Pending) => return Ok(Async::Pending),
+                        Err(err) => Err(err),
+                    };
+
+                    let writer = writer.take().unwrap();
+                    let written = written + writer.parity_bytes();
+                    return match done {
+                        Ok(()) => Ok(Async::Ready((writer.into_inner(), written))),
+                        Err(err) => Err((writer.into_inner(), err)),
+                    };
+                }
+            };
+            self.state = next;
+        }
+    }
+}
+
+impl<F, W> AsyncWriterFuture<W> for WriteWithParity<F, W>
+    where F: AsyncSerialize<ParityWriter<W>>,
+          W: AsyncWrite
+{
+    fn already_written(&self) -> usize {
+        match self.state {
+            WriteState::Body(ref body) => {
+                let written = body.already_written();
+                written + written.saturating_sub(1) / self.block_size
+            }
+            WriteState::Finish(ref writer, written) => {
+                written + writer.as_ref().map(ParityWriter::parity_bytes).unwrap_or(0)
+            }
+        }
+    }
+}
+
+impl<F, W> AsyncWriterFutureLen<W> for WriteWithParity<F, W>
+    where F: AsyncSerializeLen<ParityWriter<W>>,
+          W: AsyncWrite
+{
+    fn remaining_bytes(&self) -> usize {
+        match self.state {
+            WriteState::Body(ref body) => {
+                let written = body.already_written();
+                let remaining = body.remaining_bytes();
+                let parity = (written + remaining).div_ceil(self.block_size);
+                remaining + parity - written.saturating_sub(1) / self.block_size
+            }
+            WriteState::Finish(ref writer, _) => {
+                match writer.as_ref() {
+                    Some(writer) if writer.filled > 0 => 1,
+                    _ => 0,
+                }
+            }
+        }
+    }
+}
+
+/// Deserializes a value via `D` through a `ParityReader`, reading and verifying the parity byte of
+/// the final block once `D` is done.
+///
+/// Resolves to the wrapped reader, the value and the number of read bytes, including the parity
+/// bytes. A parity mismatch fails with `ParityError::Mismatch`, regardless of whether it was
+/// noticed by `D` or afterwards.
+pub struct ReadWithParity<D, R, T> {
+    state: ReadState<D, R, T>,
+}
+
+enum ReadState<D, R, T> {
+    Body(D),
+    Finish(Option<ParityReader<R>>, Option<T>, usize),
+}
+
+impl<D, R, T, E> ReadWithParity<D, R, T>
+    where D: Future<Item = (ParityReader<R>, T, usize),
+                    Error = (ParityReader<R>, DeserializeError<E>)>,
+          D: AsyncDeserialize<ParityReader<R>, T, E>,
+          R: AsyncRead
+{
+    /// Create a new `ReadWithParity`, expecting a parity byte after every `block_size` bytes.
+    ///
+    /// Panics if `block_size` is zero.
+    pub fn new(reader: R, block_size: usize) -> ReadWithParity<D, R, T> {
+        ReadWithParity {
+            state: ReadState::Body(D::from_reader(ParityReader::new(reader, block_size))),
+        }
+    }
+}
+
+// Convert an error of the wrapped reader into a `ParityError::Mismatch` if it was caused by one.
+fn reader_error<R, E>(reader: ParityReader<R>,
+                      err: FutIoErr)
+                      -> (R, DeserializeError<ParityError<E>>) {
+    let err = match reader.mismatch {
+        Some((expected, actual)) => {
+            DeserializeError::DataError(ParityError::Mismatch { expected, actual })
+        }
+        None => DeserializeError::ReaderError(err),
+    };
+    (reader.into_inner(), err)
+}
+
+impl<D, R, T, E> Future for ReadWithParity<D, R, T>
+    where D: Future<Item = (ParityReader<R>, T, usize),
+                    Error = (ParityReader<R>, DeserializeError<E>)>,
+          R: AsyncRead
+{
+    type Item = (R, T, usize);
+    type Error = (R, DeserializeError<ParityError<E>>);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let next = match self.state {
+                ReadState::Body(ref mut body) => {
+                    match body.poll(cx) {
+                        Ok(Async::Ready((reader, val, read))) => {
+                            ReadState::Finish(Some(reader), Some(val), read)
+                        }
+                        Ok(Async::Pending) => return Ok(Async::Pending),
+                        Err((reader, DeserializeError::ReaderError(err))) => {
+                            return Err(reader_error(reader, err))
+                        }
+                        Err((reader, DeserializeError::DataError(err))) => {
+                            return Err((reader.into_inner(),
+                                        DeserializeError::DataError(ParityError::Inner(err))))
+                        }
+                    }
+                }
+
+                ReadState::Finish(ref mut reader, ref mut val, read) => {
+                    let result = or_pending!(reader.as_mut()).poll_finish(cx);
+                    let done = match result {
+                        Ok(Async::Ready(())) => Ok(()),
+                        Ok(Async::Pending) => return Ok(Async::Pending),
+                        Err(err) => Err(err),
+                    };
+
+                    let reader = reader.take().unwrap();
+                    return match done {
+                        Ok(()) => {
+                            let read = read + reader.parity_bytes();
+                            Ok(Async::Ready((reader.into_inner(), val.take().unwrap(), read)))
+                        }
+                        Err(err) => Err(reader_error(reader, err)),
+                    };
+                }
+            };
+            self.state = next;
+        }
+    }
+}
+
+impl<D, R, T> Restorable<R> for ReadWithParity<D, R, T>
+    where D: Restorable<ParityReader<R>>
+{
+    fn restore(self) -> Option<R> {
+        match self.state {
+            ReadState::Body(inner) => inner.restore().map(ParityReader::into_inner),
+            ReadState::Finish(reader, ..) => reader.map(ParityReader::into_inner),
+        }
+    }
+}
+
+/// Everything that can go wrong when reading a value interleaved with parity bytes, apart from
+/// reader errors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParityError<E> {
+    /// The value could not be deserialized.
+    Inner(E),
+    /// The parity byte of a block does not match its bytes.
+    Mismatch {
+        /// The parity byte that was read.
+        expected: u8,
+        /// The parity of the bytes of the block.
+        actual: u8,
+    },
+}
+
+impl<E: Display> Display for ParityError<E> {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            ParityError::Inner(ref err) => write!(f, "{}", err),
+            ParityError::Mismatch { expected, actual } => {
+                write!(f, "Block parity {:#04x} does not match {:#04x}", actual, expected)
+            }
+        }
+    }
+}
+
+impl<E: Error> Error for ParityError<E> {}
@@ -0,0 +1,28 @@
+//! Best-effort recovery of partially deserialized values.
+//!
+//! Some composite deserializers of this crate, e.g. `DeserLinkedList` and `DeserPair`, offer a
+//! `from_reader_partial` constructor besides the strict `from_reader`. The futures created that way
+//! do not fail on a `DataError`, but resolve to a `PartialResult` holding the parts that were
+//! deserialized before the error. This is meant for forensic tooling that inspects corrupted data.
+//!
+//! After a partial result, the reader is left in the middle of the value, somewhere after the part
+//! that failed to deserialize. There is in general no way to tell where the next value starts, so
+//! the reader should not be used for further deserialization. Reader errors are not recovered
+//! from, they fail the future like they do in strict mode.
+
+/// The result of deserializing a value via a `from_reader_partial` constructor.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PartialResult<T, P, E> {
+    /// The value was deserialized completely.
+    Complete(T),
+    /// A part of the value could not be deserialized.
+    Partial {
+        /// The parts that were deserialized before the error.
+        parsed: P,
+        /// Why the part at `offset` could not be deserialized.
+        error: E,
+        /// Where the part that could not be deserialized starts, in bytes from the start of the
+        /// value.
+        offset: usize,
+    },
+}
@@ -0,0 +1,191 @@
+//! Serialization of filesystem paths.
+//!
+//! A path is encoded as its UTF-8 representation preceded by the length in bytes as a big-endian
+//! `u32`. The encoding is platform-independent: components are always separated by forward
+//! slashes, so on Windows backslashes are converted to forward slashes when serializing (Windows
+//! accepts forward slashes as separators, so deserialization does not convert back).
+//!
+//! Paths that are not valid unicode can not be serialized, the serializers fail with an
+//! `ErrorKind::InvalidInput` error without writing anything.
+
+use std::borrow::Cow;
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::path::{Path, PathBuf};
+
+use futures_core::{Async, Future, Poll};
+use futures_core::task::Context;
+use futures_io::{AsyncRead, AsyncWrite, Error as FutIoErr};
+
+use {AsyncDeserialize, AsyncSerialize, AsyncSerializeLen, AsyncSerializeRef,
+     AsyncSerializeRefLen, AsyncWriterFuture, AsyncWriterFutureLen, DeserializeError,
+     Restorable};
+use util::{prefix_fits, ReadLenPrefixed, TryWriteLenPrefixed};
+
+#[cfg(not(windows))]
+fn normalize(path: &str) -> Cow<'_, str> {
+    Cow::Borrowed(path)
+}
+
+#[cfg(windows)]
+fn normalize(path: &str) -> Cow<'_, str> {
+    if path.contains('\\') {
+        Cow::Owned(path.replace('\\', "/"))
+    } else {
+        Cow::Borrowed(path)
+    }
+}
+
+fn encode(path: &Path) -> Option<Cow<'_, [u8]>> {
+    let path = match normalize(path.to_str()?) {
+        Cow::Borrowed(path) => Cow::Borrowed(path.as_bytes()),
+        Cow::Owned(path) => Cow::Owned(path.into_bytes()),
+    };
+
+    if prefix_fits(path.len()) {
+        Some(path)
+    } else {
+        None
+    }
+}
+
+const INVALID: &str = "path is not valid unicode or too long";
+
+fn encoded_len(path: &Path) -> usize {
+    encode(path).map(|path| 4 + path.len()).unwrap_or(0)
+}
+
+/// Serializes a `Path` by reference.
+///
+/// For paths that can not be serialized, `total_bytes` returns zero.
+pub struct SerPath<'val, W>(TryWriteLenPrefixed<W, Cow<'val, [u8]>>);
+
+impl<'val, W: AsyncWrite> Future for SerPath<'val, W> {
+    type Item = (W, usize);
+    type Error = (W, FutIoErr);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        self.0.poll(cx)
+    }
+}
+
+impl<'val, W: AsyncWrite> AsyncWriterFuture<W> for SerPath<'val, W> {
+    fn already_written(&self) -> usize {
+        self.0.already_written()
+    }
+}
+
+impl<'val, W: AsyncWrite> AsyncWriterFutureLen<W> for SerPath<'val, W> {
+    fn remaining_bytes(&self) -> usize {
+        self.0.remaining_bytes()
+    }
+}
+
+impl<'val, W: AsyncWrite> AsyncSerializeRef<'val, W> for SerPath<'val, W> {
+    type Serialized = Path;
+
+    fn from_ref(writer: W, val: &'val Path) -> Self {
+        SerPath(TryWriteLenPrefixed::new(writer, encode(val), INVALID))
+    }
+}
+
+impl<'val, W: AsyncWrite> AsyncSerializeRefLen<'val, W> for SerPath<'val, W> {
+    fn total_bytes(val: &Path) -> usize {
+        encoded_len(val)
+    }
+}
+
+/// Serializes an owned `PathBuf`.
+///
+/// For paths that can not be serialized, `total_bytes` returns zero.
+pub struct SerPathBuf<W>(TryWriteLenPrefixed<W, Vec<u8>>);
+
+impl<W: AsyncWrite> Future for SerPathBuf<W> {
+    type Item = (W, usize);
+    type Error = (W, FutIoErr);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        self.0.poll(cx)
+    }
+}
+
+impl<W: AsyncWrite> AsyncWriterFuture<W> for SerPathBuf<W> {
+    fn already_written(&self) -> usize {
+        self.0.already_written()
+    }
+}
+
+impl<W: AsyncWrite> AsyncWriterFutureLen<W> for SerPathBuf<W> {
+    fn remaining_bytes(&self) -> usize {
+        self.0.remaining_bytes()
+    }
+}
+
+impl<W: AsyncWrite> AsyncSerialize<W> for SerPathBuf<W> {
+    type Serialized = PathBuf;
+
+    fn from_val(writer: W, val: PathBuf) -> Self {
+        let body = encode(&val).map(Cow::into_owned);
+        SerPathBuf(TryWriteLenPrefixed::new(writer, body, INVALID))
+    }
+}
+
+impl<W: AsyncWrite> AsyncSerializeLen<W> for SerPathBuf<W> {
+    fn total_bytes(val: &PathBuf) -> usize {
+        encoded_len(val)
+    }
+}
+
+/// Deserializes a `PathBuf`.
+pub struct DeserPath<R>(ReadLenPrefixed<R>);
+
+impl<R: AsyncRead> Future for DeserPath<R> {
+    type Item = (R, PathBuf, usize);
+    type Error = (R, DeserializeError<PathError>);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        match self.0.poll(cx) {
+            Ok(Async::Ready((reader, bytes, read))) => {
+                match String::from_utf8(bytes) {
+                    Ok(path) => Ok(Async::Ready((reader, PathBuf::from(path), read))),
+                    Err(_) => Err((reader, DeserializeError::DataError(PathError::NonUtf8Path))),
+                }
+            }
+            Ok(Async::Pending) => Ok(Async::Pending),
+            Err((reader, err)) => Err((reader, DeserializeError::ReaderError(err))),
+        }
+    }
+}
+
+impl<R: AsyncRead> AsyncDeserialize<R, PathBuf, PathError> for DeserPath<R> {
+    fn from_reader(reader: R) -> Self {
+        DeserPath(ReadLenPrefixed::new(reader))
+    }
+
+    fn already_read(&self) -> usize {
+        self.0.already_read()
+    }
+}
+
+impl<R> Restorable<R> for DeserPath<R> {
+    fn restore(self) -> Option<R> {
+        self.0.restore()
+    }
+}
+
+/// Everything that can go wrong when deserializing a path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PathError {
+    /// The encoded path is not valid UTF-8.
+    NonUtf8Path,
+}
+
+impl Display for PathError {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            PathError::NonUtf8Path => write!(f, "Path is not valid utf8"),
+        }
+    }
+}
+
+impl Error for PathError {}
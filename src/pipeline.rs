@@ -0,0 +1,92 @@
+//! Serialize a queue of values into the same writer, one after the other.
+
+use std::collections::VecDeque;
+
+use futures_core::{Async, Future, Poll};
+use futures_core::task::Context;
+use futures_io::{AsyncWrite, Error as FutIoErr};
+
+use AsyncSerialize;
+
+/// Serializes queued values via `S`, in the order in which they were enqueued.
+///
+/// As long as the writer is ready, the next value is started right after the previous one is
+/// done, without returning to the executor in between. The future resolves to the writer and the
+/// total number of written bytes once the queue is empty, so values must be enqueued before it is
+/// polled to completion.
+///
+/// If a value fails to serialize, the future fails with the writer and the error. The values
+/// after it are dropped together with the pipeline.
+pub struct PipelineSerializer<S, W>
+    where S: AsyncSerialize<W>,
+          W: AsyncWrite
+{
+    state: State<S, W>,
+    queue: VecDeque<S::Serialized>,
+    written: usize,
+}
+
+enum State<S, W> {
+    Idle(Option<W>),
+    Serializing(S),
+}
+
+impl<S, W> PipelineSerializer<S, W>
+    where S: AsyncSerialize<W>,
+          W: AsyncWrite
+{
+    /// Create a new `PipelineSerializer` with an empty queue, serializing into `writer`.
+    pub fn new(writer: W) -> PipelineSerializer<S, W> {
+        PipelineSerializer {
+            state: State::Idle(Some(writer)),
+            queue: VecDeque::new(),
+            written: 0,
+        }
+    }
+
+    /// Append `val` to the queue of values to serialize.
+    pub fn enqueue(&mut self, val: S::Serialized) {
+        self.queue.push_back(val);
+    }
+
+    /// Return how many values are queued but have not been started yet.
+    pub fn queued(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Return how many bytes have already been written, across all values.
+    pub fn already_written(&self) -> usize {
+        match self.state {
+            State::Idle(_) => self.written,
+            State::Serializing(ref inner) => self.written + inner.already_written(),
+        }
+    }
+}
+
+impl<S, W> Future for PipelineSerializer<S, W>
+    where S: AsyncSerialize<W>,
+          W: AsyncWrite
+{
+    type Item = (W, usize);
+    type Error = (W, FutIoErr);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let writer = match self.state {
+                State::Idle(ref mut writer) => {
+                    or_pending!(writer.take())
+                }
+                State::Serializing(ref mut inner) => {
+                    let (writer, written) = try_ready!(inner.poll(cx));
+                    self.written += written;
+                    writer
+                }
+            };
+
+            self.state = match self.queue.pop_front() {
+                Some(val) => State::Serializing(S::from_val(writer, val)),
+                None => return Ok(Async::Ready((writer, self.written))),
+            };
+        }
+    }
+}
@@ -0,0 +1,104 @@
+//! Make deserializers yield to the executor regularly, even when reading from an always-ready
+//! source.
+
+use std::cmp::min;
+
+use futures_core::{Async, Poll};
+use futures_core::task::Context;
+use futures_io::{AsyncRead, Error as FutIoErr};
+
+use AsyncDeserialize;
+
+/// Wraps an `AsyncRead` and voluntarily returns `Pending` once `budget` bytes have been read
+/// since the last time the reader was not ready, immediately waking the task again.
+///
+/// A deserializer reading from an in-memory or otherwise always-ready source never has to wait,
+/// so it would monopolize the executor until the whole value is deserialized. Wrapping the source
+/// in a `PollBudget` makes every deserializer in this crate yield after at most `budget` bytes
+/// per poll. Since the deserializers already handle `Pending` readers, their byte counts stay
+/// exact across the voluntary yields.
+///
+/// A budget of zero disables the yielding. Deserializers can also be created over a `PollBudget`
+/// directly via `WithPollBudget::with_poll_budget`.
+#[derive(Debug)]
+pub struct PollBudget<R> {
+    inner: R,
+    budget: usize,
+    used: usize,
+}
+
+impl<R> PollBudget<R> {
+    /// Create a new `PollBudget`, yielding after every `budget` bytes read from `inner`.
+    pub fn new(inner: R, budget: usize) -> PollBudget<R> {
+        PollBudget {
+            inner,
+            budget,
+            used: 0,
+        }
+    }
+
+    /// Get a reference to the wrapped reader.
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// Get a mutable reference to the wrapped reader.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    /// Consume this `PollBudget`, returning the wrapped reader.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: AsyncRead> AsyncRead for PollBudget<R> {
+    fn poll_read(&mut self, cx: &mut Context, buf: &mut [u8]) -> Poll<usize, FutIoErr> {
+        if self.budget == 0 {
+            return self.inner.poll_read(cx, buf);
+        }
+
+        if self.used >= self.budget {
+            self.used = 0;
+            cx.waker().wake();
+            return Ok(Async::Pending);
+        }
+
+        let max = min(buf.len(), self.budget - self.used);
+        match self.inner.poll_read(cx, &mut buf[..max]) {
+            Ok(Async::Ready(read)) => {
+                self.used += read;
+                Ok(Async::Ready(read))
+            }
+            Ok(Async::Pending) => {
+                self.used = 0;
+                Ok(Async::Pending)
+            }
+            Err(err) => Err(err),
+        }
+    }
+}
+
+/// Create any deserializer so that it yields to the executor regularly.
+///
+/// This is implemented for every deserializer over a `PollBudget`, so e.g.
+/// `ReadBytes::with_poll_budget(reader, 4096)` deserializes bytes from `reader`, returning
+/// `Pending` after every 4096 bytes. The deserializer resolves to the `PollBudget`, whose
+/// `into_inner` returns the original reader. Deserializers created via `from_reader` over a plain
+/// reader never yield voluntarily.
+pub trait WithPollBudget<R, S, E>: AsyncDeserialize<PollBudget<R>, S, E> + Sized
+    where R: AsyncRead
+{
+    /// Create a new deserializer reading from `reader`, yielding after every `bytes_per_poll`
+    /// bytes. A budget of zero disables the yielding.
+    fn with_poll_budget(reader: R, bytes_per_poll: usize) -> Self {
+        Self::from_reader(PollBudget::new(reader, bytes_per_poll))
+    }
+}
+
+impl<D, R, S, E> WithPollBudget<R, S, E> for D
+    where D: AsyncDeserialize<PollBudget<R>, S, E>,
+          R: AsyncRead
+{
+}
@@ -0,0 +1,98 @@
+//! Observe the progress of a single long-running deserialization, e.g. to show a progress bar
+//! while receiving a large blob.
+
+use futures_core::{Async, Future, Poll};
+use futures_core::task::Context;
+use futures_io::AsyncRead;
+
+use {AsyncDeserialize, DeserializeError};
+
+/// A deserializer that can tell how many more bytes it is going to read.
+pub trait PayloadRemaining {
+    /// Return how many more bytes this will read before completing, or `None` if that is not
+    /// known yet, e.g. because the length prefix has not been read completely.
+    fn payload_remaining(&self) -> Option<usize>;
+}
+
+/// Wraps a deserializer `D` and calls `Cb` with the number of bytes read so far and the
+/// `payload_remaining` of `D` whenever a poll made progress.
+///
+/// Polls that do not read anything do not call `Cb`. When `D` completes, `Cb` is called a final
+/// time with the total number of read bytes and `Some(0)`, even if the last poll did not read
+/// anything. It is not called when `D` fails.
+///
+/// `from_reader` reports to a callback that ignores the progress, use `new` to observe it.
+pub struct WithReadProgress<D, Cb> {
+    inner: D,
+    callback: Cb,
+    reported: usize,
+}
+
+impl<D, Cb> WithReadProgress<D, Cb> {
+    /// Create a new `WithReadProgress`, reporting the progress of `inner` to `callback`.
+    pub fn new(inner: D, callback: Cb) -> WithReadProgress<D, Cb> {
+        WithReadProgress {
+            inner,
+            callback,
+            reported: 0,
+        }
+    }
+
+    /// Get a reference to the wrapped deserializer.
+    pub fn get_ref(&self) -> &D {
+        &self.inner
+    }
+
+    /// Consume this `WithReadProgress`, returning the wrapped deserializer.
+    pub fn into_inner(self) -> D {
+        self.inner
+    }
+}
+
+impl<D, Cb, R, T, E> Future for WithReadProgress<D, Cb>
+    where D: AsyncDeserialize<R, T, E> + PayloadRemaining,
+          D: Future<Item = (R, T, usize), Error = (R, DeserializeError<E>)>,
+          Cb: FnMut(usize, Option<usize>),
+          R: AsyncRead
+{
+    type Item = (R, T, usize);
+    type Error = (R, DeserializeError<E>);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        match self.inner.poll(cx) {
+            Ok(Async::Ready((reader, val, read))) => {
+                (self.callback)(read, Some(0));
+                Ok(Async::Ready((reader, val, read)))
+            }
+            Ok(Async::Pending) => {
+                let read = self.inner.already_read();
+                if read > self.reported {
+                    self.reported = read;
+                    (self.callback)(read, self.inner.payload_remaining());
+                }
+                Ok(Async::Pending)
+            }
+            Err(err) => Err(err),
+        }
+    }
+}
+
+impl<D, R, T, E> AsyncDeserialize<R, T, E> for WithReadProgress<D, fn(usize, Option<usize>)>
+    where D: AsyncDeserialize<R, T, E> + PayloadRemaining,
+          R: AsyncRead
+{
+    fn from_reader(reader: R) -> Self {
+        fn ignore(_: usize, _: Option<usize>) {}
+        WithReadProgress::new(D::from_reader(reader), ignore)
+    }
+
+    fn already_read(&self) -> usize {
+        self.inner.already_read()
+    }
+}
+
+impl<D: PayloadRemaining, Cb> PayloadRemaining for WithReadProgress<D, Cb> {
+    fn payload_remaining(&self) -> Option<usize> {
+        self.inner.payload_remaining()
+    }
+}
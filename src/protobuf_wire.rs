@@ -0,0 +1,880 @@
+//! Futures for emitting and parsing individual fields of the protobuf wire format, without a full
+//! protobuf implementation.
+//!
+//! A protobuf message is a sequence of fields, each being a key followed by a value. The key is a
+//! varint holding the field number shifted left by three bits, combined with the wire type of the
+//! value in the lower three bits. Values are encoded according to their wire type:
+//!
+//! - `Varint`: a [varint](../varint/index.html) (`int32`, `int64`, `uint32`, `uint64`, `sint32`,
+//!   `sint64`, `bool`, `enum`)
+//! - `Fixed64`: eight little-endian bytes (`fixed64`, `sfixed64`, `double`)
+//! - `LengthDelimited`: a varint length followed by that many bytes (`string`, `bytes`, embedded
+//!   messages, packed repeated fields)
+//! - `Fixed32`: four little-endian bytes (`fixed32`, `sfixed32`, `float`)
+//!
+//! For example, field number 1 with the varint value 150 encodes as `[0x08, 0x96, 0x01]`, and
+//! field number 2 with the string `"testing"` as `[0x12, 0x07, 0x74, 0x65, 0x73, 0x74, 0x69, 0x6e,
+//! 0x67]`. The deprecated group wire types (3 and 4) are not supported.
+//!
+//! The serializers compose with [`Chain`](../chain/struct.Chain.html), so the two fields above can
+//! be written by a `Chain<WriteKey<W>, Chain<WriteVarint<W>, Chain<WriteKey<W>, WriteString<W>,
+//! W>, W>, W>`.
+//!
+//! To parse a message, read a key with `ReadKey`, then read the value of known fields with the
+//! matching deserializer and pass unknown fields to `SkipValue`. `ReadField` reads key and value
+//! generically into a `Field`.
+
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::mem;
+use std::str::Utf8Error;
+
+use futures_core::{Async, Future, Poll};
+use futures_core::task::Context;
+use futures_io::{AsyncRead, AsyncWrite, Error as FutIoErr};
+
+use {AsyncDeserialize, AsyncSerialize, AsyncSerializeLen, AsyncWriterFuture,
+     AsyncWriterFutureLen, DeserializeError, Restorable};
+use progress::PayloadRemaining;
+use util::{poll_read_vec, poll_skip, poll_write_buf, ReadExact, WriteAll};
+use varint::{self, varint_len, VarintBuf, VarintError};
+
+pub use varint::WriteVarint;
+
+/// The largest valid field number.
+pub const MAX_FIELD_NUMBER: u32 = (1 << 29) - 1;
+
+/// The wire types of protobuf values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WireType {
+    /// A varint.
+    Varint,
+    /// Eight little-endian bytes.
+    Fixed64,
+    /// A varint length followed by that many bytes.
+    LengthDelimited,
+    /// Four little-endian bytes.
+    Fixed32,
+}
+
+impl WireType {
+    /// Return the numeric id of this wire type.
+    pub fn to_u8(self) -> u8 {
+        match self {
+            WireType::Varint => 0,
+            WireType::Fixed64 => 1,
+            WireType::LengthDelimited => 2,
+            WireType::Fixed32 => 5,
+        }
+    }
+
+    /// Return the wire type with the given numeric id, if it is supported.
+    pub fn from_u8(id: u8) -> Option<WireType> {
+        match id {
+            0 => Some(WireType::Varint),
+            1 => Some(WireType::Fixed64),
+            2 => Some(WireType::LengthDelimited),
+            5 => Some(WireType::Fixed32),
+            _ => None,
+        }
+    }
+}
+
+/// The key of a field: its field number and the wire type of its value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Key {
+    /// The field number, between 1 and `MAX_FIELD_NUMBER`.
+    pub field_number: u32,
+    /// The wire type of the value.
+    pub wire_type: WireType,
+}
+
+impl Key {
+    /// Create a new key.
+    pub fn new(field_number: u32, wire_type: WireType) -> Key {
+        Key {
+            field_number,
+            wire_type,
+        }
+    }
+
+    /// Return the value that is varint-encoded to represent this key.
+    pub fn to_varint(self) -> u64 {
+        (u64::from(self.field_number) << 3) | u64::from(self.wire_type.to_u8())
+    }
+
+    /// Decode a key from its varint value.
+    pub fn from_varint(val: u64) -> Result<Key, ProtobufError> {
+        let wire_type = WireType::from_u8((val & 0b111) as u8)
+            .ok_or(ProtobufError::InvalidWireType((val & 0b111) as u8))?;
+        let field_number = val >> 3;
+
+        if field_number == 0 || field_number > u64::from(MAX_FIELD_NUMBER) {
+            Err(ProtobufError::InvalidFieldNumber(field_number))
+        } else {
+            Ok(Key::new(field_number as u32, wire_type))
+        }
+    }
+}
+
+/// Map a signed integer to an unsigned one such that values of small magnitude map to small
+/// values, as done for `sint32` and `sint64` fields.
+pub fn encode_zigzag(val: i64) -> u64 {
+    ((val << 1) ^ (val >> 63)) as u64
+}
+
+/// Reverse `encode_zigzag`.
+pub fn decode_zigzag(val: u64) -> i64 {
+    ((val >> 1) as i64) ^ -((val & 1) as i64)
+}
+
+/// Serializes a `Key`.
+pub struct WriteKey<W>(WriteVarint<W>);
+
+impl<W: AsyncWrite> Future for WriteKey<W> {
+    type Item = (W, usize);
+    type Error = (W, FutIoErr);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        self.0.poll(cx)
+    }
+}
+
+impl<W: AsyncWrite> AsyncWriterFuture<W> for WriteKey<W> {
+    fn already_written(&self) -> usize {
+        self.0.already_written()
+    }
+}
+
+impl<W: AsyncWrite> AsyncWriterFutureLen<W> for WriteKey<W> {
+    fn remaining_bytes(&self) -> usize {
+        self.0.remaining_bytes()
+    }
+}
+
+impl<W: AsyncWrite> AsyncSerialize<W> for WriteKey<W> {
+    type Serialized = Key;
+
+    fn from_val(writer: W, val: Key) -> Self {
+        WriteKey(WriteVarint::from_val(writer, val.to_varint()))
+    }
+}
+
+impl<W: AsyncWrite> AsyncSerializeLen<W> for WriteKey<W> {
+    fn total_bytes(val: &Key) -> usize {
+        varint_len(val.to_varint())
+    }
+}
+
+macro_rules! write_fixed {
+    ($name:ident, $t:ty, $len:expr, $doc:expr) => {
+        #[doc = $doc]
+        pub struct $name<W>(WriteAll<W, [u8; $len]>);
+
+        impl<W: AsyncWrite> Future for $name<W> {
+            type Item = (W, usize);
+            type Error = (W, FutIoErr);
+
+            fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+                self.0.poll(cx)
+            }
+        }
+
+        impl<W: AsyncWrite> AsyncWriterFuture<W> for $name<W> {
+            fn already_written(&self) -> usize {
+                self.0.already_written()
+            }
+        }
+
+        impl<W: AsyncWrite> AsyncWriterFutureLen<W> for $name<W> {
+            fn remaining_bytes(&self) -> usize {
+                self.0.remaining_bytes()
+            }
+        }
+
+        impl<W: AsyncWrite> AsyncSerialize<W> for $name<W> {
+            type Serialized = $t;
+
+            fn from_val(writer: W, val: $t) -> Self {
+                $name(WriteAll::new(writer, val.to_le_bytes()))
+            }
+        }
+
+        impl<W: AsyncWrite> AsyncSerializeLen<W> for $name<W> {
+            fn total_bytes(_: &$t) -> usize {
+                $len
+            }
+        }
+    }
+}
+
+write_fixed!(WriteFixed32, u32, 4, "Serializes a `u32` as a `Fixed32` value.");
+write_fixed!(WriteFixed64, u64, 8, "Serializes a `u64` as a `Fixed64` value.");
+
+/// Serializes bytes as a `LengthDelimited` value.
+pub struct WriteBytes<W> {
+    writer: Option<W>,
+    prefix: VarintBuf,
+    prefix_written: usize,
+    bytes: Vec<u8>,
+    bytes_written: usize,
+}
+
+impl<W: AsyncWrite> Future for WriteBytes<W> {
+    type Item = (W, usize);
+    type Error = (W, FutIoErr);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        let mut writer = or_pending!(self.writer.take());
+
+        let res = match poll_write_buf(&mut writer,
+                                       cx,
+                                       self.prefix.as_ref(),
+                                       &mut self.prefix_written) {
+            Ok(Async::Ready(())) => {
+                poll_write_buf(&mut writer, cx, &self.bytes, &mut self.bytes_written)
+            }
+            other => other,
+        };
+
+        match res {
+            Ok(Async::Ready(())) => {
+                Ok(Async::Ready((writer, self.prefix_written + self.bytes_written)))
+            }
+            Ok(Async::Pending) => {
+                self.writer = Some(writer);
+                Ok(Async::Pending)
+            }
+            Err(err) => Err((writer, err)),
+        }
+    }
+}
+
+impl<W: AsyncWrite> AsyncWriterFuture<W> for WriteBytes<W> {
+    fn already_written(&self) -> usize {
+        self.prefix_written + self.bytes_written
+    }
+}
+
+impl<W: AsyncWrite> AsyncWriterFutureLen<W> for WriteBytes<W> {
+    fn remaining_bytes(&self) -> usize {
+        self.prefix.as_ref().len() + self.bytes.len() - self.already_written()
+    }
+}
+
+impl<W: AsyncWrite> AsyncSerialize<W> for WriteBytes<W> {
+    type Serialized = Vec<u8>;
+
+    fn from_val(writer: W, val: Vec<u8>) -> Self {
+        WriteBytes {
+            writer: Some(writer),
+            prefix: VarintBuf::new(val.len() as u64),
+            prefix_written: 0,
+            bytes: val,
+            bytes_written: 0,
+        }
+    }
+}
+
+impl<W: AsyncWrite> AsyncSerializeLen<W> for WriteBytes<W> {
+    fn total_bytes(val: &Vec<u8>) -> usize {
+        varint_len(val.len() as u64) + val.len()
+    }
+}
+
+/// Serializes a string as a `LengthDelimited` value.
+pub struct WriteString<W>(WriteBytes<W>);
+
+impl<W: AsyncWrite> Future for WriteString<W> {
+    type Item = (W, usize);
+    type Error = (W, FutIoErr);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        self.0.poll(cx)
+    }
+}
+
+impl<W: AsyncWrite> AsyncWriterFuture<W> for WriteString<W> {
+    fn already_written(&self) -> usize {
+        self.0.already_written()
+    }
+}
+
+impl<W: AsyncWrite> AsyncWriterFutureLen<W> for WriteString<W> {
+    fn remaining_bytes(&self) -> usize {
+        self.0.remaining_bytes()
+    }
+}
+
+impl<W: AsyncWrite> AsyncSerialize<W> for WriteString<W> {
+    type Serialized = String;
+
+    fn from_val(writer: W, val: String) -> Self {
+        WriteString(WriteBytes::from_val(writer, val.into_bytes()))
+    }
+}
+
+impl<W: AsyncWrite> AsyncSerializeLen<W> for WriteString<W> {
+    fn total_bytes(val: &String) -> usize {
+        varint_len(val.len() as u64) + val.len()
+    }
+}
+
+fn from_varint_err(err: DeserializeError<VarintError>) -> DeserializeError<ProtobufError> {
+    match err {
+        DeserializeError::ReaderError(err) => DeserializeError::ReaderError(err),
+        DeserializeError::DataError(VarintError::Overflow) => {
+            DeserializeError::DataError(ProtobufError::VarintOverflow)
+        }
+    }
+}
+
+/// Deserializes a `Varint` value.
+pub struct ReadVarint<R>(varint::ReadVarint<R>);
+
+impl<R: AsyncRead> Future for ReadVarint<R> {
+    type Item = (R, u64, usize);
+    type Error = (R, DeserializeError<ProtobufError>);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        self.0
+            .poll(cx)
+            .map_err(|(reader, err)| (reader, from_varint_err(err)))
+    }
+}
+
+impl<R: AsyncRead> AsyncDeserialize<R, u64, ProtobufError> for ReadVarint<R> {
+    fn from_reader(reader: R) -> Self {
+        ReadVarint(varint::ReadVarint::from_reader(reader))
+    }
+
+    fn already_read(&self) -> usize {
+        self.0.already_read()
+    }
+}
+
+impl<R> Restorable<R> for ReadVarint<R> {
+    fn restore(self) -> Option<R> {
+        self.0.restore()
+    }
+}
+
+/// Deserializes a `Key`.
+pub struct ReadKey<R>(ReadVarint<R>);
+
+impl<R: AsyncRead> Future for ReadKey<R> {
+    type Item = (R, Key, usize);
+    type Error = (R, DeserializeError<ProtobufError>);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        let (reader, val, read) = try_ready!(self.0.poll(cx));
+
+        match Key::from_varint(val) {
+            Ok(key) => Ok(Async::Ready((reader, key, read))),
+            Err(err) => Err((reader, DeserializeError::DataError(err))),
+        }
+    }
+}
+
+impl<R: AsyncRead> AsyncDeserialize<R, Key, ProtobufError> for ReadKey<R> {
+    fn from_reader(reader: R) -> Self {
+        ReadKey(ReadVarint::from_reader(reader))
+    }
+
+    fn already_read(&self) -> usize {
+        self.0.already_read()
+    }
+}
+
+impl<R> Restorable<R> for ReadKey<R> {
+    fn restore(self) -> Option<R> {
+        self.0.restore()
+    }
+}
+
+macro_rules! read_fixed {
+    ($name:ident, $t:ty, $len:expr, $doc:expr) => {
+        #[doc = $doc]
+        pub struct $name<R>(ReadExact<R, [u8; $len]>);
+
+        impl<R: AsyncRead> Future for $name<R> {
+            type Item = (R, $t, usize);
+            type Error = (R, DeserializeError<ProtobufError>);
+
+            fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+                match self.0.poll(cx) {
+                    Ok(Async::Ready((reader, bytes, read))) => {
+                        Ok(Async::Ready((reader, <$t>::from_le_bytes(bytes), read)))
+                    }
+                    Ok(Async::Pending) => Ok(Async::Pending),
+                    Err((reader, err)) => Err((reader, DeserializeError::ReaderError(err))),
+                }
+            }
+        }
+
+        impl<R: AsyncRead> AsyncDeserialize<R, $t, ProtobufError> for $name<R> {
+            fn from_reader(reader: R) -> Self {
+                $name(ReadExact::new(reader, [0; $len]))
+            }
+
+            fn already_read(&self) -> usize {
+                self.0.already_read()
+            }
+        }
+
+        impl<R> Restorable<R> for $name<R> {
+            fn restore(self) -> Option<R> {
+                self.0.restore()
+            }
+        }
+    }
+}
+
+read_fixed!(ReadFixed32, u32, 4, "Deserializes a `Fixed32` value into a `u32`.");
+read_fixed!(ReadFixed64, u64, 8, "Deserializes a `Fixed64` value into a `u64`.");
+
+/// Deserializes a `LengthDelimited` value into its bytes.
+pub struct ReadBytes<R>(BytesState<R>);
+
+enum BytesState<R> {
+    Length(varint::ReadVarint<R>),
+    Body {
+        reader: Option<R>,
+        buf: Vec<u8>,
+        filled: usize,
+        len: usize,
+        prefix_len: usize,
+    },
+}
+
+impl<R: AsyncRead> Future for ReadBytes<R> {
+    type Item = (R, Vec<u8>, usize);
+    type Error = (R, DeserializeError<ProtobufError>);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let (reader, len, prefix_len) = match self.0 {
+                BytesState::Length(ref mut inner) => {
+                    match inner.poll(cx) {
+                        Ok(Async::Ready(done)) => done,
+                        Ok(Async::Pending) => return Ok(Async::Pending),
+                        Err((reader, err)) => return Err((reader, from_varint_err(err))),
+                    }
+                }
+
+                BytesState::Body {
+                    ref mut reader,
+                    ref mut buf,
+                    ref mut filled,
+                    len,
+                    prefix_len,
+                } => {
+                    let mut r = or_pending!(reader.take());
+                    return match poll_read_vec(&mut r, cx, buf, filled, len) {
+                               Ok(Async::Ready(())) => {
+                                   Ok(Async::Ready((r, mem::take(buf),
+                                                    prefix_len + len)))
+                               }
+                               Ok(Async::Pending) => {
+                                   *reader = Some(r);
+                                   Ok(Async::Pending)
+                               }
+                               Err(err) => Err((r, DeserializeError::ReaderError(err))),
+                           };
+                }
+            };
+
+            let len = match usize::try_from(len) {
+                Ok(len) => len,
+                Err(_) => {
+                    return Err((reader, DeserializeError::DataError(ProtobufError::LengthOverflow)))
+                }
+            };
+
+            self.0 = BytesState::Body {
+                reader: Some(reader),
+                buf: Vec::new(),
+                filled: 0,
+                len,
+                prefix_len,
+            };
+        }
+    }
+}
+
+impl<R: AsyncRead> AsyncDeserialize<R, Vec<u8>, ProtobufError> for ReadBytes<R> {
+    fn from_reader(reader: R) -> Self {
+        ReadBytes(BytesState::Length(varint::ReadVarint::from_reader(reader)))
+    }
+
+    fn already_read(&self) -> usize {
+        match self.0 {
+            BytesState::Length(ref inner) => inner.already_read(),
+            BytesState::Body {
+                filled, prefix_len, ..
+            } => prefix_len + filled,
+        }
+    }
+}
+
+impl<R> Restorable<R> for ReadBytes<R> {
+    fn restore(self) -> Option<R> {
+        match self.0 {
+            BytesState::Length(inner) => inner.restore(),
+            BytesState::Body { reader, .. } => reader,
+        }
+    }
+}
+
+impl<R> PayloadRemaining for ReadBytes<R> {
+    fn payload_remaining(&self) -> Option<usize> {
+        match self.0 {
+            BytesState::Length(_) => None,
+            BytesState::Body { filled, len, .. } => Some(len - filled),
+        }
+    }
+}
+
+/// Deserializes a `LengthDelimited` value into a string.
+pub struct ReadString<R>(ReadBytes<R>);
+
+impl<R: AsyncRead> Future for ReadString<R> {
+    type Item = (R, String, usize);
+    type Error = (R, DeserializeError<ProtobufError>);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        let (reader, bytes, read) = try_ready!(self.0.poll(cx));
+
+        match String::from_utf8(bytes) {
+            Ok(string) => Ok(Async::Ready((reader, string, read))),
+            Err(err) => {
+                Err((reader, DeserializeError::DataError(ProtobufError::InvalidUtf8(err.utf8_error()))))
+            }
+        }
+    }
+}
+
+impl<R: AsyncRead> AsyncDeserialize<R, String, ProtobufError> for ReadString<R> {
+    fn from_reader(reader: R) -> Self {
+        ReadString(ReadBytes::from_reader(reader))
+    }
+
+    fn already_read(&self) -> usize {
+        self.0.already_read()
+    }
+}
+
+impl<R> Restorable<R> for ReadString<R> {
+    fn restore(self) -> Option<R> {
+        self.0.restore()
+    }
+}
+
+impl<R> PayloadRemaining for ReadString<R> {
+    fn payload_remaining(&self) -> Option<usize> {
+        self.0.payload_remaining()
+    }
+}
+
+/// A value of any of the supported wire types.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    /// A `Varint` value.
+    Varint(u64),
+    /// A `Fixed64` value.
+    Fixed64(u64),
+    /// The bytes of a `LengthDelimited` value.
+    LengthDelimited(Vec<u8>),
+    /// A `Fixed32` value.
+    Fixed32(u32),
+}
+
+impl Value {
+    /// Return the wire type of this value.
+    pub fn wire_type(&self) -> WireType {
+        match *self {
+            Value::Varint(_) => WireType::Varint,
+            Value::Fixed64(_) => WireType::Fixed64,
+            Value::LengthDelimited(_) => WireType::LengthDelimited,
+            Value::Fixed32(_) => WireType::Fixed32,
+        }
+    }
+}
+
+/// Deserializes a value of a wire type that is known from a previously read key.
+pub struct ReadValue<R>(ValueState<R>);
+
+enum ValueState<R> {
+    Varint(ReadVarint<R>),
+    Fixed64(ReadFixed64<R>),
+    LengthDelimited(ReadBytes<R>),
+    Fixed32(ReadFixed32<R>),
+}
+
+impl<R: AsyncRead> ReadValue<R> {
+    /// Create a new `ReadValue`, consuming the reader to read a value of the given wire type
+    /// from.
+    pub fn new(reader: R, wire_type: WireType) -> ReadValue<R> {
+        ReadValue(match wire_type {
+                      WireType::Varint => ValueState::Varint(ReadVarint::from_reader(reader)),
+                      WireType::Fixed64 => ValueState::Fixed64(ReadFixed64::from_reader(reader)),
+                      WireType::LengthDelimited => {
+                          ValueState::LengthDelimited(ReadBytes::from_reader(reader))
+                      }
+                      WireType::Fixed32 => ValueState::Fixed32(ReadFixed32::from_reader(reader)),
+                  })
+    }
+
+    /// Return how many bytes have already been read.
+    pub fn already_read(&self) -> usize {
+        match self.0 {
+            ValueState::Varint(ref inner) => inner.already_read(),
+            ValueState::Fixed64(ref inner) => inner.already_read(),
+            ValueState::LengthDelimited(ref inner) => inner.already_read(),
+            ValueState::Fixed32(ref inner) => inner.already_read(),
+        }
+    }
+}
+
+impl<R> Restorable<R> for ReadValue<R> {
+    fn restore(self) -> Option<R> {
+        match self.0 {
+            ValueState::Varint(inner) => inner.restore(),
+            ValueState::Fixed64(inner) => inner.restore(),
+            ValueState::LengthDelimited(inner) => inner.restore(),
+            ValueState::Fixed32(inner) => inner.restore(),
+        }
+    }
+}
+
+impl<R: AsyncRead> Future for ReadValue<R> {
+    type Item = (R, Value, usize);
+    type Error = (R, DeserializeError<ProtobufError>);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        Ok(Async::Ready(match self.0 {
+                            ValueState::Varint(ref mut inner) => {
+                                let (reader, val, read) = try_ready!(inner.poll(cx));
+                                (reader, Value::Varint(val), read)
+                            }
+                            ValueState::Fixed64(ref mut inner) => {
+                                let (reader, val, read) = try_ready!(inner.poll(cx));
+                                (reader, Value::Fixed64(val), read)
+                            }
+                            ValueState::LengthDelimited(ref mut inner) => {
+                                let (reader, val, read) = try_ready!(inner.poll(cx));
+                                (reader, Value::LengthDelimited(val), read)
+                            }
+                            ValueState::Fixed32(ref mut inner) => {
+                                let (reader, val, read) = try_ready!(inner.poll(cx));
+                                (reader, Value::Fixed32(val), read)
+                            }
+                        }))
+    }
+}
+
+/// Skips over a value of a wire type that is known from a previously read key, e.g. because the
+/// field number is unknown.
+///
+/// Unlike `ReadValue`, this never buffers the skipped bytes.
+pub struct SkipValue<R>(SkipState<R>);
+
+enum SkipState<R> {
+    Varint(ReadVarint<R>),
+    Length(ReadVarint<R>),
+    Skip {
+        reader: Option<R>,
+        remaining: u64,
+        total: usize,
+    },
+}
+
+impl<R: AsyncRead> SkipValue<R> {
+    /// Create a new `SkipValue`, consuming the reader to skip a value of the given wire type in.
+    pub fn new(reader: R, wire_type: WireType) -> SkipValue<R> {
+        SkipValue(match wire_type {
+                      WireType::Varint => SkipState::Varint(ReadVarint::from_reader(reader)),
+                      WireType::Fixed64 => {
+                          SkipState::Skip {
+                              reader: Some(reader),
+                              remaining: 8,
+                              total: 8,
+                          }
+                      }
+                      WireType::LengthDelimited => {
+                          SkipState::Length(ReadVarint::from_reader(reader))
+                      }
+                      WireType::Fixed32 => {
+                          SkipState::Skip {
+                              reader: Some(reader),
+                              remaining: 4,
+                              total: 4,
+                          }
+                      }
+                  })
+    }
+
+    /// Return how many bytes have already been skipped.
+    pub fn already_read(&self) -> usize {
+        match self.0 {
+            SkipState::Varint(ref inner) |
+            SkipState::Length(ref inner) => inner.already_read(),
+            SkipState::Skip { remaining, total, .. } => total - remaining as usize,
+        }
+    }
+}
+
+impl<R> Restorable<R> for SkipValue<R> {
+    fn restore(self) -> Option<R> {
+        match self.0 {
+            SkipState::Varint(inner) |
+            SkipState::Length(inner) => inner.restore(),
+            SkipState::Skip { reader, .. } => reader,
+        }
+    }
+}
+
+impl<R: AsyncRead> Future for SkipValue<R> {
+    type Item = (R, usize);
+    type Error = (R, DeserializeError<ProtobufError>);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let (reader, len, prefix_len) = match self.0 {
+                SkipState::Varint(ref mut inner) => {
+                    let (reader, _, read) = try_ready!(inner.poll(cx));
+                    return Ok(Async::Ready((reader, read)));
+                }
+
+                SkipState::Length(ref mut inner) => try_ready!(inner.poll(cx)),
+
+                SkipState::Skip {
+                    ref mut reader,
+                    ref mut remaining,
+                    total,
+                } => {
+                    let mut r = or_pending!(reader.take());
+                    return match poll_skip(&mut r, cx, remaining) {
+                               Ok(Async::Ready(())) => Ok(Async::Ready((r, total))),
+                               Ok(Async::Pending) => {
+                                   *reader = Some(r);
+                                   Ok(Async::Pending)
+                               }
+                               Err(err) => Err((r, DeserializeError::ReaderError(err))),
+                           };
+                }
+            };
+
+            let total = match usize::try_from(len) {
+                Ok(len) if len <= usize::MAX - prefix_len => prefix_len + len,
+                _ => {
+                    return Err((reader, DeserializeError::DataError(ProtobufError::LengthOverflow)))
+                }
+            };
+
+            self.0 = SkipState::Skip {
+                reader: Some(reader),
+                remaining: len,
+                total,
+            };
+        }
+    }
+}
+
+/// A complete field: a key and a value of the wire type the key specifies.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Field {
+    /// The field number.
+    pub field_number: u32,
+    /// The value of the field.
+    pub value: Value,
+}
+
+/// Deserializes a key and then the value it announces into a `Field`.
+pub struct ReadField<R>(FieldState<R>);
+
+enum FieldState<R> {
+    Key(ReadKey<R>),
+    Value(ReadValue<R>, u32, usize),
+}
+
+impl<R: AsyncRead> Future for ReadField<R> {
+    type Item = (R, Field, usize);
+    type Error = (R, DeserializeError<ProtobufError>);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let value = match self.0 {
+                FieldState::Key(ref mut inner) => {
+                    let (reader, key, read) = try_ready!(inner.poll(cx));
+                    FieldState::Value(ReadValue::new(reader, key.wire_type),
+                                      key.field_number,
+                                      read)
+                }
+
+                FieldState::Value(ref mut inner, field_number, key_len) => {
+                    let (reader, value, read) = try_ready!(inner.poll(cx));
+                    let field = Field {
+                        field_number,
+                        value,
+                    };
+                    return Ok(Async::Ready((reader, field, key_len + read)));
+                }
+            };
+
+            self.0 = value;
+        }
+    }
+}
+
+impl<R: AsyncRead> AsyncDeserialize<R, Field, ProtobufError> for ReadField<R> {
+    fn from_reader(reader: R) -> Self {
+        ReadField(FieldState::Key(ReadKey::from_reader(reader)))
+    }
+
+    fn already_read(&self) -> usize {
+        match self.0 {
+            FieldState::Key(ref inner) => inner.already_read(),
+            FieldState::Value(ref inner, _, key_len) => key_len + inner.already_read(),
+        }
+    }
+}
+
+impl<R> Restorable<R> for ReadField<R> {
+    fn restore(self) -> Option<R> {
+        match self.0 {
+            FieldState::Key(inner) => inner.restore(),
+            FieldState::Value(inner, ..) => inner.restore(),
+        }
+    }
+}
+
+/// Everything that can go wrong when decoding protobuf fields.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProtobufError {
+    /// A varint does not fit into a `u64`.
+    VarintOverflow,
+    /// A key has a wire type that is not supported. Contains the wire type id.
+    InvalidWireType(u8),
+    /// A key has a field number outside of the valid range.
+    InvalidFieldNumber(u64),
+    /// The length of a `LengthDelimited` value does not fit into a `usize`.
+    LengthOverflow,
+    /// A string value is not valid UTF-8.
+    InvalidUtf8(Utf8Error),
+}
+
+impl Display for ProtobufError {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            ProtobufError::VarintOverflow => write!(f, "Varint overflows a u64"),
+            ProtobufError::InvalidWireType(id) => write!(f, "Invalid wire type: {}", id),
+            ProtobufError::InvalidFieldNumber(num) => write!(f, "Invalid field number: {}", num),
+            ProtobufError::LengthOverflow => write!(f, "Length overflows a usize"),
+            ProtobufError::InvalidUtf8(ref err) => write!(f, "Invalid utf8: {}", err),
+        }
+    }
+}
+
+impl Error for ProtobufError {}
@@ -0,0 +1,78 @@
+//! Limit how many bytes can be written into an `AsyncWrite` in total.
+
+use std::cmp::min;
+
+use futures_core::{Async, Poll};
+use futures_core::task::Context;
+use futures_io::{AsyncWrite, Error as FutIoErr, ErrorKind};
+
+/// Wraps an `AsyncWrite` and writes at most `quota` bytes into it, across all values serialized
+/// into it. Once the quota is exhausted, writing fails with an `ErrorKind::WriteZero` error.
+///
+/// This is useful for writers that represent a bounded resource, such as a fixed-size log
+/// segment: comparing `remaining_quota` with the `total_bytes` of the next value tells whether it
+/// still fits, and if it does not, the caller can move on to a new segment. A value that is
+/// written anyway is cut off where the quota ends.
+#[derive(Debug)]
+pub struct QuotaWriter<W> {
+    inner: W,
+    quota: u64,
+}
+
+impl<W> QuotaWriter<W> {
+    /// Create a new `QuotaWriter`, allowing at most `quota` bytes to be written into `inner`.
+    pub fn new(inner: W, quota: u64) -> QuotaWriter<W> {
+        QuotaWriter { inner, quota }
+    }
+
+    /// Return how many more bytes can be written before writing fails.
+    pub fn remaining_quota(&self) -> u64 {
+        self.quota
+    }
+
+    /// Change how many more bytes can be written before writing fails.
+    pub fn set_quota(&mut self, quota: u64) {
+        self.quota = quota;
+    }
+
+    /// Get a reference to the wrapped writer.
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Get a mutable reference to the wrapped writer.
+    ///
+    /// Writing into it directly does not count towards the quota.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// Consume this `QuotaWriter`, returning the wrapped writer.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: AsyncWrite> AsyncWrite for QuotaWriter<W> {
+    fn poll_write(&mut self, cx: &mut Context, buf: &[u8]) -> Poll<usize, FutIoErr> {
+        if buf.is_empty() {
+            return Ok(Async::Ready(0));
+        }
+        if self.quota == 0 {
+            return Err(FutIoErr::new(ErrorKind::WriteZero, "write quota exhausted"));
+        }
+
+        let max = min(buf.len() as u64, self.quota) as usize;
+        let written = try_ready!(self.inner.poll_write(cx, &buf[..max]));
+        self.quota -= written as u64;
+        Ok(Async::Ready(written))
+    }
+
+    fn poll_flush(&mut self, cx: &mut Context) -> Poll<(), FutIoErr> {
+        self.inner.poll_flush(cx)
+    }
+
+    fn poll_close(&mut self, cx: &mut Context) -> Poll<(), FutIoErr> {
+        self.inner.poll_close(cx)
+    }
+}
@@ -0,0 +1,168 @@
+//! Serialization of ranges of `u64`s, e.g. byte ranges or offsets into a log.
+//!
+//! A range is encoded as its start followed by its end, both as big-endian `u64`s, so every range
+//! takes exactly `RANGE_BYTES` bytes. `Range` and `RangeInclusive` share this encoding, the end of
+//! a `RangeInclusive` is simply the last value in the range rather than the first value after it.
+//! Which of the two a range is, is not part of the encoding. Formats that need both can precede
+//! the range with a [tag](../tagged/index.html).
+//!
+//! Deserializing rejects ranges whose start lies after their end with `RangeError`. Empty ranges
+//! whose start equals their end are accepted for `Range`.
+
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::ops::{Range, RangeInclusive};
+
+use futures_core::{Async, Future, Poll};
+use futures_core::task::Context;
+use futures_io::{AsyncRead, AsyncWrite, Error as FutIoErr};
+
+use {AsyncDeserialize, AsyncSerialize, AsyncSerializeLen, AsyncWriterFuture,
+     AsyncWriterFutureLen, DeserializeError, Restorable};
+use util::{ReadExact, WriteAll};
+
+/// The number of bytes a range takes up.
+pub const RANGE_BYTES: usize = 16;
+
+fn encode(start: u64, end: u64) -> [u8; RANGE_BYTES] {
+    let mut bytes = [0; RANGE_BYTES];
+    bytes[..8].copy_from_slice(&start.to_be_bytes());
+    bytes[8..].copy_from_slice(&end.to_be_bytes());
+    bytes
+}
+
+fn decode(bytes: [u8; RANGE_BYTES]) -> (u64, u64) {
+    let mut start = [0; 8];
+    let mut end = [0; 8];
+    start.copy_from_slice(&bytes[..8]);
+    end.copy_from_slice(&bytes[8..]);
+    (u64::from_be_bytes(start), u64::from_be_bytes(end))
+}
+
+macro_rules! ser_range {
+    ($name:ident, $t:ty, $bounds:expr, $doc:expr) => {
+        #[doc = $doc]
+        pub struct $name<W>(WriteAll<W, [u8; RANGE_BYTES]>);
+
+        impl<W: AsyncWrite> Future for $name<W> {
+            type Item = (W, usize);
+            type Error = (W, FutIoErr);
+
+            fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+                self.0.poll(cx)
+            }
+        }
+
+        impl<W: AsyncWrite> AsyncWriterFuture<W> for $name<W> {
+            fn already_written(&self) -> usize {
+                self.0.already_written()
+            }
+        }
+
+        impl<W: AsyncWrite> AsyncWriterFutureLen<W> for $name<W> {
+            fn remaining_bytes(&self) -> usize {
+                self.0.remaining_bytes()
+            }
+        }
+
+        impl<W: AsyncWrite> AsyncSerialize<W> for $name<W> {
+            type Serialized = $t;
+
+            fn from_val(writer: W, val: $t) -> Self {
+                let (start, end) = $bounds(&val);
+                $name(WriteAll::new(writer, encode(start, end)))
+            }
+        }
+
+        impl<W: AsyncWrite> AsyncSerializeLen<W> for $name<W> {
+            fn total_bytes(_: &$t) -> usize {
+                RANGE_BYTES
+            }
+        }
+    }
+}
+
+macro_rules! deser_range {
+    ($name:ident, $t:ty, $new:expr, $doc:expr) => {
+        #[doc = $doc]
+        pub struct $name<R>(ReadExact<R, [u8; RANGE_BYTES]>);
+
+        impl<R: AsyncRead> Future for $name<R> {
+            type Item = (R, $t, usize);
+            type Error = (R, DeserializeError<RangeError>);
+
+            fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+                match self.0.poll(cx) {
+                    Ok(Async::Ready((reader, bytes, read))) => {
+                        let (start, end) = decode(bytes);
+                        if start > end {
+                            let err = RangeError::InvalidRange { start, end };
+                            Err((reader, DeserializeError::DataError(err)))
+                        } else {
+                            Ok(Async::Ready((reader, $new(start, end), read)))
+                        }
+                    }
+                    Ok(Async::Pending) => Ok(Async::Pending),
+                    Err((reader, err)) => Err((reader, DeserializeError::ReaderError(err))),
+                }
+            }
+        }
+
+        impl<R: AsyncRead> AsyncDeserialize<R, $t, RangeError> for $name<R> {
+            fn from_reader(reader: R) -> Self {
+                $name(ReadExact::new(reader, [0; RANGE_BYTES]))
+            }
+
+            fn already_read(&self) -> usize {
+                self.0.already_read()
+            }
+        }
+
+        impl<R> Restorable<R> for $name<R> {
+            fn restore(self) -> Option<R> {
+                self.0.restore()
+            }
+        }
+    }
+}
+
+ser_range!(SerRange,
+           Range<u64>,
+           |val: &Range<u64>| (val.start, val.end),
+           "Serializes a `Range<u64>`.");
+ser_range!(SerRangeInclusive,
+           RangeInclusive<u64>,
+           |val: &RangeInclusive<u64>| (*val.start(), *val.end()),
+           "Serializes a `RangeInclusive<u64>`, writing the inclusive end.");
+deser_range!(DeserRange,
+             Range<u64>,
+             |start, end| start..end,
+             "Deserializes a `Range<u64>`.");
+deser_range!(DeserRangeInclusive,
+             RangeInclusive<u64>,
+             |start, end| start..=end,
+             "Deserializes a `RangeInclusive<u64>`, reading the inclusive end.");
+
+/// Everything that can go wrong when deserializing a range.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RangeError {
+    /// The start of the range lies after its end.
+    InvalidRange {
+        /// The start of the range.
+        start: u64,
+        /// The end of the range.
+        end: u64,
+    },
+}
+
+impl Display for RangeError {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            RangeError::InvalidRange { start, end } => {
+                write!(f, "Invalid range: start {} lies after end {}", start, end)
+            }
+        }
+    }
+}
+
+impl Error for RangeError {}
@@ -0,0 +1,400 @@
+//! Store a value twice, so that corruption of either copy is detected when reading it.
+//!
+//! A redundant value is encoded as the value, followed by a second copy of it. With
+//! `Redundancy::Copy`, the second copy is byte-for-byte the same as the first, with
+//! `Redundancy::Complement` every byte of it is inverted, which additionally detects bits that
+//! are stuck at the same value throughout a storage device. Both copies are read via the same
+//! deserializer and must deserialize to equal values.
+//!
+//! This is meant for small critical values like a superblock, for which doubling the size is
+//! cheap. Both copies are serialized through a `RedundantWriter` and read through a
+//! `RedundantReader`, which invert the bytes of the second copy if needed.
+
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::marker::PhantomData;
+
+use futures_core::{Async, Future, Poll};
+use futures_core::task::Context;
+use futures_io::{AsyncRead, AsyncWrite, Error as FutIoErr};
+
+use {AsyncDeserialize, AsyncSerialize, AsyncSerializeLen, AsyncWriterFuture,
+     AsyncWriterFutureLen, DeserializeError, Restorable};
+
+/// How the second copy of a redundant value is encoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Redundancy {
+    /// The second copy equals the first one.
+    Copy,
+    /// Every byte of the second copy is the bitwise complement of the corresponding byte of the
+    /// first one.
+    Complement,
+}
+
+/// Wraps an `AsyncWrite` and inverts all bytes written to it while writing the second copy of a
+/// `Redundancy::Complement` value.
+#[derive(Debug)]
+pub struct RedundantWriter<W> {
+    inner: W,
+    complement: bool,
+    buf: Vec<u8>,
+}
+
+impl<W> RedundantWriter<W> {
+    fn new(inner: W, complement: bool) -> RedundantWriter<W> {
+        RedundantWriter {
+            inner,
+            complement,
+            buf: Vec::new(),
+        }
+    }
+
+    /// Return whether the written bytes are currently being inverted.
+    pub fn is_complement(&self) -> bool {
+        self.complement
+    }
+
+    /// Get a reference to the wrapped writer.
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Get a mutable reference to the wrapped writer.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// Consume this `RedundantWriter`, returning the wrapped writer.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: AsyncWrite> AsyncWrite for RedundantWriter<W> {
+    fn poll_write(&mut self, cx: &mut Context, buf: &[u8]) -> Poll<usize, FutIoErr> {
+        if !self.complement {
+            return self.inner.poll_write(cx, buf);
+        }
+
+        self.buf.clear();
+        self.buf.extend(buf.iter().map(|byte| !byte));
+        self.inner.poll_write(cx, &self.buf)
+    }
+
+    fn poll_flush(&mut self, cx: &mut Context) -> Poll<(), FutIoErr> {
+        self.inner.poll_flush(cx)
+    }
+
+    fn poll_close(&mut self, cx: &mut Context) -> Poll<(), FutIoErr> {
+        self.inner.poll_close(cx)
+    }
+}
+
+/// Wraps an `AsyncRead` and inverts all bytes read from it while reading the second copy of a
+/// `Redundancy::Complement` value.
+#[derive(Debug)]
+pub struct RedundantReader<R> {
+    inner: R,
+    complement: bool,
+}
+
+impl<R> RedundantReader<R> {
+    /// Return whether the read bytes are currently being inverted.
+    pub fn is_complement(&self) -> bool {
+        self.complement
+    }
+
+    /// Get a reference to the wrapped reader.
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// Get a mutable reference to the wrapped reader.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    /// Consume this `RedundantReader`, returning the wrapped reader.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: AsyncRead> AsyncRead for RedundantReader<R> {
+    fn poll_read(&mut self, cx: &mut Context, buf: &mut [u8]) -> Poll<usize, FutIoErr> {
+        let read = try_ready!(self.inner.poll_read(cx, buf));
+        if self.complement {
+            for byte in &mut buf[..read] {
+                *byte = !*byte;
+            }
+        }
+        Ok(Async::Ready(read))
+    }
+}
+
+/// Serializes a value twice via `F`, the second time from a clone of the value.
+///
+/// `from_val` uses `Redundancy::Copy`, use `new` to choose the encoding of the second copy.
+pub struct WriteRedundant<F, W>
+    where F: AsyncSerialize<RedundantWriter<W>>,
+          W: AsyncWrite
+{
+    state: WriteState<F, F::Serialized>,
+    redundancy: Redundancy,
+    written: usize,
+}
+
+enum WriteState<F, T> {
+    First(F, Option<T>),
+    Second(F),
+}
+
+impl<F, W> WriteRedundant<F, W>
+    where F: AsyncSerialize<RedundantWriter<W>>,
+          F::Serialized: Clone,
+          W: AsyncWrite
+{
+    /// Create a new `WriteRedundant`, encoding the second copy of `val` as given by `redundancy`.
+    pub fn new(writer: W, redundancy: Redundancy, val: F::Serialized) -> WriteRedundant<F, W> {
+        let second = val.clone();
+        WriteRedundant {
+            state: WriteState::First(F::from_val(RedundantWriter::new(writer, false), val),
+                                     Some(second)),
+            redundancy,
+            written: 0,
+        }
+    }
+}
+
+impl<F, W> Future for WriteRedundant<F, W>
+    where F: AsyncSerialize<RedundantWriter<W>>,
+          W: AsyncWrite
+{
+    type Item = (W, usize);
+    type Error = (W, FutIoErr);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let next = match self.state {
+                WriteState::First(ref mut inner, ref mut val) => {
+                    let (mut writer, written) = match inner.poll(cx) {
+                        Ok(Async::Ready(done)) => done,
+                        Ok(Async::Pending) => return Ok(Async::Pending),
+                        Err((writer, err)) => return Err((writer.into_inner(), err)),
+                    };
+                    self.written = written;
+                    writer.complement = self.redundancy == Redundancy::Complement;
+
+                    let val = or_pending!(val.take());
+                    WriteState::Second(F::from_val(writer, val))
+                }
+
+                WriteState::Second(ref mut inner) => {
+                    return match inner.poll(cx) {
+                        Ok(Async::Ready((writer, written))) => {
+                            Ok(Async::Ready((writer.into_inner(), self.written + written)))
+                        }
+                        Ok(Async::Pending) => Ok(Async::Pending),
+                        Err((writer, err)) => Err((writer.into_inner(), err)),
+                    };
+                }
+            };
+            self.state = next;
+        }
+    }
+}
+
+impl<F, W> AsyncWriterFuture<W> for WriteRedundant<F, W>
+    where F: AsyncSerialize<RedundantWriter<W>>,
+          W: AsyncWrite
+{
+    fn already_written(&self) -> usize {
+        match self.state {
+            WriteState::First(ref inner, _) => inner.already_written(),
+            WriteState::Second(ref inner) => self.written + inner.already_written(),
+        }
+    }
+}
+
+impl<F, W> AsyncWriterFutureLen<W> for WriteRedundant<F, W>
+    where F: AsyncSerializeLen<RedundantWriter<W>>,
+          W: AsyncWrite
+{
+    fn remaining_bytes(&self) -> usize {
+        match self.state {
+            WriteState::First(ref inner, ref val) => {
+                inner.remaining_bytes() + val.as_ref().map(F::total_bytes).unwrap_or(0)
+            }
+            WriteState::Second(ref inner) => inner.remaining_bytes(),
+        }
+    }
+}
+
+impl<F, W> AsyncSerialize<W> for WriteRedundant<F, W>
+    where F: AsyncSerialize<RedundantWriter<W>>,
+          F::Serialized: Clone,
+          W: AsyncWrite
+{
+    type Serialized = F::Serialized;
+
+    fn from_val(writer: W, val: F::Serialized) -> Self {
+        WriteRedundant::new(writer, Redundancy::Copy, val)
+    }
+}
+
+impl<F, W> AsyncSerializeLen<W> for WriteRedundant<F, W>
+    where F: AsyncSerializeLen<RedundantWriter<W>>,
+          F::Serialized: Clone,
+          W: AsyncWrite
+{
+    fn total_bytes(val: &F::Serialized) -> usize {
+        2 * F::total_bytes(val)
+    }
+}
+
+/// Deserializes both copies of a redundant value via `D`, failing with
+/// `RedundantError::RedundancyMismatch` if they differ.
+///
+/// `from_reader` expects `Redundancy::Copy`, use `new` to read values with a complemented second
+/// copy.
+pub struct ReadRedundant<D, R, T> {
+    state: ReadState<D, T>,
+    redundancy: Redundancy,
+    read: usize,
+    _reader: PhantomData<R>,
+}
+
+enum ReadState<D, T> {
+    First(D),
+    Second(D, Option<T>),
+}
+
+impl<D, R, T, E> ReadRedundant<D, R, T>
+    where D: AsyncDeserialize<RedundantReader<R>, T, E>,
+          D: Future<Item = (RedundantReader<R>, T, usize),
+                    Error = (RedundantReader<R>, DeserializeError<E>)>,
+          R: AsyncRead,
+          T: PartialEq
+{
+    /// Create a new `ReadRedundant`, expecting the second copy to be encoded as given by
+    /// `redundancy`.
+    pub fn new(reader: R, redundancy: Redundancy) -> ReadRedundant<D, R, T> {
+        let reader = RedundantReader {
+            inner: reader,
+            complement: false,
+        };
+        ReadRedundant {
+            state: ReadState::First(D::from_reader(reader)),
+            redundancy,
+            read: 0,
+            _reader: PhantomData,
+        }
+    }
+}
+
+impl<D, R, T, E> Future for ReadRedundant<D, R, T>
+    where D: AsyncDeserialize<RedundantReader<R>, T, E>,
+          D: Future<Item = (RedundantReader<R>, T, usize),
+                    Error = (RedundantReader<R>, DeserializeError<E>)>,
+          R: AsyncRead,
+          T: PartialEq
+{
+    type Item = (R, T, usize);
+    type Error = (R, DeserializeError<RedundantError<E>>);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let next = match self.state {
+                ReadState::First(ref mut inner) => {
+                    let (mut reader, val, read) = match inner.poll(cx) {
+                        Ok(Async::Ready(done)) => done,
+                        Ok(Async::Pending) => return Ok(Async::Pending),
+                        Err((reader, DeserializeError::ReaderError(err))) => {
+                            return Err((reader.into_inner(), DeserializeError::ReaderError(err)))
+                        }
+                        Err((reader, DeserializeError::DataError(err))) => {
+                            return Err((reader.into_inner(),
+                                        DeserializeError::DataError(RedundantError::First(err))))
+                        }
+                    };
+                    self.read = read;
+                    reader.complement = self.redundancy == Redundancy::Complement;
+                    ReadState::Second(D::from_reader(reader), Some(val))
+                }
+
+                ReadState::Second(ref mut inner, ref mut first) => {
+                    let (reader, second, read) = match inner.poll(cx) {
+                        Ok(Async::Ready(done)) => done,
+                        Ok(Async::Pending) => return Ok(Async::Pending),
+                        Err((reader, DeserializeError::ReaderError(err))) => {
+                            return Err((reader.into_inner(), DeserializeError::ReaderError(err)))
+                        }
+                        Err((reader, DeserializeError::DataError(err))) => {
+                            return Err((reader.into_inner(),
+                                        DeserializeError::DataError(RedundantError::Second(err))))
+                        }
+                    };
+
+                    let first = or_pending!(first.take());
+                    if first != second {
+                        let err = RedundantError::RedundancyMismatch;
+                        return Err((reader.into_inner(), DeserializeError::DataError(err)));
+                    }
+                    return Ok(Async::Ready((reader.into_inner(), first, self.read + read)));
+                }
+            };
+            self.state = next;
+        }
+    }
+}
+
+impl<D, R, T, E> AsyncDeserialize<R, T, RedundantError<E>> for ReadRedundant<D, R, T>
+    where D: AsyncDeserialize<RedundantReader<R>, T, E>,
+          R: AsyncRead,
+          T: PartialEq
+{
+    fn from_reader(reader: R) -> Self {
+        ReadRedundant::new(reader, Redundancy::Copy)
+    }
+
+    fn already_read(&self) -> usize {
+        match self.state {
+            ReadState::First(ref inner) => inner.already_read(),
+            ReadState::Second(ref inner, _) => self.read + inner.already_read(),
+        }
+    }
+}
+
+impl<D, R, T> Restorable<R> for ReadRedundant<D, R, T>
+    where D: Restorable<RedundantReader<R>>
+{
+    fn restore(self) -> Option<R> {
+        match self.state {
+            ReadState::First(inner) |
+            ReadState::Second(inner, _) => inner.restore().map(RedundantReader::into_inner),
+        }
+    }
+}
+
+/// Everything that can go wrong when deserializing a redundant value, apart from reader errors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RedundantError<E> {
+    /// The first copy could not be deserialized.
+    First(E),
+    /// The second copy could not be deserialized.
+    Second(E),
+    /// The two copies deserialized to different values.
+    RedundancyMismatch,
+}
+
+impl<E: Display> Display for RedundantError<E> {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            RedundantError::First(ref err) => write!(f, "Invalid first copy: {}", err),
+            RedundantError::Second(ref err) => write!(f, "Invalid second copy: {}", err),
+            RedundantError::RedundancyMismatch => write!(f, "The two copies differ"),
+        }
+    }
+}
+
+impl<E: Error> Error for RedundantError<E> {}
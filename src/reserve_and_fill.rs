@@ -0,0 +1,135 @@
+//! Serialize a value behind a reserved prefix whose content is only known once the whole value
+//! has been serialized (e.g. a checksum).
+
+use std::io::Cursor;
+
+use futures_core::{Async, Future, Poll};
+use futures_core::task::Context;
+use futures_io::{AsyncWrite, Error as FutIoErr, ErrorKind};
+
+use {AsyncSerialize, AsyncWriterFuture, AsyncWriterFutureLen};
+use util::poll_write_buf;
+
+/// Serializes a value into an in-memory buffer behind `reserved` placeholder bytes, lets a
+/// callback compute the content of those bytes from the serialized body, and then writes prefix
+/// and body into the wrapped `AsyncWrite`.
+///
+/// This works for writers that can not seek, at the cost of buffering the *whole* serialized
+/// value in memory: nothing is written to the wrapped writer until the inner serializer has
+/// finished, and the buffer lives until everything has been written.
+///
+/// The callback must return exactly `reserved` bytes, otherwise the future errors with
+/// `ErrorKind::InvalidInput` before anything has been written.
+pub struct ReserveAndFill<F, W, C> {
+    writer: Option<W>,
+    state: State<F, C>,
+    reserved: usize,
+    written: usize,
+}
+
+enum State<F, C> {
+    Body(F, Option<C>),
+    Flush(Vec<u8>),
+}
+
+impl<F, W, C> ReserveAndFill<F, W, C>
+    where F: AsyncSerialize<Cursor<Vec<u8>>>
+{
+    /// Create a new `ReserveAndFill`, wrapping the `AsyncWrite` to eventually write into and
+    /// consuming the value to serialize.
+    ///
+    /// `fill` receives the serialized body and returns the `reserved` bytes to put in front of it.
+    pub fn new(writer: W, val: F::Serialized, reserved: usize, fill: C) -> ReserveAndFill<F, W, C> {
+        let mut buf = Cursor::new(vec![0; reserved]);
+        buf.set_position(reserved as u64);
+
+        ReserveAndFill {
+            writer: Some(writer),
+            state: State::Body(F::from_val(buf, val), Some(fill)),
+            reserved,
+            written: 0,
+        }
+    }
+}
+
+impl<F, W, C> Future for ReserveAndFill<F, W, C>
+    where F: AsyncWriterFuture<Cursor<Vec<u8>>>,
+          W: AsyncWrite,
+          C: FnOnce(&[u8]) -> Vec<u8>
+{
+    type Item = (W, usize);
+    type Error = (W, FutIoErr);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let buf = match self.state {
+                State::Body(ref mut inner, ref mut fill) => {
+                    match inner.poll(cx) {
+                        Ok(Async::Ready((cursor, _))) => {
+                            let mut buf = cursor.into_inner();
+                            let fill = or_pending!(fill.take());
+                            let prefix = fill(&buf[self.reserved..]);
+
+                            if prefix.len() != self.reserved {
+                                let err = FutIoErr::new(ErrorKind::InvalidInput,
+                                                        "fill returned a prefix of wrong length");
+                                return Err((or_pending!(self.writer.take()),
+                                            err));
+                            }
+
+                            buf[..self.reserved].copy_from_slice(&prefix);
+                            buf
+                        }
+                        Ok(Async::Pending) => return Ok(Async::Pending),
+                        Err((_, err)) => {
+                            return Err((or_pending!(self.writer.take()),
+                                        err))
+                        }
+                    }
+                }
+
+                State::Flush(ref buf) => {
+                    let mut writer = or_pending!(self.writer.take());
+
+                    return match poll_write_buf(&mut writer, cx, buf, &mut self.written) {
+                               Ok(Async::Ready(())) => Ok(Async::Ready((writer, self.written))),
+                               Ok(Async::Pending) => {
+                                   self.writer = Some(writer);
+                                   Ok(Async::Pending)
+                               }
+                               Err(err) => Err((writer, err)),
+                           };
+                }
+            };
+
+            self.state = State::Flush(buf);
+        }
+    }
+}
+
+impl<F, W, C> AsyncWriterFuture<W> for ReserveAndFill<F, W, C>
+    where F: AsyncWriterFuture<Cursor<Vec<u8>>>,
+          W: AsyncWrite,
+          C: FnOnce(&[u8]) -> Vec<u8>
+{
+    /// Return how many bytes have already been written into the wrapped writer. This stays zero
+    /// while the inner value is still being buffered.
+    fn already_written(&self) -> usize {
+        self.written
+    }
+}
+
+impl<F, W, C> AsyncWriterFutureLen<W> for ReserveAndFill<F, W, C>
+    where F: AsyncWriterFutureLen<Cursor<Vec<u8>>>,
+          W: AsyncWrite,
+          C: FnOnce(&[u8]) -> Vec<u8>
+{
+    fn remaining_bytes(&self) -> usize {
+        match self.state {
+            State::Body(ref inner, _) => {
+                self.reserved + inner.already_written() + inner.remaining_bytes()
+            }
+            State::Flush(ref buf) => buf.len() - self.written,
+        }
+    }
+}
@@ -0,0 +1,99 @@
+//! Write into a fixed-size ring buffer that overwrites the oldest data.
+
+use std::cmp::min;
+
+use futures_core::{Async, Poll};
+use futures_core::task::Context;
+use futures_io::{AsyncWrite, Error as FutIoErr};
+
+/// An `AsyncWrite` over a ring buffer of `N` bytes. Once the buffer is full, writing overwrites
+/// the oldest bytes, so this always accepts all bytes immediately and the buffer holds the last
+/// `N` bytes that were written.
+#[derive(Debug)]
+pub struct RingWriter<const N: usize> {
+    buf: [u8; N],
+    position: u64,
+}
+
+impl<const N: usize> RingWriter<N> {
+    /// Create a new, empty `RingWriter`.
+    pub fn new() -> RingWriter<N> {
+        RingWriter {
+            buf: [0; N],
+            position: 0,
+        }
+    }
+
+    /// Return how many bytes have been written in total, including those that have been
+    /// overwritten since.
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Return how many bytes the buffer currently holds.
+    pub fn len(&self) -> usize {
+        min(self.position, N as u64) as usize
+    }
+
+    /// Return whether the buffer holds no bytes.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Return the bytes the buffer currently holds, from oldest to newest.
+    pub fn contents(&self) -> Vec<u8> {
+        let len = self.len();
+        if len < N {
+            return self.buf[..len].to_vec();
+        }
+
+        // The buffer is full, the oldest byte is the one that will be overwritten next.
+        let offset = self.offset();
+        let mut contents = Vec::with_capacity(N);
+        contents.extend_from_slice(&self.buf[offset..]);
+        contents.extend_from_slice(&self.buf[..offset]);
+        contents
+    }
+
+    // The index in the buffer at which the next byte is written.
+    fn offset(&self) -> usize {
+        if N == 0 {
+            0
+        } else {
+            (self.position % N as u64) as usize
+        }
+    }
+}
+
+impl<const N: usize> Default for RingWriter<N> {
+    fn default() -> RingWriter<N> {
+        RingWriter::new()
+    }
+}
+
+impl<const N: usize> AsyncWrite for RingWriter<N> {
+    fn poll_write(&mut self, _: &mut Context, buf: &[u8]) -> Poll<usize, FutIoErr> {
+        // Only the last N bytes of `buf` survive anyway.
+        let skipped = buf.len().saturating_sub(N);
+        self.position += skipped as u64;
+        let mut rest = &buf[skipped..];
+
+        while !rest.is_empty() {
+            let offset = self.offset();
+            let len = min(rest.len(), N - offset);
+            self.buf[offset..offset + len].copy_from_slice(&rest[..len]);
+            self.position += len as u64;
+            rest = &rest[len..];
+        }
+
+        Ok(Async::Ready(buf.len()))
+    }
+
+    fn poll_flush(&mut self, _: &mut Context) -> Poll<(), FutIoErr> {
+        Ok(Async::Ready(()))
+    }
+
+    fn poll_close(&mut self, _: &mut Context) -> Poll<(), FutIoErr> {
+        Ok(Async::Ready(()))
+    }
+}
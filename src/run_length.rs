@@ -0,0 +1,304 @@
+//! Run-length encoding of sequences with long runs of equal values.
+//!
+//! A sequence of runs is encoded as the number of runs as a [varint](../varint/index.html),
+//! followed by each run as its length as a big-endian `u16` and then its value.
+//!
+//! So the sequence `[0, 0, 0, 0, 1, 1]`, i.e. the runs `[(0, 4), (1, 2)]`, with single-byte values
+//! encodes as `[0x02, 0x00, 0x04, 0x00, 0x00, 0x02, 0x01]`.
+
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::iter::repeat_n;
+use std::marker::PhantomData;
+use std::mem;
+
+use futures_core::{Async, Future, Poll};
+use futures_core::task::Context;
+use futures_io::{AsyncRead, AsyncWrite, Error as FutIoErr};
+
+use {AsyncDeserialize, AsyncSerialize, AsyncSerializeLen, AsyncSerializeRef,
+     AsyncSerializeRefLen, AsyncWriterFuture, AsyncWriterFutureLen, DeserializeError,
+     Restorable};
+use util::{ReadExact, WriteAll};
+use varint::{varint_len, ReadVarint, VarintBuf, VarintError};
+
+/// Serializes a slice of runs, given as pairs of a value and the length of the run. The values are
+/// serialized via `S`.
+pub struct SerRLE<'val, S, W>
+    where S: AsyncSerialize<W>,
+          W: AsyncWrite
+{
+    state: SerState<S, W>,
+    runs: &'val [(S::Serialized, u16)],
+    index: usize,
+    written: usize,
+}
+
+enum SerState<S, W> {
+    Count(WriteAll<W, VarintBuf>),
+    Length(WriteAll<W, [u8; 2]>),
+    Value(S),
+    Done,
+}
+
+impl<'val, S, W> Future for SerRLE<'val, S, W>
+    where S: AsyncSerialize<W>,
+          S::Serialized: Clone,
+          W: AsyncWrite
+{
+    type Item = (W, usize);
+    type Error = (W, FutIoErr);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let (writer, written) = match self.state {
+                SerState::Count(ref mut inner) => try_ready!(inner.poll(cx)),
+                SerState::Length(ref mut inner) => try_ready!(inner.poll(cx)),
+                SerState::Value(ref mut inner) => try_ready!(inner.poll(cx)),
+                SerState::Done => return Ok(Async::Pending),
+            };
+            self.written += written;
+
+            self.state = match self.state {
+                SerState::Length(_) => {
+                    let val = self.runs[self.index].0.clone();
+                    self.index += 1;
+                    SerState::Value(S::from_val(writer, val))
+                }
+                _ if self.index < self.runs.len() => {
+                    let len = self.runs[self.index].1;
+                    SerState::Length(WriteAll::new(writer, len.to_be_bytes()))
+                }
+                _ => {
+                    self.state = SerState::Done;
+                    return Ok(Async::Ready((writer, self.written)));
+                }
+            };
+        }
+    }
+}
+
+impl<'val, S, W> AsyncWriterFuture<W> for SerRLE<'val, S, W>
+    where S: AsyncSerialize<W>,
+          S::Serialized: Clone,
+          W: AsyncWrite
+{
+    fn already_written(&self) -> usize {
+        self.written +
+        match self.state {
+            SerState::Count(ref inner) => inner.already_written(),
+            SerState::Length(ref inner) => inner.already_written(),
+            SerState::Value(ref inner) => inner.already_written(),
+            SerState::Done => 0,
+        }
+    }
+}
+
+impl<'val, S, W> AsyncWriterFutureLen<W> for SerRLE<'val, S, W>
+    where S: AsyncSerializeLen<W>,
+          S::Serialized: Clone,
+          W: AsyncWrite
+{
+    fn remaining_bytes(&self) -> usize {
+        let current = match self.state {
+            SerState::Count(ref inner) => inner.remaining_bytes(),
+            SerState::Length(ref inner) => {
+                inner.remaining_bytes() + S::total_bytes(&self.runs[self.index].0)
+            }
+            SerState::Value(ref inner) => inner.remaining_bytes(),
+            SerState::Done => return 0,
+        };
+
+        let next = match self.state {
+            SerState::Length(_) => self.index + 1,
+            _ => self.index,
+        };
+
+        current + runs_len::<S, W>(&self.runs[next..])
+    }
+}
+
+fn runs_len<S, W>(runs: &[(S::Serialized, u16)]) -> usize
+    where S: AsyncSerializeLen<W>,
+          W: AsyncWrite
+{
+    runs.iter().map(|run| 2 + S::total_bytes(&run.0)).sum()
+}
+
+impl<'val, S, W> AsyncSerializeRef<'val, W> for SerRLE<'val, S, W>
+    where S: AsyncSerialize<W>,
+          S::Serialized: Clone,
+          W: AsyncWrite
+{
+    type Serialized = [(S::Serialized, u16)];
+
+    fn from_ref(writer: W, val: &'val [(S::Serialized, u16)]) -> Self {
+        SerRLE {
+            state: SerState::Count(WriteAll::new(writer, VarintBuf::new(val.len() as u64))),
+            runs: val,
+            index: 0,
+            written: 0,
+        }
+    }
+}
+
+impl<'val, S, W> AsyncSerializeRefLen<'val, W> for SerRLE<'val, S, W>
+    where S: AsyncSerializeLen<W>,
+          S::Serialized: Clone,
+          W: AsyncWrite
+{
+    fn total_bytes(val: &[(S::Serialized, u16)]) -> usize {
+        varint_len(val.len() as u64) + runs_len::<S, W>(val)
+    }
+}
+
+/// Deserializes a sequence of runs via `D`, expanding them into a `Vec`.
+pub struct DeserRLE<D, R, T, E> {
+    state: DeserState<D, R>,
+    remaining: u64,
+    len: u16,
+    read: usize,
+    values: Vec<T>,
+    _error: PhantomData<E>,
+}
+
+enum DeserState<D, R> {
+    Count(ReadVarint<R>),
+    Length(ReadExact<R, [u8; 2]>),
+    Value(D),
+    Done,
+}
+
+impl<D, R, T, E> Future for DeserRLE<D, R, T, E>
+    where D: AsyncDeserialize<R, T, E>,
+          R: AsyncRead,
+          T: Clone
+{
+    type Item = (R, Vec<T>, usize);
+    type Error = (R, DeserializeError<RLEError<E>>);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let reader = match self.state {
+                DeserState::Count(ref mut inner) => {
+                    match inner.poll(cx) {
+                        Ok(Async::Ready((reader, count, read))) => {
+                            self.remaining = count;
+                            self.read += read;
+                            reader
+                        }
+                        Ok(Async::Pending) => return Ok(Async::Pending),
+                        Err((reader, DeserializeError::ReaderError(err))) => {
+                            return Err((reader, DeserializeError::ReaderError(err)))
+                        }
+                        Err((reader, DeserializeError::DataError(VarintError::Overflow))) => {
+                            return Err((reader,
+                                        DeserializeError::DataError(RLEError::VarintOverflow)))
+                        }
+                    }
+                }
+
+                DeserState::Length(ref mut inner) => {
+                    match inner.poll(cx) {
+                        Ok(Async::Ready((reader, len, read))) => {
+                            self.len = u16::from_be_bytes(len);
+                            self.read += read;
+                            self.state = DeserState::Value(D::from_reader(reader));
+                            continue;
+                        }
+                        Ok(Async::Pending) => return Ok(Async::Pending),
+                        Err((reader, err)) => {
+                            return Err((reader, DeserializeError::ReaderError(err)))
+                        }
+                    }
+                }
+
+                DeserState::Value(ref mut inner) => {
+                    match inner.poll(cx) {
+                        Ok(Async::Ready((reader, val, read))) => {
+                            self.values.extend(repeat_n(val, self.len as usize));
+                            self.read += read;
+                            self.remaining -= 1;
+                            reader
+                        }
+                        Ok(Async::Pending) => return Ok(Async::Pending),
+                        Err((reader, DeserializeError::ReaderError(err))) => {
+                            return Err((reader, DeserializeError::ReaderError(err)))
+                        }
+                        Err((reader, DeserializeError::DataError(err))) => {
+                            return Err((reader, DeserializeError::DataError(RLEError::Inner(err))))
+                        }
+                    }
+                }
+
+                DeserState::Done => return Ok(Async::Pending),
+            };
+
+            if self.remaining == 0 {
+                self.state = DeserState::Done;
+                return Ok(Async::Ready((reader, mem::take(&mut self.values), self.read)));
+            }
+            self.state = DeserState::Length(ReadExact::new(reader, [0; 2]));
+        }
+    }
+}
+
+impl<D, R, T, E> AsyncDeserialize<R, Vec<T>, RLEError<E>> for DeserRLE<D, R, T, E>
+    where D: AsyncDeserialize<R, T, E>,
+          R: AsyncRead,
+          T: Clone
+{
+    fn from_reader(reader: R) -> Self {
+        DeserRLE {
+            state: DeserState::Count(ReadVarint::from_reader(reader)),
+            remaining: 0,
+            len: 0,
+            read: 0,
+            values: Vec::new(),
+            _error: PhantomData,
+        }
+    }
+
+    fn already_read(&self) -> usize {
+        self.read +
+        match self.state {
+            DeserState::Count(ref inner) => inner.already_read(),
+            DeserState::Length(ref inner) => inner.already_read(),
+            DeserState::Value(ref inner) => inner.already_read(),
+            DeserState::Done => 0,
+        }
+    }
+}
+
+impl<D, R, T, E> Restorable<R> for DeserRLE<D, R, T, E>
+    where D: Restorable<R>
+{
+    fn restore(self) -> Option<R> {
+        match self.state {
+            DeserState::Count(count) => count.restore(),
+            DeserState::Length(length) => length.restore(),
+            DeserState::Done => None,
+            DeserState::Value(value) => value.restore(),
+        }
+    }
+}
+
+/// Everything that can go wrong when deserializing a run-length encoded sequence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RLEError<E> {
+    /// The number of runs does not fit into a `u64`.
+    VarintOverflow,
+    /// The value of a run could not be deserialized.
+    Inner(E),
+}
+
+impl<E: Display> Display for RLEError<E> {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            RLEError::VarintOverflow => write!(f, "Varint overflows a u64"),
+            RLEError::Inner(ref err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl<E: Error> Error for RLEError<E> {}
@@ -0,0 +1,118 @@
+//! Serialization of `Saturating` values, by serializing the wrapped value.
+//!
+//! A `Saturating<T>` is encoded exactly like the `T` it wraps, so e.g. a `Saturating<u64>`
+//! serialized via `SerSaturating<WriteVarint<W>, W>` is indistinguishable from a plain varint.
+//! The saturating semantics are not part of the encoding: the bytes only hold the current value,
+//! and whether arithmetic on it saturates is decided by the type the reader deserializes into.
+
+use std::marker::PhantomData;
+use std::num::Saturating;
+
+use futures_core::{Async, Future, Poll};
+use futures_core::task::Context;
+use futures_io::{AsyncRead, AsyncWrite, Error as FutIoErr};
+
+use {AsyncDeserialize, AsyncSerialize, AsyncSerializeLen, AsyncWriterFuture,
+     AsyncWriterFutureLen, DeserializeError, Restorable};
+
+/// Serializes a `Saturating<S::Serialized>` by serializing the wrapped value via `S`.
+pub struct SerSaturating<S, W> {
+    inner: S,
+    _writer: PhantomData<W>,
+}
+
+impl<S, W> Future for SerSaturating<S, W>
+    where S: AsyncSerialize<W>,
+          W: AsyncWrite
+{
+    type Item = (W, usize);
+    type Error = (W, FutIoErr);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        self.inner.poll(cx)
+    }
+}
+
+impl<S, W> AsyncWriterFuture<W> for SerSaturating<S, W>
+    where S: AsyncSerialize<W>,
+          W: AsyncWrite
+{
+    fn already_written(&self) -> usize {
+        self.inner.already_written()
+    }
+}
+
+impl<S, W> AsyncWriterFutureLen<W> for SerSaturating<S, W>
+    where S: AsyncSerializeLen<W>,
+          W: AsyncWrite
+{
+    fn remaining_bytes(&self) -> usize {
+        self.inner.remaining_bytes()
+    }
+}
+
+impl<S, W> AsyncSerialize<W> for SerSaturating<S, W>
+    where S: AsyncSerialize<W>,
+          W: AsyncWrite
+{
+    type Serialized = Saturating<S::Serialized>;
+
+    fn from_val(writer: W, val: Saturating<S::Serialized>) -> Self {
+        SerSaturating {
+            inner: S::from_val(writer, val.0),
+            _writer: PhantomData,
+        }
+    }
+}
+
+impl<S, W> AsyncSerializeLen<W> for SerSaturating<S, W>
+    where S: AsyncSerializeLen<W>,
+          W: AsyncWrite
+{
+    fn total_bytes(val: &Saturating<S::Serialized>) -> usize {
+        S::total_bytes(&val.0)
+    }
+}
+
+/// Deserializes a `Saturating<T>` by deserializing the wrapped value via `D`.
+pub struct DeserSaturating<D, R, T, E> {
+    inner: D,
+    _types: PhantomData<(R, T, E)>,
+}
+
+impl<D, R, T, E> Future for DeserSaturating<D, R, T, E>
+    where D: AsyncDeserialize<R, T, E>,
+          R: AsyncRead
+{
+    type Item = (R, Saturating<T>, usize);
+    type Error = (R, DeserializeError<E>);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        let (reader, val, read) = try_ready!(self.inner.poll(cx));
+        Ok(Async::Ready((reader, Saturating(val), read)))
+    }
+}
+
+impl<D, R, T, E> AsyncDeserialize<R, Saturating<T>, E> for DeserSaturating<D, R, T, E>
+    where D: AsyncDeserialize<R, T, E>,
+          R: AsyncRead
+{
+    fn from_reader(reader: R) -> Self {
+        DeserSaturating {
+            inner: D::from_reader(reader),
+            _types: PhantomData,
+        }
+    }
+
+    fn already_read(&self) -> usize {
+        self.inner.already_read()
+    }
+}
+
+impl<D, R, T, E> Restorable<R> for DeserSaturating<D, R, T, E>
+    where D: Restorable<R>
+{
+    fn restore(self) -> Option<R> {
+        self.inner.restore()
+    }
+}
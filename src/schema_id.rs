@@ -0,0 +1,253 @@
+//! Guard against reading values that were written with a different format, by prefixing each
+//! value with a fingerprint of its type.
+//!
+//! The fingerprint is an arbitrary `u64` that the implementor of `SchemaId` picks for a type, and
+//! changes whenever the serialized format of the type changes, e.g. a hash of its layout and a
+//! version number. It is encoded as a big-endian `u64` before the value. Unlike a magic number,
+//! which identifies a file format, the id identifies the type of a single value, so e.g. every
+//! entry of a cache can be checked on its own.
+
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::marker::PhantomData;
+
+use futures_core::{Async, Future, Poll};
+use futures_core::task::Context;
+use futures_io::{AsyncRead, AsyncWrite, Error as FutIoErr};
+
+use {AsyncDeserialize, AsyncSerialize, AsyncSerializeLen, AsyncWriterFuture,
+     AsyncWriterFutureLen, DeserializeError, Restorable};
+use util::{ReadExact, WriteAll};
+
+/// A type with a fingerprint of its serialized format.
+///
+/// To give a type of another crate an id, wrap it in a newtype.
+pub trait SchemaId {
+    /// The fingerprint, which must change whenever the serialized format of the type changes.
+    const SCHEMA_ID: u64;
+}
+
+/// Serializes a value via `F`, preceded by the `SCHEMA_ID` of its type.
+pub struct WriteWithSchemaId<F, W>
+    where F: AsyncSerialize<W>,
+          W: AsyncWrite
+{
+    state: WriteState<F, W>,
+    id_written: usize,
+}
+
+enum WriteState<F, W>
+    where F: AsyncSerialize<W>,
+          W: AsyncWrite
+{
+    Id(WriteAll<W, [u8; 8]>, Option<F::Serialized>),
+    Body(F),
+}
+
+impl<F, W> Future for WriteWithSchemaId<F, W>
+    where F: AsyncSerialize<W>,
+          W: AsyncWrite
+{
+    type Item = (W, usize);
+    type Error = (W, FutIoErr);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let body = match self.state {
+                WriteState::Id(ref mut id, ref mut val) => {
+                    let (writer, written) = try_ready!(id.poll(cx));
+                    self.id_written = written;
+                    let val = or_pending!(val.take());
+                    F::from_val(writer, val)
+                }
+                WriteState::Body(ref mut body) => {
+                    let (writer, written) = try_ready!(body.poll(cx));
+                    return Ok(Async::Ready((writer, self.id_written + written)));
+                }
+            };
+            self.state = WriteState::Body(body);
+        }
+    }
+}
+
+impl<F, W> AsyncWriterFuture<W> for WriteWithSchemaId<F, W>
+    where F: AsyncSerialize<W>,
+          W: AsyncWrite
+{
+    fn already_written(&self) -> usize {
+        match self.state {
+            WriteState::Id(ref id, _) => id.already_written(),
+            WriteState::Body(ref body) => self.id_written + body.already_written(),
+        }
+    }
+}
+
+impl<F, W> AsyncWriterFutureLen<W> for WriteWithSchemaId<F, W>
+    where F: AsyncSerializeLen<W>,
+          W: AsyncWrite
+{
+    fn remaining_bytes(&self) -> usize {
+        match self.state {
+            WriteState::Id(ref id, ref val) => {
+                id.remaining_bytes() + val.as_ref().map(F::total_bytes).unwrap_or(0)
+            }
+            WriteState::Body(ref body) => body.remaining_bytes(),
+        }
+    }
+}
+
+impl<F, W> AsyncSerialize<W> for WriteWithSchemaId<F, W>
+    where F: AsyncSerialize<W>,
+          F::Serialized: SchemaId,
+          W: AsyncWrite
+{
+    type Serialized = F::Serialized;
+
+    fn from_val(writer: W, val: F::Serialized) -> Self {
+        let id = <F::Serialized as SchemaId>::SCHEMA_ID.to_be_bytes();
+        WriteWithSchemaId {
+            state: WriteState::Id(WriteAll::new(writer, id), Some(val)),
+            id_written: 0,
+        }
+    }
+}
+
+impl<F, W> AsyncSerializeLen<W> for WriteWithSchemaId<F, W>
+    where F: AsyncSerializeLen<W>,
+          F::Serialized: SchemaId,
+          W: AsyncWrite
+{
+    fn total_bytes(val: &F::Serialized) -> usize {
+        8 + F::total_bytes(val)
+    }
+}
+
+/// Deserializes a value via `D`, after checking that it is preceded by the `SCHEMA_ID` of its
+/// type.
+///
+/// A different id fails with `SchemaError::SchemaMismatch` before the value is read.
+pub struct ReadWithSchemaId<D, R, S, E> {
+    state: ReadState<D, R>,
+    id_read: usize,
+    _types: PhantomData<(S, E)>,
+}
+
+enum ReadState<D, R> {
+    Id(ReadExact<R, [u8; 8]>),
+    Body(D),
+}
+
+impl<D, R, S, E> Future for ReadWithSchemaId<D, R, S, E>
+    where D: AsyncDeserialize<R, S, E>,
+          R: AsyncRead,
+          S: SchemaId
+{
+    type Item = (R, S, usize);
+    type Error = (R, DeserializeError<SchemaError<E>>);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let body = match self.state {
+                ReadState::Id(ref mut id) => {
+                    let (reader, id, read) = match id.poll(cx) {
+                        Ok(Async::Ready(done)) => done,
+                        Ok(Async::Pending) => return Ok(Async::Pending),
+                        Err((reader, err)) => {
+                            return Err((reader, DeserializeError::ReaderError(err)))
+                        }
+                    };
+                    self.id_read = read;
+
+                    let found = u64::from_be_bytes(id);
+                    if found != S::SCHEMA_ID {
+                        let err = SchemaError::SchemaMismatch {
+                            expected: S::SCHEMA_ID,
+                            found,
+                        };
+                        return Err((reader, DeserializeError::DataError(err)));
+                    }
+                    D::from_reader(reader)
+                }
+
+                ReadState::Body(ref mut body) => {
+                    return match body.poll(cx) {
+                               Ok(Async::Ready((reader, val, read))) => {
+                                   Ok(Async::Ready((reader, val, self.id_read + read)))
+                               }
+                               Ok(Async::Pending) => Ok(Async::Pending),
+                               Err((reader, DeserializeError::ReaderError(err))) => {
+                                   Err((reader, DeserializeError::ReaderError(err)))
+                               }
+                               Err((reader, DeserializeError::DataError(err))) => {
+                                   let err = SchemaError::Inner(err);
+                                   Err((reader, DeserializeError::DataError(err)))
+                               }
+                           };
+                }
+            };
+            self.state = ReadState::Body(body);
+        }
+    }
+}
+
+impl<D, R, S, E> AsyncDeserialize<R, S, SchemaError<E>> for ReadWithSchemaId<D, R, S, E>
+    where D: AsyncDeserialize<R, S, E>,
+          R: AsyncRead,
+          S: SchemaId
+{
+    fn from_reader(reader: R) -> Self {
+        ReadWithSchemaId {
+            state: ReadState::Id(ReadExact::new(reader, [0; 8])),
+            id_read: 0,
+            _types: PhantomData,
+        }
+    }
+
+    fn already_read(&self) -> usize {
+        match self.state {
+            ReadState::Id(ref id) => id.already_read(),
+            ReadState::Body(ref body) => self.id_read + body.already_read(),
+        }
+    }
+}
+
+impl<D, R, S, E> Restorable<R> for ReadWithSchemaId<D, R, S, E>
+    where D: Restorable<R>
+{
+    fn restore(self) -> Option<R> {
+        match self.state {
+            ReadState::Id(id) => id.restore(),
+            ReadState::Body(body) => body.restore(),
+        }
+    }
+}
+
+/// Everything that can go wrong when deserializing a value preceded by its schema id.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SchemaError<E> {
+    /// The value was written with a different schema.
+    SchemaMismatch {
+        /// The id of the schema of the type being deserialized.
+        expected: u64,
+        /// The id that was read.
+        found: u64,
+    },
+    /// The value could not be deserialized.
+    Inner(E),
+}
+
+impl<E: Display> Display for SchemaError<E> {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            SchemaError::SchemaMismatch { expected, found } => {
+                write!(f,
+                       "Schema mismatch: expected id {:#018x}, found {:#018x}",
+                       expected,
+                       found)
+            }
+            SchemaError::Inner(ref err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl<E: Error> Error for SchemaError<E> {}
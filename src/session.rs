@@ -0,0 +1,290 @@
+//! Send and receive values over one connection, with statistics shared by all operations.
+
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use futures_core::{Async, Future, Poll};
+use futures_core::task::Context;
+use futures_io::{AsyncRead, AsyncWrite, Error as FutIoErr};
+
+use {AsyncDeserialize, AsyncSerialize, DeserializeError};
+
+/// A connection, made of a reader `R` and a writer `W`, over which values are sent via
+/// `AsyncSerialize`s and received via `AsyncDeserialize`s.
+///
+/// There is at most one operation in flight per direction: while a `SendValue` has not completed
+/// yet, `send` gives back the value instead of interleaving its bytes with the ones of the pending
+/// send, and likewise for `recv`. A send and a receive can be in flight at the same time.
+///
+/// If a `SendValue` or `RecvValue` is dropped before completing, the half it uses is dropped with
+/// it, so no further operations are possible in that direction, and `shutdown` fails.
+pub struct Session<R, W> {
+    reader: Arc<Mutex<Option<SessionReader<R>>>>,
+    writer: Arc<Mutex<Option<SessionWriter<W>>>>,
+    stats: Arc<SessionStats>,
+}
+
+fn lock<T>(slot: &Mutex<T>) -> MutexGuard<'_, T> {
+    slot.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+impl<R, W> Session<R, W> {
+    /// Create a new `Session`, receiving from `reader` and sending into `writer`.
+    pub fn new(reader: R, writer: W) -> Session<R, W> {
+        let stats = Arc::new(SessionStats::default());
+        Session {
+            reader: Arc::new(Mutex::new(Some(SessionReader {
+                                                  inner: reader,
+                                                  stats: Arc::clone(&stats),
+                                              }))),
+            writer: Arc::new(Mutex::new(Some(SessionWriter {
+                                                  inner: writer,
+                                                  stats: Arc::clone(&stats),
+                                              }))),
+            stats,
+        }
+    }
+
+    /// Return the statistics of this session. They keep being updated while the returned handle
+    /// is held, also by operations that are in flight.
+    pub fn stats(&self) -> Arc<SessionStats> {
+        Arc::clone(&self.stats)
+    }
+
+    /// Send `val` via `S`, or give it back if a previous send has not completed yet.
+    pub fn send<S>(&self, val: S::Serialized) -> Result<SendValue<S, W>, S::Serialized>
+        where S: AsyncSerialize<SessionWriter<W>>,
+              W: AsyncWrite
+    {
+        match lock(&self.writer).take() {
+            Some(writer) => {
+                Ok(SendValue {
+                       inner: S::from_val(writer, val),
+                       slot: Arc::clone(&self.writer),
+                   })
+            }
+            None => Err(val),
+        }
+    }
+
+    /// Receive a value via `D`, or return `None` if a previous receive has not completed yet.
+    pub fn recv<D, T, E>(&self) -> Option<RecvValue<D, R>>
+        where D: AsyncDeserialize<SessionReader<R>, T, E>,
+              R: AsyncRead
+    {
+        lock(&self.reader).take().map(|reader| {
+                                          RecvValue {
+                                              inner: D::from_reader(reader),
+                                              slot: Arc::clone(&self.reader),
+                                          }
+                                      })
+    }
+
+    /// Flush and close the writer, then yield the reader and the writer.
+    ///
+    /// Gives back the session if a send or a receive is still in flight.
+    pub fn shutdown(self) -> Result<Shutdown<R, W>, Session<R, W>> {
+        let halves = {
+            let mut reader = lock(&self.reader);
+            let mut writer = lock(&self.writer);
+            if reader.is_some() && writer.is_some() {
+                reader.take().and_then(|reader| writer.take().map(|writer| (reader, writer)))
+            } else {
+                None
+            }
+        };
+
+        match halves {
+            Some((reader, writer)) => {
+                Ok(Shutdown {
+                       halves: Some((reader.inner, writer.inner)),
+                       flushed: false,
+                   })
+            }
+            None => Err(self),
+        }
+    }
+}
+
+/// The statistics of a `Session`.
+#[derive(Debug, Default)]
+pub struct SessionStats {
+    bytes_sent: AtomicU64,
+    bytes_received: AtomicU64,
+    messages_sent: AtomicU64,
+    messages_received: AtomicU64,
+}
+
+impl SessionStats {
+    /// Return how many bytes have been written, including the ones of sends in flight.
+    pub fn bytes_sent(&self) -> u64 {
+        self.bytes_sent.load(Ordering::Relaxed)
+    }
+
+    /// Return how many bytes have been read, including the ones of receives in flight.
+    pub fn bytes_received(&self) -> u64 {
+        self.bytes_received.load(Ordering::Relaxed)
+    }
+
+    /// Return how many sends have completed successfully.
+    pub fn messages_sent(&self) -> u64 {
+        self.messages_sent.load(Ordering::Relaxed)
+    }
+
+    /// Return how many receives have completed successfully.
+    pub fn messages_received(&self) -> u64 {
+        self.messages_received.load(Ordering::Relaxed)
+    }
+}
+
+/// The writer of a `Session`, which counts the bytes written through it.
+#[derive(Debug)]
+pub struct SessionWriter<W> {
+    inner: W,
+    stats: Arc<SessionStats>,
+}
+
+impl<W> SessionWriter<W> {
+    /// Get a reference to the wrapped writer.
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+}
+
+impl<W: AsyncWrite> AsyncWrite for SessionWriter<W> {
+    fn poll_write(&mut self, cx: &mut Context, buf: &[u8]) -> Poll<usize, FutIoErr> {
+        let written = try_ready!(self.inner.poll_write(cx, buf));
+        self.stats.bytes_sent.fetch_add(written as u64, Ordering::Relaxed);
+        Ok(Async::Ready(written))
+    }
+
+    fn poll_flush(&mut self, cx: &mut Context) -> Poll<(), FutIoErr> {
+        self.inner.poll_flush(cx)
+    }
+
+    fn poll_close(&mut self, cx: &mut Context) -> Poll<(), FutIoErr> {
+        self.inner.poll_close(cx)
+    }
+}
+
+/// The reader of a `Session`, which counts the bytes read through it.
+#[derive(Debug)]
+pub struct SessionReader<R> {
+    inner: R,
+    stats: Arc<SessionStats>,
+}
+
+impl<R> SessionReader<R> {
+    /// Get a reference to the wrapped reader.
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+}
+
+impl<R: AsyncRead> AsyncRead for SessionReader<R> {
+    fn poll_read(&mut self, cx: &mut Context, buf: &mut [u8]) -> Poll<usize, FutIoErr> {
+        let read = try_ready!(self.inner.poll_read(cx, buf));
+        self.stats.bytes_received.fetch_add(read as u64, Ordering::Relaxed);
+        Ok(Async::Ready(read))
+    }
+}
+
+/// Future that sends a value over a `Session`, yielding the number of written bytes.
+///
+/// The writer is given back to the session when this completes, also if it fails.
+pub struct SendValue<S, W> {
+    inner: S,
+    slot: Arc<Mutex<Option<SessionWriter<W>>>>,
+}
+
+impl<S, W> Future for SendValue<S, W>
+    where S: Future<Item = (SessionWriter<W>, usize), Error = (SessionWriter<W>, FutIoErr)>
+{
+    type Item = usize;
+    type Error = FutIoErr;
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        match self.inner.poll(cx) {
+            Ok(Async::Ready((writer, written))) => {
+                writer.stats.messages_sent.fetch_add(1, Ordering::Relaxed);
+                *lock(&self.slot) = Some(writer);
+                Ok(Async::Ready(written))
+            }
+            Ok(Async::Pending) => Ok(Async::Pending),
+            Err((writer, err)) => {
+                *lock(&self.slot) = Some(writer);
+                Err(err)
+            }
+        }
+    }
+}
+
+/// Future that receives a value from a `Session`, yielding the value and the number of read
+/// bytes.
+///
+/// The reader is given back to the session when this completes, also if it fails.
+pub struct RecvValue<D, R> {
+    inner: D,
+    slot: Arc<Mutex<Option<SessionReader<R>>>>,
+}
+
+impl<D, R, T, E> Future for RecvValue<D, R>
+    where D: Future<Item = (SessionReader<R>, T, usize),
+                    Error = (SessionReader<R>, DeserializeError<E>)>
+{
+    type Item = (T, usize);
+    type Error = DeserializeError<E>;
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        match self.inner.poll(cx) {
+            Ok(Async::Ready((reader, val, read))) => {
+                reader.stats.messages_received.fetch_add(1, Ordering::Relaxed);
+                *lock(&self.slot) = Some(reader);
+                Ok(Async::Ready((val, read)))
+            }
+            Ok(Async::Pending) => Ok(Async::Pending),
+            Err((reader, err)) => {
+                *lock(&self.slot) = Some(reader);
+                Err(err)
+            }
+        }
+    }
+}
+
+/// Future that flushes and closes the writer of a `Session`, yielding its reader and writer.
+pub struct Shutdown<R, W> {
+    halves: Option<(R, W)>,
+    flushed: bool,
+}
+
+impl<R, W: AsyncWrite> Future for Shutdown<R, W> {
+    type Item = (R, W);
+    type Error = (R, W, FutIoErr);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        let res = match self.halves {
+            Some((_, ref mut writer)) => close(writer, &mut self.flushed, cx),
+            None => return Ok(Async::Pending),
+        };
+
+        match res {
+            Ok(Async::Pending) => Ok(Async::Pending),
+            Ok(Async::Ready(())) => Ok(Async::Ready(self.halves.take().unwrap())),
+            Err(err) => {
+                let (reader, writer) = self.halves.take().unwrap();
+                Err((reader, writer, err))
+            }
+        }
+    }
+}
+
+fn close<W: AsyncWrite>(writer: &mut W,
+                        flushed: &mut bool,
+                        cx: &mut Context)
+                        -> Poll<(), FutIoErr> {
+    if !*flushed {
+        try_ready!(writer.poll_flush(cx));
+        *flushed = true;
+    }
+    writer.poll_close(cx)
+}
@@ -0,0 +1,96 @@
+//! Stop writing after the first error.
+
+use futures_core::Poll;
+use futures_core::task::Context;
+use futures_io::{AsyncWrite, Error as FutIoErr, ErrorKind};
+
+/// Wraps an `AsyncWrite` and remembers the first error it emits. Afterwards, all writes and
+/// flushes fail immediately with an error of the same kind and message, without calling the
+/// wrapped writer.
+///
+/// This way, a sequence of serializers that share a writer stops at the first error, even if the
+/// serializers are driven independently. Closing is still forwarded to the wrapped writer, so
+/// that the connection can be shut down.
+#[derive(Debug)]
+pub struct ShortCircuitWriter<W> {
+    inner: W,
+    error: Option<FutIoErr>,
+    failed: Option<(ErrorKind, String)>,
+}
+
+impl<W> ShortCircuitWriter<W> {
+    /// Create a new `ShortCircuitWriter` wrapping `inner`.
+    pub fn new(inner: W) -> ShortCircuitWriter<W> {
+        ShortCircuitWriter {
+            inner,
+            error: None,
+            failed: None,
+        }
+    }
+
+    /// Return whether the wrapped writer has emitted an error.
+    pub fn is_failed(&self) -> bool {
+        self.failed.is_some()
+    }
+
+    /// Take the error the wrapped writer emitted, if any.
+    ///
+    /// This does not reset the writer, later writes and flushes still fail.
+    pub fn take_error(&mut self) -> Option<FutIoErr> {
+        self.error.take()
+    }
+
+    /// Get a reference to the wrapped writer.
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Get a mutable reference to the wrapped writer.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// Consume this `ShortCircuitWriter`, returning the wrapped writer.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+
+    fn check(&self) -> Result<(), FutIoErr> {
+        match self.failed {
+            Some((kind, ref message)) => Err(FutIoErr::new(kind, message.clone())),
+            None => Ok(()),
+        }
+    }
+
+    fn remember<T>(&mut self, res: Poll<T, FutIoErr>) -> Poll<T, FutIoErr> {
+        res.map_err(|err| {
+            if self.failed.is_none() {
+                self.failed = Some((err.kind(), err.to_string()));
+                let returned = FutIoErr::new(err.kind(), err.to_string());
+                self.error = Some(err);
+                returned
+            } else {
+                err
+            }
+        })
+    }
+}
+
+impl<W: AsyncWrite> AsyncWrite for ShortCircuitWriter<W> {
+    fn poll_write(&mut self, cx: &mut Context, buf: &[u8]) -> Poll<usize, FutIoErr> {
+        self.check()?;
+        let res = self.inner.poll_write(cx, buf);
+        self.remember(res)
+    }
+
+    fn poll_flush(&mut self, cx: &mut Context) -> Poll<(), FutIoErr> {
+        self.check()?;
+        let res = self.inner.poll_flush(cx);
+        self.remember(res)
+    }
+
+    fn poll_close(&mut self, cx: &mut Context) -> Poll<(), FutIoErr> {
+        let res = self.inner.poll_close(cx);
+        self.remember(res)
+    }
+}
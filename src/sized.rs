@@ -0,0 +1,222 @@
+//! Check that a deserializer consumes exactly the declared size of a record.
+//!
+//! This is stricter than a `TakeReader`, which only stops a deserializer from reading too much:
+//! `ReadSized` also fails if the deserializer finishes early. That catches mismatches between a
+//! schema and the deserializers implementing it, e.g. during development.
+//!
+//! `Bounded` only enforces an upper bound on the size of a value, fixed at compile time.
+
+use std::cmp::min;
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::marker::PhantomData;
+
+use futures_core::{Async, Future, Poll};
+use futures_core::task::Context;
+use futures_io::{AsyncRead, Error as FutIoErr, ErrorKind};
+
+use {AsyncDeserialize, DeserializeError, Restorable};
+
+/// Wraps an `AsyncRead` and reads at most `size` bytes from it. Trying to read more fails with an
+/// `ErrorKind::InvalidData` error.
+#[derive(Debug)]
+pub struct SizedReader<R> {
+    inner: R,
+    size: u64,
+    consumed: u64,
+}
+
+impl<R> SizedReader<R> {
+    /// Create a new `SizedReader`, allowing exactly `size` bytes to be read from `inner`.
+    pub fn new(inner: R, size: u64) -> SizedReader<R> {
+        SizedReader {
+            inner,
+            size,
+            consumed: 0,
+        }
+    }
+
+    /// Return the declared size.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Return how many bytes have been read so far.
+    pub fn consumed(&self) -> u64 {
+        self.consumed
+    }
+
+    /// Get a reference to the wrapped reader.
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// Get a mutable reference to the wrapped reader.
+    ///
+    /// Reading from it directly does not count towards the size.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    /// Consume this `SizedReader`, returning the wrapped reader.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: AsyncRead> AsyncRead for SizedReader<R> {
+    fn poll_read(&mut self, cx: &mut Context, buf: &mut [u8]) -> Poll<usize, FutIoErr> {
+        if buf.is_empty() {
+            return Ok(Async::Ready(0));
+        }
+        if self.consumed == self.size {
+            return Err(FutIoErr::new(ErrorKind::InvalidData,
+                                     "read past the declared size of the record"));
+        }
+
+        let max = min(buf.len() as u64, self.size - self.consumed) as usize;
+        let read = try_ready!(self.inner.poll_read(cx, &mut buf[..max]));
+        self.consumed += read as u64;
+        Ok(Async::Ready(read))
+    }
+}
+
+/// Deserializes a record of a declared size via `D`, failing unless `D` consumes exactly that many
+/// bytes.
+///
+/// `D` reads through a `SizedReader`, so reading past the declared size fails with a
+/// `ReaderError` of kind `ErrorKind::InvalidData`. If `D` finishes before consuming the whole
+/// record, this fails with `SizedError::UnderRead`. In both cases, the wrapped reader is returned
+/// as it is, without skipping the rest of the record.
+pub struct ReadSized<D, R, S, E> {
+    inner: D,
+    _types: PhantomData<(R, S, E)>,
+}
+
+impl<D, R, S, E> ReadSized<D, R, S, E>
+    where D: AsyncDeserialize<SizedReader<R>, S, E>,
+          R: AsyncRead
+{
+    /// Create a new `ReadSized`, deserializing a record of `size` bytes from `reader`.
+    pub fn new(reader: R, size: u64) -> ReadSized<D, R, S, E> {
+        ReadSized {
+            inner: D::from_reader(SizedReader::new(reader, size)),
+            _types: PhantomData,
+        }
+    }
+
+    /// Return how many bytes have already been read.
+    pub fn already_read(&self) -> usize {
+        self.inner.already_read()
+    }
+}
+
+impl<D, R, S, E> Future for ReadSized<D, R, S, E>
+    where D: AsyncDeserialize<SizedReader<R>, S, E>,
+          R: AsyncRead
+{
+    type Item = (R, S, usize);
+    type Error = (R, DeserializeError<SizedError<E>>);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        match self.inner.poll(cx) {
+            Ok(Async::Ready((sized, val, read))) => {
+                if sized.consumed == sized.size {
+                    Ok(Async::Ready((sized.into_inner(), val, read)))
+                } else {
+                    let err = SizedError::UnderRead {
+                        declared: sized.size,
+                        consumed: sized.consumed,
+                    };
+                    Err((sized.into_inner(), DeserializeError::DataError(err)))
+                }
+            }
+            Ok(Async::Pending) => Ok(Async::Pending),
+            Err((sized, DeserializeError::ReaderError(err))) => {
+                Err((sized.into_inner(), DeserializeError::ReaderError(err)))
+            }
+            Err((sized, DeserializeError::DataError(err))) => {
+                Err((sized.into_inner(), DeserializeError::DataError(SizedError::Inner(err))))
+            }
+        }
+    }
+}
+
+/// Deserializes a value via `D`, reading at most `MAX` bytes.
+///
+/// This is a `ReadSized` whose size is an upper bound known at compile time rather than the exact
+/// size of the record, e.g. `Bounded<ReadVarint<SizedReader<R>>, 5>` rejects varints longer than
+/// five bytes. `D` reads through a `SizedReader`, so reading more than `MAX` bytes fails with a
+/// `ReaderError` of kind `ErrorKind::InvalidData`. Data errors of `D` are passed through as they
+/// are.
+pub struct Bounded<D, const MAX: usize>(D);
+
+impl<D, R, S, E, const MAX: usize> Future for Bounded<D, MAX>
+    where D: Future<Item = (SizedReader<R>, S, usize),
+                    Error = (SizedReader<R>, DeserializeError<E>)>
+{
+    type Item = (R, S, usize);
+    type Error = (R, DeserializeError<E>);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        match self.0.poll(cx) {
+            Ok(Async::Ready((sized, val, read))) => {
+                Ok(Async::Ready((sized.into_inner(), val, read)))
+            }
+            Ok(Async::Pending) => Ok(Async::Pending),
+            Err((sized, err)) => Err((sized.into_inner(), err)),
+        }
+    }
+}
+
+impl<D, R, S, E, const MAX: usize> AsyncDeserialize<R, S, E> for Bounded<D, MAX>
+    where D: AsyncDeserialize<SizedReader<R>, S, E>,
+          R: AsyncRead
+{
+    fn from_reader(reader: R) -> Self {
+        Bounded(D::from_reader(SizedReader::new(reader, MAX as u64)))
+    }
+
+    fn already_read(&self) -> usize {
+        self.0.already_read()
+    }
+}
+
+impl<D, R, const MAX: usize> Restorable<R> for Bounded<D, MAX>
+    where D: Restorable<SizedReader<R>>
+{
+    fn restore(self) -> Option<R> {
+        self.0.restore().map(SizedReader::into_inner)
+    }
+}
+
+/// Everything that can go wrong when deserializing a record of a declared size, apart from reader
+/// errors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SizedError<E> {
+    /// The inner deserializer failed.
+    Inner(E),
+    /// The inner deserializer finished without consuming the whole record.
+    UnderRead {
+        /// The declared size of the record.
+        declared: u64,
+        /// How many bytes of the record the inner deserializer consumed.
+        consumed: u64,
+    },
+}
+
+impl<E: Display> Display for SizedError<E> {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            SizedError::Inner(ref err) => write!(f, "{}", err),
+            SizedError::UnderRead { declared, consumed } => {
+                write!(f,
+                       "Consumed only {} bytes of a record of {} bytes",
+                       consumed,
+                       declared)
+            }
+        }
+    }
+}
+
+impl<E: Error> Error for SizedError<E> {}
@@ -0,0 +1,412 @@
+//! Serialization of sparse vectors, i.e. vectors whose elements are mostly the default value.
+//!
+//! A sparse vector is encoded as its length as a big-endian `u32`, followed by the number of
+//! explicitly stored entries as a big-endian `u32`, followed by each entry as its index as a
+//! big-endian `u32` and then its value. The indices must be strictly increasing and smaller than
+//! the length, all elements without an entry are `T::default()`.
+//!
+//! So the vector `[0, 7, 0, 0, 9]` with single-byte elements, i.e. the entries `[(1, 7), (4, 9)]`,
+//! encodes as `[0, 0, 0, 5, 0, 0, 0, 2, 0, 0, 0, 1, 7, 0, 0, 0, 4, 9]`.
+
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::marker::PhantomData;
+use std::mem;
+
+use futures_core::{Async, Future, Poll};
+use futures_core::task::Context;
+use futures_io::{AsyncRead, AsyncWrite, Error as FutIoErr, ErrorKind};
+
+use {AsyncDeserialize, AsyncSerialize, AsyncSerializeLen, AsyncWriterFuture, AsyncWriterFutureLen,
+     DeserializeError, Restorable};
+use util::{prefix_fits, ReadExact, WriteAll};
+
+/// Serializes a sparse vector of the given length, given as pairs of an index and a value,
+/// serializing the values via `S`.
+///
+/// If the length or the number of entries does not fit into a `u32`, or the indices are not
+/// strictly increasing and smaller than the length, this fails with an `ErrorKind::InvalidInput`
+/// error without writing anything.
+pub struct SerSparse<'val, S, W>
+    where S: AsyncSerialize<W>,
+          W: AsyncWrite
+{
+    state: SerState<S, W>,
+    entries: &'val [(usize, S::Serialized)],
+    index: usize,
+    written: usize,
+}
+
+enum SerState<S, W> {
+    Header(WriteAll<W, [u8; 8]>),
+    Index(WriteAll<W, [u8; 4]>),
+    Value(S),
+    Invalid(Option<W>),
+    Done,
+}
+
+fn is_valid<T>(len: usize, entries: &[(usize, T)]) -> bool {
+    prefix_fits(len) && prefix_fits(entries.len()) &&
+    entries.iter().all(|entry| entry.0 < len) &&
+    entries.windows(2).all(|pair| pair[0].0 < pair[1].0)
+}
+
+impl<'val, S, W> SerSparse<'val, S, W>
+    where S: AsyncSerialize<W>,
+          S::Serialized: Clone,
+          W: AsyncWrite
+{
+    /// Create a new `SerSparse`, writing a vector of length `len` whose non-default elements are
+    /// given by `entries`.
+    pub fn new(writer: W,
+               len: usize,
+               entries: &'val [(usize, S::Serialized)])
+               -> SerSparse<'val, S, W> {
+        let state = if is_valid(len, entries) {
+            let mut header = [0; 8];
+            header[..4].copy_from_slice(&(len as u32).to_be_bytes());
+            header[4..].copy_from_slice(&(entries.len() as u32).to_be_bytes());
+            SerState::Header(WriteAll::new(writer, header))
+        } else {
+            SerState::Invalid(Some(writer))
+        };
+
+        SerSparse {
+            state,
+            entries,
+            index: 0,
+            written: 0,
+        }
+    }
+}
+
+impl<'val, S, W> SerSparse<'val, S, W>
+    where S: AsyncSerializeLen<W>,
+          W: AsyncWrite
+{
+    /// Compute the exact number of bytes that would be written in total if the given sparse
+    /// vector was serialized, or zero if it can not be serialized.
+    pub fn total_bytes(len: usize, entries: &[(usize, S::Serialized)]) -> usize {
+        if is_valid(len, entries) {
+            8 + entries_len::<S, W>(entries)
+        } else {
+            0
+        }
+    }
+}
+
+fn entries_len<S, W>(entries: &[(usize, S::Serialized)]) -> usize
+    where S: AsyncSerializeLen<W>,
+          W: AsyncWrite
+{
+    entries.iter().map(|entry| 4 + S::total_bytes(&entry.1)).sum()
+}
+
+impl<'val, S, W> Future for SerSparse<'val, S, W>
+    where S: AsyncSerialize<W>,
+          S::Serialized: Clone,
+          W: AsyncWrite
+{
+    type Item = (W, usize);
+    type Error = (W, FutIoErr);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let (writer, written) = match self.state {
+                SerState::Header(ref mut inner) => try_ready!(inner.poll(cx)),
+                SerState::Index(ref mut inner) => try_ready!(inner.poll(cx)),
+                SerState::Value(ref mut inner) => try_ready!(inner.poll(cx)),
+                SerState::Invalid(ref mut writer) => {
+                    let err = FutIoErr::new(ErrorKind::InvalidInput, "invalid sparse vector");
+                    let writer = or_pending!(writer.take());
+                    return Err((writer, err));
+                }
+                SerState::Done => return Ok(Async::Pending),
+            };
+            self.written += written;
+
+            self.state = match self.state {
+                SerState::Index(_) => {
+                    let val = self.entries[self.index].1.clone();
+                    self.index += 1;
+                    SerState::Value(S::from_val(writer, val))
+                }
+                _ if self.index < self.entries.len() => {
+                    let index = self.entries[self.index].0 as u32;
+                    SerState::Index(WriteAll::new(writer, index.to_be_bytes()))
+                }
+                _ => {
+                    self.state = SerState::Done;
+                    return Ok(Async::Ready((writer, self.written)));
+                }
+            };
+        }
+    }
+}
+
+impl<'val, S, W> AsyncWriterFuture<W> for SerSparse<'val, S, W>
+    where S: AsyncSerialize<W>,
+          S::Serialized: Clone,
+          W: AsyncWrite
+{
+    fn already_written(&self) -> usize {
+        self.written +
+        match self.state {
+            SerState::Header(ref inner) => inner.already_written(),
+            SerState::Index(ref inner) => inner.already_written(),
+            SerState::Value(ref inner) => inner.already_written(),
+            SerState::Invalid(_) | SerState::Done => 0,
+        }
+    }
+}
+
+impl<'val, S, W> AsyncWriterFutureLen<W> for SerSparse<'val, S, W>
+    where S: AsyncSerializeLen<W>,
+          S::Serialized: Clone,
+          W: AsyncWrite
+{
+    fn remaining_bytes(&self) -> usize {
+        let current = match self.state {
+            SerState::Header(ref inner) => inner.remaining_bytes(),
+            SerState::Index(ref inner) => {
+                inner.remaining_bytes() + S::total_bytes(&self.entries[self.index].1)
+            }
+            SerState::Value(ref inner) => inner.remaining_bytes(),
+            SerState::Invalid(_) | SerState::Done => return 0,
+        };
+
+        let next = match self.state {
+            SerState::Index(_) => self.index + 1,
+            _ => self.index,
+        };
+
+        current + entries_len::<S, W>(&self.entries[next..])
+    }
+}
+
+/// Deserializes a sparse vector via `D`, expanding it into a `Vec` by filling the gaps between the
+/// entries with `T::default()`.
+///
+/// `from_reader` accepts vectors of any length, use `new` to reject vectors longer than some
+/// maximum before allocating anything.
+pub struct DeserSparse<D, R, T, E> {
+    state: DeserState<D, R>,
+    max_len: u32,
+    len: u32,
+    remaining: u32,
+    read: usize,
+    values: Vec<T>,
+    _error: PhantomData<E>,
+}
+
+enum DeserState<D, R> {
+    Header(ReadExact<R, [u8; 8]>),
+    Index(ReadExact<R, [u8; 4]>),
+    Value(D),
+    Done,
+}
+
+impl<D, R, T, E> DeserSparse<D, R, T, E>
+    where D: AsyncDeserialize<R, T, E>,
+          R: AsyncRead,
+          T: Default
+{
+    /// Create a new `DeserSparse`, failing with `SparseError::TooLong` if the vector is longer
+    /// than `max_len`.
+    pub fn new(reader: R, max_len: u32) -> DeserSparse<D, R, T, E> {
+        DeserSparse {
+            state: DeserState::Header(ReadExact::new(reader, [0; 8])),
+            max_len,
+            len: 0,
+            remaining: 0,
+            read: 0,
+            values: Vec::new(),
+            _error: PhantomData,
+        }
+    }
+}
+
+impl<D, R, T, E> Future for DeserSparse<D, R, T, E>
+    where D: AsyncDeserialize<R, T, E>,
+          R: AsyncRead,
+          T: Default
+{
+    type Item = (R, Vec<T>, usize);
+    type Error = (R, DeserializeError<SparseError<E>>);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let reader = match self.state {
+                DeserState::Header(ref mut inner) => {
+                    let (reader, header, read) = match inner.poll(cx) {
+                        Ok(Async::Ready(done)) => done,
+                        Ok(Async::Pending) => return Ok(Async::Pending),
+                        Err((reader, err)) => {
+                            return Err((reader, DeserializeError::ReaderError(err)))
+                        }
+                    };
+                    self.read += read;
+
+                    let mut len = [0; 4];
+                    len.copy_from_slice(&header[..4]);
+                    let mut count = [0; 4];
+                    count.copy_from_slice(&header[4..]);
+                    self.len = u32::from_be_bytes(len);
+                    self.remaining = u32::from_be_bytes(count);
+
+                    if self.len > self.max_len {
+                        let err = SparseError::TooLong {
+                            len: self.len,
+                            max: self.max_len,
+                        };
+                        return Err((reader, DeserializeError::DataError(err)));
+                    }
+                    if self.remaining > self.len {
+                        let err = SparseError::TooManyEntries {
+                            count: self.remaining,
+                            len: self.len,
+                        };
+                        return Err((reader, DeserializeError::DataError(err)));
+                    }
+                    reader
+                }
+
+                DeserState::Index(ref mut inner) => {
+                    let (reader, index, read) = match inner.poll(cx) {
+                        Ok(Async::Ready(done)) => done,
+                        Ok(Async::Pending) => return Ok(Async::Pending),
+                        Err((reader, err)) => {
+                            return Err((reader, DeserializeError::ReaderError(err)))
+                        }
+                    };
+                    self.read += read;
+
+                    let index = u32::from_be_bytes(index);
+                    let err = if index >= self.len {
+                        SparseError::IndexOutOfBounds {
+                            index,
+                            len: self.len,
+                        }
+                    } else if (index as usize) < self.values.len() {
+                        SparseError::UnsortedIndex(index)
+                    } else {
+                        self.values.resize_with(index as usize, T::default);
+                        self.state = DeserState::Value(D::from_reader(reader));
+                        continue;
+                    };
+                    return Err((reader, DeserializeError::DataError(err)));
+                }
+
+                DeserState::Value(ref mut inner) => {
+                    let (reader, val, read) = match inner.poll(cx) {
+                        Ok(Async::Ready(done)) => done,
+                        Ok(Async::Pending) => return Ok(Async::Pending),
+                        Err((reader, DeserializeError::ReaderError(err))) => {
+                            return Err((reader, DeserializeError::ReaderError(err)))
+                        }
+                        Err((reader, DeserializeError::DataError(err))) => {
+                            return Err((reader,
+                                        DeserializeError::DataError(SparseError::Value(err))))
+                        }
+                    };
+                    self.read += read;
+                    self.values.push(val);
+                    self.remaining -= 1;
+                    reader
+                }
+
+                DeserState::Done => return Ok(Async::Pending),
+            };
+
+            if self.remaining == 0 {
+                self.values.resize_with(self.len as usize, T::default);
+                self.state = DeserState::Done;
+                return Ok(Async::Ready((reader, mem::take(&mut self.values), self.read)));
+            }
+            self.state = DeserState::Index(ReadExact::new(reader, [0; 4]));
+        }
+    }
+}
+
+impl<D, R, T, E> AsyncDeserialize<R, Vec<T>, SparseError<E>> for DeserSparse<D, R, T, E>
+    where D: AsyncDeserialize<R, T, E>,
+          R: AsyncRead,
+          T: Default
+{
+    fn from_reader(reader: R) -> Self {
+        DeserSparse::new(reader, u32::MAX)
+    }
+
+    fn already_read(&self) -> usize {
+        self.read +
+        match self.state {
+            DeserState::Header(ref inner) => inner.already_read(),
+            DeserState::Index(ref inner) => inner.already_read(),
+            DeserState::Value(ref inner) => inner.already_read(),
+            DeserState::Done => 0,
+        }
+    }
+}
+
+impl<D, R, T, E> Restorable<R> for DeserSparse<D, R, T, E>
+    where D: Restorable<R>
+{
+    fn restore(self) -> Option<R> {
+        match self.state {
+            DeserState::Header(header) => header.restore(),
+            DeserState::Index(index) => index.restore(),
+            DeserState::Value(value) => value.restore(),
+            DeserState::Done => None,
+        }
+    }
+}
+
+/// Everything that can go wrong when deserializing a sparse vector.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SparseError<E> {
+    /// The vector is longer than the maximum length of the deserializer.
+    TooLong {
+        /// The length of the vector.
+        len: u32,
+        /// The maximum length.
+        max: u32,
+    },
+    /// There are more entries than elements.
+    TooManyEntries {
+        /// The number of entries.
+        count: u32,
+        /// The length of the vector.
+        len: u32,
+    },
+    /// The index of an entry is not smaller than the length of the vector.
+    IndexOutOfBounds {
+        /// The index of the entry.
+        index: u32,
+        /// The length of the vector.
+        len: u32,
+    },
+    /// The index of an entry is not greater than the index of the previous entry.
+    UnsortedIndex(u32),
+    /// The value of an entry could not be deserialized.
+    Value(E),
+}
+
+impl<E: Display> Display for SparseError<E> {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            SparseError::TooLong { len, max } => {
+                write!(f, "Sparse vector of length {} exceeds the maximum of {}", len, max)
+            }
+            SparseError::TooManyEntries { count, len } => {
+                write!(f, "{} entries in a sparse vector of length {}", count, len)
+            }
+            SparseError::IndexOutOfBounds { index, len } => {
+                write!(f, "Index {} out of bounds for length {}", index, len)
+            }
+            SparseError::UnsortedIndex(index) => write!(f, "Index {} is out of order", index),
+            SparseError::Value(ref err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl<E: Error> Error for SparseError<E> {}
@@ -0,0 +1,109 @@
+//! Serialize fields with a context that changes as fields are written, e.g. the dynamic table of
+//! HPACK header compression.
+//!
+//! A `StatefulEncoder` owns the context and a factory that creates the serializer of a field from
+//! the context, the writer and the value of the field. The factory may update the context, e.g.
+//! to remember that a header has been sent. The resulting `Stateful` future borrows the context
+//! mutably until it is done, so the fields are written in the same order in which they updated
+//! the context, and a reader replaying the updates sees the same context as the writer.
+//!
+//! The factory runs as soon as a field is passed to `StatefulEncoder::serialize`, not when the
+//! `Stateful` future is first polled. The serializer it creates thus encodes the field with a
+//! snapshot of the context at that point, and the updates of the factory are visible right away,
+//! even if the field is never written, e.g. because the writer fails.
+
+use futures_core::{Future, Poll};
+use futures_core::task::Context;
+use futures_io::{AsyncWrite, Error as FutIoErr};
+
+use {AsyncWriterFuture, AsyncWriterFutureLen};
+
+/// Holds the context of a stateful format and the factory that creates the serializers of its
+/// fields.
+#[derive(Debug)]
+pub struct StatefulEncoder<St, F> {
+    state: St,
+    factory: F,
+}
+
+impl<St, F> StatefulEncoder<St, F> {
+    /// Create a new `StatefulEncoder`, starting with the context `state` and creating the
+    /// serializers of fields via `factory`.
+    pub fn new(state: St, factory: F) -> StatefulEncoder<St, F> {
+        StatefulEncoder { state, factory }
+    }
+
+    /// Serialize `val` into `writer` via the serializer that the factory creates from the current
+    /// context.
+    ///
+    /// The factory is called right away, so the returned `Stateful` encodes `val` with the context
+    /// as it is now, and the context already contains the updates of the factory.
+    pub fn serialize<W, T, S>(&mut self, writer: W, val: T) -> Stateful<'_, S, St>
+        where F: FnMut(&mut St, W, T) -> S,
+              S: AsyncWriterFuture<W>,
+              W: AsyncWrite
+    {
+        Stateful {
+            inner: (self.factory)(&mut self.state, writer, val),
+            state: &mut self.state,
+        }
+    }
+
+    /// Get a reference to the current context.
+    pub fn state(&self) -> &St {
+        &self.state
+    }
+
+    /// Consume this `StatefulEncoder`, returning the current context.
+    pub fn into_state(self) -> St {
+        self.state
+    }
+}
+
+/// Serializes a single field of a stateful format via `S`, borrowing the context of the
+/// `StatefulEncoder` that created it until it is done.
+pub struct Stateful<'state, S, St: 'state> {
+    inner: S,
+    state: &'state mut St,
+}
+
+impl<'state, S, St> Stateful<'state, S, St> {
+    /// Get a reference to the wrapped serializer.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Get a reference to the context, which already contains the updates for this field.
+    pub fn state(&self) -> &St {
+        self.state
+    }
+}
+
+impl<'state, S, St, W> Future for Stateful<'state, S, St>
+    where S: Future<Item = (W, usize), Error = (W, FutIoErr)>
+{
+    type Item = (W, usize);
+    type Error = (W, FutIoErr);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        self.inner.poll(cx)
+    }
+}
+
+impl<'state, S, St, W> AsyncWriterFuture<W> for Stateful<'state, S, St>
+    where S: AsyncWriterFuture<W>,
+          W: AsyncWrite
+{
+    fn already_written(&self) -> usize {
+        self.inner.already_written()
+    }
+}
+
+impl<'state, S, St, W> AsyncWriterFutureLen<W> for Stateful<'state, S, St>
+    where S: AsyncWriterFutureLen<W>,
+          W: AsyncWrite
+{
+    fn remaining_bytes(&self) -> usize {
+        self.inner.remaining_bytes()
+    }
+}
@@ -0,0 +1,194 @@
+//! Deserialization of strings that validates the UTF-8 as the bytes arrive.
+//!
+//! The encoding is that of a [`Cow<str>`](../cow/index.html): the length in bytes as a big-endian
+//! `u32`, followed by the bytes. Unlike reading all bytes and validating them afterwards, the
+//! string is built from validated chunks while reading, and invalid UTF-8 is reported as soon as
+//! it has been read, together with where exactly in the string it starts.
+
+use std::cmp::min;
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::str;
+
+use futures_core::{Async, Future, Poll};
+use futures_core::task::Context;
+use futures_io::{AsyncRead, Error as FutIoErr, ErrorKind};
+
+use {AsyncDeserialize, DeserializeError, Restorable};
+use progress::PayloadRemaining;
+use util::ReadExact;
+
+// How many bytes are read and validated at a time.
+const CHUNK: usize = 256;
+
+/// Deserializes a length-prefixed string, validating it while reading.
+///
+/// A character that is split between two reads is kept until its remaining bytes arrive, all
+/// other bytes are appended to the string right away. The bytes after the first invalid sequence
+/// are not read.
+pub struct StreamingUtf8Deserializer<R>(State<R>);
+
+enum State<R> {
+    Length(ReadExact<R, [u8; 4]>),
+    Body {
+        reader: Option<R>,
+        string: String,
+        // The bytes of a character that has not been read completely.
+        partial: [u8; 3],
+        partial_len: usize,
+        filled: usize,
+        len: usize,
+    },
+}
+
+impl<R: AsyncRead> Future for StreamingUtf8Deserializer<R> {
+    type Item = (R, String, usize);
+    type Error = (R, DeserializeError<StreamingUtf8Error>);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let (reader, len) = match self.0 {
+                State::Length(ref mut inner) => {
+                    match inner.poll(cx) {
+                        Ok(Async::Ready((reader, len, _))) => (reader, u32::from_be_bytes(len)),
+                        Ok(Async::Pending) => return Ok(Async::Pending),
+                        Err((reader, err)) => {
+                            return Err((reader, DeserializeError::ReaderError(err)))
+                        }
+                    }
+                }
+
+                State::Body {
+                    ref mut reader,
+                    ref mut string,
+                    ref mut partial,
+                    ref mut partial_len,
+                    ref mut filled,
+                    len,
+                } => {
+                    let mut r = or_pending!(reader.take());
+
+                    while *filled < len {
+                        let mut buf = [0u8; CHUNK];
+                        buf[..*partial_len].copy_from_slice(&partial[..*partial_len]);
+                        let max = min(CHUNK - *partial_len, len - *filled);
+
+                        let unread = &mut buf[*partial_len..*partial_len + max];
+                        let read = match r.poll_read(cx, unread) {
+                            Ok(Async::Ready(0)) => {
+                                let err = FutIoErr::new(ErrorKind::UnexpectedEof,
+                                                        "unexpected end of string");
+                                return Err((r, DeserializeError::ReaderError(err)));
+                            }
+                            Ok(Async::Ready(read)) => read,
+                            Ok(Async::Pending) => {
+                                *reader = Some(r);
+                                return Ok(Async::Pending);
+                            }
+                            Err(err) => return Err((r, DeserializeError::ReaderError(err))),
+                        };
+
+                        // The offset of `buf[0]` in the string.
+                        let start = *filled - *partial_len;
+                        *filled += read;
+                        let available = *partial_len + read;
+                        *partial_len = 0;
+
+                        let mut offset = start;
+                        for chunk in buf[..available].utf8_chunks() {
+                            string.push_str(chunk.valid());
+                            offset += chunk.valid().len();
+
+                            let invalid = chunk.invalid();
+                            if invalid.is_empty() {
+                                continue;
+                            }
+
+                            // An incomplete character at the end of the bytes read so far may
+                            // still be completed by the next read.
+                            let incomplete = offset + invalid.len() == *filled &&
+                                             str::from_utf8(invalid)
+                                                 .unwrap_err()
+                                                 .error_len()
+                                                 .is_none();
+                            if incomplete && *filled < len {
+                                partial[..invalid.len()].copy_from_slice(invalid);
+                                *partial_len = invalid.len();
+                            } else {
+                                let err = StreamingUtf8Error::InvalidUtf8 { byte_offset: offset };
+                                return Err((r, DeserializeError::DataError(err)));
+                            }
+                        }
+                    }
+
+                    return Ok(Async::Ready((r, ::std::mem::take(string), 4 + len)));
+                }
+            };
+
+            self.0 = State::Body {
+                reader: Some(reader),
+                string: String::new(),
+                partial: [0; 3],
+                partial_len: 0,
+                filled: 0,
+                len: len as usize,
+            };
+        }
+    }
+}
+
+impl<R: AsyncRead> AsyncDeserialize<R, String, StreamingUtf8Error>
+    for StreamingUtf8Deserializer<R> {
+    fn from_reader(reader: R) -> Self {
+        StreamingUtf8Deserializer(State::Length(ReadExact::new(reader, [0; 4])))
+    }
+
+    fn already_read(&self) -> usize {
+        match self.0 {
+            State::Length(ref inner) => inner.already_read(),
+            State::Body { filled, .. } => 4 + filled,
+        }
+    }
+}
+
+impl<R> PayloadRemaining for StreamingUtf8Deserializer<R> {
+    fn payload_remaining(&self) -> Option<usize> {
+        match self.0 {
+            State::Length(_) => None,
+            State::Body { filled, len, .. } => Some(len - filled),
+        }
+    }
+}
+
+impl<R> Restorable<R> for StreamingUtf8Deserializer<R> {
+    fn restore(self) -> Option<R> {
+        match self.0 {
+            State::Length(inner) => inner.restore(),
+            State::Body { reader, .. } => reader,
+        }
+    }
+}
+
+/// Everything that can go wrong when deserializing a string with a
+/// `StreamingUtf8Deserializer`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamingUtf8Error {
+    /// The string is not valid UTF-8.
+    InvalidUtf8 {
+        /// The offset of the first byte of the invalid sequence within the string, not counting
+        /// the length prefix.
+        byte_offset: usize,
+    },
+}
+
+impl Display for StreamingUtf8Error {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            StreamingUtf8Error::InvalidUtf8 { byte_offset } => {
+                write!(f, "Invalid UTF-8 at byte {} of the string", byte_offset)
+            }
+        }
+    }
+}
+
+impl Error for StreamingUtf8Error {}
@@ -0,0 +1,423 @@
+//! Serialization of tagged unions, i.e. values preceded by a discriminant that tells which kind of
+//! value follows.
+//!
+//! The discriminant is a `u64` that is encoded with a `TagWidth` chosen when creating the
+//! serializer: a single byte, a big-endian `u16`, or a [varint](../varint/index.html). The width is
+//! not part of the encoding, so the reader must be created with the same width as the writer.
+//!
+//! Reading a tagged union happens in two steps: `ReadTag` reads the discriminant, which the caller
+//! then uses to choose the deserializer for the value.
+//!
+//! For forward compatibility, formats can put each value into a [frame](../framed/index.html).
+//! The value of an unknown discriminant can then be read as an opaque `Unknown` via
+//! `ReadUnknown`, instead of failing.
+
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::mem;
+
+use futures_core::{Async, Future, Poll};
+use futures_core::task::Context;
+use futures_io::{AsyncRead, AsyncWrite, Error as FutIoErr, ErrorKind};
+
+use {AsyncDeserialize, AsyncSerialize, AsyncSerializeLen, AsyncWriterFuture,
+     AsyncWriterFutureLen, DeserializeError, Restorable};
+use util::{poll_read_vec, ReadExact, WriteAll};
+use varint::{varint_len, ReadVarint, VarintBuf, VarintError};
+
+/// How a discriminant is encoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TagWidth {
+    /// A single byte, for discriminants up to 255.
+    U8,
+    /// A big-endian `u16`, for discriminants up to 65535.
+    U16,
+    /// A varint, for arbitrary discriminants.
+    Varint,
+}
+
+impl TagWidth {
+    /// Return how many bytes the encoding of `tag` takes, or `None` if `tag` can not be encoded
+    /// with this width.
+    pub fn tag_len(self, tag: u64) -> Option<usize> {
+        match self {
+            TagWidth::U8 if tag <= u64::from(u8::MAX) => Some(1),
+            TagWidth::U16 if tag <= u64::from(u16::MAX) => Some(2),
+            TagWidth::Varint => Some(varint_len(tag)),
+            _ => None,
+        }
+    }
+}
+
+enum TagBuf {
+    Fixed([u8; 2], usize),
+    Varint(VarintBuf),
+}
+
+impl TagBuf {
+    fn new(width: TagWidth, tag: u64) -> Option<TagBuf> {
+        width.tag_len(tag)?;
+        Some(match width {
+                 TagWidth::U8 => TagBuf::Fixed([tag as u8, 0], 1),
+                 TagWidth::U16 => TagBuf::Fixed((tag as u16).to_be_bytes(), 2),
+                 TagWidth::Varint => TagBuf::Varint(VarintBuf::new(tag)),
+             })
+    }
+}
+
+impl AsRef<[u8]> for TagBuf {
+    fn as_ref(&self) -> &[u8] {
+        match *self {
+            TagBuf::Fixed(ref bytes, len) => &bytes[..len],
+            TagBuf::Varint(ref buf) => buf.as_ref(),
+        }
+    }
+}
+
+/// Serializes a discriminant followed by a value via `P`.
+///
+/// If the discriminant does not fit the chosen `TagWidth`, this fails with an
+/// `ErrorKind::InvalidInput` error without writing anything.
+pub struct WriteTagged<P, W>
+    where P: AsyncSerialize<W>,
+          W: AsyncWrite
+{
+    state: State<P, W>,
+    tag_written: usize,
+}
+
+enum State<P, W>
+    where P: AsyncSerialize<W>,
+          W: AsyncWrite
+{
+    Tag(WriteAll<W, TagBuf>, Option<P::Serialized>),
+    Payload(P),
+    Invalid(Option<W>),
+}
+
+impl<P, W> WriteTagged<P, W>
+    where P: AsyncSerialize<W>,
+          W: AsyncWrite
+{
+    /// Create a new `WriteTagged`, writing `tag` with the given `width`, followed by `val`.
+    pub fn new(writer: W, width: TagWidth, tag: u64, val: P::Serialized) -> WriteTagged<P, W> {
+        let state = match TagBuf::new(width, tag) {
+            Some(buf) => State::Tag(WriteAll::new(writer, buf), Some(val)),
+            None => State::Invalid(Some(writer)),
+        };
+
+        WriteTagged {
+            state,
+            tag_written: 0,
+        }
+    }
+}
+
+impl<P, W> WriteTagged<P, W>
+    where P: AsyncSerializeLen<W>,
+          W: AsyncWrite
+{
+    /// Compute the exact number of bytes that would be written in total if the given tag and
+    /// value were serialized, or zero if the tag does not fit the width.
+    pub fn total_bytes(width: TagWidth, tag: u64, val: &P::Serialized) -> usize {
+        width
+            .tag_len(tag)
+            .map(|len| len + P::total_bytes(val))
+            .unwrap_or(0)
+    }
+}
+
+impl<P, W> Future for WriteTagged<P, W>
+    where P: AsyncSerialize<W>,
+          W: AsyncWrite
+{
+    type Item = (W, usize);
+    type Error = (W, FutIoErr);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let payload = match self.state {
+                State::Tag(ref mut tag, ref mut val) => {
+                    let (writer, written) = try_ready!(tag.poll(cx));
+                    self.tag_written = written;
+
+                    let val = or_pending!(val.take());
+                    P::from_val(writer, val)
+                }
+
+                State::Payload(ref mut payload) => {
+                    let (writer, written) = try_ready!(payload.poll(cx));
+                    return Ok(Async::Ready((writer, self.tag_written + written)));
+                }
+
+                State::Invalid(ref mut writer) => {
+                    let err = FutIoErr::new(ErrorKind::InvalidInput,
+                                            "discriminant does not fit the tag width");
+                    let writer = or_pending!(writer.take());
+                    return Err((writer, err));
+                }
+            };
+
+            self.state = State::Payload(payload);
+        }
+    }
+}
+
+impl<P, W> AsyncWriterFuture<W> for WriteTagged<P, W>
+    where P: AsyncSerialize<W>,
+          W: AsyncWrite
+{
+    fn already_written(&self) -> usize {
+        match self.state {
+            State::Tag(ref tag, _) => tag.already_written(),
+            State::Payload(ref payload) => self.tag_written + payload.already_written(),
+            State::Invalid(_) => 0,
+        }
+    }
+}
+
+impl<P, W> AsyncWriterFutureLen<W> for WriteTagged<P, W>
+    where P: AsyncSerializeLen<W>,
+          W: AsyncWrite
+{
+    fn remaining_bytes(&self) -> usize {
+        match self.state {
+            State::Tag(ref tag, ref val) => {
+                tag.remaining_bytes() + val.as_ref().map(P::total_bytes).unwrap_or(0)
+            }
+            State::Payload(ref payload) => payload.remaining_bytes(),
+            State::Invalid(_) => 0,
+        }
+    }
+}
+
+/// Deserializes a discriminant of the given `TagWidth`.
+///
+/// `from_reader` reads a varint, use `new` to choose a different width.
+pub struct ReadTag<R>(ReadTagState<R>);
+
+enum ReadTagState<R> {
+    U8(ReadExact<R, [u8; 1]>),
+    U16(ReadExact<R, [u8; 2]>),
+    Varint(ReadVarint<R>),
+}
+
+impl<R: AsyncRead> ReadTag<R> {
+    /// Create a new `ReadTag`, reading a discriminant of the given `width`.
+    pub fn new(reader: R, width: TagWidth) -> ReadTag<R> {
+        ReadTag(match width {
+                    TagWidth::U8 => ReadTagState::U8(ReadExact::new(reader, [0; 1])),
+                    TagWidth::U16 => ReadTagState::U16(ReadExact::new(reader, [0; 2])),
+                    TagWidth::Varint => ReadTagState::Varint(ReadVarint::from_reader(reader)),
+                })
+    }
+}
+
+impl<R: AsyncRead> Future for ReadTag<R> {
+    type Item = (R, u64, usize);
+    type Error = (R, DeserializeError<TagError>);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        match self.0 {
+            ReadTagState::U8(ref mut inner) => {
+                match inner.poll(cx) {
+                    Ok(Async::Ready((reader, bytes, read))) => {
+                        Ok(Async::Ready((reader, u64::from(bytes[0]), read)))
+                    }
+                    Ok(Async::Pending) => Ok(Async::Pending),
+                    Err((reader, err)) => Err((reader, DeserializeError::ReaderError(err))),
+                }
+            }
+
+            ReadTagState::U16(ref mut inner) => {
+                match inner.poll(cx) {
+                    Ok(Async::Ready((reader, bytes, read))) => {
+                        Ok(Async::Ready((reader, u64::from(u16::from_be_bytes(bytes)), read)))
+                    }
+                    Ok(Async::Pending) => Ok(Async::Pending),
+                    Err((reader, err)) => Err((reader, DeserializeError::ReaderError(err))),
+                }
+            }
+
+            ReadTagState::Varint(ref mut inner) => {
+                match inner.poll(cx) {
+                    Ok(Async::Ready(done)) => Ok(Async::Ready(done)),
+                    Ok(Async::Pending) => Ok(Async::Pending),
+                    Err((reader, DeserializeError::ReaderError(err))) => {
+                        Err((reader, DeserializeError::ReaderError(err)))
+                    }
+                    Err((reader, DeserializeError::DataError(VarintError::Overflow))) => {
+                        Err((reader, DeserializeError::DataError(TagError::VarintOverflow)))
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<R: AsyncRead> AsyncDeserialize<R, u64, TagError> for ReadTag<R> {
+    fn from_reader(reader: R) -> Self {
+        ReadTag::new(reader, TagWidth::Varint)
+    }
+
+    fn already_read(&self) -> usize {
+        match self.0 {
+            ReadTagState::U8(ref inner) => inner.already_read(),
+            ReadTagState::U16(ref inner) => inner.already_read(),
+            ReadTagState::Varint(ref inner) => inner.already_read(),
+        }
+    }
+}
+
+impl<R> Restorable<R> for ReadTag<R> {
+    fn restore(self) -> Option<R> {
+        match self.0 {
+            ReadTagState::U8(inner) => inner.restore(),
+            ReadTagState::U16(inner) => inner.restore(),
+            ReadTagState::Varint(inner) => inner.restore(),
+        }
+    }
+}
+
+/// The value of a discriminant that is not known to the reader.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Unknown {
+    /// The discriminant.
+    pub tag: u64,
+    /// The raw content of the frame that followed the discriminant.
+    pub bytes: Vec<u8>,
+}
+
+/// Deserializes the frame following a discriminant that the caller does not know, into an
+/// `Unknown`.
+pub struct ReadUnknown<R> {
+    state: ReadUnknownState<R>,
+    tag: u64,
+}
+
+enum ReadUnknownState<R> {
+    Length(ReadVarint<R>),
+    Body {
+        reader: Option<R>,
+        bytes: Vec<u8>,
+        len: usize,
+        filled: usize,
+        prefix_len: usize,
+    },
+}
+
+impl<R: AsyncRead> ReadUnknown<R> {
+    /// Create a new `ReadUnknown`, reading the frame of a value with the discriminant `tag`.
+    pub fn new(reader: R, tag: u64) -> ReadUnknown<R> {
+        ReadUnknown {
+            state: ReadUnknownState::Length(ReadVarint::from_reader(reader)),
+            tag,
+        }
+    }
+
+    /// Return how many bytes have already been read.
+    pub fn already_read(&self) -> usize {
+        match self.state {
+            ReadUnknownState::Length(ref inner) => inner.already_read(),
+            ReadUnknownState::Body {
+                filled,
+                prefix_len,
+                ..
+            } => prefix_len + filled,
+        }
+    }
+}
+
+impl<R> Restorable<R> for ReadUnknown<R> {
+    fn restore(self) -> Option<R> {
+        match self.state {
+            ReadUnknownState::Length(inner) => inner.restore(),
+            ReadUnknownState::Body { reader, .. } => reader,
+        }
+    }
+}
+
+impl<R: AsyncRead> Future for ReadUnknown<R> {
+    type Item = (R, Unknown, usize);
+    type Error = (R, DeserializeError<TagError>);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let (reader, len, prefix_len) = match self.state {
+                ReadUnknownState::Length(ref mut inner) => {
+                    match inner.poll(cx) {
+                        Ok(Async::Ready(done)) => done,
+                        Ok(Async::Pending) => return Ok(Async::Pending),
+                        Err((reader, DeserializeError::ReaderError(err))) => {
+                            return Err((reader, DeserializeError::ReaderError(err)))
+                        }
+                        Err((reader, DeserializeError::DataError(VarintError::Overflow))) => {
+                            return Err((reader,
+                                        DeserializeError::DataError(TagError::VarintOverflow)))
+                        }
+                    }
+                }
+
+                ReadUnknownState::Body {
+                    ref mut reader,
+                    ref mut bytes,
+                    len,
+                    ref mut filled,
+                    prefix_len,
+                } => {
+                    let mut r = or_pending!(reader.take());
+                    match poll_read_vec(&mut r, cx, bytes, filled, len) {
+                        Ok(Async::Ready(())) => {}
+                        Ok(Async::Pending) => {
+                            *reader = Some(r);
+                            return Ok(Async::Pending);
+                        }
+                        Err(err) => return Err((r, DeserializeError::ReaderError(err))),
+                    }
+
+                    let unknown = Unknown {
+                        tag: self.tag,
+                        bytes: mem::take(bytes),
+                    };
+                    return Ok(Async::Ready((r, unknown, prefix_len + len)));
+                }
+            };
+
+            let len = match usize::try_from(len) {
+                Ok(len) => len,
+                Err(_) => {
+                    return Err((reader, DeserializeError::DataError(TagError::LengthOverflow)))
+                }
+            };
+
+            self.state = ReadUnknownState::Body {
+                reader: Some(reader),
+                bytes: Vec::new(),
+                len,
+                filled: 0,
+                prefix_len,
+            };
+        }
+    }
+}
+
+/// Everything that can go wrong when deserializing a discriminant or an `Unknown`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TagError {
+    /// A varint does not fit into a `u64`.
+    VarintOverflow,
+    /// The length of the frame of an `Unknown` does not fit into a `usize`.
+    LengthOverflow,
+}
+
+impl Display for TagError {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            TagError::VarintOverflow => write!(f, "Varint overflows a u64"),
+            TagError::LengthOverflow => write!(f, "Length overflows a usize"),
+        }
+    }
+}
+
+impl Error for TagError {}
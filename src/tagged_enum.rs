@@ -0,0 +1,332 @@
+//! Deserialization of closed enums, i.e. tagged unions whose every discriminant is known.
+//!
+//! The `deserialize_enum!` macro generates a deserializer that reads a discriminant via a
+//! [`ReadTag`](../tagged/struct.ReadTag.html), then the fields of the corresponding variant one
+//! after the other, and constructs the variant. The encoding is that of a `WriteTagged` whose
+//! value is the concatenation of the fields.
+//!
+//! The fields of all variants share a single data error type `E`, the data errors of the field
+//! deserializers are converted into it via `Into`. Reading an unknown discriminant fails with
+//! `EnumError::UnknownTag`.
+
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::marker::PhantomData;
+
+use futures_core::{Async, Future, Poll};
+use futures_core::task::Context;
+use futures_io::AsyncRead;
+
+use {AsyncDeserialize, DeserializeError, Restorable};
+use tagged::TagError;
+
+#[doc(hidden)]
+pub mod __private {
+    pub use futures_core::{Async, Future, Poll};
+    pub use futures_core::task::Context;
+    pub use futures_io::AsyncRead;
+}
+
+/// Names the type of the values that a deserializer produces, so that `Fields` does not need a
+/// type parameter for it.
+#[doc(hidden)]
+pub trait FieldValue {
+    type Value;
+}
+
+impl<D, R, T, E> FieldValue for D
+    where D: Future<Item = (R, T, usize), Error = (R, DeserializeError<E>)>
+{
+    type Value = T;
+}
+
+/// Deserializes the fields of a variant as nested pairs `(a, (b, ()))`, the first one via `D` and
+/// the rest via `N`, converting the data errors of `D` into `E`.
+#[doc(hidden)]
+pub struct Fields<D: FieldValue, N, E> {
+    state: FieldsState<D, N>,
+    read: usize,
+    _error: PhantomData<E>,
+}
+
+enum FieldsState<D: FieldValue, N> {
+    Head(D),
+    Tail(N, Option<D::Value>),
+}
+
+impl<D, N, R, T, NT, ED, E> Future for Fields<D, N, E>
+    where D: AsyncDeserialize<R, T, ED>,
+          D: Future<Item = (R, T, usize), Error = (R, DeserializeError<ED>)>,
+          N: AsyncDeserialize<R, NT, E>,
+          N: Future<Item = (R, NT, usize), Error = (R, DeserializeError<E>)>,
+          ED: Into<E>,
+          R: AsyncRead
+{
+    type Item = (R, (T, NT), usize);
+    type Error = (R, DeserializeError<E>);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let next = match self.state {
+                FieldsState::Head(ref mut inner) => {
+                    let (reader, val, read) = match inner.poll(cx) {
+                        Ok(Async::Ready(done)) => done,
+                        Ok(Async::Pending) => return Ok(Async::Pending),
+                        Err((reader, DeserializeError::ReaderError(err))) => {
+                            return Err((reader, DeserializeError::ReaderError(err)))
+                        }
+                        Err((reader, DeserializeError::DataError(err))) => {
+                            return Err((reader, DeserializeError::DataError(err.into())))
+                        }
+                    };
+                    self.read = read;
+                    FieldsState::Tail(N::from_reader(reader), Some(val))
+                }
+
+                FieldsState::Tail(ref mut inner, ref mut val) => {
+                    let (reader, rest, read) = try_ready!(inner.poll(cx));
+                    let val = or_pending!(val.take());
+                    return Ok(Async::Ready((reader, (val, rest), self.read + read)));
+                }
+            };
+            self.state = next;
+        }
+    }
+}
+
+impl<D, N, R, T, NT, ED, E> AsyncDeserialize<R, (T, NT), E> for Fields<D, N, E>
+    where D: AsyncDeserialize<R, T, ED>,
+          D: Future<Item = (R, T, usize), Error = (R, DeserializeError<ED>)>,
+          N: AsyncDeserialize<R, NT, E>,
+          N: Future<Item = (R, NT, usize), Error = (R, DeserializeError<E>)>,
+          ED: Into<E>,
+          R: AsyncRead
+{
+    fn from_reader(reader: R) -> Self {
+        Fields {
+            state: FieldsState::Head(D::from_reader(reader)),
+            read: 0,
+            _error: PhantomData,
+        }
+    }
+
+    fn already_read(&self) -> usize {
+        match self.state {
+            FieldsState::Head(ref inner) => inner.already_read(),
+            FieldsState::Tail(ref inner, _) => self.read + inner.already_read(),
+        }
+    }
+}
+
+impl<D, N, R, E> Restorable<R> for Fields<D, N, E>
+    where D: FieldValue + Restorable<R>,
+          N: Restorable<R>
+{
+    fn restore(self) -> Option<R> {
+        match self.state {
+            FieldsState::Head(inner) => inner.restore(),
+            FieldsState::Tail(inner, _) => inner.restore(),
+        }
+    }
+}
+
+/// Deserializes the empty list of fields of a variant, without reading anything.
+#[doc(hidden)]
+pub struct NoFields<R, E>(Option<R>, PhantomData<E>);
+
+impl<R: AsyncRead, E> Future for NoFields<R, E> {
+    type Item = (R, (), usize);
+    type Error = (R, DeserializeError<E>);
+
+    fn poll(&mut self, _: &mut Context) -> Poll<Self::Item, Self::Error> {
+        let reader = or_pending!(self.0.take());
+        Ok(Async::Ready((reader, (), 0)))
+    }
+}
+
+impl<R: AsyncRead, E> AsyncDeserialize<R, (), E> for NoFields<R, E> {
+    fn from_reader(reader: R) -> Self {
+        NoFields(Some(reader), PhantomData)
+    }
+
+    fn already_read(&self) -> usize {
+        0
+    }
+}
+
+impl<R, E> Restorable<R> for NoFields<R, E> {
+    fn restore(self) -> Option<R> {
+        self.0
+    }
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __deserialize_enum_fields {
+    ($r:ident, $err:ty;) => {
+        $crate::tagged_enum::NoFields<$r, $err>
+    };
+    ($r:ident, $err:ty; $de:ty $(, $rest:ty)*) => {
+        $crate::tagged_enum::Fields<$de,
+                                    $crate::__deserialize_enum_fields!($r, $err; $($rest),*),
+                                    $err>
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __deserialize_enum_pattern {
+    () => { () };
+    ($field:ident $(, $rest:ident)*) => {
+        ($field, $crate::__deserialize_enum_pattern!($($rest),*))
+    };
+}
+
+/// Generate a deserializer for a closed enum.
+///
+/// The deserializer is itself an enum, whose variants are an implementation detail. It implements
+/// `AsyncDeserialize<R, T, EnumError<E>>`, where `T` is the deserialized enum and `E` the data
+/// error type shared by all fields. The fields of each variant are given as `name: Deserializer`
+/// pairs, the names only serve to construct the variant. Variants without fields are written
+/// without parentheses.
+///
+/// ```rust,ignore
+/// deserialize_enum! {
+///     /// Deserializes a `Shape`.
+///     pub enum ReadShape<R>: Shape {
+///         tag: TagWidth::U8,
+///         error: VarintError,
+///         0 => Circle(radius: ReadVarint<R>),
+///         1 => Rect(width: ReadVarint<R>, height: ReadVarint<R>),
+///         2 => Empty,
+///     }
+/// }
+/// ```
+#[macro_export]
+macro_rules! deserialize_enum {
+    (
+        $(#[$attr:meta])*
+        $vis:vis enum $name:ident<$r:ident>: $enum:ident {
+            tag: $width:expr,
+            error: $err:ty,
+            $($tag:expr => $variant:ident $(($($field:ident: $de:ty),*))*),* $(,)*
+        }
+    ) => {
+        $(#[$attr])*
+        $vis enum $name<$r>
+            where $r: $crate::tagged_enum::__private::AsyncRead
+        {
+            #[doc(hidden)]
+            __Tag($crate::tagged::ReadTag<$r>),
+            $(
+                #[doc(hidden)]
+                $variant($crate::__deserialize_enum_fields!($r, $err; $($($de),*)*), usize),
+            )*
+        }
+
+        impl<$r> $crate::tagged_enum::__private::Future for $name<$r>
+            where $r: $crate::tagged_enum::__private::AsyncRead
+        {
+            type Item = ($r, $enum, usize);
+            type Error = ($r, $crate::DeserializeError<$crate::tagged_enum::EnumError<$err>>);
+
+            fn poll(&mut self,
+                    cx: &mut $crate::tagged_enum::__private::Context)
+                    -> $crate::tagged_enum::__private::Poll<Self::Item, Self::Error> {
+                use $crate::AsyncDeserialize;
+                use $crate::DeserializeError;
+                use $crate::tagged_enum::EnumError;
+                use $crate::tagged_enum::__private::{Async, Future};
+
+                loop {
+                    let next = match *self {
+                        $name::__Tag(ref mut inner) => {
+                            let (reader, tag, read) = match inner.poll(cx) {
+                                Ok(Async::Ready(done)) => done,
+                                Ok(Async::Pending) => return Ok(Async::Pending),
+                                Err((reader, DeserializeError::ReaderError(err))) => {
+                                    return Err((reader, DeserializeError::ReaderError(err)))
+                                }
+                                Err((reader, DeserializeError::DataError(err))) => {
+                                    let err = EnumError::Tag(err);
+                                    return Err((reader, DeserializeError::DataError(err)));
+                                }
+                            };
+
+                            $(
+                                if tag == $tag {
+                                    $name::$variant(AsyncDeserialize::from_reader(reader), read)
+                                } else
+                            )* {
+                                let err = EnumError::UnknownTag(tag);
+                                return Err((reader, DeserializeError::DataError(err)));
+                            }
+                        }
+
+                        $(
+                            $name::$variant(ref mut inner, tag_read) => {
+                                let (reader, fields, read) = match inner.poll(cx) {
+                                    Ok(Async::Ready(done)) => done,
+                                    Ok(Async::Pending) => return Ok(Async::Pending),
+                                    Err((reader, DeserializeError::ReaderError(err))) => {
+                                        return Err((reader, DeserializeError::ReaderError(err)))
+                                    }
+                                    Err((reader, DeserializeError::DataError(err))) => {
+                                        let err = EnumError::Field(err);
+                                        return Err((reader, DeserializeError::DataError(err)));
+                                    }
+                                };
+
+                                let $crate::__deserialize_enum_pattern!($($($field),*)*) = fields;
+                                let val = $enum::$variant $(($($field),*))*;
+                                return Ok(Async::Ready((reader, val, tag_read + read)));
+                            }
+                        )*
+                    };
+                    *self = next;
+                }
+            }
+        }
+
+        impl<$r: $crate::tagged_enum::__private::AsyncRead>
+            $crate::AsyncDeserialize<$r, $enum, $crate::tagged_enum::EnumError<$err>>
+            for $name<$r>
+        {
+            fn from_reader(reader: $r) -> Self {
+                $name::__Tag($crate::tagged::ReadTag::new(reader, $width))
+            }
+
+            fn already_read(&self) -> usize {
+                use $crate::AsyncDeserialize;
+
+                match *self {
+                    $name::__Tag(ref inner) => inner.already_read(),
+                    $($name::$variant(ref inner, tag_read) => tag_read + inner.already_read(),)*
+                }
+            }
+        }
+    }
+}
+
+/// Everything that can go wrong when deserializing a closed enum, apart from reader errors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EnumError<E> {
+    /// The discriminant could not be deserialized.
+    Tag(TagError),
+    /// The discriminant does not belong to any variant.
+    UnknownTag(u64),
+    /// A field of the variant could not be deserialized.
+    Field(E),
+}
+
+impl<E: Display> Display for EnumError<E> {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            EnumError::Tag(ref err) => write!(f, "{}", err),
+            EnumError::UnknownTag(tag) => write!(f, "Unknown discriminant {}", tag),
+            EnumError::Field(ref err) => write!(f, "Invalid field: {}", err),
+        }
+    }
+}
+
+impl<E: Error> Error for EnumError<E> {}
@@ -0,0 +1,65 @@
+//! Limit how many bytes can be read from an `AsyncRead`.
+
+use std::cmp::min;
+
+use futures_core::{Async, Poll};
+use futures_core::task::Context;
+use futures_io::{AsyncRead, Error as FutIoErr};
+
+/// Wraps an `AsyncRead` and reads at most `limit` bytes from it, signaling the end of the stream
+/// afterwards.
+///
+/// This is useful to run a deserializer over a length-prefixed frame: the deserializer physically
+/// can not read past the end of the frame, it just sees an end of file.
+#[derive(Debug)]
+pub struct TakeReader<R> {
+    inner: R,
+    limit: u64,
+}
+
+impl<R> TakeReader<R> {
+    /// Create a new `TakeReader`, allowing at most `limit` bytes to be read from `inner`.
+    pub fn new(inner: R, limit: u64) -> TakeReader<R> {
+        TakeReader { inner, limit }
+    }
+
+    /// Return how many more bytes can be read before this signals the end of the stream.
+    pub fn limit(&self) -> u64 {
+        self.limit
+    }
+
+    /// Change how many more bytes can be read before this signals the end of the stream.
+    pub fn set_limit(&mut self, limit: u64) {
+        self.limit = limit;
+    }
+
+    /// Get a reference to the wrapped reader.
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// Get a mutable reference to the wrapped reader.
+    ///
+    /// Reading from it directly does not count towards the limit.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    /// Consume this `TakeReader`, returning the wrapped reader.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: AsyncRead> AsyncRead for TakeReader<R> {
+    fn poll_read(&mut self, cx: &mut Context, buf: &mut [u8]) -> Poll<usize, FutIoErr> {
+        if self.limit == 0 {
+            return Ok(Async::Ready(0));
+        }
+
+        let max = min(buf.len() as u64, self.limit) as usize;
+        let read = try_ready!(self.inner.poll_read(cx, &mut buf[..max]));
+        self.limit -= read as u64;
+        Ok(Async::Ready(read))
+    }
+}
@@ -0,0 +1,124 @@
+//! Count the bytes written per type of value, e.g. to find out which message types of a protocol
+//! use the most bandwidth. This module is only available with the `telemetry` feature.
+//!
+//! All `TelemetryWriter`s of a service share one `TelemetryStore`, and each one attributes the
+//! bytes written through it to a tag, usually the name of the type of the serialized values.
+
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use futures_core::{Async, Poll};
+use futures_core::task::Context;
+use futures_io::{AsyncWrite, Error as FutIoErr};
+
+/// The number of bytes written per tag, shared between any number of `TelemetryWriter`s.
+#[derive(Debug, Default)]
+pub struct TelemetryStore {
+    bytes_written: RwLock<HashMap<&'static str, AtomicU64>>,
+}
+
+impl TelemetryStore {
+    /// Create a new, empty `TelemetryStore`.
+    pub fn new() -> TelemetryStore {
+        TelemetryStore::default()
+    }
+
+    /// Attribute `bytes` more written bytes to `tag`.
+    pub fn record(&self, tag: &'static str, bytes: u64) {
+        {
+            let counters = self.bytes_written.read().unwrap_or_else(|err| err.into_inner());
+            if let Some(counter) = counters.get(tag) {
+                counter.fetch_add(bytes, Ordering::Relaxed);
+                return;
+            }
+        }
+
+        let mut counters = self.bytes_written.write().unwrap_or_else(|err| err.into_inner());
+        counters
+            .entry(tag)
+            .or_insert_with(|| AtomicU64::new(0))
+            .fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// Return the number of bytes written for `tag`.
+    pub fn bytes_written(&self, tag: &str) -> u64 {
+        let counters = self.bytes_written.read().unwrap_or_else(|err| err.into_inner());
+        counters
+            .get(tag)
+            .map(|counter| counter.load(Ordering::Relaxed))
+            .unwrap_or(0)
+    }
+
+    /// Return the number of bytes written for every tag, the tags with the most bytes first (and
+    /// tags with the same number of bytes in alphabetical order).
+    pub fn report(&self) -> Vec<(&'static str, u64)> {
+        let mut report: Vec<(&'static str, u64)> = {
+            let counters = self.bytes_written.read().unwrap_or_else(|err| err.into_inner());
+            counters
+                .iter()
+                .map(|(tag, counter)| (*tag, counter.load(Ordering::Relaxed)))
+                .collect()
+        };
+        report.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        report
+    }
+}
+
+/// Wraps an `AsyncWrite` and records every successfully written byte under a tag in a
+/// `TelemetryStore`.
+#[derive(Debug)]
+pub struct TelemetryWriter<W> {
+    inner: W,
+    store: Arc<TelemetryStore>,
+    tag: &'static str,
+}
+
+impl<W> TelemetryWriter<W> {
+    /// Create a new `TelemetryWriter`, recording the bytes written to `inner` under `tag`.
+    pub fn new(inner: W, store: &Arc<TelemetryStore>, tag: &'static str) -> TelemetryWriter<W> {
+        TelemetryWriter {
+            inner,
+            store: Arc::clone(store),
+            tag,
+        }
+    }
+
+    /// Return the tag under which the written bytes are recorded.
+    pub fn tag(&self) -> &'static str {
+        self.tag
+    }
+
+    /// Get a reference to the wrapped writer.
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Get a mutable reference to the wrapped writer.
+    ///
+    /// Writing to it directly bypasses the telemetry.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// Consume this `TelemetryWriter`, returning the wrapped writer.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: AsyncWrite> AsyncWrite for TelemetryWriter<W> {
+    fn poll_write(&mut self, cx: &mut Context, buf: &[u8]) -> Poll<usize, FutIoErr> {
+        let written = try_ready!(self.inner.poll_write(cx, buf));
+        self.store.record(self.tag, written as u64);
+        Ok(Async::Ready(written))
+    }
+
+    fn poll_flush(&mut self, cx: &mut Context) -> Poll<(), FutIoErr> {
+        self.inner.poll_flush(cx)
+    }
+
+    fn poll_close(&mut self, cx: &mut Context) -> Poll<(), FutIoErr> {
+        self.inner.poll_close(cx)
+    }
+}
@@ -0,0 +1,193 @@
+//! Serialization of byte strings that are ended by a terminator byte, e.g. C strings.
+//!
+//! A value is encoded as its bytes followed by the terminator. Values that contain the
+//! terminator can not be serialized, the serializer fails with an `ErrorKind::InvalidInput` error
+//! without writing anything.
+
+use futures_core::{Async, Future, Poll};
+use futures_core::task::Context;
+use futures_io::{AsyncRead, AsyncWrite, Error as FutIoErr, ErrorKind};
+
+use {AsyncDeserialize, AsyncSerialize, AsyncSerializeLen, AsyncWriterFuture,
+     AsyncWriterFutureLen, DeserializeError, Restorable};
+use grow_buf::{GrowBuf, LimitExceeded};
+use util::WriteAll;
+
+/// The terminator used by `from_reader`.
+pub const DEFAULT_TERMINATOR: u8 = 0;
+
+/// The initial capacity of the buffer used by `from_reader`.
+pub const DEFAULT_INITIAL: usize = 64;
+
+/// The maximum length of a value read via `from_reader`.
+pub const DEFAULT_LIMIT: usize = 1 << 20;
+
+/// Serializes a byte string followed by a terminator.
+///
+/// For values that can not be serialized with `DEFAULT_TERMINATOR`, `total_bytes` returns zero.
+pub struct WriteTerminated<W>(State<W>);
+
+enum State<W> {
+    Bytes(WriteAll<W, Vec<u8>>, u8),
+    Terminator(WriteAll<W, [u8; 1]>, usize),
+    Invalid(Option<W>),
+}
+
+impl<W: AsyncWrite> WriteTerminated<W> {
+    /// Create a new `WriteTerminated`, writing `val` followed by `terminator` into `writer`.
+    pub fn new(writer: W, val: Vec<u8>, terminator: u8) -> WriteTerminated<W> {
+        if val.contains(&terminator) {
+            WriteTerminated(State::Invalid(Some(writer)))
+        } else {
+            WriteTerminated(State::Bytes(WriteAll::new(writer, val), terminator))
+        }
+    }
+
+}
+
+impl<W: AsyncWrite> Future for WriteTerminated<W> {
+    type Item = (W, usize);
+    type Error = (W, FutIoErr);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let next = match self.0 {
+                State::Bytes(ref mut inner, terminator) => {
+                    let (writer, written) = try_ready!(inner.poll(cx));
+                    State::Terminator(WriteAll::new(writer, [terminator]), written)
+                }
+                State::Terminator(ref mut inner, written) => {
+                    let (writer, terminator_written) = try_ready!(inner.poll(cx));
+                    return Ok(Async::Ready((writer, written + terminator_written)));
+                }
+                State::Invalid(ref mut writer) => {
+                    let err = FutIoErr::new(ErrorKind::InvalidInput,
+                                            "value contains the terminator");
+                    let writer = or_pending!(writer.take());
+                    return Err((writer, err));
+                }
+            };
+            self.0 = next;
+        }
+    }
+}
+
+impl<W: AsyncWrite> AsyncWriterFuture<W> for WriteTerminated<W> {
+    fn already_written(&self) -> usize {
+        match self.0 {
+            State::Bytes(ref inner, _) => inner.already_written(),
+            State::Terminator(ref inner, written) => written + inner.already_written(),
+            State::Invalid(_) => 0,
+        }
+    }
+}
+
+impl<W: AsyncWrite> AsyncWriterFutureLen<W> for WriteTerminated<W> {
+    fn remaining_bytes(&self) -> usize {
+        match self.0 {
+            State::Bytes(ref inner, _) => inner.remaining_bytes() + 1,
+            State::Terminator(ref inner, _) => inner.remaining_bytes(),
+            State::Invalid(_) => 0,
+        }
+    }
+}
+
+impl<W: AsyncWrite> AsyncSerialize<W> for WriteTerminated<W> {
+    type Serialized = Vec<u8>;
+
+    /// Writes `val` followed by `DEFAULT_TERMINATOR`.
+    fn from_val(writer: W, val: Vec<u8>) -> Self {
+        WriteTerminated::new(writer, val, DEFAULT_TERMINATOR)
+    }
+}
+
+impl<W: AsyncWrite> AsyncSerializeLen<W> for WriteTerminated<W> {
+    /// Returns zero for values that contain `DEFAULT_TERMINATOR`.
+    fn total_bytes(val: &Vec<u8>) -> usize {
+        if val.contains(&DEFAULT_TERMINATOR) {
+            0
+        } else {
+            val.len() + 1
+        }
+    }
+}
+
+/// Deserializes a byte string that is ended by a terminator, into a `GrowBuf`.
+///
+/// The terminator is consumed but not part of the value. The reader is read one byte at a time,
+/// so that nothing after the terminator is consumed.
+///
+/// To avoid allocating for every value, pass the vector of the previous value back via
+/// `GrowBuf::seeded` when creating the next `ReadTerminated`.
+pub struct ReadTerminated<R> {
+    reader: Option<R>,
+    terminator: u8,
+    buf: GrowBuf,
+    read: usize,
+}
+
+impl<R: AsyncRead> ReadTerminated<R> {
+    /// Create a new `ReadTerminated`, reading up to `terminator` into `buf`.
+    pub fn new(reader: R, terminator: u8, buf: GrowBuf) -> ReadTerminated<R> {
+        ReadTerminated {
+            reader: Some(reader),
+            terminator,
+            buf,
+            read: 0,
+        }
+    }
+}
+
+impl<R: AsyncRead> Future for ReadTerminated<R> {
+    type Item = (R, Vec<u8>, usize);
+    type Error = (R, DeserializeError<LimitExceeded>);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        let mut reader = or_pending!(self.reader.take());
+
+        loop {
+            let mut byte = [0u8; 1];
+            match reader.poll_read(cx, &mut byte) {
+                Ok(Async::Ready(0)) => {
+                    let err = FutIoErr::new(ErrorKind::UnexpectedEof,
+                                            "unexpected end of terminated value");
+                    return Err((reader, DeserializeError::ReaderError(err)));
+                }
+                Ok(Async::Ready(_)) => {
+                    self.read += 1;
+                    if byte[0] == self.terminator {
+                        return Ok(Async::Ready((reader, self.buf.take(), self.read)));
+                    }
+                    if let Err(err) = self.buf.push(byte[0]) {
+                        return Err((reader, DeserializeError::DataError(err)));
+                    }
+                }
+                Ok(Async::Pending) => {
+                    self.reader = Some(reader);
+                    return Ok(Async::Pending);
+                }
+                Err(err) => return Err((reader, DeserializeError::ReaderError(err))),
+            }
+        }
+    }
+}
+
+impl<R: AsyncRead> AsyncDeserialize<R, Vec<u8>, LimitExceeded> for ReadTerminated<R> {
+    /// Reads up to `DEFAULT_TERMINATOR`, with a buffer of `DEFAULT_INITIAL` bytes growing up to
+    /// `DEFAULT_LIMIT` bytes.
+    fn from_reader(reader: R) -> Self {
+        ReadTerminated::new(reader,
+                            DEFAULT_TERMINATOR,
+                            GrowBuf::new(DEFAULT_INITIAL, DEFAULT_LIMIT))
+    }
+
+    fn already_read(&self) -> usize {
+        self.read
+    }
+}
+
+impl<R> Restorable<R> for ReadTerminated<R> {
+    fn restore(self) -> Option<R> {
+        self.reader
+    }
+}
@@ -0,0 +1,1066 @@
+//! Helpers for testing implementations of the traits of this crate, available with the `testing`
+//! feature.
+
+use std::borrow::Borrow;
+use std::cmp::min;
+use std::collections::VecDeque;
+use std::fmt::Debug;
+use std::marker::PhantomData;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use futures_core::{Async, Future, Poll};
+use futures_core::task::{Context, LocalMap, Wake, Waker};
+use futures_io::{AsyncRead, AsyncWrite, Error as FutIoErr, ErrorKind};
+
+use {AsyncDeserialize, AsyncSerialize, AsyncSerializeLen, AsyncSerializeRef, AsyncSerializeRefLen,
+     AsyncWriterFuture, AsyncWriterFutureLen, DeserializeError, Restorable};
+
+struct NoopWake;
+
+impl Wake for NoopWake {
+    fn wake(_: &Arc<NoopWake>) {}
+}
+
+/// Run a future to completion by polling it in a loop on the current thread.
+///
+/// This never blocks, so it only terminates for futures that do not wait for anything but
+/// themselves, e.g. (de)serializers over the readers and writers of this module.
+pub fn block_on<F: Future>(mut fut: F) -> Result<F::Item, F::Error> {
+    let waker = Waker::from(Arc::new(NoopWake));
+    let mut map = LocalMap::new();
+    let mut cx = Context::without_spawn(&mut map, &waker);
+
+    loop {
+        if let Async::Ready(item) = fut.poll(&mut cx)? {
+            return Ok(item);
+        }
+    }
+}
+
+/// An `AsyncWrite` that appends everything to a `Vec<u8>`.
+#[derive(Debug, Default)]
+pub struct VecWriter {
+    data: Vec<u8>,
+}
+
+impl VecWriter {
+    /// Create a new, empty `VecWriter`.
+    pub fn new() -> VecWriter {
+        VecWriter { data: Vec::new() }
+    }
+
+    /// Return the bytes written so far.
+    pub fn bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Consume this `VecWriter`, returning the bytes written to it.
+    pub fn into_inner(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+impl AsyncWrite for VecWriter {
+    fn poll_write(&mut self, _: &mut Context, buf: &[u8]) -> Poll<usize, FutIoErr> {
+        self.data.extend_from_slice(buf);
+        Ok(Async::Ready(buf.len()))
+    }
+
+    fn poll_flush(&mut self, _: &mut Context) -> Poll<(), FutIoErr> {
+        Ok(Async::Ready(()))
+    }
+
+    fn poll_close(&mut self, _: &mut Context) -> Poll<(), FutIoErr> {
+        Ok(Async::Ready(()))
+    }
+}
+
+/// An `AsyncWrite` that discards everything, only counting the bytes written to it.
+///
+/// Serializing into a `NullWriter` measures the cost of a serializer without the cost of storing
+/// its output.
+#[derive(Debug, Default)]
+pub struct NullWriter {
+    written: u64,
+}
+
+impl NullWriter {
+    /// Create a new `NullWriter`.
+    pub fn new() -> NullWriter {
+        NullWriter::default()
+    }
+
+    /// Return how many bytes have been written so far.
+    pub fn written(&self) -> u64 {
+        self.written
+    }
+}
+
+impl AsyncWrite for NullWriter {
+    fn poll_write(&mut self, _: &mut Context, buf: &[u8]) -> Poll<usize, FutIoErr> {
+        self.written += buf.len() as u64;
+        Ok(Async::Ready(buf.len()))
+    }
+
+    fn poll_flush(&mut self, _: &mut Context) -> Poll<(), FutIoErr> {
+        Ok(Async::Ready(()))
+    }
+
+    fn poll_close(&mut self, _: &mut Context) -> Poll<(), FutIoErr> {
+        Ok(Async::Ready(()))
+    }
+}
+
+/// An `AsyncWrite` that appends to a `Vec<u8>`, accepting at most `chunk` bytes per write, and
+/// that is not ready before every chunk.
+///
+/// This exercises the code paths of a serializer that resume after partial writes and after a
+/// pending writer.
+#[derive(Debug)]
+pub struct ChunkedWriter {
+    data: Vec<u8>,
+    chunk: usize,
+    ready: bool,
+}
+
+impl ChunkedWriter {
+    /// Create a new, empty `ChunkedWriter`, accepting chunks of at most `chunk` bytes.
+    ///
+    /// Panics if `chunk` is zero.
+    pub fn new(chunk: usize) -> ChunkedWriter {
+        assert!(chunk > 0, "ChunkedWriter needs a positive chunk size");
+        ChunkedWriter {
+            data: Vec::new(),
+            chunk,
+            ready: false,
+        }
+    }
+
+    /// Return the bytes written so far.
+    pub fn bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Consume this `ChunkedWriter`, returning the bytes written to it.
+    pub fn into_inner(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+impl AsyncWrite for ChunkedWriter {
+    fn poll_write(&mut self, cx: &mut Context, buf: &[u8]) -> Poll<usize, FutIoErr> {
+        if !self.ready {
+            self.ready = true;
+            cx.waker().wake();
+            return Ok(Async::Pending);
+        }
+
+        self.ready = false;
+        let len = min(buf.len(), self.chunk);
+        self.data.extend_from_slice(&buf[..len]);
+        Ok(Async::Ready(len))
+    }
+
+    fn poll_flush(&mut self, _: &mut Context) -> Poll<(), FutIoErr> {
+        Ok(Async::Ready(()))
+    }
+
+    fn poll_close(&mut self, _: &mut Context) -> Poll<(), FutIoErr> {
+        Ok(Async::Ready(()))
+    }
+}
+
+/// An `AsyncRead` over a `Vec<u8>` that delivers at most `chunk` bytes per read, and that is not
+/// ready before every chunk.
+///
+/// This exercises the code paths of a deserializer that resume after partial reads and after a
+/// pending reader.
+#[derive(Debug)]
+pub struct ChunkedReader {
+    data: Vec<u8>,
+    position: usize,
+    chunk: usize,
+    ready: bool,
+}
+
+impl ChunkedReader {
+    /// Create a new `ChunkedReader`, delivering `data` in chunks of at most `chunk` bytes.
+    ///
+    /// Panics if `chunk` is zero.
+    pub fn new(data: Vec<u8>, chunk: usize) -> ChunkedReader {
+        assert!(chunk > 0, "ChunkedReader needs a positive chunk size");
+        ChunkedReader {
+            data,
+            position: 0,
+            chunk,
+            ready: false,
+        }
+    }
+
+    /// Return how many bytes have been read so far.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+}
+
+impl AsyncRead for ChunkedReader {
+    fn poll_read(&mut self, cx: &mut Context, buf: &mut [u8]) -> Poll<usize, FutIoErr> {
+        if !self.ready {
+            self.ready = true;
+            cx.waker().wake();
+            return Ok(Async::Pending);
+        }
+
+        self.ready = false;
+        let len = min(min(buf.len(), self.chunk), self.data.len() - self.position);
+        buf[..len].copy_from_slice(&self.data[self.position..self.position + len]);
+        self.position += len;
+        Ok(Async::Ready(len))
+    }
+}
+
+/// Serialize `val` with an `S` into a `VecWriter`, deserialize it again with a `D` from a
+/// `ChunkedReader` that delivers one byte per read, and assert that the result equals `val`.
+///
+/// This also asserts that the serializer and deserializer both report the number of bytes that
+/// were actually written, and that the deserializer reads all of them.
+pub fn assert_roundtrip<S, D, T, E>(val: T)
+    where S: AsyncSerialize<VecWriter, Serialized = T>,
+          D: AsyncDeserialize<ChunkedReader, T, E>,
+          T: Clone + PartialEq + Debug,
+          E: Debug
+{
+    let (writer, written) = match block_on(S::from_val(VecWriter::new(), val.clone())) {
+        Ok(done) => done,
+        Err((_, err)) => panic!("serialization failed: {}", err),
+    };
+    let bytes = writer.into_inner();
+    assert_eq!(written, bytes.len(), "serializer reported a wrong number of written bytes");
+
+    let reader = ChunkedReader::new(bytes, 1);
+    let (reader, deserialized, read) = match block_on(D::from_reader(reader)) {
+        Ok(done) => done,
+        Err((_, err)) => panic!("deserialization failed: {:?}", err),
+    };
+    assert_eq!(deserialized, val, "deserialized value differs from the serialized one");
+    assert_eq!(read, written, "deserializer reported a wrong number of read bytes");
+    assert_eq!(reader.position(), written, "deserializer did not read all bytes");
+}
+
+// Parse a hex string, ignoring whitespace.
+fn parse_hex(hex: &str) -> Vec<u8> {
+    let digits: Vec<u8> = hex.bytes().filter(|byte| !byte.is_ascii_whitespace()).collect();
+    assert!(digits.len().is_multiple_of(2), "hex string has an odd number of digits: {:?}", hex);
+
+    digits.chunks(2)
+        .map(|pair| {
+            let pair = ::std::str::from_utf8(pair).unwrap_or("");
+            u8::from_str_radix(pair, 16)
+                .unwrap_or_else(|_| panic!("invalid hex digits {:?} in {:?}", pair, hex))
+        })
+        .collect()
+}
+
+/// Like `assert_golden`, but for serializers that are not created via `AsyncSerialize`:
+/// `serialize` creates the serializer for a given writer, and is called once per writer.
+pub fn assert_golden_with<F, M>(serialize: M, expected_hex: &str)
+    where F: AsyncWriterFuture<ChunkedWriter>,
+          M: Fn(ChunkedWriter) -> F
+{
+    let expected = parse_hex(expected_hex);
+
+    for &chunk in &[usize::MAX, 1] {
+        let (writer, written) = match block_on(serialize(ChunkedWriter::new(chunk))) {
+            Ok(done) => done,
+            Err((_, err)) => panic!("serialization failed: {}", err),
+        };
+        assert_eq!(writer.bytes(),
+                   &expected[..],
+                   "serialized bytes differ from the golden vector (chunk size {})",
+                   chunk);
+        assert_eq!(written,
+                   expected.len(),
+                   "serializer reported a wrong number of written bytes (chunk size {})",
+                   chunk);
+    }
+}
+
+/// Serialize `val` with an `S` and assert that the result is exactly `expected_hex`, a hex string
+/// which may contain whitespace.
+///
+/// The value is serialized twice: into a `ChunkedWriter` that accepts everything it is given, and
+/// into one that accepts a single byte per write. Both must produce the expected bytes, and the
+/// serializer must report their number as the number of written bytes.
+///
+/// This is how the formats of this crate are pinned, and downstream crates can use it to pin
+/// their own formats the same way.
+pub fn assert_golden<S>(val: S::Serialized, expected_hex: &str)
+    where S: AsyncSerialize<ChunkedWriter>,
+          S::Serialized: Clone
+{
+    assert_golden_with(|writer| S::from_val(writer, val.clone()), expected_hex);
+}
+
+/// Like `assert_golden`, but for serializers that serialize by reference.
+pub fn assert_golden_ref<'val, S>(val: &'val S::Serialized, expected_hex: &str)
+    where S: AsyncSerializeRef<'val, ChunkedWriter>
+{
+    assert_golden_with(|writer| S::from_ref(writer, val), expected_hex);
+}
+
+/// Serialize `val` with an `S` into `writer`, panicking unless the serializer writes exactly
+/// `expected` bytes.
+///
+/// Once the serializer is done, both the number of bytes it reports and its `already_written`
+/// must equal `expected`. This catches serializers that would silently shift the frames following
+/// them, e.g. in the state machine of a protocol.
+pub fn write_exactly<S, W>(writer: W, val: S::Serialized, expected: usize) -> WriteExactly<S, W>
+    where S: AsyncSerialize<W>,
+          W: AsyncWrite
+{
+    WriteExactly {
+        inner: S::from_val(writer, val),
+        expected,
+        _writer: PhantomData,
+    }
+}
+
+/// The future returned by `write_exactly`.
+pub struct WriteExactly<S, W> {
+    inner: S,
+    expected: usize,
+    _writer: PhantomData<W>,
+}
+
+impl<S, W> Future for WriteExactly<S, W>
+    where S: AsyncSerialize<W>,
+          W: AsyncWrite
+{
+    type Item = (W, usize);
+    type Error = (W, FutIoErr);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        let (writer, written) = try_ready!(self.inner.poll(cx));
+        let already_written = self.inner.already_written();
+        if written != self.expected || already_written != self.expected {
+            panic!("{} wrote the wrong number of bytes: expected {}, reported {}, \
+                    already_written {}",
+                   ::std::any::type_name::<S>(),
+                   self.expected,
+                   written,
+                   already_written);
+        }
+        Ok(Async::Ready((writer, written)))
+    }
+}
+
+impl<S, W> AsyncWriterFuture<W> for WriteExactly<S, W>
+    where S: AsyncSerialize<W>,
+          W: AsyncWrite
+{
+    fn already_written(&self) -> usize {
+        self.inner.already_written()
+    }
+}
+
+/// Check the behavior of an `S` and a `D` for a value that serializes to no more than a fixed
+/// prefix, typically an empty collection or a value of a zero-sized type.
+///
+/// Asserts that:
+///
+/// - `total_bytes` and `remaining_bytes` agree with the number of bytes that are actually written,
+/// - serializing completes on the first poll of an always-ready writer, calling it at most once
+///   (and not at all if nothing needs to be written),
+/// - deserializing completes on the first poll of an always-ready reader, calling it at most once
+///   (and not at all if nothing needs to be read),
+/// - the deserializer reads exactly the serialized bytes and not a single byte more, and
+/// - polling the serializer or deserializer again after completion returns `Async::Pending`
+///   rather than panicking.
+pub fn check_empty_value_behavior<S, D, T, E>(val: T)
+    where S: AsyncSerializeLen<CountingWriter, Serialized = T>,
+          D: AsyncDeserialize<CountingReader, T, E>,
+          T: Clone + PartialEq + Debug,
+          E: Debug
+{
+    let total = S::total_bytes(&val);
+    let bytes = check_empty_serializer(S::from_val(CountingWriter::new(), val.clone()), total);
+    let deserialized = check_empty_deserializer(bytes, D::from_reader, D::already_read);
+    assert_eq!(deserialized, val, "deserialized value differs from the serialized one");
+}
+
+/// Like `check_empty_value_behavior`, but for serializers that work by reference.
+///
+/// The deserialized values are compared to `val` via `Borrow`, so that e.g. a `PathBuf` can be
+/// compared to a `Path`.
+pub fn check_empty_ref_value_behavior<'val, S, D, T, E>(val: &'val S::Serialized)
+    where S: AsyncSerializeRefLen<'val, CountingWriter>,
+          S::Serialized: PartialEq + Debug,
+          D: AsyncDeserialize<CountingReader, T, E>,
+          T: Borrow<S::Serialized>,
+          E: Debug
+{
+    let total = S::total_bytes(val);
+    let bytes = check_empty_serializer(S::from_ref(CountingWriter::new(), val), total);
+    let deserialized = check_empty_deserializer(bytes, D::from_reader, D::already_read);
+    assert_eq!(deserialized.borrow(), val, "deserialized value differs from the serialized one");
+}
+
+/// Like `check_empty_value_behavior`, but for serializers and deserializers that need more than a
+/// value or a reader to be created, e.g. a length or a configuration.
+///
+/// `ser` must write into a fresh `CountingWriter`, and `total` is the number of bytes it is
+/// expected to write. The deserializer is created from the reader via `de`, and `already_read`
+/// returns how many bytes it has read. The deserialized value is returned rather than compared,
+/// so that it can also be checked if it does not implement `PartialEq`.
+pub fn check_empty_behavior_with<S, D, F, T, E>(ser: S,
+                                                total: usize,
+                                                de: F,
+                                                already_read: fn(&D) -> usize)
+                                                -> T
+    where S: AsyncWriterFutureLen<CountingWriter>,
+          D: Future<Item = (CountingReader, T, usize),
+                    Error = (CountingReader, DeserializeError<E>)>,
+          F: FnOnce(CountingReader) -> D,
+          E: Debug
+{
+    let bytes = check_empty_serializer(ser, total);
+    check_empty_deserializer(bytes, de, already_read)
+}
+
+fn check_empty_serializer<S>(mut ser: S, total: usize) -> Vec<u8>
+    where S: AsyncWriterFutureLen<CountingWriter>
+{
+    assert_eq!(ser.remaining_bytes(),
+               total,
+               "remaining_bytes of a fresh serializer differs from total_bytes");
+
+    let (writer, written) = match poll_once(&mut ser) {
+        Some(Ok(done)) => done,
+        Some(Err((_, err))) => panic!("serialization failed: {}", err),
+        None => panic!("serializer did not complete on the first poll of a ready writer"),
+    };
+    assert_eq!(written, total, "serializer wrote a different number of bytes than total_bytes");
+    assert_eq!(written,
+               writer.data.len(),
+               "serializer reported a wrong number of written bytes");
+    assert_eq!(ser.already_written(),
+               written,
+               "already_written of a completed serializer is wrong");
+    check_calls(writer.calls, written, "writer");
+    assert!(poll_once(&mut ser).is_none(),
+            "serializer did not return pending when polled after completion");
+
+    writer.data
+}
+
+fn check_empty_deserializer<D, F, T, E>(bytes: Vec<u8>,
+                                        de: F,
+                                        already_read: fn(&D) -> usize)
+                                        -> T
+    where D: Future<Item = (CountingReader, T, usize),
+                    Error = (CountingReader, DeserializeError<E>)>,
+          F: FnOnce(CountingReader) -> D,
+          E: Debug
+{
+    let len = bytes.len();
+    let mut data = bytes;
+    // A sentinel byte that must not be read.
+    data.push(0xff);
+
+    let mut de = de(CountingReader::new(data));
+    let (reader, deserialized, read) = match poll_once(&mut de) {
+        Some(Ok(done)) => done,
+        Some(Err((_, err))) => panic!("deserialization failed: {:?}", err),
+        None => panic!("deserializer did not complete on the first poll of a ready reader"),
+    };
+    assert_eq!(read, len, "deserializer reported a wrong number of read bytes");
+    assert_eq!(reader.position, len, "deserializer read a different number of bytes");
+    assert_eq!(already_read(&de), read, "already_read of a completed deserializer is wrong");
+    check_calls(reader.calls, len, "reader");
+    assert!(poll_once(&mut de).is_none(),
+            "deserializer did not return pending when polled after completion");
+
+    deserialized
+}
+
+fn check_calls(calls: usize, len: usize, name: &str) {
+    if len == 0 {
+        assert_eq!(calls, 0, "the {} was called although there were no bytes", name);
+    } else {
+        assert!(calls <= 1, "the {} was called {} times for a single prefix", name, calls);
+    }
+}
+
+fn poll_once<F: Future>(fut: &mut F) -> Option<Result<F::Item, F::Error>> {
+    let waker = Waker::from(Arc::new(NoopWake));
+    let mut map = LocalMap::new();
+    let mut cx = Context::without_spawn(&mut map, &waker);
+
+    match fut.poll(&mut cx) {
+        Ok(Async::Ready(item)) => Some(Ok(item)),
+        Ok(Async::Pending) => None,
+        Err(err) => Some(Err(err)),
+    }
+}
+
+/// An always-ready `AsyncWrite` that records the written bytes and how often it was written to,
+/// used by `check_empty_value_behavior`.
+#[derive(Debug, Default)]
+pub struct CountingWriter {
+    data: Vec<u8>,
+    calls: usize,
+}
+
+impl CountingWriter {
+    /// Create a new, empty `CountingWriter`.
+    pub fn new() -> CountingWriter {
+        CountingWriter::default()
+    }
+
+    /// Return how often `poll_write` was called.
+    pub fn calls(&self) -> usize {
+        self.calls
+    }
+}
+
+impl AsyncWrite for CountingWriter {
+    fn poll_write(&mut self, _: &mut Context, buf: &[u8]) -> Poll<usize, FutIoErr> {
+        self.calls += 1;
+        self.data.extend_from_slice(buf);
+        Ok(Async::Ready(buf.len()))
+    }
+
+    fn poll_flush(&mut self, _: &mut Context) -> Poll<(), FutIoErr> {
+        Ok(Async::Ready(()))
+    }
+
+    fn poll_close(&mut self, _: &mut Context) -> Poll<(), FutIoErr> {
+        Ok(Async::Ready(()))
+    }
+}
+
+/// An always-ready `AsyncRead` over a `Vec<u8>` that records how often it was read from, used by
+/// `check_empty_value_behavior`.
+#[derive(Debug)]
+pub struct CountingReader {
+    data: Vec<u8>,
+    position: usize,
+    calls: usize,
+}
+
+impl CountingReader {
+    /// Create a new `CountingReader` over `data`.
+    pub fn new(data: Vec<u8>) -> CountingReader {
+        CountingReader {
+            data,
+            position: 0,
+            calls: 0,
+        }
+    }
+
+    /// Return how many bytes have been read so far.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Return how often `poll_read` was called.
+    pub fn calls(&self) -> usize {
+        self.calls
+    }
+}
+
+impl AsyncRead for CountingReader {
+    fn poll_read(&mut self, _: &mut Context, buf: &mut [u8]) -> Poll<usize, FutIoErr> {
+        self.calls += 1;
+        let len = min(buf.len(), self.data.len() - self.position);
+        buf[..len].copy_from_slice(&self.data[self.position..self.position + len]);
+        self.position += len;
+        Ok(Async::Ready(len))
+    }
+}
+
+/// Wraps an `AsyncWrite` and counts the bytes the wrapped writer accepts, used by
+/// `ConsistencyChecker`.
+#[derive(Debug)]
+pub struct WriteCounter<W> {
+    inner: W,
+    count: Arc<AtomicUsize>,
+}
+
+impl<W> WriteCounter<W> {
+    /// Create a new `WriteCounter` wrapping `inner`.
+    pub fn new(inner: W) -> WriteCounter<W> {
+        WriteCounter {
+            inner,
+            count: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Return how many bytes the wrapped writer has accepted.
+    pub fn count(&self) -> usize {
+        self.count.load(Ordering::SeqCst)
+    }
+
+    /// Consume this `WriteCounter`, returning the wrapped writer.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: AsyncWrite> AsyncWrite for WriteCounter<W> {
+    fn poll_write(&mut self, cx: &mut Context, buf: &[u8]) -> Poll<usize, FutIoErr> {
+        let written = try_ready!(self.inner.poll_write(cx, buf));
+        self.count.fetch_add(written, Ordering::SeqCst);
+        Ok(Async::Ready(written))
+    }
+
+    fn poll_flush(&mut self, cx: &mut Context) -> Poll<(), FutIoErr> {
+        self.inner.poll_flush(cx)
+    }
+
+    fn poll_close(&mut self, cx: &mut Context) -> Poll<(), FutIoErr> {
+        self.inner.poll_close(cx)
+    }
+}
+
+/// Wraps a serializer `S` and panics as soon as its `already_written` or the number of bytes it
+/// claims to have written differ from the number of bytes that actually reached the writer.
+///
+/// The check runs after every poll, so the panic message points at the first inconsistency. It
+/// implements `AsyncSerialize` itself, so it can replace `S` anywhere, e.g. in `assert_roundtrip`.
+pub struct ConsistencyChecker<S, W> {
+    inner: S,
+    count: Arc<AtomicUsize>,
+    _writer: PhantomData<W>,
+}
+
+impl<S, W> ConsistencyChecker<S, W> {
+    fn check(&self, already_written: usize, when: &str) {
+        let actual = self.count.load(Ordering::SeqCst);
+        assert_eq!(already_written,
+                   actual,
+                   "serializer reports {} written bytes {}, but {} bytes reached the writer",
+                   already_written,
+                   when,
+                   actual);
+    }
+}
+
+impl<S, W> Future for ConsistencyChecker<S, W>
+    where S: AsyncWriterFuture<WriteCounter<W>>,
+          W: AsyncWrite
+{
+    type Item = (W, usize);
+    type Error = (W, FutIoErr);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        match self.inner.poll(cx) {
+            Ok(Async::Ready((writer, written))) => {
+                self.check(written, "on completion");
+                self.check(self.inner.already_written(), "after completion");
+                Ok(Async::Ready((writer.into_inner(), written)))
+            }
+            Ok(Async::Pending) => {
+                self.check(self.inner.already_written(), "while pending");
+                Ok(Async::Pending)
+            }
+            Err((writer, err)) => {
+                self.check(self.inner.already_written(), "after an error");
+                Err((writer.into_inner(), err))
+            }
+        }
+    }
+}
+
+impl<S, W> AsyncWriterFuture<W> for ConsistencyChecker<S, W>
+    where S: AsyncWriterFuture<WriteCounter<W>>,
+          W: AsyncWrite
+{
+    fn already_written(&self) -> usize {
+        self.inner.already_written()
+    }
+}
+
+impl<S, W> AsyncWriterFutureLen<W> for ConsistencyChecker<S, W>
+    where S: AsyncWriterFutureLen<WriteCounter<W>>,
+          W: AsyncWrite
+{
+    fn remaining_bytes(&self) -> usize {
+        self.inner.remaining_bytes()
+    }
+}
+
+impl<S, W> AsyncSerialize<W> for ConsistencyChecker<S, W>
+    where S: AsyncSerialize<WriteCounter<W>>,
+          W: AsyncWrite
+{
+    type Serialized = S::Serialized;
+
+    fn from_val(writer: W, val: S::Serialized) -> Self {
+        let writer = WriteCounter::new(writer);
+        let count = writer.count.clone();
+        ConsistencyChecker {
+            inner: S::from_val(writer, val),
+            count,
+            _writer: PhantomData,
+        }
+    }
+}
+
+impl<S, W> AsyncSerializeLen<W> for ConsistencyChecker<S, W>
+    where S: AsyncSerializeLen<WriteCounter<W>>,
+          W: AsyncWrite
+{
+    fn total_bytes(val: &S::Serialized) -> usize {
+        S::total_bytes(val)
+    }
+}
+
+/// Wraps an `AsyncWrite` and panics once `poll_write` has been called more than `limit` times in a
+/// row without the wrapped writer accepting a single byte.
+///
+/// A serializer that busy-loops on a writer that is not ready, or that returns `Pending` after a
+/// zero-length write, trips the watchdog right away instead of hanging the test.
+#[derive(Debug)]
+pub struct WatchdogWriter<W> {
+    inner: W,
+    limit: usize,
+    idle: usize,
+}
+
+impl<W> WatchdogWriter<W> {
+    /// Create a new `WatchdogWriter`, allowing `limit` consecutive writes without progress.
+    pub fn new(inner: W, limit: usize) -> WatchdogWriter<W> {
+        WatchdogWriter {
+            inner,
+            limit,
+            idle: 0,
+        }
+    }
+
+    /// Consume this `WatchdogWriter`, returning the wrapped writer.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: AsyncWrite> AsyncWrite for WatchdogWriter<W> {
+    fn poll_write(&mut self, cx: &mut Context, buf: &[u8]) -> Poll<usize, FutIoErr> {
+        let res = self.inner.poll_write(cx, buf);
+        watch(&mut self.idle, self.limit, "poll_write", &res);
+        res
+    }
+
+    fn poll_flush(&mut self, cx: &mut Context) -> Poll<(), FutIoErr> {
+        self.inner.poll_flush(cx)
+    }
+
+    fn poll_close(&mut self, cx: &mut Context) -> Poll<(), FutIoErr> {
+        self.inner.poll_close(cx)
+    }
+}
+
+/// Wraps an `AsyncRead` and panics once `poll_read` has been called more than `limit` times in a
+/// row without the wrapped reader delivering a single byte.
+///
+/// This is the reading counterpart of `WatchdogWriter`. Reaching the end of the reader counts as
+/// no progress, so a deserializer that keeps reading after the end trips it as well.
+#[derive(Debug)]
+pub struct WatchdogReader<R> {
+    inner: R,
+    limit: usize,
+    idle: usize,
+}
+
+impl<R> WatchdogReader<R> {
+    /// Create a new `WatchdogReader`, allowing `limit` consecutive reads without progress.
+    pub fn new(inner: R, limit: usize) -> WatchdogReader<R> {
+        WatchdogReader {
+            inner,
+            limit,
+            idle: 0,
+        }
+    }
+
+    /// Consume this `WatchdogReader`, returning the wrapped reader.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: AsyncRead> AsyncRead for WatchdogReader<R> {
+    fn poll_read(&mut self, cx: &mut Context, buf: &mut [u8]) -> Poll<usize, FutIoErr> {
+        let res = self.inner.poll_read(cx, buf);
+        watch(&mut self.idle, self.limit, "poll_read", &res);
+        res
+    }
+}
+
+fn watch(idle: &mut usize, limit: usize, name: &str, res: &Poll<usize, FutIoErr>) {
+    match *res {
+        Ok(Async::Ready(n)) if n > 0 => *idle = 0,
+        Err(_) => *idle = 0,
+        _ => {
+            *idle += 1;
+            assert!(*idle <= limit,
+                    "{} was called {} times in a row without making progress",
+                    name,
+                    *idle);
+        }
+    }
+}
+
+/// Wraps an `AsyncRead` of a record-oriented protocol and keeps track of the message boundaries in
+/// it, used by `BoundaryChecker`.
+///
+/// The start of the reader is a boundary, further boundaries are set by calling `mark` after each
+/// complete message. Starting a deserialization consumes the boundary, so every message must be
+/// followed by a call to `mark`.
+#[derive(Debug)]
+pub struct BoundaryReader<R> {
+    inner: R,
+    position: usize,
+    boundary: Option<usize>,
+}
+
+impl<R> BoundaryReader<R> {
+    /// Create a new `BoundaryReader` wrapping `inner`, with a boundary at the start.
+    pub fn new(inner: R) -> BoundaryReader<R> {
+        BoundaryReader {
+            inner,
+            position: 0,
+            boundary: Some(0),
+        }
+    }
+
+    /// Mark the current position as the boundary where the next message starts.
+    pub fn mark(&mut self) {
+        self.boundary = Some(self.position);
+    }
+
+    /// Return how many bytes have been read so far.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Return whether the current position is a marked boundary.
+    pub fn is_at_boundary(&self) -> bool {
+        self.boundary == Some(self.position)
+    }
+
+    /// Consume this `BoundaryReader`, returning the wrapped reader.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: AsyncRead> AsyncRead for BoundaryReader<R> {
+    fn poll_read(&mut self, cx: &mut Context, buf: &mut [u8]) -> Poll<usize, FutIoErr> {
+        let read = try_ready!(self.inner.poll_read(cx, buf));
+        self.position += read;
+        Ok(Async::Ready(read))
+    }
+}
+
+/// Wraps a deserializer `D` reading from a `BoundaryReader`, and panics if it does not stay within
+/// a single message.
+///
+/// Creating a `BoundaryChecker` panics unless the reader is at a marked boundary, which catches
+/// deserializations that start in the middle of a message, e.g. because the previous one read too
+/// much. On completion, it panics if `D` reports a different number of bytes than it actually read
+/// from the reader, which catches deserializers that read into the next message.
+pub struct BoundaryChecker<D, R> {
+    inner: D,
+    start: usize,
+    _reader: PhantomData<R>,
+}
+
+impl<D, R, T, E> Future for BoundaryChecker<D, R>
+    where D: Future<Item = (BoundaryReader<R>, T, usize),
+                    Error = (BoundaryReader<R>, DeserializeError<E>)>
+{
+    type Item = (BoundaryReader<R>, T, usize);
+    type Error = (BoundaryReader<R>, DeserializeError<E>);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        let (reader, val, read) = try_ready!(self.inner.poll(cx));
+        let actual = reader.position() - self.start;
+        assert_eq!(read,
+                   actual,
+                   "deserializer reports {} read bytes, but read {} bytes of the stream",
+                   read,
+                   actual);
+        Ok(Async::Ready((reader, val, read)))
+    }
+}
+
+impl<D, R, T, E> AsyncDeserialize<BoundaryReader<R>, T, E> for BoundaryChecker<D, R>
+    where D: AsyncDeserialize<BoundaryReader<R>, T, E>,
+          R: AsyncRead
+{
+    fn from_reader(mut reader: BoundaryReader<R>) -> Self {
+        assert!(reader.is_at_boundary(),
+                "deserialization starts at byte {}, which is not a message boundary (last \
+                 boundary: {:?})",
+                reader.position,
+                reader.boundary);
+        reader.boundary = None;
+
+        BoundaryChecker {
+            start: reader.position,
+            inner: D::from_reader(reader),
+            _reader: PhantomData,
+        }
+    }
+
+    fn already_read(&self) -> usize {
+        self.inner.already_read()
+    }
+}
+
+impl<D, R> Restorable<BoundaryReader<R>> for BoundaryChecker<D, R>
+    where D: Restorable<BoundaryReader<R>>
+{
+    fn restore(self) -> Option<BoundaryReader<R>> {
+        self.inner.restore()
+    }
+}
+
+/// Create an in-memory pipe that buffers at most `capacity` bytes, to connect a serializer with a
+/// deserializer, e.g. the two peers of a protocol.
+///
+/// Writing to a full pipe is pending until the reader has made room, and reading from an empty
+/// pipe is pending until the writer has written something, so a small capacity exposes peers that
+/// deadlock because both wait for the other one to read. Reading yields zero bytes once the writer
+/// has been closed or dropped and everything has been read. Writing fails with an
+/// `ErrorKind::BrokenPipe` error once the reader has been dropped.
+///
+/// Panics if `capacity` is zero.
+pub fn pipe(capacity: usize) -> (PipeWriter, PipeReader) {
+    assert!(capacity > 0, "pipe needs a positive capacity");
+    let state = Arc::new(Mutex::new(PipeState {
+                                        buf: VecDeque::with_capacity(capacity),
+                                        capacity,
+                                        closed: false,
+                                        reader_dropped: false,
+                                        read_waker: None,
+                                        write_waker: None,
+                                    }));
+    (PipeWriter(Arc::clone(&state)), PipeReader(state))
+}
+
+#[derive(Debug)]
+struct PipeState {
+    buf: VecDeque<u8>,
+    capacity: usize,
+    closed: bool,
+    reader_dropped: bool,
+    read_waker: Option<Waker>,
+    write_waker: Option<Waker>,
+}
+
+fn lock(state: &Mutex<PipeState>) -> MutexGuard<'_, PipeState> {
+    state.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+/// The writing end of a `pipe`.
+#[derive(Debug)]
+pub struct PipeWriter(Arc<Mutex<PipeState>>);
+
+impl PipeWriter {
+    /// Return how many written bytes have not been read yet.
+    pub fn buffered(&self) -> usize {
+        lock(&self.0).buf.len()
+    }
+}
+
+impl AsyncWrite for PipeWriter {
+    fn poll_write(&mut self, cx: &mut Context, buf: &[u8]) -> Poll<usize, FutIoErr> {
+        let mut state = lock(&self.0);
+        if state.reader_dropped {
+            return Err(FutIoErr::new(ErrorKind::BrokenPipe, "pipe reader dropped"));
+        }
+        if state.closed {
+            return Err(FutIoErr::new(ErrorKind::BrokenPipe, "pipe writer closed"));
+        }
+
+        let len = min(buf.len(), state.capacity - state.buf.len());
+        if len == 0 && !buf.is_empty() {
+            state.write_waker = Some(cx.waker().clone());
+            return Ok(Async::Pending);
+        }
+
+        state.buf.extend(&buf[..len]);
+        if let Some(waker) = state.read_waker.take() {
+            waker.wake();
+        }
+        Ok(Async::Ready(len))
+    }
+
+    fn poll_flush(&mut self, _: &mut Context) -> Poll<(), FutIoErr> {
+        Ok(Async::Ready(()))
+    }
+
+    fn poll_close(&mut self, _: &mut Context) -> Poll<(), FutIoErr> {
+        let mut state = lock(&self.0);
+        state.closed = true;
+        if let Some(waker) = state.read_waker.take() {
+            waker.wake();
+        }
+        Ok(Async::Ready(()))
+    }
+}
+
+impl Drop for PipeWriter {
+    fn drop(&mut self) {
+        let mut state = lock(&self.0);
+        state.closed = true;
+        if let Some(waker) = state.read_waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// The reading end of a `pipe`.
+#[derive(Debug)]
+pub struct PipeReader(Arc<Mutex<PipeState>>);
+
+impl PipeReader {
+    /// Return how many written bytes have not been read yet.
+    pub fn buffered(&self) -> usize {
+        lock(&self.0).buf.len()
+    }
+}
+
+impl AsyncRead for PipeReader {
+    fn poll_read(&mut self, cx: &mut Context, buf: &mut [u8]) -> Poll<usize, FutIoErr> {
+        let mut state = lock(&self.0);
+        if state.buf.is_empty() && !buf.is_empty() {
+            if state.closed {
+                return Ok(Async::Ready(0));
+            }
+            state.read_waker = Some(cx.waker().clone());
+            return Ok(Async::Pending);
+        }
+
+        let len = min(buf.len(), state.buf.len());
+        for (dst, src) in buf.iter_mut().zip(state.buf.drain(..len)) {
+            *dst = src;
+        }
+        if let Some(waker) = state.write_waker.take() {
+            waker.wake();
+        }
+        Ok(Async::Ready(len))
+    }
+}
+
+impl Drop for PipeReader {
+    fn drop(&mut self) {
+        let mut state = lock(&self.0);
+        state.reader_dropped = true;
+        if let Some(waker) = state.write_waker.take() {
+            waker.wake();
+        }
+    }
+}
@@ -0,0 +1,121 @@
+//! Limit how fast a reader or writer transfers bytes, with a pluggable time source.
+
+use std::cmp::min;
+use std::time::Duration;
+
+use futures_core::{Async, Future, Poll};
+use futures_core::task::Context;
+use futures_io::{AsyncRead, AsyncWrite, Error as FutIoErr};
+
+use timeout::Delay;
+
+/// Wraps an `AsyncRead` or `AsyncWrite` and transfers at most `rate` bytes per `period`.
+///
+/// A period starts with the first read or write after the previous one has ended, and reads and
+/// writes share the same budget. Once the budget of a period is used up, reads and writes are
+/// pending until the period has passed, as measured by the time source `C`. Budget that is not
+/// used within a period is not carried over to the next one.
+#[derive(Debug)]
+pub struct Throttled<T, C: Delay> {
+    inner: T,
+    clock: C,
+    rate: usize,
+    period: Duration,
+    budget: usize,
+    timer: Option<C::Delay>,
+}
+
+impl<T, C: Delay> Throttled<T, C> {
+    /// Create a new `Throttled`, transferring at most `rate` bytes per `period` as measured by
+    /// `clock`.
+    ///
+    /// Panics if `rate` is zero.
+    pub fn new(inner: T, clock: C, rate: usize, period: Duration) -> Throttled<T, C> {
+        assert!(rate > 0, "a throttle needs a positive rate");
+        Throttled {
+            inner,
+            clock,
+            rate,
+            period,
+            budget: 0,
+            timer: None,
+        }
+    }
+
+    /// Get a reference to the wrapped reader or writer.
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    /// Get a mutable reference to the wrapped reader or writer.
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    /// Consume this `Throttled`, returning the wrapped reader or writer.
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+
+    // Resolve to the remaining budget of the current period, starting a new period if the current
+    // one has passed.
+    fn poll_budget(&mut self, cx: &mut Context) -> Async<usize> {
+        let passed = match self.timer {
+            Some(ref mut timer) => {
+                match timer.poll(cx) {
+                    Ok(Async::Ready(())) => true,
+                    Ok(Async::Pending) => false,
+                    Err(never) => match never {},
+                }
+            }
+            None => true,
+        };
+
+        if passed {
+            self.timer = Some(self.clock.delay(self.period));
+            self.budget = self.rate;
+        }
+
+        if self.budget == 0 {
+            Async::Pending
+        } else {
+            Async::Ready(self.budget)
+        }
+    }
+}
+
+impl<R: AsyncRead, C: Delay> AsyncRead for Throttled<R, C> {
+    fn poll_read(&mut self, cx: &mut Context, buf: &mut [u8]) -> Poll<usize, FutIoErr> {
+        let budget = match self.poll_budget(cx) {
+            Async::Ready(budget) => budget,
+            Async::Pending => return Ok(Async::Pending),
+        };
+
+        let len = min(budget, buf.len());
+        let read = try_ready!(self.inner.poll_read(cx, &mut buf[..len]));
+        self.budget -= read;
+        Ok(Async::Ready(read))
+    }
+}
+
+impl<W: AsyncWrite, C: Delay> AsyncWrite for Throttled<W, C> {
+    fn poll_write(&mut self, cx: &mut Context, buf: &[u8]) -> Poll<usize, FutIoErr> {
+        let budget = match self.poll_budget(cx) {
+            Async::Ready(budget) => budget,
+            Async::Pending => return Ok(Async::Pending),
+        };
+
+        let len = min(budget, buf.len());
+        let written = try_ready!(self.inner.poll_write(cx, &buf[..len]));
+        self.budget -= written;
+        Ok(Async::Ready(written))
+    }
+
+    fn poll_flush(&mut self, cx: &mut Context) -> Poll<(), FutIoErr> {
+        self.inner.poll_flush(cx)
+    }
+
+    fn poll_close(&mut self, cx: &mut Context) -> Poll<(), FutIoErr> {
+        self.inner.poll_close(cx)
+    }
+}
@@ -0,0 +1,363 @@
+//! Time out reads and writes that make no progress, with a pluggable time source.
+//!
+//! This crate does not depend on any timer implementation. Instead, the timeouts are generic over
+//! a `Delay`, which creates futures that complete after a given duration. Adapters for the timer
+//! of an executor are usually a few lines, and `MockClock` is a time source for tests that only
+//! advances when told to.
+
+use std::fmt::{self, Debug, Formatter};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures_core::{Async, Future, Never, Poll};
+use futures_core::task::{Context, Waker};
+use futures_io::{AsyncRead, AsyncWrite, Error as FutIoErr, ErrorKind};
+
+/// A source of futures that complete after some duration.
+pub trait Delay {
+    /// The future returned by `delay`.
+    type Delay: Future<Item = (), Error = Never>;
+
+    /// Create a future that completes once `duration` has passed.
+    fn delay(&self, duration: Duration) -> Self::Delay;
+}
+
+/// An object-safe version of `Delay`, implemented for all `Delay`s whose futures are `Send`.
+///
+/// `Arc<dyn DynDelay + Send + Sync>` implements `Delay`, so a single shared time source can be
+/// used without naming its type.
+pub trait DynDelay {
+    /// Create a boxed future that completes once `duration` has passed.
+    fn delay_dyn(&self, duration: Duration) -> Box<dyn Future<Item = (), Error = Never> + Send>;
+}
+
+impl<D> DynDelay for D
+    where D: Delay,
+          D::Delay: Send + 'static
+{
+    fn delay_dyn(&self, duration: Duration) -> Box<dyn Future<Item = (), Error = Never> + Send> {
+        Box::new(self.delay(duration))
+    }
+}
+
+impl Delay for Arc<dyn DynDelay + Send + Sync> {
+    type Delay = Box<dyn Future<Item = (), Error = Never> + Send>;
+
+    fn delay(&self, duration: Duration) -> Self::Delay {
+        (**self).delay_dyn(duration)
+    }
+}
+
+/// A manually advanced time source, for deterministic tests.
+///
+/// Clones share the same time. Time starts at zero and only moves via `advance`, which wakes the
+/// tasks waiting for delays that have expired.
+#[derive(Clone, Default)]
+pub struct MockClock(Arc<Mutex<ClockState>>);
+
+#[derive(Default)]
+struct ClockState {
+    now: Duration,
+    waiting: Vec<Waker>,
+}
+
+impl MockClock {
+    /// Create a new `MockClock` at time zero.
+    pub fn new() -> MockClock {
+        MockClock::default()
+    }
+
+    /// Return how much time has passed since the clock was created.
+    pub fn now(&self) -> Duration {
+        self.0.lock().unwrap().now
+    }
+
+    /// Let `duration` pass, waking all tasks that wait for a delay of this clock.
+    pub fn advance(&self, duration: Duration) {
+        let waiting = {
+            let mut state = self.0.lock().unwrap();
+            state.now += duration;
+            ::std::mem::take(&mut state.waiting)
+        };
+
+        for waker in waiting {
+            waker.wake();
+        }
+    }
+}
+
+impl Debug for MockClock {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        f.debug_struct("MockClock").field("now", &self.now()).finish()
+    }
+}
+
+impl Delay for MockClock {
+    type Delay = MockDelay;
+
+    fn delay(&self, duration: Duration) -> MockDelay {
+        MockDelay {
+            clock: self.clone(),
+            deadline: self.now() + duration,
+        }
+    }
+}
+
+/// The future returned by `MockClock::delay`.
+#[derive(Debug)]
+pub struct MockDelay {
+    clock: MockClock,
+    deadline: Duration,
+}
+
+impl Future for MockDelay {
+    type Item = ();
+    type Error = Never;
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<(), Never> {
+        let mut state = self.clock.0.lock().unwrap();
+        if state.now >= self.deadline {
+            Ok(Async::Ready(()))
+        } else {
+            if !state.waiting.iter().any(|waiting| waiting.will_wake(cx.waker())) {
+                state.waiting.push(cx.waker().clone());
+            }
+            Ok(Async::Pending)
+        }
+    }
+}
+
+// Poll `res`, the result of an operation on the wrapped reader or writer, against the timer. The
+// timer runs while the operation is pending, and is reset by every operation that is ready.
+fn poll_timeout<C, T>(clock: &C,
+                      duration: Duration,
+                      timer: &mut Option<C::Delay>,
+                      cx: &mut Context,
+                      res: Poll<T, FutIoErr>)
+                      -> Poll<T, FutIoErr>
+    where C: Delay
+{
+    match res {
+        Ok(Async::Pending) => {}
+        res => {
+            *timer = None;
+            return res;
+        }
+    }
+
+    let expired = match timer.get_or_insert_with(|| clock.delay(duration)).poll(cx) {
+        Ok(Async::Ready(())) => true,
+        Ok(Async::Pending) => false,
+        Err(never) => match never {},
+    };
+
+    if expired {
+        *timer = None;
+        Err(FutIoErr::new(ErrorKind::TimedOut, "no progress within the timeout"))
+    } else {
+        Ok(Async::Pending)
+    }
+}
+
+/// Wraps an `AsyncRead` and fails a read with an `ErrorKind::TimedOut` error if the wrapped
+/// reader stays pending for longer than a timeout.
+///
+/// The timer only runs while a read is pending, so a deserializer over a `ReadTimeout` fails if
+/// the peer stalls for too long, no matter how long the whole value takes.
+#[derive(Debug)]
+pub struct ReadTimeout<R, C: Delay> {
+    inner: R,
+    clock: C,
+    duration: Duration,
+    timer: Option<C::Delay>,
+}
+
+impl<R, C: Delay> ReadTimeout<R, C> {
+    /// Create a new `ReadTimeout`, timing out after `duration` as measured by `clock`.
+    pub fn new(inner: R, clock: C, duration: Duration) -> ReadTimeout<R, C> {
+        ReadTimeout {
+            inner,
+            clock,
+            duration,
+            timer: None,
+        }
+    }
+
+    /// Get a reference to the wrapped reader.
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// Get a mutable reference to the wrapped reader.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    /// Consume this `ReadTimeout`, returning the wrapped reader.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: AsyncRead, C: Delay> AsyncRead for ReadTimeout<R, C> {
+    fn poll_read(&mut self, cx: &mut Context, buf: &mut [u8]) -> Poll<usize, FutIoErr> {
+        let res = self.inner.poll_read(cx, buf);
+        poll_timeout(&self.clock, self.duration, &mut self.timer, cx, res)
+    }
+}
+
+/// Wraps an `AsyncWrite` and fails a write or flush with an `ErrorKind::TimedOut` error if the
+/// wrapped writer stays pending for longer than a timeout.
+///
+/// The timer only runs while a write or flush is pending. Closing is forwarded without a timeout.
+#[derive(Debug)]
+pub struct WriteTimeout<W, C: Delay> {
+    inner: W,
+    clock: C,
+    duration: Duration,
+    timer: Option<C::Delay>,
+}
+
+impl<W, C: Delay> WriteTimeout<W, C> {
+    /// Create a new `WriteTimeout`, timing out after `duration` as measured by `clock`.
+    pub fn new(inner: W, clock: C, duration: Duration) -> WriteTimeout<W, C> {
+        WriteTimeout {
+            inner,
+            clock,
+            duration,
+            timer: None,
+        }
+    }
+
+    /// Get a reference to the wrapped writer.
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Get a mutable reference to the wrapped writer.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// Consume this `WriteTimeout`, returning the wrapped writer.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: AsyncWrite, C: Delay> AsyncWrite for WriteTimeout<W, C> {
+    fn poll_write(&mut self, cx: &mut Context, buf: &[u8]) -> Poll<usize, FutIoErr> {
+        let res = self.inner.poll_write(cx, buf);
+        poll_timeout(&self.clock, self.duration, &mut self.timer, cx, res)
+    }
+
+    fn poll_flush(&mut self, cx: &mut Context) -> Poll<(), FutIoErr> {
+        let res = self.inner.poll_flush(cx);
+        poll_timeout(&self.clock, self.duration, &mut self.timer, cx, res)
+    }
+
+    fn poll_close(&mut self, cx: &mut Context) -> Poll<(), FutIoErr> {
+        self.inner.poll_close(cx)
+    }
+}
+
+/// Wraps an `AsyncRead` and `AsyncWrite` and fails a read, write or flush with an
+/// `ErrorKind::TimedOut` error if it is pending while nothing has been read or written for longer
+/// than a timeout.
+///
+/// Unlike with a `ReadTimeout` or `WriteTimeout`, the timer runs from the last read or write that
+/// transferred any bytes, in either direction, also while no operation is pending. So this closes
+/// connections on which nothing happens, e.g. because the application stopped sending, while
+/// operations that are ready never fail. Closing is forwarded without a timeout.
+#[derive(Debug)]
+pub struct IdleTimeout<T, C: Delay> {
+    inner: T,
+    clock: C,
+    duration: Duration,
+    timer: C::Delay,
+}
+
+impl<T, C: Delay> IdleTimeout<T, C> {
+    /// Create a new `IdleTimeout`, timing out after `duration` as measured by `clock`. The timer
+    /// starts right away.
+    pub fn new(inner: T, clock: C, duration: Duration) -> IdleTimeout<T, C> {
+        IdleTimeout {
+            timer: clock.delay(duration),
+            inner,
+            clock,
+            duration,
+        }
+    }
+
+    /// Get a reference to the wrapped reader or writer.
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    /// Get a mutable reference to the wrapped reader or writer.
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    /// Consume this `IdleTimeout`, returning the wrapped reader or writer.
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+
+    // Restart the timer if `res` transferred any bytes, and poll the timer if it is pending.
+    fn poll_idle<V>(&mut self,
+                    cx: &mut Context,
+                    res: Poll<V, FutIoErr>,
+                    transferred: bool)
+                    -> Poll<V, FutIoErr> {
+        match res {
+            Ok(Async::Pending) => {}
+            res => {
+                if transferred {
+                    self.timer = self.clock.delay(self.duration);
+                }
+                return res;
+            }
+        }
+
+        match self.timer.poll(cx) {
+            Ok(Async::Ready(())) => {
+                Err(FutIoErr::new(ErrorKind::TimedOut, "idle for longer than the timeout"))
+            }
+            Ok(Async::Pending) => Ok(Async::Pending),
+            Err(never) => match never {},
+        }
+    }
+}
+
+fn transferred(res: &Poll<usize, FutIoErr>) -> bool {
+    match *res {
+        Ok(Async::Ready(len)) => len > 0,
+        _ => false,
+    }
+}
+
+impl<R: AsyncRead, C: Delay> AsyncRead for IdleTimeout<R, C> {
+    fn poll_read(&mut self, cx: &mut Context, buf: &mut [u8]) -> Poll<usize, FutIoErr> {
+        let res = self.inner.poll_read(cx, buf);
+        let transferred = transferred(&res);
+        self.poll_idle(cx, res, transferred)
+    }
+}
+
+impl<W: AsyncWrite, C: Delay> AsyncWrite for IdleTimeout<W, C> {
+    fn poll_write(&mut self, cx: &mut Context, buf: &[u8]) -> Poll<usize, FutIoErr> {
+        let res = self.inner.poll_write(cx, buf);
+        let transferred = transferred(&res);
+        self.poll_idle(cx, res, transferred)
+    }
+
+    fn poll_flush(&mut self, cx: &mut Context) -> Poll<(), FutIoErr> {
+        let res = self.inner.poll_flush(cx);
+        self.poll_idle(cx, res, false)
+    }
+
+    fn poll_close(&mut self, cx: &mut Context) -> Poll<(), FutIoErr> {
+        self.inner.poll_close(cx)
+    }
+}
@@ -0,0 +1,269 @@
+//! Sequences of tag-length-value triples, for extensible formats in which readers skip the
+//! fields they do not know.
+//!
+//! Each field is encoded as its tag as a [varint](../varint/index.html), the length of its value
+//! as a varint, and then the value.
+
+use std::cmp::min;
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+
+use futures_core::{Async, Future, Poll};
+use futures_core::task::Context;
+use futures_io::{AsyncRead, AsyncWrite, Error as FutIoErr, ErrorKind};
+
+use {AsyncSerialize, AsyncSerializeLen, AsyncWriterFuture, AsyncWriterFutureLen,
+     DeserializeError};
+use chain::Chain;
+use util::poll_skip;
+use varint::{varint_len, MAX_VARINT_LEN, WriteVarint};
+
+/// Reads the fields of a tag-length-value sequence one after the other.
+///
+/// `next_field` yields the tag and length of the next field, together with a `FieldReader` from
+/// which exactly the value of the field can be read. The rest of the field must be read or
+/// skipped before moving on to the next field, otherwise `next_field` fails with
+/// `TlvError::UnfinishedField`.
+#[derive(Debug)]
+pub struct TlvReader<R> {
+    reader: R,
+    remaining: u64,
+}
+
+impl<R> TlvReader<R> {
+    /// Create a new `TlvReader`, reading fields from `reader`.
+    pub fn new(reader: R) -> TlvReader<R> {
+        TlvReader {
+            reader,
+            remaining: 0,
+        }
+    }
+
+    /// Return how many bytes of the current field have not been read yet.
+    pub fn remaining(&self) -> u64 {
+        self.remaining
+    }
+
+    /// Read the header of the next field.
+    ///
+    /// Yields `None` if the reader ends before the next field.
+    pub fn next_field(&mut self) -> NextField<'_, R> {
+        NextField {
+            tlv: Some(self),
+            tag: None,
+            val: 0,
+            read: 0,
+        }
+    }
+
+    /// Discard the remaining bytes of the current field.
+    pub fn skip_rest(&mut self) -> SkipRest<'_, R> {
+        SkipRest(self)
+    }
+
+    /// Get a reference to the wrapped reader.
+    pub fn get_ref(&self) -> &R {
+        &self.reader
+    }
+
+    /// Get a mutable reference to the wrapped reader.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.reader
+    }
+
+    /// Consume this `TlvReader`, returning the wrapped reader.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+/// The future returned by `TlvReader::next_field`.
+pub struct NextField<'a, R: 'a> {
+    tlv: Option<&'a mut TlvReader<R>>,
+    tag: Option<u64>,
+    val: u64,
+    read: usize,
+}
+
+impl<'a, R: AsyncRead> Future for NextField<'a, R> {
+    type Item = Option<(u64, u64, FieldReader<'a, R>)>;
+    type Error = DeserializeError<TlvError>;
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        {
+            let tlv = or_pending!(self.tlv.as_mut());
+            if tlv.remaining > 0 {
+                let remaining = tlv.remaining;
+                return Err(DeserializeError::DataError(TlvError::UnfinishedField { remaining }));
+            }
+
+            loop {
+                let mut byte = [0u8; 1];
+                if try_ready!(tlv.reader.poll_read(cx, &mut byte)) == 0 {
+                    if self.tag.is_none() && self.read == 0 {
+                        break;
+                    }
+                    let err = FutIoErr::new(ErrorKind::UnexpectedEof,
+                                            "unexpected end of field header");
+                    return Err(DeserializeError::ReaderError(err));
+                }
+
+                let byte = byte[0];
+                if self.read == MAX_VARINT_LEN - 1 && byte > 1 {
+                    return Err(DeserializeError::DataError(TlvError::VarintOverflow));
+                }
+                self.val |= u64::from(byte & 0x7f) << (7 * self.read);
+                self.read += 1;
+
+                if byte & 0x80 == 0 {
+                    let val = self.val;
+                    self.val = 0;
+                    self.read = 0;
+
+                    match self.tag {
+                        None => self.tag = Some(val),
+                        Some(_) => {
+                            tlv.remaining = val;
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+
+        let tlv = self.tlv.take().unwrap();
+        match self.tag {
+            Some(tag) => Ok(Async::Ready(Some((tag, tlv.remaining, FieldReader(tlv))))),
+            None => Ok(Async::Ready(None)),
+        }
+    }
+}
+
+/// An `AsyncRead` over exactly the value of a field of a `TlvReader`.
+pub struct FieldReader<'a, R: 'a>(&'a mut TlvReader<R>);
+
+impl<'a, R> FieldReader<'a, R> {
+    /// Return how many bytes of the field have not been read yet.
+    pub fn remaining(&self) -> u64 {
+        self.0.remaining
+    }
+
+    /// Discard the remaining bytes of the field.
+    pub fn skip_rest(self) -> SkipRest<'a, R> {
+        SkipRest(self.0)
+    }
+}
+
+impl<'a, R: AsyncRead> AsyncRead for FieldReader<'a, R> {
+    fn poll_read(&mut self, cx: &mut Context, buf: &mut [u8]) -> Poll<usize, FutIoErr> {
+        if self.0.remaining == 0 {
+            return Ok(Async::Ready(0));
+        }
+
+        let max = min(self.0.remaining, buf.len() as u64) as usize;
+        let read = try_ready!(self.0.reader.poll_read(cx, &mut buf[..max]));
+        if read == 0 && max > 0 {
+            return Err(FutIoErr::new(ErrorKind::UnexpectedEof, "unexpected end of field"));
+        }
+
+        self.0.remaining -= read as u64;
+        Ok(Async::Ready(read))
+    }
+}
+
+/// The future returned by `skip_rest`, discarding the remaining bytes of the current field
+/// through a fixed-size scratch buffer.
+pub struct SkipRest<'a, R: 'a>(&'a mut TlvReader<R>);
+
+impl<'a, R: AsyncRead> Future for SkipRest<'a, R> {
+    type Item = ();
+    type Error = FutIoErr;
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<(), FutIoErr> {
+        let tlv = &mut *self.0;
+        poll_skip(&mut tlv.reader, cx, &mut tlv.remaining)
+    }
+}
+
+/// Serializes a field of a tag-length-value sequence, i.e. a pair of a tag and a value that is
+/// serialized via `S`.
+pub struct WriteTlv<S, W>(Chain<WriteVarint<W>, Chain<WriteVarint<W>, S, W>, W>)
+    where S: AsyncSerialize<W>,
+          W: AsyncWrite;
+
+impl<S, W> Future for WriteTlv<S, W>
+    where S: AsyncSerialize<W>,
+          W: AsyncWrite
+{
+    type Item = (W, usize);
+    type Error = (W, FutIoErr);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        self.0.poll(cx)
+    }
+}
+
+impl<S, W> AsyncWriterFuture<W> for WriteTlv<S, W>
+    where S: AsyncSerialize<W>,
+          W: AsyncWrite
+{
+    fn already_written(&self) -> usize {
+        self.0.already_written()
+    }
+}
+
+impl<S, W> AsyncWriterFutureLen<W> for WriteTlv<S, W>
+    where S: AsyncSerializeLen<W>,
+          W: AsyncWrite
+{
+    fn remaining_bytes(&self) -> usize {
+        self.0.remaining_bytes()
+    }
+}
+
+impl<S, W> AsyncSerialize<W> for WriteTlv<S, W>
+    where S: AsyncSerializeLen<W>,
+          W: AsyncWrite
+{
+    type Serialized = (u64, S::Serialized);
+
+    fn from_val(writer: W, (tag, val): Self::Serialized) -> Self {
+        let len = S::total_bytes(&val) as u64;
+        WriteTlv(Chain::from_val(writer, (tag, (len, val))))
+    }
+}
+
+impl<S, W> AsyncSerializeLen<W> for WriteTlv<S, W>
+    where S: AsyncSerializeLen<W>,
+          W: AsyncWrite
+{
+    fn total_bytes(&(tag, ref val): &Self::Serialized) -> usize {
+        let len = S::total_bytes(val);
+        varint_len(tag) + varint_len(len as u64) + len
+    }
+}
+
+/// Everything that can go wrong when reading the header of a field.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TlvError {
+    /// The tag or length does not fit into a `u64`.
+    VarintOverflow,
+    /// The next field was requested before the current one was read or skipped completely.
+    UnfinishedField {
+        /// How many bytes of the current field are left.
+        remaining: u64,
+    },
+}
+
+impl Display for TlvError {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            TlvError::VarintOverflow => write!(f, "Varint overflows a u64"),
+            TlvError::UnfinishedField { remaining } => {
+                write!(f, "Current field still has {} unread bytes", remaining)
+            }
+        }
+    }
+}
+
+impl Error for TlvError {}
@@ -0,0 +1,387 @@
+//! Helpers shared by the futures in this crate.
+
+use std::cmp::{max, min};
+
+use futures_core::{Async, Future, Poll};
+use futures_core::task::Context;
+use futures_io::{AsyncRead, AsyncWrite, Error as FutIoErr, ErrorKind};
+
+use ValidationError;
+
+/// Evaluate to the content of an `Option` holding something that a future hands out when it
+/// completes, e.g. its writer or reader, or return `Async::Pending` from the surrounding `poll` if
+/// the `Option` is empty because the future has already completed.
+///
+/// This way, polling a completed future does nothing rather than panicking.
+macro_rules! or_pending {
+    ($opt:expr) => {
+        match $opt {
+            Some(val) => val,
+            None => return Ok(::futures_core::Async::Pending),
+        }
+    };
+}
+
+/// Write `buf[*offset..]` into the writer, advancing `offset` by the number of written bytes.
+///
+/// Resolves once the whole buffer has been written.
+pub(crate) fn poll_write_buf<W: AsyncWrite>(writer: &mut W,
+                                            cx: &mut Context,
+                                            buf: &[u8],
+                                            offset: &mut usize)
+                                            -> Poll<(), FutIoErr> {
+    while *offset < buf.len() {
+        let written = try_ready!(writer.poll_write(cx, &buf[*offset..]));
+        if written == 0 {
+            return Err(FutIoErr::new(ErrorKind::WriteZero, "failed to write whole buffer"));
+        }
+        *offset += written;
+    }
+    Ok(Async::Ready(()))
+}
+
+/// Fill `buf[*offset..]` from the reader, advancing `offset` by the number of read bytes.
+///
+/// Resolves once the whole buffer has been filled, errors with `UnexpectedEof` if the reader
+/// ends before that.
+pub(crate) fn poll_read_buf<R: AsyncRead>(reader: &mut R,
+                                          cx: &mut Context,
+                                          buf: &mut [u8],
+                                          offset: &mut usize)
+                                          -> Poll<(), FutIoErr> {
+    while *offset < buf.len() {
+        let read = try_ready!(reader.poll_read(cx, &mut buf[*offset..]));
+        if read == 0 {
+            return Err(FutIoErr::new(ErrorKind::UnexpectedEof, "failed to fill whole buffer"));
+        }
+        *offset += read;
+    }
+    Ok(Async::Ready(()))
+}
+
+/// Read `len` bytes into `buf`, where `*filled` bytes have already been read.
+///
+/// The buffer grows as data arrives rather than being allocated up front, so a bogus length read
+/// from an untrusted source does not trigger a huge allocation by itself.
+pub(crate) fn poll_read_vec<R: AsyncRead>(reader: &mut R,
+                                          cx: &mut Context,
+                                          buf: &mut Vec<u8>,
+                                          filled: &mut usize,
+                                          len: usize)
+                                          -> Poll<(), FutIoErr> {
+    while *filled < len {
+        if *filled == buf.len() {
+            let new_len = min(len, max(64, buf.len() * 2));
+            buf.resize(new_len, 0);
+        }
+
+        let end = buf.len();
+        try_ready!(poll_read_buf(reader, cx, &mut buf[..end], filled));
+    }
+    Ok(Async::Ready(()))
+}
+
+/// Discard `*remaining` bytes from the reader, decrementing `remaining` as bytes are read.
+pub(crate) fn poll_skip<R: AsyncRead>(reader: &mut R,
+                                      cx: &mut Context,
+                                      remaining: &mut u64)
+                                      -> Poll<(), FutIoErr> {
+    let mut scratch = [0u8; 256];
+    while *remaining > 0 {
+        let len = min(*remaining, scratch.len() as u64) as usize;
+        let read = try_ready!(reader.poll_read(cx, &mut scratch[..len]));
+        if read == 0 {
+            return Err(FutIoErr::new(ErrorKind::UnexpectedEof, "failed to skip bytes"));
+        }
+        *remaining -= read as u64;
+    }
+    Ok(Async::Ready(()))
+}
+
+/// A future that writes a complete buffer into a writer.
+pub(crate) struct WriteAll<W, B> {
+    writer: Option<W>,
+    buf: B,
+    offset: usize,
+}
+
+impl<W, B: AsRef<[u8]>> WriteAll<W, B> {
+    pub(crate) fn new(writer: W, buf: B) -> WriteAll<W, B> {
+        WriteAll {
+            writer: Some(writer),
+            buf,
+            offset: 0,
+        }
+    }
+
+    pub(crate) fn already_written(&self) -> usize {
+        self.offset
+    }
+
+    pub(crate) fn remaining_bytes(&self) -> usize {
+        self.buf.as_ref().len() - self.offset
+    }
+}
+
+impl<W: AsyncWrite, B: AsRef<[u8]>> Future for WriteAll<W, B> {
+    type Item = (W, usize);
+    type Error = (W, FutIoErr);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        let mut writer = or_pending!(self.writer.take());
+
+        match poll_write_buf(&mut writer, cx, self.buf.as_ref(), &mut self.offset) {
+            Ok(Async::Ready(())) => Ok(Async::Ready((writer, self.offset))),
+            Ok(Async::Pending) => {
+                self.writer = Some(writer);
+                Ok(Async::Pending)
+            }
+            Err(err) => Err((writer, err)),
+        }
+    }
+}
+
+/// A future that fills a complete buffer from a reader.
+pub(crate) struct ReadExact<R, B> {
+    reader: Option<R>,
+    buf: Option<B>,
+    offset: usize,
+}
+
+impl<R, B: AsMut<[u8]>> ReadExact<R, B> {
+    pub(crate) fn new(reader: R, buf: B) -> ReadExact<R, B> {
+        ReadExact {
+            reader: Some(reader),
+            buf: Some(buf),
+            offset: 0,
+        }
+    }
+
+    pub(crate) fn already_read(&self) -> usize {
+        self.offset
+    }
+
+    pub(crate) fn restore(self) -> Option<R> {
+        self.reader
+    }
+}
+
+impl<R: AsyncRead, B: AsMut<[u8]>> Future for ReadExact<R, B> {
+    type Item = (R, B, usize);
+    type Error = (R, FutIoErr);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        let mut reader = or_pending!(self.reader.take());
+
+        let res = {
+            let buf = or_pending!(self.buf.as_mut());
+            poll_read_buf(&mut reader, cx, buf.as_mut(), &mut self.offset)
+        };
+
+        match res {
+            Ok(Async::Ready(())) => {
+                Ok(Async::Ready((reader, self.buf.take().unwrap(), self.offset)))
+            }
+            Ok(Async::Pending) => {
+                self.reader = Some(reader);
+                Ok(Async::Pending)
+            }
+            Err(err) => Err((reader, err)),
+        }
+    }
+}
+
+/// A future that writes a buffer preceded by its length as a big-endian `u32`.
+pub(crate) struct WriteLenPrefixed<W, B> {
+    writer: Option<W>,
+    prefix: [u8; 4],
+    prefix_written: usize,
+    body: B,
+    body_written: usize,
+}
+
+impl<W, B: AsRef<[u8]>> WriteLenPrefixed<W, B> {
+    /// Panics if the buffer is longer than `u32::MAX` bytes, check via `prefix_fits` first.
+    pub(crate) fn new(writer: W, body: B) -> WriteLenPrefixed<W, B> {
+        let len = body.as_ref().len();
+        assert!(prefix_fits(len));
+
+        WriteLenPrefixed {
+            writer: Some(writer),
+            prefix: (len as u32).to_be_bytes(),
+            prefix_written: 0,
+            body,
+            body_written: 0,
+        }
+    }
+
+    pub(crate) fn already_written(&self) -> usize {
+        self.prefix_written + self.body_written
+    }
+
+    pub(crate) fn remaining_bytes(&self) -> usize {
+        4 + self.body.as_ref().len() - self.already_written()
+    }
+}
+
+/// Returns whether a buffer of length `len` can be written by a `WriteLenPrefixed`.
+pub(crate) fn prefix_fits(len: usize) -> bool {
+    len as u64 <= u64::from(u32::MAX)
+}
+
+/// Check that a length fits into a big-endian `u32` prefix.
+pub(crate) fn validate_prefix(len: usize) -> Result<(), ValidationError> {
+    if prefix_fits(len) {
+        Ok(())
+    } else {
+        Err(ValidationError::TooLong {
+                len: len as u64,
+                max: u64::from(u32::MAX),
+            })
+    }
+}
+
+impl<W: AsyncWrite, B: AsRef<[u8]>> Future for WriteLenPrefixed<W, B> {
+    type Item = (W, usize);
+    type Error = (W, FutIoErr);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        let mut writer = or_pending!(self.writer.take());
+
+        let res = match poll_write_buf(&mut writer, cx, &self.prefix, &mut self.prefix_written) {
+            Ok(Async::Ready(())) => {
+                poll_write_buf(&mut writer, cx, self.body.as_ref(), &mut self.body_written)
+            }
+            other => other,
+        };
+
+        match res {
+            Ok(Async::Ready(())) => Ok(Async::Ready((writer, self.already_written()))),
+            Ok(Async::Pending) => {
+                self.writer = Some(writer);
+                Ok(Async::Pending)
+            }
+            Err(err) => Err((writer, err)),
+        }
+    }
+}
+
+/// A `WriteLenPrefixed` for buffers that may be invalid, failing with an
+/// `ErrorKind::InvalidInput` error without writing anything if there is no buffer.
+pub(crate) enum TryWriteLenPrefixed<W, B> {
+    Write(WriteLenPrefixed<W, B>),
+    Invalid(Option<W>, &'static str),
+}
+
+impl<W: AsyncWrite, B: AsRef<[u8]>> TryWriteLenPrefixed<W, B> {
+    /// Panics if the buffer is longer than `u32::MAX` bytes, check via `prefix_fits` first.
+    pub(crate) fn new(writer: W,
+                      body: Option<B>,
+                      invalid: &'static str)
+                      -> TryWriteLenPrefixed<W, B> {
+        match body {
+            Some(body) => TryWriteLenPrefixed::Write(WriteLenPrefixed::new(writer, body)),
+            None => TryWriteLenPrefixed::Invalid(Some(writer), invalid),
+        }
+    }
+
+    pub(crate) fn poll(&mut self, cx: &mut Context) -> Poll<(W, usize), (W, FutIoErr)> {
+        match *self {
+            TryWriteLenPrefixed::Write(ref mut inner) => inner.poll(cx),
+            TryWriteLenPrefixed::Invalid(ref mut writer, invalid) => {
+                let err = FutIoErr::new(ErrorKind::InvalidInput, invalid);
+                Err((or_pending!(writer.take()), err))
+            }
+        }
+    }
+
+    pub(crate) fn already_written(&self) -> usize {
+        match *self {
+            TryWriteLenPrefixed::Write(ref inner) => inner.already_written(),
+            TryWriteLenPrefixed::Invalid(..) => 0,
+        }
+    }
+
+    pub(crate) fn remaining_bytes(&self) -> usize {
+        match *self {
+            TryWriteLenPrefixed::Write(ref inner) => inner.remaining_bytes(),
+            TryWriteLenPrefixed::Invalid(..) => 0,
+        }
+    }
+}
+
+/// A future that reads a big-endian `u32` length and then that many bytes.
+pub(crate) struct ReadLenPrefixed<R> {
+    reader: Option<R>,
+    prefix: [u8; 4],
+    prefix_read: usize,
+    body: Vec<u8>,
+    body_read: usize,
+}
+
+impl<R> ReadLenPrefixed<R> {
+    pub(crate) fn new(reader: R) -> ReadLenPrefixed<R> {
+        ReadLenPrefixed {
+            reader: Some(reader),
+            prefix: [0; 4],
+            prefix_read: 0,
+            body: Vec::new(),
+            body_read: 0,
+        }
+    }
+
+    /// Create a `ReadLenPrefixed` whose prefix has already been read, e.g. together with a tag
+    /// before it.
+    pub(crate) fn with_prefix(reader: R, prefix: [u8; 4]) -> ReadLenPrefixed<R> {
+        ReadLenPrefixed {
+            prefix,
+            prefix_read: 4,
+            ..ReadLenPrefixed::new(reader)
+        }
+    }
+
+    pub(crate) fn already_read(&self) -> usize {
+        self.prefix_read + self.body_read
+    }
+
+    pub(crate) fn restore(self) -> Option<R> {
+        self.reader
+    }
+
+    pub(crate) fn payload_remaining(&self) -> Option<usize> {
+        if self.prefix_read < 4 {
+            None
+        } else {
+            Some(u32::from_be_bytes(self.prefix) as usize - self.body_read)
+        }
+    }
+}
+
+impl<R: AsyncRead> Future for ReadLenPrefixed<R> {
+    type Item = (R, Vec<u8>, usize);
+    type Error = (R, FutIoErr);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        let mut reader = or_pending!(self.reader.take());
+
+        let res = match poll_read_buf(&mut reader, cx, &mut self.prefix, &mut self.prefix_read) {
+            Ok(Async::Ready(())) => {
+                let len = u32::from_be_bytes(self.prefix) as usize;
+                poll_read_vec(&mut reader, cx, &mut self.body, &mut self.body_read, len)
+            }
+            other => other,
+        };
+
+        match res {
+            Ok(Async::Ready(())) => {
+                let read = self.already_read();
+                Ok(Async::Ready((reader, ::std::mem::take(&mut self.body), read)))
+            }
+            Ok(Async::Pending) => {
+                self.reader = Some(reader);
+                Ok(Async::Pending)
+            }
+            Err(err) => Err((reader, err)),
+        }
+    }
+}
@@ -0,0 +1,376 @@
+//! Check deserialized values against a predicate, or values against a predicate before
+//! serializing them.
+
+use futures_core::{Async, Future, Poll};
+use futures_core::task::Context;
+use futures_io::{AsyncRead, AsyncWrite, Error as FutIoErr, ErrorKind};
+
+use {AsyncDeserialize, AsyncSerialize, AsyncSerializeLen, AsyncWriterFuture,
+     AsyncWriterFutureLen, DeserializeError, Restorable};
+
+pub use either::Either;
+
+/// Wraps a deserializer and checks the value it produces with a validator function. If the
+/// validator rejects the value, this fails with a `DataError` holding the given validation error
+/// as `Either::Right`. Data errors of the inner deserializer are passed on as `Either::Left`.
+///
+/// ```rust,ignore
+/// Validated::new(DeserPath::from_reader(reader), |p| p.is_relative(), MyError::AbsolutePath)
+/// ```
+pub struct Validated<D, F, V> {
+    inner: D,
+    validator: Option<F>,
+    error: Option<V>,
+}
+
+impl<D, F, V> Validated<D, F, V> {
+    /// Create a new `Validated`, checking the value produced by `inner` with `validator` and
+    /// failing with `error` if the validator returns `false`.
+    pub fn new(inner: D, validator: F, error: V) -> Validated<D, F, V> {
+        Validated {
+            inner,
+            validator: Some(validator),
+            error: Some(error),
+        }
+    }
+
+    /// Get a reference to the wrapped deserializer.
+    pub fn get_ref(&self) -> &D {
+        &self.inner
+    }
+}
+
+impl<D, F, V, R, S, E> Future for Validated<D, F, V>
+    where D: Future<Item = (R, S, usize), Error = (R, DeserializeError<E>)>,
+          F: FnOnce(&S) -> bool
+{
+    type Item = (R, S, usize);
+    type Error = (R, DeserializeError<Either<E, V>>);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        match self.inner.poll(cx) {
+            Ok(Async::Ready((reader, val, read))) => {
+                let validator = or_pending!(self.validator.take());
+
+                if validator(&val) {
+                    Ok(Async::Ready((reader, val, read)))
+                } else {
+                    let err = or_pending!(self.error.take());
+                    Err((reader, DeserializeError::DataError(Either::Right(err))))
+                }
+            }
+            Ok(Async::Pending) => Ok(Async::Pending),
+            Err((reader, DeserializeError::ReaderError(err))) => {
+                Err((reader, DeserializeError::ReaderError(err)))
+            }
+            Err((reader, DeserializeError::DataError(err))) => {
+                Err((reader, DeserializeError::DataError(Either::Left(err))))
+            }
+        }
+    }
+}
+
+impl<D, F, V, R> Restorable<R> for Validated<D, F, V>
+    where D: Restorable<R>
+{
+    fn restore(self) -> Option<R> {
+        self.inner.restore()
+    }
+}
+
+/// Wraps a deserializer and converts the value it produces with a fallible conversion function,
+/// e.g. to check that an integer lies within some range. If the conversion fails, this fails with
+/// a `DataError` holding the conversion error as `Either::Right`, data errors of the inner
+/// deserializer are passed on as `Either::Left`.
+///
+/// After a failed conversion, the `already_read` of the inner deserializer still reports the
+/// number of bytes it consumed, and no further bytes are read.
+pub struct Converted<D, F> {
+    inner: D,
+    convert: Option<F>,
+}
+
+impl<D, F> Converted<D, F> {
+    /// Create a new `Converted`, converting the value produced by `inner` with `convert`.
+    pub fn new(inner: D, convert: F) -> Converted<D, F> {
+        Converted {
+            inner,
+            convert: Some(convert),
+        }
+    }
+
+    /// Get a reference to the wrapped deserializer.
+    pub fn get_ref(&self) -> &D {
+        &self.inner
+    }
+}
+
+impl<D, F, R, S, T, E, V> Future for Converted<D, F>
+    where D: Future<Item = (R, S, usize), Error = (R, DeserializeError<E>)>,
+          F: FnOnce(S) -> Result<T, V>
+{
+    type Item = (R, T, usize);
+    type Error = (R, DeserializeError<Either<E, V>>);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        match self.inner.poll(cx) {
+            Ok(Async::Ready((reader, val, read))) => {
+                let convert = or_pending!(self.convert.take());
+
+                match convert(val) {
+                    Ok(val) => Ok(Async::Ready((reader, val, read))),
+                    Err(err) => {
+                        Err((reader, DeserializeError::DataError(Either::Right(err))))
+                    }
+                }
+            }
+            Ok(Async::Pending) => Ok(Async::Pending),
+            Err((reader, DeserializeError::ReaderError(err))) => {
+                Err((reader, DeserializeError::ReaderError(err)))
+            }
+            Err((reader, DeserializeError::DataError(err))) => {
+                Err((reader, DeserializeError::DataError(Either::Left(err))))
+            }
+        }
+    }
+}
+
+impl<D, F, R> Restorable<R> for Converted<D, F>
+    where D: Restorable<R>
+{
+    fn restore(self) -> Option<R> {
+        self.inner.restore()
+    }
+}
+
+/// Wraps a deserializer and transforms the reader it returns with a function, e.g. to wrap it in
+/// a decrypting layer after reading a header that announces encrypted data.
+///
+/// The function is applied to the reader both when the inner deserializer completes and when it
+/// fails, so the returned reader has the same type in both cases.
+pub struct MappedReader<D, F> {
+    inner: D,
+    map: Option<F>,
+}
+
+impl<D, F> MappedReader<D, F> {
+    /// Create a new `MappedReader`, transforming the reader returned by `inner` with `map`.
+    pub fn new(inner: D, map: F) -> MappedReader<D, F> {
+        MappedReader {
+            inner,
+            map: Some(map),
+        }
+    }
+
+    /// Get a reference to the wrapped deserializer.
+    pub fn get_ref(&self) -> &D {
+        &self.inner
+    }
+}
+
+impl<D, F, R, R2, S, E> Future for MappedReader<D, F>
+    where D: Future<Item = (R, S, usize), Error = (R, DeserializeError<E>)>,
+          F: FnOnce(R) -> R2
+{
+    type Item = (R2, S, usize);
+    type Error = (R2, DeserializeError<E>);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        match self.inner.poll(cx) {
+            Ok(Async::Ready((reader, val, read))) => {
+                let map = or_pending!(self.map.take());
+                Ok(Async::Ready((map(reader), val, read)))
+            }
+            Ok(Async::Pending) => Ok(Async::Pending),
+            Err((reader, err)) => {
+                let map = or_pending!(self.map.take());
+                Err((map(reader), err))
+            }
+        }
+    }
+}
+
+/// Restores the mapped reader, or nothing if the map has already been used.
+impl<D, F, R, R2, S, E> Restorable<R2> for MappedReader<D, F>
+    where D: Future<Item = (R, S, usize), Error = (R, DeserializeError<E>)> + Restorable<R>,
+          F: FnOnce(R) -> R2
+{
+    fn restore(self) -> Option<R2> {
+        let map = self.map?;
+        self.inner.restore().map(map)
+    }
+}
+
+/// Wraps a deserializer and handles the reader errors it fails with via a function, e.g. to turn
+/// an unexpected end of the input into a value that signals the end of a sequence of records.
+///
+/// The function either produces a value, reported together with the number of bytes the inner
+/// deserializer read before failing, or a `DataError`. Data errors of the inner deserializer are
+/// passed on as `Either::Left`, those of the function as `Either::Right`.
+pub struct MapReaderErr<D, F> {
+    inner: D,
+    map: Option<F>,
+}
+
+impl<D, F> MapReaderErr<D, F> {
+    /// Create a new `MapReaderErr`, handling the reader errors of `inner` with `map`.
+    pub fn new(inner: D, map: F) -> MapReaderErr<D, F> {
+        MapReaderErr {
+            inner,
+            map: Some(map),
+        }
+    }
+
+    /// Get a reference to the wrapped deserializer.
+    pub fn get_ref(&self) -> &D {
+        &self.inner
+    }
+}
+
+impl<D, F, R, S, E, V> Future for MapReaderErr<D, F>
+    where D: AsyncDeserialize<R, S, E>,
+          D: Future<Item = (R, S, usize), Error = (R, DeserializeError<E>)>,
+          F: FnOnce(FutIoErr) -> Result<S, V>,
+          R: AsyncRead
+{
+    type Item = (R, S, usize);
+    type Error = (R, DeserializeError<Either<E, V>>);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        match self.inner.poll(cx) {
+            Ok(Async::Ready(done)) => Ok(Async::Ready(done)),
+            Ok(Async::Pending) => Ok(Async::Pending),
+            Err((reader, DeserializeError::ReaderError(err))) => {
+                let map = or_pending!(self.map.take());
+                match map(err) {
+                    Ok(val) => Ok(Async::Ready((reader, val, self.inner.already_read()))),
+                    Err(err) => {
+                        Err((reader, DeserializeError::DataError(Either::Right(err))))
+                    }
+                }
+            }
+            Err((reader, DeserializeError::DataError(err))) => {
+                Err((reader, DeserializeError::DataError(Either::Left(err))))
+            }
+        }
+    }
+}
+
+impl<D, F, R> Restorable<R> for MapReaderErr<D, F>
+    where D: Restorable<R>
+{
+    fn restore(self) -> Option<R> {
+        self.inner.restore()
+    }
+}
+
+/// Adds validating and mapping combinators to all deserializers.
+pub trait AsyncDeserializeExt<R, S, E>
+    : Future<Item = (R, S, usize), Error = (R, DeserializeError<E>)> + Sized {
+    /// Check the deserialized value with `validator`, failing with `error` if it returns `false`.
+    fn validate<F, V>(self, validator: F, error: V) -> Validated<Self, F, V>
+        where F: FnOnce(&S) -> bool
+    {
+        Validated::new(self, validator, error)
+    }
+
+    /// Convert the deserialized value with `convert`, failing if the conversion fails.
+    fn convert<F, T, V>(self, convert: F) -> Converted<Self, F>
+        where F: FnOnce(S) -> Result<T, V>
+    {
+        Converted::new(self, convert)
+    }
+
+    /// Transform the reader returned after deserializing with `map`.
+    fn map_reader<F, R2>(self, map: F) -> MappedReader<Self, F>
+        where F: FnOnce(R) -> R2
+    {
+        MappedReader::new(self, map)
+    }
+
+    /// Handle the reader errors of this deserializer with `map`, which produces either a value or
+    /// a data error.
+    fn map_reader_error<F, V>(self, map: F) -> MapReaderErr<Self, F>
+        where F: FnOnce(FutIoErr) -> Result<S, V>
+    {
+        MapReaderErr::new(self, map)
+    }
+}
+
+impl<D, R, S, E> AsyncDeserializeExt<R, S, E> for D
+    where D: Future<Item = (R, S, usize), Error = (R, DeserializeError<E>)>
+{
+}
+
+/// Serializes a value via `S` only if it passes a validator function. Otherwise, this fails with
+/// an `ErrorKind::InvalidInput` error without writing anything.
+pub struct ValidatedSerialize<S, W> {
+    state: SerState<S, W>,
+}
+
+enum SerState<S, W> {
+    Valid(S),
+    Invalid(Option<W>),
+}
+
+impl<S, W> ValidatedSerialize<S, W>
+    where S: AsyncSerialize<W>,
+          W: AsyncWrite
+{
+    /// Create a new `ValidatedSerialize`, serializing `val` into `writer` if `validator` returns
+    /// `true` for it.
+    pub fn new<F>(writer: W, val: S::Serialized, validator: F) -> ValidatedSerialize<S, W>
+        where F: FnOnce(&S::Serialized) -> bool
+    {
+        ValidatedSerialize {
+            state: if validator(&val) {
+                SerState::Valid(S::from_val(writer, val))
+            } else {
+                SerState::Invalid(Some(writer))
+            },
+        }
+    }
+}
+
+impl<S, W> Future for ValidatedSerialize<S, W>
+    where S: AsyncSerialize<W>,
+          W: AsyncWrite
+{
+    type Item = (W, usize);
+    type Error = (W, FutIoErr);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        match self.state {
+            SerState::Valid(ref mut inner) => inner.poll(cx),
+            SerState::Invalid(ref mut writer) => {
+                let err = FutIoErr::new(ErrorKind::InvalidInput, "value failed validation");
+                let writer = or_pending!(writer.take());
+                Err((writer, err))
+            }
+        }
+    }
+}
+
+impl<S, W> AsyncWriterFuture<W> for ValidatedSerialize<S, W>
+    where S: AsyncSerialize<W>,
+          W: AsyncWrite
+{
+    fn already_written(&self) -> usize {
+        match self.state {
+            SerState::Valid(ref inner) => inner.already_written(),
+            SerState::Invalid(_) => 0,
+        }
+    }
+}
+
+impl<S, W> AsyncWriterFutureLen<W> for ValidatedSerialize<S, W>
+    where S: AsyncSerializeLen<W>,
+          W: AsyncWrite
+{
+    fn remaining_bytes(&self) -> usize {
+        match self.state {
+            SerState::Valid(ref inner) => inner.remaining_bytes(),
+            SerState::Invalid(_) => 0,
+        }
+    }
+}
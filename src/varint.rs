@@ -0,0 +1,187 @@
+//! Variable-length encoding of unsigned 64 bit integers (LEB128).
+//!
+//! A value is split into groups of seven bits, least significant group first. Each group is
+//! written as one byte, with the most significant bit set on every byte but the last one. So
+//! `1` encodes as `[0x01]`, `150` as `[0x96, 0x01]` and `u64::MAX` takes ten bytes.
+
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+
+use futures_core::{Async, Future, Poll};
+use futures_core::task::Context;
+use futures_io::{AsyncRead, AsyncWrite, Error as FutIoErr, ErrorKind};
+
+use {AsyncDeserialize, AsyncSerialize, AsyncSerializeLen, AsyncWriterFuture,
+     AsyncWriterFutureLen, DeserializeError, Restorable};
+use util::WriteAll;
+
+/// The maximum number of bytes of an encoded varint.
+pub const MAX_VARINT_LEN: usize = 10;
+
+/// Return how many bytes the varint encoding of `val` takes up.
+pub fn varint_len(val: u64) -> usize {
+    let mut len = 1;
+    let mut val = val >> 7;
+    while val != 0 {
+        len += 1;
+        val >>= 7;
+    }
+    len
+}
+
+/// The encoding of a single varint.
+#[derive(Clone, Copy)]
+pub(crate) struct VarintBuf {
+    bytes: [u8; MAX_VARINT_LEN],
+    len: usize,
+}
+
+impl VarintBuf {
+    pub(crate) fn new(mut val: u64) -> VarintBuf {
+        let mut bytes = [0; MAX_VARINT_LEN];
+        let mut len = 0;
+
+        loop {
+            let byte = (val & 0x7f) as u8;
+            val >>= 7;
+            if val == 0 {
+                bytes[len] = byte;
+                len += 1;
+                break;
+            } else {
+                bytes[len] = byte | 0x80;
+                len += 1;
+            }
+        }
+
+        VarintBuf { bytes, len }
+    }
+}
+
+impl AsRef<[u8]> for VarintBuf {
+    fn as_ref(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
+}
+
+/// Serializes a `u64` as a varint.
+pub struct WriteVarint<W>(WriteAll<W, VarintBuf>);
+
+impl<W: AsyncWrite> Future for WriteVarint<W> {
+    type Item = (W, usize);
+    type Error = (W, FutIoErr);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        self.0.poll(cx)
+    }
+}
+
+impl<W: AsyncWrite> AsyncWriterFuture<W> for WriteVarint<W> {
+    fn already_written(&self) -> usize {
+        self.0.already_written()
+    }
+}
+
+impl<W: AsyncWrite> AsyncWriterFutureLen<W> for WriteVarint<W> {
+    fn remaining_bytes(&self) -> usize {
+        self.0.remaining_bytes()
+    }
+}
+
+impl<W: AsyncWrite> AsyncSerialize<W> for WriteVarint<W> {
+    type Serialized = u64;
+
+    fn from_val(writer: W, val: u64) -> Self {
+        WriteVarint(WriteAll::new(writer, VarintBuf::new(val)))
+    }
+}
+
+impl<W: AsyncWrite> AsyncSerializeLen<W> for WriteVarint<W> {
+    fn total_bytes(val: &u64) -> usize {
+        varint_len(*val)
+    }
+}
+
+/// Deserializes a varint into a `u64`.
+///
+/// Encodings with superfluous trailing zero groups (e.g. `[0x80, 0x00]` for zero) are accepted.
+pub struct ReadVarint<R> {
+    reader: Option<R>,
+    val: u64,
+    read: usize,
+}
+
+impl<R: AsyncRead> Future for ReadVarint<R> {
+    type Item = (R, u64, usize);
+    type Error = (R, DeserializeError<VarintError>);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        let mut reader = or_pending!(self.reader.take());
+
+        loop {
+            let mut byte = [0u8; 1];
+            match reader.poll_read(cx, &mut byte) {
+                Ok(Async::Ready(0)) => {
+                    let err = FutIoErr::new(ErrorKind::UnexpectedEof, "unexpected end of varint");
+                    return Err((reader, DeserializeError::ReaderError(err)));
+                }
+                Ok(Async::Ready(_)) => {
+                    let byte = byte[0];
+                    if self.read == MAX_VARINT_LEN - 1 && byte > 1 {
+                        self.read += 1;
+                        return Err((reader, DeserializeError::DataError(VarintError::Overflow)));
+                    }
+
+                    self.val |= u64::from(byte & 0x7f) << (7 * self.read);
+                    self.read += 1;
+
+                    if byte & 0x80 == 0 {
+                        return Ok(Async::Ready((reader, self.val, self.read)));
+                    }
+                }
+                Ok(Async::Pending) => {
+                    self.reader = Some(reader);
+                    return Ok(Async::Pending);
+                }
+                Err(err) => return Err((reader, DeserializeError::ReaderError(err))),
+            }
+        }
+    }
+}
+
+impl<R: AsyncRead> AsyncDeserialize<R, u64, VarintError> for ReadVarint<R> {
+    fn from_reader(reader: R) -> Self {
+        ReadVarint {
+            reader: Some(reader),
+            val: 0,
+            read: 0,
+        }
+    }
+
+    fn already_read(&self) -> usize {
+        self.read
+    }
+}
+
+impl<R> Restorable<R> for ReadVarint<R> {
+    fn restore(self) -> Option<R> {
+        self.reader
+    }
+}
+
+/// Everything that can go wrong when decoding a varint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VarintError {
+    /// The encoded value does not fit into a `u64`.
+    Overflow,
+}
+
+impl Display for VarintError {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            VarintError::Overflow => write!(f, "Varint overflows a u64"),
+        }
+    }
+}
+
+impl Error for VarintError {}
@@ -0,0 +1,362 @@
+//! Golden-vector tests pinning the exact bytes of every serializer of the crate.
+//!
+//! The expected bytes live in the `vectors` directory, see its README for the stability policy.
+#![allow(deprecated)]
+
+extern crate async_serialization;
+
+use std::borrow::Cow;
+use std::collections::{BinaryHeap, LinkedList};
+use std::ffi::OsString;
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::num::Saturating;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use async_serialization::{AsyncSerialize, AsyncSerializeRef};
+use async_serialization::arc_bytes::SerArcBytes;
+#[cfg(feature = "base64")]
+use async_serialization::base64::{Alphabet, Base64Config, Padding, SerBase64};
+use async_serialization::binary_heap::SerBinaryHeap;
+use async_serialization::bitset::WriteBitset;
+use async_serialization::chain::Chain;
+use async_serialization::cow::{SerCowBytes, SerCowStr, WriteCowBytes, WriteCowStr};
+use async_serialization::eager_header::EagerHeader;
+use async_serialization::envelope::{CrcWriter, WriteEnvelope};
+use async_serialization::fixed_point::{FixedPoint, WriteFixedPoint};
+use async_serialization::framed::LengthWidth;
+#[cfg(feature = "tokio-compat")]
+use async_serialization::length_delimited::{ByteOrder, LengthDelimitedCodec};
+use async_serialization::front_coded::WriteFrontCoded;
+use async_serialization::hex_str::SerHexStr;
+use async_serialization::ip_addr::SerIpAddr;
+use async_serialization::linked_list::SerLinkedList;
+use async_serialization::log_record::WriteLoggedRecord;
+use async_serialization::message::{Message, NoParts};
+use async_serialization::option::SerOptionRef;
+use async_serialization::os_string::SerOsString;
+use async_serialization::parity::{ParityWriter, WriteWithParity};
+use async_serialization::path::{SerPath, SerPathBuf};
+use async_serialization::protobuf_wire::{encode_zigzag, Key, WireType, WriteBytes, WriteFixed32,
+                                         WriteFixed64, WriteKey, WriteString, MAX_FIELD_NUMBER};
+use async_serialization::range::{SerRange, SerRangeInclusive};
+use async_serialization::redundant::{Redundancy, RedundantWriter, WriteRedundant};
+use async_serialization::run_length::SerRLE;
+use async_serialization::saturating::SerSaturating;
+use async_serialization::sparse::SerSparse;
+use async_serialization::tagged::{TagWidth, WriteTagged};
+use async_serialization::terminated::WriteTerminated;
+use async_serialization::testing::{assert_golden, assert_golden_ref, assert_golden_with,
+                                   ChunkedWriter};
+use async_serialization::tlv::WriteTlv;
+use async_serialization::varint::WriteVarint;
+
+type CW = ChunkedWriter;
+
+// The vectors of one file of the `vectors` directory, remembering which of them were checked.
+struct Vectors {
+    file: String,
+    vectors: Vec<(String, String, bool)>,
+}
+
+impl Vectors {
+    // Load `vectors/<name>.txt`, which consists of lines of the form `description = hex`, blank
+    // lines and comment lines starting with `#`.
+    fn load(name: &str) -> Vectors {
+        let file = format!("{}/vectors/{}.txt", env!("CARGO_MANIFEST_DIR"), name);
+        let contents = fs::read_to_string(&file)
+            .unwrap_or_else(|err| panic!("could not read {}: {}", file, err));
+
+        let vectors = contents.lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| {
+                let mut parts = line.splitn(2, " = ");
+                let description = parts.next().unwrap().to_string();
+                let hex = parts
+                    .next()
+                    .unwrap_or_else(|| panic!("malformed line in {}: {:?}", file, line))
+                    .to_string();
+                (description, hex, false)
+            })
+            .collect();
+
+        Vectors { file, vectors }
+    }
+
+    fn hex(&mut self, description: &str) -> String {
+        let file = &self.file;
+        let vector = self.vectors
+            .iter_mut()
+            .find(|vector| vector.0 == description)
+            .unwrap_or_else(|| panic!("no vector {:?} in {}", description, file));
+        vector.2 = true;
+        vector.1.clone()
+    }
+
+    fn check<S>(&mut self, description: &str, val: S::Serialized)
+        where S: AsyncSerialize<CW>,
+              S::Serialized: Clone
+    {
+        assert_golden::<S>(val, &self.hex(description));
+    }
+
+    fn check_ref<'val, S>(&mut self, description: &str, val: &'val S::Serialized)
+        where S: AsyncSerializeRef<'val, CW>
+    {
+        assert_golden_ref::<S>(val, &self.hex(description));
+    }
+
+    // Assert that every vector of the file has been checked, so that none goes stale.
+    fn finish(self) {
+        for (description, _, checked) in self.vectors {
+            assert!(checked, "vector {:?} in {} is not checked", description, self.file);
+        }
+    }
+}
+
+#[test]
+fn varint() {
+    let mut v = Vectors::load("varint");
+    v.check::<WriteVarint<CW>>("0", 0);
+    v.check::<WriteVarint<CW>>("1", 1);
+    v.check::<WriteVarint<CW>>("127", 127);
+    v.check::<WriteVarint<CW>>("128", 128);
+    v.check::<WriteVarint<CW>>("300", 300);
+    v.check::<WriteVarint<CW>>("u32::MAX", u64::from(u32::MAX));
+    v.check::<WriteVarint<CW>>("u64::MAX", u64::MAX);
+    v.finish();
+}
+
+#[test]
+fn protobuf_wire() {
+    let mut v = Vectors::load("protobuf_wire");
+    v.check::<WriteVarint<CW>>("zigzag 0", encode_zigzag(0));
+    v.check::<WriteVarint<CW>>("zigzag -1", encode_zigzag(-1));
+    v.check::<WriteVarint<CW>>("zigzag i64::MAX", encode_zigzag(i64::MAX));
+    v.check::<WriteVarint<CW>>("zigzag i64::MIN", encode_zigzag(i64::MIN));
+    v.check::<WriteFixed32<CW>>("fixed32 0", 0);
+    v.check::<WriteFixed32<CW>>("fixed32 0x01020304", 0x0102_0304);
+    v.check::<WriteFixed32<CW>>("fixed32 f32 1.0", 1.0f32.to_bits());
+    v.check::<WriteFixed64<CW>>("fixed64 u64::MAX", u64::MAX);
+    v.check::<WriteFixed64<CW>>("fixed64 f64 -0.0", (-0.0f64).to_bits());
+    v.check::<WriteFixed64<CW>>("fixed64 f64 infinity", f64::INFINITY.to_bits());
+    v.check::<WriteKey<CW>>("key 1 varint", Key::new(1, WireType::Varint));
+    v.check::<WriteKey<CW>>("key 2 length delimited", Key::new(2, WireType::LengthDelimited));
+    v.check::<WriteKey<CW>>("key max fixed32", Key::new(MAX_FIELD_NUMBER, WireType::Fixed32));
+    v.check::<WriteBytes<CW>>("bytes empty", vec![]);
+    v.check::<WriteBytes<CW>>("bytes 00 01 ff", vec![0, 1, 255]);
+    v.check::<WriteString<CW>>("string empty", String::new());
+    v.check::<WriteString<CW>>("string grüße", "grüße".to_string());
+    v.finish();
+}
+
+#[test]
+fn fixed_point() {
+    let mut v = Vectors::load("fixed_point");
+    v.check::<WriteFixedPoint<CW>>("0 scale 0", FixedPoint::new(0, 0));
+    v.check::<WriteFixedPoint<CW>>("-1 scale 0", FixedPoint::new(-1, 0));
+    v.check::<WriteFixedPoint<CW>>("123.45", FixedPoint::new(12345, 2));
+    v.check::<WriteFixedPoint<CW>>("i64::MIN scale 18", FixedPoint::new(i64::MIN, 18));
+    v.finish();
+}
+
+#[test]
+fn range() {
+    let mut v = Vectors::load("range");
+    v.check::<SerRange<CW>>("range 0..0", 0..0);
+    v.check::<SerRange<CW>>("range 1..0x0102", 1..0x0102);
+    v.check::<SerRangeInclusive<CW>>("range inclusive 1..=u64::MAX", 1..=u64::MAX);
+    v.finish();
+}
+
+#[test]
+fn bytes_and_strings() {
+    let mut v = Vectors::load("bytes_and_strings");
+    v.check::<SerArcBytes<CW>>("arc empty", Arc::from(&[][..]));
+    v.check::<SerArcBytes<CW>>("arc 01 02 03", Arc::from(&[1, 2, 3][..]));
+    v.check::<WriteCowBytes<CW>>("cow bytes 61 62", Cow::Borrowed(&b"ab"[..]));
+    v.check::<WriteCowStr<CW>>("cow str empty", Cow::Owned(String::new()));
+    v.check::<WriteCowStr<CW>>("cow str ab", Cow::Borrowed("ab"));
+    v.check_ref::<SerCowBytes<CW>>("cow bytes 61 62", &Cow::Borrowed(&b"ab"[..]));
+    v.check_ref::<SerCowStr<CW>>("cow str ab", &Cow::Borrowed("ab"));
+    v.check::<SerPathBuf<CW>>("path empty", PathBuf::new());
+    v.check::<SerPathBuf<CW>>("path a/b.txt", PathBuf::from("a/b.txt"));
+    v.check_ref::<SerPath<CW>>("path a/b.txt", Path::new("a/b.txt"));
+    v.check::<WriteTerminated<CW>>("terminated empty", vec![]);
+    v.check::<WriteTerminated<CW>>("terminated 01 02", vec![1, 2]);
+    v.check::<SerHexStr<CW>>("hex 00 ab ff", vec![0x00, 0xab, 0xff]);
+    v.finish();
+}
+
+#[cfg(feature = "base64")]
+#[test]
+fn base64() {
+    let mut v = Vectors::load("base64");
+    v.check_ref::<SerBase64<CW>>("base64 standard 66", b"f");
+    v.check_ref::<SerBase64<CW>>("base64 standard 66 6f", b"fo");
+    v.check_ref::<SerBase64<CW>>("base64 standard 66 6f 6f", b"foo");
+    v.check_ref::<SerBase64<CW>>("base64 standard 66 6f 6f 62 61 72", b"foobar");
+    v.check_ref::<SerBase64<CW>>("base64 standard fb ff", &[0xfb, 0xff]);
+
+    let hex = v.hex("base64 url safe fb ff");
+    assert_golden_with(|writer| SerBase64::new(writer, Base64Config::URL_SAFE, &[0xfb, 0xff]),
+                       &hex);
+    let hex = v.hex("base64 url safe padded 66");
+    let config = Base64Config::new(Alphabet::UrlSafe, Padding::Padded);
+    assert_golden_with(|writer| SerBase64::new(writer, config, b"f"), &hex);
+    v.finish();
+}
+
+#[test]
+fn ip_addr() {
+    let mut v = Vectors::load("ip_addr");
+    v.check::<SerIpAddr<CW>>("ip addr 127.0.0.1", IpAddr::V4(Ipv4Addr::LOCALHOST));
+    v.check::<SerIpAddr<CW>>("ip addr 2001:db8::1",
+                             IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1)));
+    v.finish();
+}
+
+#[cfg(unix)]
+#[test]
+fn os_string() {
+    use std::os::unix::ffi::OsStringExt;
+
+    let mut v = Vectors::load("os_string_unix");
+    v.check::<SerOsString<CW>>("os string empty", OsString::new());
+    v.check::<SerOsString<CW>>("os string ab", OsString::from("ab"));
+    v.check::<SerOsString<CW>>("os string non-utf8 ff", OsString::from_vec(vec![0xff]));
+    v.finish();
+}
+
+#[cfg(windows)]
+#[test]
+fn os_string() {
+    use std::os::windows::ffi::OsStringExt;
+
+    let mut v = Vectors::load("os_string_windows");
+    v.check::<SerOsString<CW>>("os string empty", OsString::new());
+    v.check::<SerOsString<CW>>("os string ab", OsString::from("ab"));
+    v.check::<SerOsString<CW>>("os string unpaired surrogate d800",
+                               OsString::from_wide(&[0xd800]));
+    v.finish();
+}
+
+#[cfg(feature = "tokio-compat")]
+#[test]
+fn length_delimited() {
+    let mut v = Vectors::load("length_delimited");
+    let codecs = [("default", LengthDelimitedCodec::new()),
+                  ("4 byte little endian",
+                   LengthDelimitedCodec::new().byte_order(ByteOrder::LittleEndian)),
+                  ("2 byte big endian", LengthDelimitedCodec::new().length_field_length(2)),
+                  ("2 byte little endian",
+                   LengthDelimitedCodec::new()
+                       .length_field_length(2)
+                       .byte_order(ByteOrder::LittleEndian)),
+                  ("1 byte", LengthDelimitedCodec::new().length_field_length(1))];
+    for &(name, codec) in &codecs {
+        let hex = v.hex(&format!("length delimited {} fixed32 0x01020304", name));
+        assert_golden_with(|writer| codec.write_frame::<WriteFixed32<CW>, _>(writer, 0x0102_0304),
+                           &hex);
+    }
+    v.finish();
+}
+
+#[test]
+fn collections() {
+    let mut v = Vectors::load("collections");
+    v.check_ref::<WriteBitset<CW>>("bitset empty", &[]);
+    v.check_ref::<WriteBitset<CW>>("bitset true", &[true]);
+    v.check_ref::<WriteBitset<CW>>("bitset 1001100001",
+                                   &[true, false, false, true, true, false, false, false, false,
+                                     true]);
+
+    let empty = LinkedList::new();
+    v.check_ref::<SerLinkedList<SerCowStr<CW>, CW>>("linked list empty", &empty);
+    let list: LinkedList<Cow<str>> = vec![Cow::Borrowed("a"), Cow::Borrowed("")]
+        .into_iter()
+        .collect();
+    v.check_ref::<SerLinkedList<SerCowStr<CW>, CW>>("linked list a empty", &list);
+
+    v.check_ref::<SerRLE<WriteVarint<CW>, CW>>("rle empty", &[]);
+    v.check_ref::<SerRLE<WriteVarint<CW>, CW>>("rle 5x3 300x1", &[(5, 3), (300, 1)]);
+
+    v.check::<SerBinaryHeap<WriteVarint<CW>, CW>>("binary heap empty", BinaryHeap::new());
+    v.check::<SerBinaryHeap<WriteVarint<CW>, CW>>("binary heap 300 1 5",
+                                                   vec![300, 1, 5].into_iter().collect());
+
+    v.check_ref::<WriteFrontCoded<CW>>("front coded empty", &[]);
+    v.check_ref::<WriteFrontCoded<CW>>("front coded ab abc b", &["ab", "abc", "b"]);
+    v.check_ref::<WriteFrontCoded<CW>>("front coded é è", &["é", "è"]);
+
+    let hex = v.hex("sparse empty");
+    assert_golden_with(|writer| SerSparse::<WriteVarint<CW>, CW>::new(writer, 0, &[]), &hex);
+    let hex = v.hex("sparse len 5 1:7 4:300");
+    assert_golden_with(|writer| {
+                           SerSparse::<WriteVarint<CW>, CW>::new(writer, 5, &[(1, 7), (4, 300)])
+                       },
+                       &hex);
+    v.finish();
+}
+
+#[test]
+fn combinators() {
+    let mut v = Vectors::load("combinators");
+    v.check::<Chain<WriteVarint<CW>, WriteFixed32<CW>, CW>>("chain varint 1 fixed32 2", (1, 2));
+    v.check::<EagerHeader<CW, WriteVarint<CW>, WriteString<CW>>>("eager header 1 x",
+                                                                  (1, "x".to_string()));
+    v.check::<Message<WriteString<CW>, CW>>("message hi", "hi".to_string());
+    v.check::<Message<NoParts<CW>, CW>>("message empty", ());
+    v.check::<WriteTlv<WriteString<CW>, CW>>("tlv 7 hi", (7, "hi".to_string()));
+
+    let cases = [("tagged u8 1 varint 5", TagWidth::U8, 1),
+                 ("tagged u16 258 varint 5", TagWidth::U16, 258),
+                 ("tagged varint 300 varint 5", TagWidth::Varint, 300)];
+    for &(description, width, tag) in &cases {
+        let hex = v.hex(description);
+        assert_golden_with(|writer| WriteTagged::<WriteVarint<CW>, CW>::new(writer, width, tag, 5),
+                           &hex);
+    }
+
+    let cases = [("message u8 hi", LengthWidth::U8),
+                 ("message u16 hi", LengthWidth::U16),
+                 ("message u32 hi", LengthWidth::U32),
+                 ("message u64 hi", LengthWidth::U64),
+                 ("message varint hi", LengthWidth::Varint)];
+    for &(description, width) in &cases {
+        let hex = v.hex(description);
+        assert_golden_with(|writer| {
+                               Message::<WriteString<CW>, CW>::with_width(writer,
+                                                                          width,
+                                                                          "hi".to_string())
+                           },
+                           &hex);
+    }
+
+    type Envelope = WriteEnvelope<WriteString<CrcWriter<CW>>, CW, 0x4153_4552, 1>;
+    v.check::<Envelope>("envelope ASER 1 hi", "hi".to_string());
+
+    v.check::<SerSaturating<WriteVarint<CW>, CW>>("saturating varint 300", Saturating(300));
+
+    type Parity = WriteWithParity<WriteFixed32<ParityWriter<CW>>, CW>;
+    for &block_size in &[2, 3, 4] {
+        let hex = v.hex(&format!("parity block {} fixed32 0x01020304", block_size));
+        assert_golden_with(|writer| Parity::new(writer, block_size, 0x0102_0304), &hex);
+    }
+
+    type Redundant<W> = WriteRedundant<WriteVarint<RedundantWriter<W>>, W>;
+    v.check::<Redundant<CW>>("redundant copy varint 300", 300);
+    let hex = v.hex("redundant complement varint 300");
+    assert_golden_with(|writer| Redundant::new(writer, Redundancy::Complement, 300), &hex);
+
+    type LoggedRecord = WriteLoggedRecord<WriteVarint<CrcWriter<CW>>, CW>;
+    v.check::<LoggedRecord>("log record varint 300", 300);
+    v.check::<LoggedRecord>("log record varint 0", 0);
+
+    type OptionRef<'val> = SerOptionRef<'val, SerCowBytes<'val, CW>, CW>;
+    v.check_ref::<OptionRef>("option none cow bytes", &None);
+    v.check_ref::<OptionRef>("option some cow bytes 61 62", &Some(Cow::Borrowed(&b"ab"[..])));
+    v.finish();
+}
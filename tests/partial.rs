@@ -0,0 +1,102 @@
+//! Tests for recovering partially deserialized values via `from_reader_partial`.
+#![allow(deprecated)]
+
+extern crate async_serialization;
+
+use std::collections::LinkedList;
+
+use async_serialization::DeserializeError;
+use async_serialization::linked_list::DeserLinkedList;
+use async_serialization::pair::{DeserPair, PairError};
+use async_serialization::partial::PartialResult;
+use async_serialization::protobuf_wire::{Key, ProtobufError, ReadKey, WireType};
+use async_serialization::testing::{block_on, ChunkedReader};
+
+type CR = ChunkedReader;
+type KeyList = DeserLinkedList<ReadKey<CR>, CR, Key, ProtobufError>;
+type KeyPair = DeserPair<ReadKey<CR>, ReadKey<CR>, CR, Key, Key, ProtobufError, ProtobufError>;
+
+// A key of field number 0 with wire type 7, which is malformed in two ways and one byte long.
+const CORRUPT: u8 = 0x07;
+
+// The one-byte keys of fields 1 to `n` with the `Varint` wire type.
+fn keys(n: u32) -> LinkedList<Key> {
+    (1..n + 1).map(|field| Key::new(field, WireType::Varint)).collect()
+}
+
+#[test]
+fn linked_list_complete() {
+    let data = vec![0, 0, 0, 3, 0x08, 0x10, 0x18];
+    let (_, result, read) = block_on(KeyList::from_reader_partial(ChunkedReader::new(data, 1)))
+        .ok()
+        .unwrap();
+    assert_eq!(result, PartialResult::Complete(keys(3)));
+    assert_eq!(read, 7);
+}
+
+// Corrupting element k of a list recovers exactly the elements before it.
+#[test]
+fn linked_list_recovers_prefix() {
+    let encoding = vec![0, 0, 0, 5, 0x08, 0x10, 0x18, 0x20, 0x28];
+
+    for k in 4..encoding.len() {
+        let mut data = encoding.clone();
+        data[k] = CORRUPT;
+        let (_, result, read) =
+            block_on(KeyList::from_reader_partial(ChunkedReader::new(data, 1))).ok().unwrap();
+
+        assert_eq!(result,
+                   PartialResult::Partial {
+                       parsed: keys(k as u32 - 4),
+                       error: ProtobufError::InvalidWireType(7),
+                       offset: k,
+                   },
+                   "corrupted byte {}",
+                   k);
+        assert_eq!(read, k + 1, "corrupted byte {}", k);
+    }
+}
+
+#[test]
+fn linked_list_reader_errors_are_not_recovered() {
+    let data = vec![0, 0, 0, 2, 0x08];
+    match block_on(KeyList::from_reader_partial(ChunkedReader::new(data, 1))) {
+        Err((_, DeserializeError::ReaderError(_))) => {}
+        _ => panic!("expected a reader error"),
+    }
+}
+
+#[test]
+fn pair_recovers_key() {
+    let key = Key::new(1, WireType::Varint);
+
+    let data = vec![0x08, 0x10];
+    let (_, result, _) = block_on(KeyPair::from_reader_partial(ChunkedReader::new(data, 1)))
+        .ok()
+        .unwrap();
+    assert_eq!(result, PartialResult::Complete((key, Key::new(2, WireType::Varint))));
+
+    let data = vec![CORRUPT, 0x10];
+    let (_, result, read) = block_on(KeyPair::from_reader_partial(ChunkedReader::new(data, 1)))
+        .ok()
+        .unwrap();
+    assert_eq!(result,
+               PartialResult::Partial {
+                   parsed: None,
+                   error: PairError::Key(ProtobufError::InvalidWireType(7)),
+                   offset: 0,
+               });
+    assert_eq!(read, 1);
+
+    let data = vec![0x08, CORRUPT];
+    let (_, result, read) = block_on(KeyPair::from_reader_partial(ChunkedReader::new(data, 1)))
+        .ok()
+        .unwrap();
+    assert_eq!(result,
+               PartialResult::Partial {
+                   parsed: Some(key),
+                   error: PairError::Value(ProtobufError::InvalidWireType(7)),
+                   offset: 1,
+               });
+    assert_eq!(read, 2);
+}
@@ -0,0 +1,3599 @@
+//! Round-trip and error-injection tests for the primitive (de)serializers of the crate.
+#![allow(deprecated)]
+
+#[macro_use]
+extern crate async_serialization;
+extern crate futures_core;
+extern crate futures_io;
+
+use std::borrow::Cow;
+use std::cmp::Ordering as CmpOrdering;
+use std::collections::{BinaryHeap, LinkedList};
+use std::ffi::OsString;
+use std::fmt::Debug;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::Duration;
+
+use futures_core::{Async, Future, Poll, Stream};
+use futures_core::task::{Context, LocalMap, Wake, Waker};
+use futures_io::{AsyncRead, AsyncWrite, Error as FutIoErr, ErrorKind};
+
+use async_serialization::{AsyncDeserialize, AsyncSerialize, AsyncSerializeLen, AsyncSerializeRef,
+                          AsyncSerializeRefLen, AsyncWriterFuture, AsyncWriterFutureLen,
+                          DeserializeError, Restorable, ValidationError};
+use async_serialization::arc_bytes::{ArcBytesError, DeserArcBytes, SerArcBytes};
+use async_serialization::ascii_char::{AsciiCharError, DeserAsciiChar};
+#[cfg(feature = "base64")]
+use async_serialization::base64::{Alphabet, Base64Config, Base64End, Base64Error, DeserBase64,
+                                  Padding, SerBase64};
+use async_serialization::binary_heap::{DeserBinaryHeap, SerBinaryHeap};
+use async_serialization::bitset::{BitsetError, ReadBitset, WriteBitset};
+use async_serialization::buffered::Buffered;
+use async_serialization::cancellable::{Cancellable, CancellationToken, Cancelled};
+use async_serialization::canonical::{Canonical, CanonicalError, Comparison, Recording};
+use async_serialization::capture::{capture_on_error, Capture, CaptureOnError, Captured};
+use async_serialization::chain::{Chain, SerializeBatch};
+use async_serialization::counted::{SerializeCounted, Spool, Strategy};
+use async_serialization::cow::{SerCowBytes, SerCowStr, WriteCowBytes, WriteCowStr};
+use async_serialization::eager_header::{EagerHeader, HeaderError};
+use async_serialization::envelope::{crc32, CrcReader, CrcWriter, EnvelopeError, ReadEnvelope,
+                                    WriteEnvelope};
+use async_serialization::fixed_point::{FixedPoint, FixedPointError, ReadFixedPoint,
+                                       WriteFixedPoint, MAX_SCALE};
+use async_serialization::fold::{FoldError, ReadFold};
+use async_serialization::framed::{Framed, FramedError, LengthWidth, ReadFramed, Trailing};
+use async_serialization::front_coded::{FrontCodedError, ReadFrontCoded, WriteFrontCoded};
+#[cfg(feature = "fuzz_support")]
+use async_serialization::fuzz_support::{drive_from_bytes, FuzzReader};
+use async_serialization::graceful_close::{GracefulClose, GracefulCloseError};
+use async_serialization::grow_buf::{GrowBuf, LimitExceeded};
+use async_serialization::handshake::{Handshake, HandshakeError};
+use async_serialization::heartbeat::WithHeartbeat;
+#[cfg(feature = "tokio-compat")]
+use async_serialization::length_delimited::{ByteOrder, DelimitedError, LengthDelimitedCodec};
+use async_serialization::hex_str::{DeserHexStr, HexError, SerHexStr};
+use async_serialization::in_memory::{from_slice, to_string, to_vec, StringWriter};
+use async_serialization::ip_addr::{DeserIpAddr, IpAddrError, SerIpAddr, V4, V6};
+use async_serialization::lenient_seq::ReadLenientSeq;
+use async_serialization::linked_list::{DeserLinkedList, SerLinkedList};
+use async_serialization::log_record::{LogRecordError, ReadLoggedRecord, WriteLoggedRecord};
+use async_serialization::merge::{MergeError, MergeReader};
+use async_serialization::message::{Message, NoParts};
+use async_serialization::min_write_size::{Adaptive, MinWriteSize};
+use async_serialization::named::Named;
+use async_serialization::offset_reader::OffsetReader;
+use async_serialization::option::{DeserOption, OptionError, SerOptionRef, NONE, SOME};
+use async_serialization::os_string::{DeserOsString, OsStringError, SerOsString, UNIX, WINDOWS};
+use async_serialization::pair::{DeserPair, PairError};
+use async_serialization::parity::{ParityError, ParityReader, ParityWriter, ReadWithParity,
+                                  WriteWithParity};
+use async_serialization::partial::PartialResult;
+use async_serialization::path::{DeserPath, PathError, SerPathBuf};
+use async_serialization::pipeline::PipelineSerializer;
+use async_serialization::poll_budget::WithPollBudget;
+use async_serialization::progress::{PayloadRemaining, WithReadProgress};
+use async_serialization::protobuf_wire::{decode_zigzag, encode_zigzag, Field, Key,
+                                         ProtobufError, ReadBytes, ReadField, ReadFixed32,
+                                         ReadFixed64, ReadKey, ReadString, SkipValue, Value,
+                                         WireType, WriteBytes, WriteFixed32, WriteFixed64,
+                                         WriteKey, WriteString};
+use async_serialization::quota::QuotaWriter;
+use async_serialization::range::{DeserRange, DeserRangeInclusive, RangeError, SerRange,
+                                 SerRangeInclusive, RANGE_BYTES};
+use async_serialization::redundant::{ReadRedundant, Redundancy, RedundantError, RedundantReader,
+                                     RedundantWriter, WriteRedundant};
+use async_serialization::reserve_and_fill::ReserveAndFill;
+use async_serialization::ring_writer::RingWriter;
+use async_serialization::run_length::{DeserRLE, RLEError, SerRLE};
+use async_serialization::schema_id::{ReadWithSchemaId, SchemaError, SchemaId,
+                                     WriteWithSchemaId};
+use async_serialization::session::Session;
+use async_serialization::short_circuit::ShortCircuitWriter;
+use async_serialization::sized::{Bounded, ReadSized, SizedError, SizedReader};
+use async_serialization::sparse::{DeserSparse, SerSparse, SparseError};
+use async_serialization::stateful::StatefulEncoder;
+use async_serialization::streaming_utf8::{StreamingUtf8Deserializer, StreamingUtf8Error};
+use async_serialization::tagged::{ReadTag, ReadUnknown, TagError, TagWidth, Unknown, WriteTagged};
+use async_serialization::tagged_enum::{EnumError, NoFields};
+use async_serialization::take_reader::TakeReader;
+#[cfg(feature = "telemetry")]
+use async_serialization::telemetry::{TelemetryStore, TelemetryWriter};
+use async_serialization::terminated::{ReadTerminated, WriteTerminated};
+use async_serialization::testing::{assert_roundtrip, block_on, check_empty_behavior_with,
+                                   check_empty_ref_value_behavior, check_empty_value_behavior,
+                                   pipe, write_exactly, BoundaryChecker, BoundaryReader,
+                                   ChunkedReader, ChunkedWriter, ConsistencyChecker,
+                                   CountingReader, CountingWriter, PipeReader, PipeWriter,
+                                   VecWriter, WatchdogReader, WatchdogWriter, WriteCounter};
+use async_serialization::throttle::Throttled;
+use async_serialization::timeout::{IdleTimeout, MockClock, ReadTimeout, WriteTimeout};
+use async_serialization::tlv::{TlvError, TlvReader, WriteTlv};
+use async_serialization::validated::{AsyncDeserializeExt, Either, ValidatedSerialize};
+use async_serialization::varint::{ReadVarint, VarintError, WriteVarint};
+
+type VW = VecWriter;
+type CW = ChunkedWriter;
+type CR = ChunkedReader;
+type QW = QuotaWriter<VecWriter>;
+
+// Serialize `val` via `S` into an always-ready writer, asserting that `total_bytes` is correct.
+fn write<S>(val: S::Serialized) -> Vec<u8>
+    where S: AsyncSerializeLen<VW>
+{
+    let total = S::total_bytes(&val);
+    let (writer, written) = match block_on(S::from_val(VecWriter::new(), val)) {
+        Ok(done) => done,
+        Err((_, err)) => panic!("serialization failed: {}", err),
+    };
+    assert_eq!(total, written, "total_bytes differs from the number of written bytes");
+    writer.into_inner()
+}
+
+// Serialize `val` via `S` into a writer that accepts a single byte per write and is not ready
+// before every byte, asserting that the result is `expected`.
+fn assert_chunked_write<S>(val: S::Serialized, expected: &[u8])
+    where S: AsyncSerialize<CW>
+{
+    let (writer, written) = match block_on(S::from_val(ChunkedWriter::new(1), val)) {
+        Ok(done) => done,
+        Err((_, err)) => panic!("serialization failed: {}", err),
+    };
+    assert_eq!(written, expected.len(), "serializer reported a wrong number of written bytes");
+    assert_eq!(writer.bytes(), expected, "partial writes changed the encoding");
+}
+
+// Serialize `val` via `S` into a writer that fails once `quota` bytes have been written.
+fn write_err<S>(val: S::Serialized, quota: u64) -> FutIoErr
+    where S: AsyncSerialize<QW>
+{
+    match block_on(S::from_val(QuotaWriter::new(VecWriter::new(), quota), val)) {
+        Ok(_) => panic!("serialization unexpectedly succeeded"),
+        Err((_, err)) => err,
+    }
+}
+
+// Deserialize `bytes` via `D`, one byte per read, expecting an error.
+fn read_err<D, T, E>(bytes: Vec<u8>) -> DeserializeError<E>
+    where D: AsyncDeserialize<CR, T, E>,
+          T: Debug
+{
+    match block_on(D::from_reader(ChunkedReader::new(bytes, 1))) {
+        Ok((_, val, _)) => panic!("deserialization unexpectedly succeeded with {:?}", val),
+        Err((_, err)) => err,
+    }
+}
+
+fn data_err<E: Debug>(err: DeserializeError<E>) -> E {
+    match err {
+        DeserializeError::DataError(err) => err,
+        DeserializeError::ReaderError(err) => panic!("unexpected reader error {}", err),
+    }
+}
+
+fn is_eof<E>(err: &DeserializeError<E>) -> bool {
+    match *err {
+        DeserializeError::ReaderError(ref err) => err.kind() == ErrorKind::UnexpectedEof,
+        DeserializeError::DataError(_) => false,
+    }
+}
+
+// Flushes a writer and then yields it.
+struct Flush<W>(Option<W>);
+
+impl<W: AsyncWrite> Future for Flush<W> {
+    type Item = W;
+    type Error = (W, FutIoErr);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<W, (W, FutIoErr)> {
+        let mut writer = self.0.take().unwrap();
+        match writer.poll_flush(cx) {
+            Ok(Async::Ready(())) => Ok(Async::Ready(writer)),
+            Ok(Async::Pending) => {
+                self.0 = Some(writer);
+                Ok(Async::Pending)
+            }
+            Err(err) => Err((writer, err)),
+        }
+    }
+}
+
+fn flush<W: AsyncWrite>(writer: W) -> Result<W, (W, FutIoErr)> {
+    block_on(Flush(Some(writer)))
+}
+
+// An always-ready writer that accepts at most `accept` bytes per write, and records the size of
+// every write.
+#[derive(Debug)]
+struct SizesWriter {
+    data: Vec<u8>,
+    sizes: Vec<usize>,
+    accept: usize,
+}
+
+impl SizesWriter {
+    fn new(accept: usize) -> SizesWriter {
+        SizesWriter {
+            data: Vec::new(),
+            sizes: Vec::new(),
+            accept,
+        }
+    }
+}
+
+impl AsyncWrite for SizesWriter {
+    fn poll_write(&mut self, _: &mut Context, buf: &[u8]) -> Poll<usize, FutIoErr> {
+        let len = buf.len().min(self.accept);
+        self.data.extend_from_slice(&buf[..len]);
+        self.sizes.push(len);
+        Ok(Async::Ready(len))
+    }
+
+    fn poll_flush(&mut self, _: &mut Context) -> Poll<(), FutIoErr> {
+        Ok(Async::Ready(()))
+    }
+
+    fn poll_close(&mut self, _: &mut Context) -> Poll<(), FutIoErr> {
+        Ok(Async::Ready(()))
+    }
+}
+
+#[test]
+fn varint_roundtrip() {
+    for &val in &[0, 1, 127, 128, 300, u64::from(u32::MAX), u64::MAX] {
+        assert_roundtrip::<WriteVarint<VW>, ReadVarint<CR>, _, _>(val);
+    }
+    assert_eq!(write::<WriteVarint<VW>>(0), [0]);
+    assert_eq!(write::<WriteVarint<VW>>(u64::MAX).len(), 10);
+    assert_chunked_write::<WriteVarint<CW>>(u64::MAX, &write::<WriteVarint<VW>>(u64::MAX));
+}
+
+#[test]
+fn varint_errors() {
+    let err = read_err::<ReadVarint<CR>, _, _>(vec![0xff; 11]);
+    assert_eq!(data_err(err), VarintError::Overflow);
+    let mut too_large = vec![0xff; 9];
+    too_large.push(0x02);
+    let err = read_err::<ReadVarint<CR>, _, _>(too_large);
+    assert_eq!(data_err(err), VarintError::Overflow);
+
+    assert!(is_eof(&read_err::<ReadVarint<CR>, _, _>(vec![])));
+    assert!(is_eof(&read_err::<ReadVarint<CR>, _, _>(vec![0x80, 0x80])));
+    assert_eq!(write_err::<WriteVarint<QW>>(u64::MAX, 9).kind(), ErrorKind::WriteZero);
+}
+
+#[test]
+fn zigzag_roundtrip() {
+    for &val in &[0, 1, -1, i64::MAX, i64::MIN] {
+        assert_eq!(decode_zigzag(encode_zigzag(val)), val);
+        assert_roundtrip::<WriteVarint<VW>, ReadVarint<CR>, _, _>(encode_zigzag(val));
+    }
+    assert_eq!(encode_zigzag(0), 0);
+    assert_eq!(encode_zigzag(-1), 1);
+    assert_eq!(encode_zigzag(i64::MIN), u64::MAX);
+}
+
+#[test]
+fn fixed_roundtrip() {
+    for &val in &[0, 1, u64::MAX] {
+        assert_roundtrip::<WriteFixed64<VW>, ReadFixed64<CR>, _, _>(val);
+    }
+    for &val in &[0, 1, u32::MAX] {
+        assert_roundtrip::<WriteFixed32<VW>, ReadFixed32<CR>, _, _>(val);
+    }
+    assert_eq!(write::<WriteFixed64<VW>>(1), [1, 0, 0, 0, 0, 0, 0, 0]);
+    assert_chunked_write::<WriteFixed32<CW>>(0x0403_0201, &[1, 2, 3, 4]);
+}
+
+#[test]
+fn float_bits_roundtrip() {
+    let doubles = [0.0, -0.0, f64::MIN, f64::MAX, f64::INFINITY, f64::NEG_INFINITY, f64::NAN];
+    for &val in &doubles {
+        assert_roundtrip::<WriteFixed64<VW>, ReadFixed64<CR>, _, _>(val.to_bits());
+    }
+    let floats = [0.0, -0.0, f32::MIN, f32::MAX, f32::INFINITY, f32::NEG_INFINITY, f32::NAN];
+    for &val in &floats {
+        assert_roundtrip::<WriteFixed32<VW>, ReadFixed32<CR>, _, _>(val.to_bits());
+    }
+}
+
+#[test]
+fn fixed_errors() {
+    assert!(is_eof(&read_err::<ReadFixed64<CR>, _, _>(vec![1, 2, 3, 4, 5, 6, 7])));
+    assert!(is_eof(&read_err::<ReadFixed32<CR>, _, _>(vec![])));
+    assert_eq!(write_err::<WriteFixed64<QW>>(u64::MAX, 7).kind(), ErrorKind::WriteZero);
+}
+
+#[test]
+fn protobuf_bytes_roundtrip() {
+    assert_roundtrip::<WriteBytes<VW>, ReadBytes<CR>, _, _>(vec![]);
+    assert_roundtrip::<WriteBytes<VW>, ReadBytes<CR>, _, _>(vec![0, 1, 255]);
+    assert_roundtrip::<WriteString<VW>, ReadString<CR>, _, _>(String::new());
+    assert_roundtrip::<WriteString<VW>, ReadString<CR>, _, _>("grüße".to_string());
+    assert_eq!(write::<WriteBytes<VW>>(vec![]), [0]);
+    assert_chunked_write::<WriteString<CW>>("abc".to_string(), &[3, b'a', b'b', b'c']);
+}
+
+#[test]
+fn protobuf_bytes_errors() {
+    match read_err::<ReadString<CR>, _, _>(vec![2, 0xc3, 0x28]) {
+        DeserializeError::DataError(ProtobufError::InvalidUtf8(_)) => {}
+        err => panic!("unexpected error {:?}", err),
+    }
+    assert!(is_eof(&read_err::<ReadBytes<CR>, _, _>(vec![3, 1, 2])));
+    assert!(is_eof(&read_err::<ReadBytes<CR>, _, _>(vec![])));
+    assert_eq!(write_err::<WriteBytes<QW>>(vec![1, 2, 3], 2).kind(), ErrorKind::WriteZero);
+}
+
+#[test]
+fn protobuf_key_roundtrip() {
+    for &wire_type in &[WireType::Varint,
+                        WireType::Fixed64,
+                        WireType::LengthDelimited,
+                        WireType::Fixed32] {
+        assert_roundtrip::<WriteKey<VW>, ReadKey<CR>, _, _>(Key::new(1, wire_type));
+    }
+}
+
+#[test]
+fn protobuf_key_errors() {
+    // Field number 1 with the wire types 3, 4 (groups) and 7 (unassigned).
+    for &id in &[3u8, 4, 7] {
+        let err = read_err::<ReadKey<CR>, _, _>(vec![(1 << 3) | id]);
+        assert_eq!(data_err(err), ProtobufError::InvalidWireType(id));
+    }
+    let err = read_err::<ReadKey<CR>, _, _>(vec![0]);
+    assert_eq!(data_err(err), ProtobufError::InvalidFieldNumber(0));
+    assert!(is_eof(&read_err::<ReadKey<CR>, _, _>(vec![0x80])));
+}
+
+#[test]
+fn serialize_batch() {
+    let expected = [0xac, 0x02, 2, b'h', b'i', 7, 0, 0, 0];
+    let batch = SerializeBatch::new(ChunkedWriter::new(1))
+        .then::<WriteVarint<_>>(300)
+        .then::<WriteString<_>>("hi".to_string())
+        .then::<WriteFixed32<_>>(7)
+        .execute();
+    assert_eq!(batch.remaining_bytes(), expected.len());
+    let (writer, written) = block_on(batch).unwrap();
+    assert_eq!((writer.bytes(), written), (&expected[..], expected.len()));
+
+    let batch = batch_serialize!(VecWriter::new(),
+                                 (WriteVarint<_>, 300),
+                                 (WriteString<_>, "hi".to_string()),
+                                 (WriteFixed32<_>, 7));
+    let (writer, written) = block_on(batch).unwrap();
+    assert_eq!((writer.bytes(), written), (&expected[..], expected.len()));
+
+    let reader = CR::new(expected.to_vec(), 1);
+    let (reader, varint, _) = block_on(ReadVarint::from_reader(reader)).unwrap();
+    let (reader, string, _) = block_on(ReadString::from_reader(reader)).unwrap();
+    let (reader, fixed, _) = block_on(ReadFixed32::from_reader(reader)).unwrap();
+    assert_eq!((varint, &string[..], fixed, reader.position()), (300, "hi", 7, 9));
+
+    let (writer, written) = block_on(batch_serialize!(VecWriter::new())).unwrap();
+    assert_eq!((writer.bytes(), written), (&[][..], 0));
+}
+
+#[test]
+fn serialize_batch_errors() {
+    // The batch stops at the value that fails to write.
+    let batch = batch_serialize!(QuotaWriter::new(VecWriter::new(), 4),
+                                 (WriteVarint<_>, 300),
+                                 (WriteString<_>, "hi".to_string()),
+                                 (WriteFixed32<_>, 7));
+    let (writer, err) = block_on(batch).err().unwrap();
+    assert_eq!(err.kind(), ErrorKind::WriteZero);
+    assert_eq!(writer.get_ref().bytes(), [0xac, 0x02, 2, b'h']);
+}
+
+type WriteVarintField<W> = Chain<WriteKey<W>, WriteVarint<W>, W>;
+type WriteStringField<W> = Chain<WriteKey<W>, WriteString<W>, W>;
+
+#[test]
+fn protobuf_message() {
+    // The two fields of the example in the protobuf documentation.
+    let message = ((Key::new(1, WireType::Varint), 150),
+                   (Key::new(2, WireType::LengthDelimited), "testing".to_string()));
+    let expected = [0x08, 0x96, 0x01, 0x12, 0x07, b't', b'e', b's', b't', b'i', b'n', b'g'];
+    type WriteMessage<W> = Chain<WriteVarintField<W>, WriteStringField<W>, W>;
+    assert_eq!(write::<WriteMessage<VW>>(message.clone()), expected);
+    assert_chunked_write::<WriteMessage<CW>>(message, &expected);
+
+    let reader = CR::new(expected.to_vec(), 1);
+    let (reader, first, read) = block_on(ReadField::from_reader(reader)).unwrap();
+    assert_eq!((first.field_number, first.value, read), (1, Value::Varint(150), 3));
+    let (_, second, read) = block_on(ReadField::from_reader(reader)).unwrap();
+    let value = Value::LengthDelimited(b"testing".to_vec());
+    assert_eq!(second, Field { field_number: 2, value });
+    assert_eq!(read, 9);
+
+    // Unknown fields of every wire type can be skipped.
+    let values: &[(WireType, &[u8])] = &[(WireType::Varint, &[0x96, 0x01]),
+                                         (WireType::Fixed64, &[0; 8]),
+                                         (WireType::LengthDelimited, &[2, 0, 0]),
+                                         (WireType::Fixed32, &[0; 4])];
+    for &(wire_type, bytes) in values {
+        let mut data = bytes.to_vec();
+        data.push(0x2a);
+        let (reader, skipped) = block_on(SkipValue::new(CR::new(data, 1), wire_type)).unwrap();
+        assert_eq!((skipped, reader.position()), (bytes.len(), bytes.len()));
+    }
+    let err = block_on(SkipValue::new(CR::new(vec![5, 0], 1), WireType::LengthDelimited))
+        .err()
+        .unwrap()
+        .1;
+    assert!(is_eof(&err));
+    assert!(is_eof(&read_err::<ReadField<CR>, _, _>(vec![0x08])));
+}
+
+#[test]
+fn tag_roundtrip() {
+    let cases = [(TagWidth::U8, 0), (TagWidth::U8, 255), (TagWidth::U16, 65535),
+                 (TagWidth::Varint, 0), (TagWidth::Varint, u64::MAX)];
+    for &(width, tag) in &cases {
+        let writer = WriteTagged::<WriteVarint<VW>, VW>::new(VecWriter::new(), width, tag, 7);
+        let (writer, written) = block_on(writer).unwrap();
+        let bytes = writer.into_inner();
+        assert_eq!(written, WriteTagged::<WriteVarint<VW>, VW>::total_bytes(width, tag, &7));
+
+        let reader = ChunkedReader::new(bytes.clone(), 1);
+        let (reader, read_tag, tag_len) = block_on(ReadTag::new(reader, width)).unwrap();
+        assert_eq!(read_tag, tag);
+        let (_, val, val_len) = block_on(ReadVarint::from_reader(reader)).unwrap();
+        assert_eq!((val, tag_len + val_len), (7, bytes.len()));
+    }
+}
+
+#[test]
+fn tag_errors() {
+    let writer = WriteTagged::<WriteVarint<VW>, VW>::new(VecWriter::new(), TagWidth::U8, 256, 7);
+    match block_on(writer) {
+        Ok(_) => panic!("serialization unexpectedly succeeded"),
+        Err((writer, err)) => {
+            assert_eq!(err.kind(), ErrorKind::InvalidInput);
+            assert!(writer.bytes().is_empty());
+        }
+    }
+    assert_eq!(WriteTagged::<WriteVarint<VW>, VW>::total_bytes(TagWidth::U16, 1 << 16, &7), 0);
+
+    let reader = ChunkedReader::new(vec![1], 1);
+    assert!(is_eof(&block_on(ReadTag::new(reader, TagWidth::U16)).unwrap_err().1));
+}
+
+#[test]
+fn read_unknown() {
+    let reader = ChunkedReader::new(vec![9, 3, 1, 2, 3, 0x2a], 1);
+    let (reader, tag, _) = block_on(ReadTag::new(reader, TagWidth::U8)).unwrap();
+    let (reader, unknown, read) = block_on(ReadUnknown::new(reader, tag)).unwrap();
+    let expected = Unknown {
+        tag: 9,
+        bytes: vec![1, 2, 3],
+    };
+    assert_eq!((unknown, read, reader.position()), (expected, 4, 5));
+
+    let (_, empty, read) = block_on(ReadUnknown::new(CR::new(vec![0], 1), 1)).unwrap();
+    assert_eq!((empty.bytes, read), (vec![], 1));
+
+    let unknown = abandon(ReadUnknown::new(CR::new(vec![3, 1, 2, 3], 1), 9), 4);
+    assert_eq!(unknown.already_read(), 3);
+    assert_eq!(unknown.restore().unwrap().position(), 3);
+
+    let overflow = ReadUnknown::new(CR::new(vec![0xff; 11], 1), 9);
+    assert_eq!(data_err(block_on(overflow).err().unwrap().1), TagError::VarintOverflow);
+    let truncated = ReadUnknown::new(CR::new(vec![3, 1], 1), 9);
+    assert!(is_eof(&block_on(truncated).err().unwrap().1));
+}
+
+#[test]
+fn arc_bytes_roundtrip() {
+    assert_roundtrip::<SerArcBytes<VW>, DeserArcBytes<CR>, _, _>(Arc::from(&[][..]));
+    assert_roundtrip::<SerArcBytes<VW>, DeserArcBytes<CR>, _, _>(Arc::from(&[0, 255][..]));
+    assert_eq!(write::<SerArcBytes<VW>>(Arc::from(&[][..])), [0, 0, 0, 0]);
+    assert_chunked_write::<SerArcBytes<CW>>(Arc::from(&[9][..]), &[0, 0, 0, 1, 9]);
+}
+
+#[test]
+fn arc_bytes_errors() {
+    let err = read_err::<DeserArcBytes<CR>, _, ArcBytesError>(vec![0, 0, 0, 2, 1]);
+    assert!(is_eof(&err));
+    assert!(is_eof(&read_err::<DeserArcBytes<CR>, _, ArcBytesError>(vec![0, 0])));
+    let err = write_err::<SerArcBytes<QW>>(Arc::from(&[1, 2][..]), 5);
+    assert_eq!(err.kind(), ErrorKind::WriteZero);
+}
+
+#[test]
+fn cow_roundtrip() {
+    // Cows share the encoding of `Arc<[u8]>`.
+    let empty = write::<WriteCowBytes<VW>>(Cow::Borrowed(&[]));
+    assert_eq!(empty, write::<SerArcBytes<VW>>(Arc::from(&[][..])));
+    let bytes = write::<WriteCowStr<VW>>(Cow::Borrowed("hi"));
+    assert_eq!(bytes, [0, 0, 0, 2, b'h', b'i']);
+    assert_chunked_write::<WriteCowStr<CW>>(Cow::Owned("hi".to_string()), &bytes);
+
+    for val in &[Cow::Borrowed("hi"), Cow::Owned("hi".to_string())] {
+        let (writer, written) = block_on(SerCowStr::from_ref(VecWriter::new(), val)).unwrap();
+        assert_eq!(writer.into_inner(), bytes);
+        assert_eq!(written, SerCowStr::<VW>::total_bytes(val));
+    }
+
+    let reader = ChunkedReader::new(bytes, 1);
+    let (_, val, _) = block_on(DeserArcBytes::from_reader(reader)).unwrap();
+    assert_eq!(&val[..], b"hi");
+}
+
+#[test]
+fn cow_errors() {
+    let err = write_err::<WriteCowBytes<QW>>(Cow::Borrowed(&[1, 2]), 0);
+    assert_eq!(err.kind(), ErrorKind::WriteZero);
+}
+
+#[test]
+fn path_roundtrip() {
+    assert_roundtrip::<SerPathBuf<VW>, DeserPath<CR>, _, _>(PathBuf::new());
+    assert_roundtrip::<SerPathBuf<VW>, DeserPath<CR>, _, _>(PathBuf::from("a/b.txt"));
+    assert_chunked_write::<SerPathBuf<CW>>(PathBuf::from("a"), &[0, 0, 0, 1, b'a']);
+}
+
+#[test]
+fn path_errors() {
+    let err = read_err::<DeserPath<CR>, _, _>(vec![0, 0, 0, 1, 0xff]);
+    assert_eq!(data_err(err), PathError::NonUtf8Path);
+    assert!(is_eof(&read_err::<DeserPath<CR>, _, PathError>(vec![0, 0, 0, 1])));
+}
+
+#[test]
+fn os_string_roundtrip() {
+    assert_roundtrip::<SerOsString<VW>, DeserOsString<CR>, _, _>(OsString::new());
+    assert_roundtrip::<SerOsString<VW>, DeserOsString<CR>, _, _>(OsString::from("grüße"));
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStringExt;
+        let val = OsString::from_vec(vec![0x61, 0xff, 0xfe]);
+        assert_roundtrip::<SerOsString<VW>, DeserOsString<CR>, _, _>(val);
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::ffi::OsStringExt;
+        let val = OsString::from_wide(&[0x61, 0xd800]);
+        assert_roundtrip::<SerOsString<VW>, DeserOsString<CR>, _, _>(val);
+    }
+    assert_eq!(write::<SerOsString<VW>>(OsString::from("a")).len(), 6);
+}
+
+#[test]
+fn os_string_errors() {
+    let foreign = if cfg!(windows) { UNIX } else { WINDOWS };
+    let err = read_err::<DeserOsString<CR>, _, _>(vec![foreign, 0, 0, 0, 0]);
+    assert_eq!(data_err(err), OsStringError::ForeignPlatform(foreign));
+    let err = read_err::<DeserOsString<CR>, _, _>(vec![7, 0, 0, 0, 0]);
+    assert_eq!(data_err(err), OsStringError::UnknownPlatform(7));
+    let native = if cfg!(windows) { WINDOWS } else { UNIX };
+    assert!(is_eof(&read_err::<DeserOsString<CR>, _, _>(vec![native, 0, 0, 0, 2, 0])));
+}
+
+#[test]
+fn hex_str_roundtrip() {
+    for bytes in &[vec![], vec![0x00, 0x7f, 0xff], (0..=255).collect::<Vec<u8>>()] {
+        let encoded = write::<SerHexStr<VW>>(bytes.clone());
+        assert_chunked_write::<SerHexStr<CW>>(bytes.clone(), &encoded);
+
+        let reader = ChunkedReader::new(encoded.clone(), 1);
+        let (_, val, read) = block_on(DeserHexStr::new(reader, bytes.len())).unwrap();
+        assert_eq!(&val, bytes);
+        assert_eq!(read, encoded.len());
+    }
+    assert_eq!(write::<SerHexStr<VW>>(vec![0xab, 0x0c]), b"ab0c");
+
+    let (_, val, _) = block_on(DeserHexStr::new(ChunkedReader::new(b"AB0c".to_vec(), 1), 2))
+        .unwrap();
+    assert_eq!(val, [0xab, 0x0c]);
+}
+
+#[test]
+fn hex_str_errors() {
+    let read_hex = |bytes: &[u8], len| {
+        match block_on(DeserHexStr::new(ChunkedReader::new(bytes.to_vec(), 1), len)) {
+            Ok((_, val, _)) => panic!("deserialization unexpectedly succeeded with {:?}", val),
+            Err((_, err)) => err,
+        }
+    };
+
+    assert_eq!(data_err(read_hex(b"0g", 1)), HexError::InvalidHexChar('g'));
+    assert_eq!(data_err(read_hex(b"00 0", 2)), HexError::InvalidHexChar(' '));
+    assert_eq!(data_err(read_hex(b"\xff0", 1)), HexError::InvalidHexChar('\u{ff}'));
+    assert!(is_eof(&read_hex(b"000", 2)));
+    assert!(is_eof(&read_hex(b"", 1)));
+}
+
+#[test]
+fn ascii_char() {
+    for &(byte, c) in &[(b'A', 'A'), (0x00, '\0'), (0x7f, '\u{7f}')] {
+        let reader = ChunkedReader::new(vec![byte], 1);
+        let (reader, val, read) = block_on(DeserAsciiChar::from_reader(reader)).unwrap();
+        assert_eq!((val, read, reader.position()), (c, 1, 1));
+    }
+
+    let err = read_err::<DeserAsciiChar<CR>, _, _>(vec![0x80]);
+    assert_eq!(data_err(err), AsciiCharError::NonAscii(0x80));
+    let err = read_err::<DeserAsciiChar<CR>, _, _>(vec![0xff]);
+    assert_eq!(data_err(err), AsciiCharError::NonAscii(0xff));
+    assert!(is_eof(&read_err::<DeserAsciiChar<CR>, _, _>(vec![])));
+
+    for &(byte, c) in &[(b'a', 'a'), (0xe9, 'é'), (0xff, 'ÿ')] {
+        let reader = ChunkedReader::new(vec![byte], 1);
+        let (_, val, _) = block_on(DeserAsciiChar::allow_extended(reader)).unwrap();
+        assert_eq!(val, c);
+    }
+}
+
+#[cfg(feature = "base64")]
+#[test]
+fn base64_roundtrip() {
+    let configs = [Base64Config::STANDARD,
+                   Base64Config::URL_SAFE,
+                   Base64Config::new(Alphabet::Standard, Padding::Unpadded),
+                   Base64Config::new(Alphabet::UrlSafe, Padding::Padded)];
+
+    for &config in &configs {
+        for len in 0..8 {
+            let bytes: Vec<u8> = (0..len).map(|i| 0xff - 37 * i as u8).collect();
+            let (writer, written) = block_on(SerBase64::new(VecWriter::new(), config, &bytes))
+                .unwrap();
+            let mut encoded = writer.into_inner();
+            assert_eq!(written, encoded.len());
+            assert_eq!(config.encoded_len(len), encoded.len());
+
+            let (chunked, _) = block_on(SerBase64::new(ChunkedWriter::new(1), config, &bytes))
+                .unwrap();
+            assert_eq!(chunked.bytes(), &encoded[..]);
+
+            let reader = ChunkedReader::new(encoded.clone(), 1);
+            let end = Base64End::Len(encoded.len());
+            let (_, val, read) = block_on(DeserBase64::new(reader, config, end)).unwrap();
+            assert_eq!(val, bytes);
+            assert_eq!(read, written);
+
+            encoded.push(b'\n');
+            let reader = ChunkedReader::new(encoded, 1);
+            let end = Base64End::Terminator {
+                terminator: b'\n',
+                limit: written,
+            };
+            let (_, val, read) = block_on(DeserBase64::new(reader, config, end)).unwrap();
+            assert_eq!(val, bytes);
+            assert_eq!(read, written + 1);
+        }
+    }
+}
+
+#[cfg(feature = "base64")]
+#[test]
+fn base64_errors() {
+    let read_base64 = |bytes: &[u8], config| {
+        let end = Base64End::Terminator {
+            terminator: b'.',
+            limit: 8,
+        };
+        match block_on(DeserBase64::new(ChunkedReader::new(bytes.to_vec(), 1), config, end)) {
+            Ok((_, val, _)) => panic!("deserialization unexpectedly succeeded with {:?}", val),
+            Err((_, err)) => err,
+        }
+    };
+    let invalid = |offset| Base64Error::InvalidBase64 { offset };
+    let padded = Base64Config::STANDARD;
+    let unpadded = Base64Config::new(Alphabet::Standard, Padding::Unpadded);
+
+    assert_eq!(data_err(read_base64(b"Zm9*.", padded)), invalid(3));
+    assert_eq!(data_err(read_base64(b"-_8=.", padded)), invalid(0));
+    assert_eq!(data_err(read_base64(b"+/8.", Base64Config::URL_SAFE)), invalid(0));
+    // The unused bits of the last character of "f" must be zero.
+    assert_eq!(data_err(read_base64(b"Zh==.", padded)), invalid(3));
+    assert_eq!(data_err(read_base64(b"Zh.", unpadded)), invalid(2));
+    assert_eq!(data_err(read_base64(b"Zg.", padded)), invalid(2));
+    assert_eq!(data_err(read_base64(b"Zg==.", unpadded)), invalid(2));
+    assert_eq!(data_err(read_base64(b"Zg==Zg==.", padded)), invalid(4));
+    assert_eq!(data_err(read_base64(b"Z===.", padded)), invalid(1));
+    assert_eq!(data_err(read_base64(b"Zm9vZ.", unpadded)), invalid(5));
+    assert_eq!(data_err(read_base64(b"Zm9vYmFyZg.", unpadded)),
+               Base64Error::TooLong { limit: 8 });
+    assert!(is_eof(&read_base64(b"Zm9v", padded)));
+}
+
+#[test]
+fn streaming_utf8_roundtrip() {
+    let long = "ab€🎉".repeat(100);
+    for string in &["", "hi", "ab€🎉", &long[..]] {
+        let bytes = write::<WriteCowStr<VW>>(Cow::Borrowed(string));
+        for chunk in &[1, 3, 1000] {
+            let reader = ChunkedReader::new(bytes.clone(), *chunk);
+            let (_, val, read) = block_on(StreamingUtf8Deserializer::from_reader(reader)).unwrap();
+            assert_eq!((&val[..], read), (*string, bytes.len()));
+        }
+    }
+}
+
+#[test]
+fn streaming_utf8_errors() {
+    type D = StreamingUtf8Deserializer<CR>;
+    let invalid = |byte_offset| StreamingUtf8Error::InvalidUtf8 { byte_offset };
+
+    let err = read_err::<D, _, _>(vec![0, 0, 0, 3, b'a', 0xff, b'b']);
+    assert_eq!(data_err(err), invalid(1));
+    // A string that ends in the middle of a character.
+    let err = read_err::<D, _, _>(vec![0, 0, 0, 3, b'a', 0xf0, 0x9f]);
+    assert_eq!(data_err(err), invalid(1));
+    // Overlong encodings are rejected once their second byte has been read.
+    let err = read_err::<D, _, _>(vec![0, 0, 0, 4, 0xc0, 0x80]);
+    assert_eq!(data_err(err), invalid(0));
+    let mut long = vec![0, 0, 1, 0];
+    long.extend(vec![b'a'; 255]);
+    long.push(0xe2);
+    assert_eq!(data_err(read_err::<D, _, _>(long)), invalid(255));
+
+    assert!(is_eof(&read_err::<D, _, _>(vec![0, 0, 0, 3, b'a'])));
+    assert!(is_eof(&read_err::<D, _, _>(vec![0, 0, 0, 4, b'a', 0xe2, 0x82])));
+}
+
+#[test]
+fn ip_addr_roundtrip() {
+    let addrs = [IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+                 IpAddr::V4(Ipv4Addr::new(192, 168, 0, 1)),
+                 IpAddr::V6(Ipv6Addr::LOCALHOST),
+                 IpAddr::V6(Ipv6Addr::new(0xfe80, 0, 0, 0, 0x1, 0x2, 0x3, 0xffff))];
+    for &addr in &addrs {
+        assert_roundtrip::<SerIpAddr<VW>, DeserIpAddr<CR>, _, _>(addr);
+        assert_chunked_write::<SerIpAddr<CW>>(addr, &write::<SerIpAddr<VW>>(addr));
+    }
+    assert_eq!(write::<SerIpAddr<VW>>(IpAddr::V4(Ipv4Addr::new(1, 2, 3, 4))),
+               [V4, 1, 2, 3, 4]);
+    assert_eq!(write::<SerIpAddr<VW>>(IpAddr::V6(Ipv6Addr::UNSPECIFIED))[0], V6);
+}
+
+#[test]
+fn ip_addr_errors() {
+    let err = read_err::<DeserIpAddr<CR>, _, _>(vec![0x05, 1, 2, 3, 4]);
+    assert_eq!(data_err(err), IpAddrError::UnknownAddressFamily(0x05));
+    assert!(is_eof(&read_err::<DeserIpAddr<CR>, _, _>(vec![])));
+    assert!(is_eof(&read_err::<DeserIpAddr<CR>, _, _>(vec![V4, 1, 2, 3])));
+    assert!(is_eof(&read_err::<DeserIpAddr<CR>, _, _>(vec![V6, 1, 2, 3, 4])));
+}
+
+#[test]
+fn bitset_roundtrip() {
+    let nine = [true, false, true, true, false, false, false, true, true];
+    let cases: [&[bool]; 4] = [&[], &[true], &[false; 8], &nine];
+    for bits in &cases {
+        let (writer, written) = block_on(WriteBitset::from_ref(VecWriter::new(), *bits)).unwrap();
+        let bytes = writer.into_inner();
+        assert_eq!(written, bytes.len());
+
+        let (chunked, _) = block_on(WriteBitset::from_ref(ChunkedWriter::new(1), *bits)).unwrap();
+        assert_eq!(chunked.bytes(), &bytes[..]);
+
+        let reader = ChunkedReader::new(bytes, 1);
+        let (_, val, read) = block_on(ReadBitset::from_reader(reader)).unwrap();
+        assert_eq!((&val[..], read), (*bits, written));
+    }
+}
+
+#[test]
+fn bitset_errors() {
+    let err = read_err::<ReadBitset<CR>, _, _>(vec![1, 0b10]);
+    assert_eq!(data_err(err), BitsetError::NonZeroPadding);
+    let err = read_err::<ReadBitset<CR>, _, _>(vec![0xff; 11]);
+    assert_eq!(data_err(err), BitsetError::VarintOverflow);
+    assert!(is_eof(&read_err::<ReadBitset<CR>, _, _>(vec![9, 0])));
+}
+
+#[test]
+fn front_coded_roundtrip() {
+    let cases: [&[&str]; 5] = [&[],
+                               &[""],
+                               &["a", "a", "ab"],
+                               &["apple", "applesauce", "apply", "banana"],
+                               &["é", "è", "ë"]];
+    for strings in &cases {
+        let (writer, written) = block_on(WriteFrontCoded::from_ref(VecWriter::new(), *strings))
+            .unwrap();
+        let bytes = writer.into_inner();
+        assert_eq!(written, bytes.len());
+        assert_eq!(WriteFrontCoded::<VW>::total_bytes(*strings), bytes.len());
+
+        let (chunked, _) = block_on(WriteFrontCoded::from_ref(ChunkedWriter::new(1), *strings))
+            .unwrap();
+        assert_eq!(chunked.bytes(), &bytes[..]);
+
+        let reader = ChunkedReader::new(bytes, 1);
+        let (_, val, read) = block_on(ReadFrontCoded::from_reader(reader)).unwrap();
+        assert_eq!(val, *strings);
+        assert_eq!(read, written);
+    }
+}
+
+#[test]
+fn front_coded_errors() {
+    let err = read_err::<ReadFrontCoded<CR>, _, _>(vec![1, 1, 0]);
+    assert_eq!(data_err(err),
+               FrontCodedError::SharedPrefixTooLong {
+                   shared: 1,
+                   previous: 0,
+               });
+    let err = read_err::<ReadFrontCoded<CR>, _, _>(vec![2, 0, 1, 0x61, 2, 0]);
+    assert_eq!(data_err(err),
+               FrontCodedError::SharedPrefixTooLong {
+                   shared: 2,
+                   previous: 1,
+               });
+    match read_err::<ReadFrontCoded<CR>, _, _>(vec![1, 0, 1, 0xff]) {
+        DeserializeError::DataError(FrontCodedError::InvalidUtf8(_)) => {}
+        err => panic!("expected invalid utf8, got {:?}", err),
+    }
+    let err = read_err::<ReadFrontCoded<CR>, _, _>(vec![0xff; 11]);
+    assert_eq!(data_err(err), FrontCodedError::VarintOverflow);
+    assert!(is_eof(&read_err::<ReadFrontCoded<CR>, _, _>(vec![2, 0, 1, 0x61, 1, 1])));
+}
+
+#[test]
+fn sparse_roundtrip() {
+    let cases: [(usize, &[(usize, u64)]); 4] = [(0, &[]),
+                                                (3, &[]),
+                                                (5, &[(1, 7), (4, 300)]),
+                                                (2, &[(0, 1), (1, 2)])];
+    for &(len, entries) in &cases {
+        let (writer, written) = block_on(SerSparse::<WriteVarint<VW>, VW>::new(VecWriter::new(),
+                                                                               len,
+                                                                               entries))
+            .unwrap();
+        let bytes = writer.into_inner();
+        assert_eq!(written, bytes.len());
+        assert_eq!(SerSparse::<WriteVarint<VW>, VW>::total_bytes(len, entries), bytes.len());
+
+        let mut dense = vec![0; len];
+        for &(index, val) in entries {
+            dense[index] = val;
+        }
+        let reader = ChunkedReader::new(bytes, 1);
+        let (_, val, read) = block_on(DeserSparse::<ReadVarint<CR>, _, _, _>::from_reader(reader))
+            .unwrap();
+        assert_eq!(val, dense);
+        assert_eq!(read, written);
+    }
+}
+
+#[test]
+fn sparse_errors() {
+    let invalid: [(usize, &[(usize, u64)]); 3] = [(2, &[(2, 1)]),
+                                                  (5, &[(3, 1), (1, 2)]),
+                                                  (5, &[(1, 1), (1, 2)])];
+    for &(len, entries) in &invalid {
+        let ser = SerSparse::<WriteVarint<VW>, VW>::new(VecWriter::new(), len, entries);
+        let (writer, err) = match block_on(ser) {
+            Ok(_) => panic!("serialization unexpectedly succeeded"),
+            Err(failed) => failed,
+        };
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        assert!(writer.into_inner().is_empty());
+        assert_eq!(SerSparse::<WriteVarint<VW>, VW>::total_bytes(len, entries), 0);
+    }
+
+    type Sparse = DeserSparse<ReadVarint<CR>, CR, u64, VarintError>;
+    let err = read_err::<Sparse, _, _>(vec![0, 0, 0, 1, 0, 0, 0, 2]);
+    assert_eq!(data_err(err), SparseError::TooManyEntries { count: 2, len: 1 });
+    let err = read_err::<Sparse, _, _>(vec![0, 0, 0, 2, 0, 0, 0, 1, 0, 0, 0, 2, 1]);
+    assert_eq!(data_err(err), SparseError::IndexOutOfBounds { index: 2, len: 2 });
+    let err = read_err::<Sparse, _, _>(vec![0, 0, 0, 3, 0, 0, 0, 2, 0, 0, 0, 1, 1, 0, 0, 0, 1]);
+    assert_eq!(data_err(err), SparseError::UnsortedIndex(1));
+    let err = read_err::<Sparse, _, _>(vec![0, 0, 0, 2, 0, 0, 0, 1, 0, 0, 0, 0, 0xff, 0xff, 0xff,
+                                            0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]);
+    assert_eq!(data_err(err), SparseError::Value(VarintError::Overflow));
+    assert!(is_eof(&read_err::<Sparse, _, _>(vec![0, 0, 0, 2, 0, 0, 0, 1, 0, 0, 0])));
+
+    let reader = ChunkedReader::new(vec![0, 0, 0, 5, 0, 0, 0, 0], 1);
+    match block_on(Sparse::new(reader, 4)) {
+        Err((_, DeserializeError::DataError(err))) => {
+            assert_eq!(err, SparseError::TooLong { len: 5, max: 4 })
+        }
+        _ => panic!("expected the length to exceed the maximum"),
+    }
+}
+
+#[test]
+fn parity_roundtrip() {
+    type Parity<W> = WriteWithParity<WriteBytes<ParityWriter<W>>, W>;
+    type ReadParity = ReadWithParity<ReadBytes<ParityReader<CR>>, CR, Vec<u8>>;
+
+    for &block_size in &[1, 3, 256] {
+        for &len in &[0, 1, 2, 3, 255, 256, 600] {
+            let bytes: Vec<u8> = (0..len).map(|i| i as u8).collect();
+            let (writer, written) = block_on(Parity::new(VecWriter::new(),
+                                                         block_size,
+                                                         bytes.clone()))
+                .unwrap();
+            let encoded = writer.into_inner();
+            assert_eq!(written, encoded.len());
+            assert_eq!(Parity::<VW>::total_bytes(block_size, &bytes), encoded.len());
+
+            let (chunked, _) = block_on(Parity::new(ChunkedWriter::new(1),
+                                                    block_size,
+                                                    bytes.clone()))
+                .unwrap();
+            assert_eq!(chunked.bytes(), &encoded[..]);
+
+            let reader = ChunkedReader::new(encoded, 1);
+            let (_, val, read) = block_on(ReadParity::new(reader, block_size)).unwrap();
+            assert_eq!(val, bytes);
+            assert_eq!(read, written);
+        }
+    }
+}
+
+#[test]
+fn parity_errors() {
+    type ReadParity = ReadWithParity<ReadBytes<ParityReader<CR>>, CR, Vec<u8>>;
+    let read_parity = |bytes: Vec<u8>| {
+        match block_on(ReadParity::new(ChunkedReader::new(bytes, 1), 2)) {
+            Ok((_, val, _)) => panic!("deserialization unexpectedly succeeded with {:?}", val),
+            Err((_, err)) => err,
+        }
+    };
+
+    // The length prefix 3 and the byte 1 form a block with parity 2, the bytes 2 and 3 one with
+    // parity 1.
+    let err = read_parity(vec![3, 1, 0xff, 2, 3, 1]);
+    assert_eq!(data_err(err),
+               ParityError::Mismatch {
+                   expected: 0xff,
+                   actual: 2,
+               });
+    let err = read_parity(vec![3, 1, 2, 2, 3, 0xff]);
+    assert_eq!(data_err(err),
+               ParityError::Mismatch {
+                   expected: 0xff,
+                   actual: 1,
+               });
+    assert!(is_eof(&read_parity(vec![3, 1, 2, 2, 3])));
+    assert!(is_eof(&read_parity(vec![3, 1, 2, 2])));
+}
+
+#[test]
+fn redundant_roundtrip() {
+    type Redundant<W> = WriteRedundant<WriteVarint<RedundantWriter<W>>, W>;
+    type ReadRedundantVarint = ReadRedundant<ReadVarint<RedundantReader<CR>>, CR, u64>;
+
+    assert_roundtrip::<Redundant<VW>, ReadRedundantVarint, _, _>(300);
+    assert_eq!(write::<Redundant<VW>>(1), [1, 1]);
+
+    for &val in &[0, 300, u64::MAX] {
+        let (writer, written) = block_on(Redundant::new(VecWriter::new(),
+                                                        Redundancy::Complement,
+                                                        val))
+            .unwrap();
+        let bytes = writer.into_inner();
+        assert_eq!(written, bytes.len());
+
+        let reader = ChunkedReader::new(bytes, 1);
+        let (_, read_val, read) = block_on(ReadRedundantVarint::new(reader,
+                                                                    Redundancy::Complement))
+            .unwrap();
+        assert_eq!(read_val, val);
+        assert_eq!(read, written);
+    }
+}
+
+#[test]
+fn redundant_errors() {
+    type ReadRedundantVarint = ReadRedundant<ReadVarint<RedundantReader<CR>>, CR, u64>;
+
+    let err = read_err::<ReadRedundantVarint, _, _>(vec![1, 2]);
+    assert_eq!(data_err(err), RedundantError::RedundancyMismatch);
+    let err = read_err::<ReadRedundantVarint, _, _>(vec![0xff; 11]);
+    assert_eq!(data_err(err), RedundantError::First(VarintError::Overflow));
+    let mut bytes = vec![1];
+    bytes.extend(vec![0xff; 10]);
+    let err = read_err::<ReadRedundantVarint, _, _>(bytes);
+    assert_eq!(data_err(err), RedundantError::Second(VarintError::Overflow));
+    assert!(is_eof(&read_err::<ReadRedundantVarint, _, _>(vec![1])));
+
+    // The complement of 0xfe is the varint 1, so this is only valid with `Redundancy::Complement`.
+    let err = read_err::<ReadRedundantVarint, _, _>(vec![1, 0xfe]);
+    assert!(is_eof(&err));
+    let reader = ChunkedReader::new(vec![1, 0xfd], 1);
+    match block_on(ReadRedundantVarint::new(reader, Redundancy::Complement)) {
+        Err((_, DeserializeError::DataError(err))) => {
+            assert_eq!(err, RedundantError::RedundancyMismatch)
+        }
+        _ => panic!("expected the copies to differ"),
+    }
+}
+
+#[cfg(feature = "tokio-compat")]
+#[test]
+fn length_delimited_roundtrip() {
+    let codecs = [LengthDelimitedCodec::new(),
+                  LengthDelimitedCodec::new().byte_order(ByteOrder::LittleEndian),
+                  LengthDelimitedCodec::new().length_field_length(2),
+                  LengthDelimitedCodec::new().length_field_length(1)];
+
+    for codec in &codecs {
+        for &len in &[0, 1, 200] {
+            let bytes: Vec<u8> = (0..len).map(|i| i as u8).collect();
+            let frame = codec.write_frame::<WriteBytes<VW>, _>(VecWriter::new(), bytes.clone());
+            let (writer, written) = block_on(frame).unwrap();
+            let encoded = writer.into_inner();
+            assert_eq!(written, encoded.len());
+
+            let chunked = ChunkedWriter::new(1);
+            let frame = codec.write_frame::<WriteBytes<CW>, _>(chunked, bytes.clone());
+            let (chunked, _) = block_on(frame).unwrap();
+            assert_eq!(chunked.bytes(), &encoded[..]);
+
+            let reader = ChunkedReader::new(encoded, 1);
+            let frame = codec.read_frame::<ReadBytes<TakeReader<CR>>, _, _, _>(reader);
+            let (_, val, read) = block_on(frame).unwrap();
+            assert_eq!(val, bytes);
+            assert_eq!(read, written);
+        }
+    }
+}
+
+#[cfg(feature = "tokio-compat")]
+#[test]
+fn length_delimited_errors() {
+    let read_frame = |codec: LengthDelimitedCodec, bytes: Vec<u8>| {
+        let reader = ChunkedReader::new(bytes, 1);
+        let frame = codec.read_frame::<ReadVarint<TakeReader<CR>>, _, _, _>(reader);
+        match block_on(frame) {
+            Ok((_, val, _)) => panic!("deserialization unexpectedly succeeded with {:?}", val),
+            Err((_, err)) => err,
+        }
+    };
+    let codec = LengthDelimitedCodec::new().length_field_length(1);
+
+    let err = read_frame(codec.max_frame_length(3), vec![4, 1, 2, 3, 4]);
+    assert_eq!(data_err(err), DelimitedError::FrameTooLong { len: 4, max: 3 });
+    let err = read_frame(codec, vec![2, 1, 0]);
+    assert_eq!(data_err(err),
+               DelimitedError::LengthMismatch {
+                   declared: 2,
+                   consumed: 1,
+               });
+    let mut bytes = vec![11];
+    bytes.extend(vec![0xff; 11]);
+    assert_eq!(data_err(read_frame(codec, bytes)),
+               DelimitedError::Inner(VarintError::Overflow));
+    // The varint continues past the end of the frame.
+    assert!(is_eof(&read_frame(codec, vec![1, 0xff, 0])));
+    assert!(is_eof(&read_frame(codec, vec![])));
+
+    let frame = codec.max_frame_length(3).write_frame::<WriteFixed32<VW>, _>(VecWriter::new(), 7);
+    let (writer, err) = block_on(frame).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    assert!(writer.into_inner().is_empty());
+}
+
+#[test]
+fn log_record_roundtrip() {
+    type LoggedRecord<W> = WriteLoggedRecord<WriteVarint<CrcWriter<W>>, W>;
+    type ReadRecord = ReadLoggedRecord<ReadVarint<CrcReader<OffsetReader<CR>>>, CR, u64>;
+
+    let vals = [0, 300, u64::MAX];
+    let mut log = VecWriter::new();
+    for &val in &vals {
+        assert_chunked_write::<LoggedRecord<CW>>(val, &write::<LoggedRecord<VW>>(val));
+        log = block_on(LoggedRecord::from_val(log, val)).unwrap().0;
+    }
+
+    let mut reader = ChunkedReader::new(log.into_inner(), 1);
+    for &val in &vals {
+        let (r, record, _) = block_on(ReadRecord::from_reader(reader)).unwrap();
+        assert_eq!(record, Some(val));
+        reader = r;
+    }
+    let (_, record, read) = block_on(ReadRecord::from_reader(reader)).unwrap();
+    assert_eq!(record, None);
+    assert_eq!(read, 0);
+}
+
+#[test]
+fn log_record_errors() {
+    type ReadRecord = ReadLoggedRecord<ReadVarint<CrcReader<OffsetReader<CR>>>, CR, u64>;
+
+    // The record of the varint 300, see the golden vectors.
+    let record = vec![0xac, 0x02, 0, 0, 0, 2, 0xad, 0x65, 0x80, 0x36];
+    for len in 1..record.len() {
+        let err = read_err::<ReadRecord, _, _>(record[..len].to_vec());
+        assert_eq!(data_err(err), LogRecordError::Truncated { read: len as u64 });
+    }
+
+    let mut bad_crc = record.clone();
+    bad_crc[9] ^= 1;
+    let err = read_err::<ReadRecord, _, _>(bad_crc);
+    assert_eq!(data_err(err),
+               LogRecordError::CrcMismatch {
+                   expected: 0xad65_8037,
+                   actual: 0xad65_8036,
+               });
+    let mut bad_len = record.clone();
+    bad_len[5] = 3;
+    let err = read_err::<ReadRecord, _, _>(bad_len);
+    assert_eq!(data_err(err),
+               LogRecordError::LengthMismatch {
+                   declared: 3,
+                   consumed: 2,
+               });
+    let err = read_err::<ReadRecord, _, _>(vec![0xff; 11]);
+    assert_eq!(data_err(err), LogRecordError::Inner(VarintError::Overflow));
+}
+
+#[test]
+fn option_roundtrip() {
+    type OptionRef<'val, W> = SerOptionRef<'val, SerCowBytes<'val, W>, W>;
+    type ReadOption = DeserOption<DeserArcBytes<CR>, CR, Arc<[u8]>, ArcBytesError>;
+
+    let vals = [None, Some(Cow::Borrowed(&[][..])), Some(Cow::Owned(vec![1, 2, 3]))];
+    for val in &vals {
+        let (writer, written) = block_on(OptionRef::from_ref(VecWriter::new(), val)).unwrap();
+        let encoded = writer.into_inner();
+        assert_eq!(written, encoded.len());
+        assert_eq!(OptionRef::<VW>::total_bytes(val), encoded.len());
+
+        let (chunked, _) = block_on(OptionRef::from_ref(ChunkedWriter::new(1), val)).unwrap();
+        assert_eq!(chunked.bytes(), &encoded[..]);
+
+        let reader = ChunkedReader::new(encoded, 1);
+        let (_, decoded, read) = block_on(ReadOption::from_reader(reader)).unwrap();
+        assert_eq!(decoded.as_ref().map(|bytes| &bytes[..]), val.as_ref().map(|bytes| &bytes[..]));
+        assert_eq!(read, written);
+    }
+}
+
+#[test]
+fn option_errors() {
+    type ReadOption = DeserOption<ReadVarint<CR>, CR, u64, VarintError>;
+
+    let err = read_err::<ReadOption, _, _>(vec![2, 0]);
+    assert_eq!(data_err(err), OptionError::InvalidTag(2));
+    let mut bytes = vec![SOME];
+    bytes.extend(vec![0xff; 10]);
+    let err = read_err::<ReadOption, _, _>(bytes);
+    assert_eq!(data_err(err), OptionError::Inner(VarintError::Overflow));
+    assert!(is_eof(&read_err::<ReadOption, _, _>(vec![])));
+    assert!(is_eof(&read_err::<ReadOption, _, _>(vec![SOME])));
+
+    let reader = ChunkedReader::new(vec![NONE, 7], 1);
+    let (_, val, read) = block_on(ReadOption::from_reader(reader)).unwrap();
+    assert_eq!((val, read), (None, 1));
+}
+
+#[test]
+fn terminated_roundtrip() {
+    assert_roundtrip::<WriteTerminated<VW>, ReadTerminated<CR>, _, _>(vec![]);
+    assert_roundtrip::<WriteTerminated<VW>, ReadTerminated<CR>, _, _>(vec![1, 255]);
+    assert_eq!(write::<WriteTerminated<VW>>(vec![]), [0]);
+    assert_chunked_write::<WriteTerminated<CW>>(vec![1, 2], &[1, 2, 0]);
+}
+
+#[test]
+fn terminated_errors() {
+    let err = match block_on(WriteTerminated::from_val(VecWriter::new(), vec![1, 0, 2])) {
+        Ok(_) => panic!("serialization unexpectedly succeeded"),
+        Err((_, err)) => err,
+    };
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    assert!(is_eof(&read_err::<ReadTerminated<CR>, _, LimitExceeded>(vec![1, 2])));
+}
+
+#[test]
+fn write_exactly_passes_exact_writes() {
+    let serializer = write_exactly::<WriteVarint<CW>, _>(ChunkedWriter::new(1), 300, 2);
+    let (writer, written) = block_on(serializer).unwrap();
+    assert_eq!(written, 2);
+    assert_eq!(writer.bytes(), [0xac, 0x02]);
+}
+
+#[test]
+#[should_panic(expected = "wrote the wrong number of bytes: expected 3, reported 2")]
+fn write_exactly_panics_on_mismatch() {
+    let _ = block_on(write_exactly::<WriteVarint<VW>, _>(VecWriter::new(), 300, 3));
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Shape {
+    Circle(u64),
+    Rect(u64, u64),
+    Empty,
+}
+
+deserialize_enum! {
+    enum ReadShape<R>: Shape {
+        tag: TagWidth::U8,
+        error: VarintError,
+        0 => Circle(radius: ReadVarint<R>),
+        1 => Rect(width: ReadVarint<R>, height: ReadVarint<R>),
+        7 => Empty,
+    }
+}
+
+#[test]
+fn deserialize_enum() {
+    let cases = vec![(vec![0, 0xac, 0x02], Shape::Circle(300)),
+                     (vec![1, 3, 4], Shape::Rect(3, 4)),
+                     (vec![7], Shape::Empty)];
+    for (bytes, expected) in cases {
+        let len = bytes.len();
+        let (reader, val, read) = block_on(ReadShape::from_reader(CR::new(bytes, 1))).unwrap();
+        assert_eq!((val, read, reader.position()), (expected, len, len));
+    }
+}
+
+#[test]
+fn deserialize_enum_errors() {
+    let (reader, err) = block_on(ReadShape::from_reader(CR::new(vec![2, 0], 1))).err().unwrap();
+    assert_eq!((data_err(err), reader.position()), (EnumError::UnknownTag(2), 1));
+
+    let mut bytes = vec![1, 3];
+    bytes.extend_from_slice(&[0xff; 11]);
+    assert_eq!(data_err(read_err::<ReadShape<CR>, _, _>(bytes)),
+               EnumError::Field(VarintError::Overflow));
+    assert!(is_eof(&read_err::<ReadShape<CR>, _, _>(vec![1, 3])));
+    assert!(is_eof(&read_err::<ReadShape<CR>, _, _>(vec![])));
+}
+
+#[test]
+fn offset_reader() {
+    let bytes = vec![0xac, 0x02, 7, 0xff, 0xff];
+    let reader = OffsetReader::new(CR::new(bytes.clone(), 1));
+    let (reader, val, _) = block_on(ReadVarint::from_reader(reader)).unwrap();
+    assert_eq!((val, reader.offset()), (300, 2));
+    let (reader, val, _) = block_on(ReadVarint::from_reader(reader)).unwrap();
+    assert_eq!((val, reader.offset()), (7, 3));
+
+    // The offset tells where the stream ended in the middle of a value.
+    let (reader, err) = block_on(ReadVarint::from_reader(reader)).err().unwrap();
+    assert!(is_eof(&err));
+    let (inner, offset) = reader.into_inner();
+    assert_eq!((offset, inner.position()), (5, 5));
+
+    let reader = OffsetReader::starting_at(CR::new(bytes, 2), 100);
+    let (reader, _, _) = block_on(ReadVarint::from_reader(reader)).unwrap();
+    assert_eq!((reader.offset(), reader.get_ref().position()), (102, 2));
+}
+
+#[test]
+fn map_reader() {
+    // E.g. limit the reader to a body whose length the header announced.
+    let read = |bytes: Vec<u8>| {
+        let header = ReadVarint::from_reader(ChunkedReader::new(bytes, 1));
+        block_on(header.map_reader(|reader| TakeReader::new(reader, 5)))
+    };
+
+    let (reader, val, read_bytes) = read(vec![0xac, 0x02, 7]).unwrap();
+    assert_eq!((val, read_bytes), (300, 2));
+    assert_eq!((reader.limit(), reader.get_ref().position()), (5, 2));
+
+    // The reader is mapped on errors as well.
+    match read(vec![0xff; 11]) {
+        Err((reader, err)) => {
+            assert_eq!(data_err(err), VarintError::Overflow);
+            assert_eq!((reader.limit(), reader.get_ref().position()), (5, 10));
+        }
+        Ok(_) => panic!("expected the varint to overflow"),
+    }
+    match read(vec![0x80]) {
+        Err((reader, err)) => {
+            assert!(is_eof(&err));
+            assert_eq!((reader.limit(), reader.into_inner().position()), (5, 1));
+        }
+        Ok(_) => panic!("expected the reader to end"),
+    }
+}
+
+#[test]
+fn map_reader_error() {
+    let eof_as_zero = |err: FutIoErr| {
+        if err.kind() == ErrorKind::UnexpectedEof {
+            Ok(0)
+        } else {
+            Err(err.kind())
+        }
+    };
+    let read = |bytes: Vec<u8>| {
+        let reader = ChunkedReader::new(bytes, 1);
+        block_on(ReadVarint::from_reader(reader).map_reader_error(eof_as_zero))
+    };
+
+    assert_eq!(read(vec![0xac, 0x02]).unwrap().1, 300);
+    let (_, val, read_bytes) = read(vec![0x80]).unwrap();
+    assert_eq!((val, read_bytes), (0, 1));
+    assert_eq!(read(vec![]).unwrap().1, 0);
+    match read(vec![0xff; 11]) {
+        Err((_, err)) => assert_eq!(data_err(err), Either::Left(VarintError::Overflow)),
+        Ok(_) => panic!("expected the varint to overflow"),
+    }
+
+    // Reading past the size of a `SizedReader` is not an end of file.
+    let reader = SizedReader::new(ChunkedReader::new(vec![1], 1), 0);
+    match block_on(ReadVarint::from_reader(reader).map_reader_error(eof_as_zero)) {
+        Err((_, err)) => assert_eq!(data_err(err), Either::Right(ErrorKind::InvalidData)),
+        Ok(_) => panic!("expected the reader to fail"),
+    }
+}
+
+#[test]
+fn validated() {
+    let read = |bytes: Vec<u8>| {
+        let reader = ChunkedReader::new(bytes, 1);
+        block_on(ReadVarint::from_reader(reader).validate(|val: &u64| *val < 1000, "too large"))
+    };
+
+    let (reader, val, read_bytes) = read(vec![0xac, 0x02]).unwrap();
+    assert_eq!((val, read_bytes, reader.position()), (300, 2, 2));
+    match read(vec![0xe8, 0x07]) {
+        Err((reader, err)) => {
+            assert_eq!(data_err(err), Either::Right("too large"));
+            assert_eq!(reader.position(), 2);
+        }
+        Ok(_) => panic!("expected the validation to fail"),
+    }
+    match read(vec![0xff; 11]) {
+        Err((_, err)) => assert_eq!(data_err(err), Either::Left(VarintError::Overflow)),
+        Ok(_) => panic!("expected the varint to overflow"),
+    }
+    assert!(is_eof(&read(vec![0x80]).err().unwrap().1));
+}
+
+#[test]
+fn converted() {
+    let waker = Waker::from(Arc::new(CountWakes(AtomicUsize::new(0))));
+    let mut map = LocalMap::new();
+    let mut cx = Context::without_spawn(&mut map, &waker);
+    let to_u8 = |val: u64| if val < 256 { Ok(val as u8) } else { Err(val) };
+
+    let mut convert = ReadVarint::from_reader(CountingReader::new(vec![0xac, 0x02, 9]))
+        .convert(to_u8);
+    match poll_done(&mut convert, &mut cx) {
+        Some(Err((reader, err))) => {
+            assert_eq!(data_err(err), Either::Right(300));
+            assert_eq!((reader.position(), reader.calls()), (2, 2));
+        }
+        _ => panic!("expected the conversion to fail"),
+    }
+    // The failure was detected after two bytes, and nothing more was read.
+    assert_eq!(convert.get_ref().already_read(), 2);
+
+    let convert = ReadVarint::from_reader(CountingReader::new(vec![0x7f, 9])).convert(to_u8);
+    let (reader, val, read) = block_on(convert).unwrap();
+    assert_eq!((val, read, reader.position()), (127, 1, 1));
+    let convert = ReadVarint::from_reader(ChunkedReader::new(vec![0xff; 11], 1)).convert(to_u8);
+    assert_eq!(data_err(block_on(convert).err().unwrap().1), Either::Left(VarintError::Overflow));
+}
+
+#[test]
+fn validated_serialize() {
+    let small = |val: &u64| *val < 1000;
+    let ser = ValidatedSerialize::<WriteVarint<_>, _>::new(ChunkedWriter::new(1), 300, small);
+    assert_eq!(ser.remaining_bytes(), 2);
+    let (writer, written) = block_on(ser).unwrap();
+    assert_eq!((writer.bytes(), written), (&[0xac, 0x02][..], 2));
+
+    let ser = ValidatedSerialize::<WriteVarint<_>, _>::new(VecWriter::new(), 1000, small);
+    assert_eq!((ser.already_written(), ser.remaining_bytes()), (0, 0));
+    let (writer, err) = block_on(ser).err().unwrap();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    assert!(writer.bytes().is_empty());
+}
+
+#[test]
+fn deserialize_error_eq() {
+    let err = read_err::<ReadVarint<CR>, _, _>(vec![0xff; 11]);
+    assert_eq!(err, DeserializeError::DataError(VarintError::Overflow));
+
+    // Reader errors are never equal, even if they have the same kind.
+    let eof = read_err::<ReadVarint<CR>, _, _>(vec![]);
+    assert_ne!(eof, DeserializeError::from(FutIoErr::from(ErrorKind::UnexpectedEof)));
+    assert_ne!(eof, DeserializeError::from(FutIoErr::from(ErrorKind::InvalidData)));
+    assert_ne!(eof, DeserializeError::DataError(VarintError::Overflow));
+    assert!(is_eof(&eof));
+}
+
+#[test]
+fn deserialize_error_display() {
+    let err = read_err::<ReadVarint<CR>, _, _>(vec![0xff; 11]);
+    assert_eq!(format!("{:?}", err), "DataError(Overflow)");
+    assert_eq!(err.into_string(), "Deserialize data error: Varint overflows a u64");
+
+    let err = DeserializeError::<VarintError>::from(FutIoErr::new(ErrorKind::UnexpectedEof, "eof"));
+    assert_eq!(format!("{:?}", err), "ReaderError { kind: UnexpectedEof, message: eof }");
+    assert_eq!(err.into_string(), "Deserialize reader error: eof");
+}
+
+#[test]
+fn bounded() {
+    type BoundedVarint = Bounded<ReadVarint<SizedReader<CR>>, 2>;
+
+    let reader = ChunkedReader::new(vec![0xac, 0x02, 0x01], 1);
+    let (reader, val, read) = block_on(BoundedVarint::from_reader(reader)).unwrap();
+    assert_eq!((val, read), (300, 2));
+    // The limit applies to each value separately.
+    let (reader, val, _) = block_on(BoundedVarint::from_reader(reader)).unwrap();
+    assert_eq!((val, reader.position()), (1, 3));
+
+    match read_err::<BoundedVarint, _, _>(vec![0x80, 0x80, 0x01]) {
+        DeserializeError::ReaderError(err) => assert_eq!(err.kind(), ErrorKind::InvalidData),
+        DeserializeError::DataError(err) => panic!("unexpected data error {:?}", err),
+    }
+    assert!(is_eof(&read_err::<BoundedVarint, _, _>(vec![0x80])));
+}
+
+// Writes each of the chunks into a `StringWriter` with a single `poll_write`.
+struct WriteChunks<'a>(Option<StringWriter>, &'a [&'a [u8]]);
+
+impl<'a> Future for WriteChunks<'a> {
+    type Item = StringWriter;
+    type Error = (StringWriter, FutIoErr);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<StringWriter, (StringWriter, FutIoErr)> {
+        let mut writer = self.0.take().unwrap();
+        for chunk in self.1 {
+            match writer.poll_write(cx, chunk) {
+                Ok(Async::Ready(written)) => assert_eq!(written, chunk.len()),
+                Ok(Async::Pending) => panic!("StringWriter is always ready"),
+                Err(err) => return Err((writer, err)),
+            }
+        }
+        Ok(Async::Ready(writer))
+    }
+}
+
+#[test]
+fn string_writer() {
+    assert_eq!(to_string::<SerHexStr<_>>(vec![0x00, 0xab, 0xff]).unwrap(), "00abff");
+    let err = to_string::<WriteCowBytes<_>>(Cow::Borrowed(&[0xff])).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+    let err = to_string::<WriteCowBytes<_>>(Cow::Borrowed(&[0xe2, 0x82])).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+    // A character split between writes.
+    let chunks: &[&[u8]] = &[b"a\xe2", b"\x82", b"\xacb\xf0\x9f"];
+    let writer = block_on(WriteChunks(Some(StringWriter::new()), chunks)).ok().unwrap();
+    assert_eq!((writer.as_str(), writer.incomplete()), ("a€b", &[0xf0, 0x9f][..]));
+    assert_eq!(writer.into_inner(), "a€b");
+
+    // A failed write does not change the writer.
+    let chunks: &[&[u8]] = &[b"a\xe2", b"b"];
+    let (writer, err) = block_on(WriteChunks(Some(StringWriter::new()), chunks)).err().unwrap();
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+    assert_eq!((writer.as_str(), writer.incomplete()), ("a", &[0xe2][..]));
+}
+
+#[test]
+fn in_memory() {
+    let bytes = to_vec::<WritePoint<_>>(Point { x: 300, y: 1 }).unwrap();
+    assert_eq!(bytes, [0xac, 0x02, 1]);
+    assert_eq!(from_slice::<ReadPoint<_>, _, _>(&bytes).unwrap(), Point { x: 300, y: 1 });
+
+    let blob: Arc<[u8]> = Arc::from(vec![7; 1024]);
+    let bytes = to_vec::<SerArcBytes<_>>(blob.clone()).unwrap();
+    assert_eq!(bytes.len(), 1028);
+    assert_eq!(from_slice::<DeserArcBytes<_>, _, _>(&bytes).unwrap(), blob);
+
+    // Trailing bytes are ignored.
+    assert_eq!(from_slice::<ReadVarint<_>, _, _>(&[0xac, 0x02, 0xff]).unwrap(), 300);
+}
+
+#[test]
+fn in_memory_errors() {
+    match from_slice::<ReadPoint<_>, _, _>(&[0xac, 0x02]) {
+        Err(ref err) => assert!(is_eof(err)),
+        Ok(val) => panic!("deserialization unexpectedly succeeded with {:?}", val),
+    }
+    match from_slice::<DeserArcBytes<_>, _, _>(&[0, 0, 0, 3, 1, 2]) {
+        Err(ref err) => assert!(is_eof(err)),
+        Ok(val) => panic!("deserialization unexpectedly succeeded with {:?}", val),
+    }
+    let err = from_slice::<DeserAsciiChar<_>, _, _>(&[0xff]).unwrap_err();
+    assert_eq!(data_err(err), AsciiCharError::NonAscii(0xff));
+}
+
+#[test]
+fn pipeline() {
+    let mut pipeline = PipelineSerializer::<WriteVarint<CW>, _>::new(ChunkedWriter::new(1));
+    for val in &[1, 300, u64::MAX] {
+        pipeline.enqueue(*val);
+    }
+    assert_eq!(pipeline.queued(), 3);
+
+    let (writer, written) = block_on(pipeline).unwrap();
+    let expected: Vec<u8> = [1, 300, u64::MAX]
+        .iter()
+        .flat_map(|val| write::<WriteVarint<VW>>(*val))
+        .collect();
+    assert_eq!(writer.bytes(), &expected[..]);
+    assert_eq!(written, expected.len());
+
+    let mut pipeline = PipelineSerializer::<WriteVarint<CW>, _>::new(ChunkedWriter::new(1));
+    for val in &[300, 1, 2] {
+        pipeline.enqueue(*val);
+    }
+    let pipeline = abandon(pipeline, 3);
+    assert_eq!((pipeline.already_written(), pipeline.queued()), (2, 1));
+
+    let pipeline = PipelineSerializer::<WriteVarint<VW>, _>::new(VecWriter::new());
+    let (writer, written) = block_on(pipeline).unwrap();
+    assert_eq!((writer.into_inner(), written), (vec![], 0));
+
+    let mut pipeline = PipelineSerializer::<WriteVarint<QW>, _>::new(QW::new(VecWriter::new(), 2));
+    pipeline.enqueue(1);
+    pipeline.enqueue(300);
+    let (_, err) = block_on(pipeline).err().unwrap();
+    assert_eq!(err.kind(), ErrorKind::WriteZero);
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Point {
+    x: u64,
+    y: u64,
+}
+
+compose_serialize! {
+    struct WritePoint<W>: Point {
+        x: WriteVarint<W>,
+        y: WriteVarint<W>,
+    }
+}
+
+compose_deserialize! {
+    struct ReadPoint<R>: Point {
+        error: VarintError,
+        x: ReadVarint<R>,
+        y: ReadVarint<R>,
+    }
+}
+
+#[test]
+fn compose() {
+    for &(x, y) in &[(0, 0), (1, 300), (u64::MAX, 7)] {
+        assert_roundtrip::<WritePoint<VW>, ReadPoint<CR>, _, _>(Point { x, y });
+    }
+
+    let expected = [write::<WriteVarint<VW>>(300), write::<WriteVarint<VW>>(1)].concat();
+    assert_eq!(write::<WritePoint<VW>>(Point { x: 300, y: 1 }), expected);
+    assert_chunked_write::<WritePoint<CW>>(Point { x: 300, y: 1 }, &expected);
+
+    assert!(is_eof(&read_err::<ReadPoint<CR>, _, _>(vec![0xac, 0x02])));
+    let reader = abandon(ReadPoint::from_reader(ChunkedReader::new(expected, 1)), 3);
+    assert_eq!(reader.already_read(), 2);
+    assert_eq!(reader.restore().unwrap().position(), 2);
+}
+
+impl SchemaId for Point {
+    const SCHEMA_ID: u64 = 0x0102_0304_0506_0708;
+}
+
+type WriteIdPoint<W> = WriteWithSchemaId<WritePoint<W>, W>;
+type ReadIdPoint<R> = ReadWithSchemaId<ReadPoint<R>, R, Point, VarintError>;
+
+#[test]
+fn schema_id() {
+    for &(x, y) in &[(0, 0), (1, 300), (u64::MAX, 7)] {
+        assert_roundtrip::<WriteIdPoint<VW>, ReadIdPoint<CR>, _, _>(Point { x, y });
+    }
+
+    let point = Point { x: 300, y: 1 };
+    let expected = [vec![1, 2, 3, 4, 5, 6, 7, 8], write::<WritePoint<VW>>(point.clone())].concat();
+    assert_eq!(write::<WriteIdPoint<VW>>(point.clone()), expected);
+    assert_chunked_write::<WriteIdPoint<CW>>(point, &expected);
+}
+
+#[test]
+fn schema_id_errors() {
+    // A different id fails before the value is read.
+    let reader = CR::new(vec![1, 2, 3, 4, 5, 6, 7, 9, 0, 0], 1);
+    let (reader, err) = block_on(ReadIdPoint::from_reader(reader)).err().unwrap();
+    assert_eq!(data_err(err),
+               SchemaError::SchemaMismatch {
+                   expected: Point::SCHEMA_ID,
+                   found: 0x0102_0304_0506_0709,
+               });
+    assert_eq!(reader.position(), 8);
+
+    // Data errors of the value are wrapped.
+    let bytes = vec![1, 2, 3, 4, 5, 6, 7, 8, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80,
+                     0x80, 0x01];
+    assert!(matches!(data_err(read_err::<ReadIdPoint<CR>, _, _>(bytes)), SchemaError::Inner(_)));
+
+    assert!(is_eof(&read_err::<ReadIdPoint<CR>, _, _>(vec![1, 2, 3, 4])));
+    assert!(is_eof(&read_err::<ReadIdPoint<CR>, _, _>(vec![1, 2, 3, 4, 5, 6, 7, 8, 1])));
+    assert_eq!(write_err::<WriteIdPoint<QW>>(Point { x: 1, y: 2 }, 9).kind(),
+               ErrorKind::WriteZero);
+}
+
+// Polls a future the given number of times and then yields it, panicking if it resolves earlier.
+struct Abandon<F> {
+    fut: Option<F>,
+    polls: usize,
+}
+
+impl<F: Future> Future for Abandon<F> {
+    type Item = F;
+    type Error = F::Error;
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<F, F::Error> {
+        if self.polls == 0 {
+            return Ok(Async::Ready(self.fut.take().unwrap()));
+        }
+        self.polls -= 1;
+        match self.fut.as_mut().unwrap().poll(cx)? {
+            Async::Ready(_) => panic!("Resolved before being abandoned"),
+            Async::Pending => Ok(Async::Pending),
+        }
+    }
+}
+
+fn abandon<F: Future>(fut: F, polls: usize) -> F {
+    let abandon = Abandon {
+        fut: Some(fut),
+        polls,
+    };
+    block_on(abandon).ok().unwrap()
+}
+
+#[test]
+fn restorable() {
+    let reader = CR::new(vec![0xac, 0x02], 1);
+    let varint = abandon(ReadVarint::from_reader(reader), 2);
+    assert_eq!(varint.already_read(), 1);
+    assert_eq!(varint.restore().unwrap().position(), 1);
+
+    let reader = CR::new(vec![SOME, 0xac, 0x02], 1);
+    let option = abandon(DeserOption::<ReadVarint<CR>, _, _, _>::from_reader(reader), 3);
+    assert_eq!(option.already_read(), 2);
+    assert_eq!(option.restore().unwrap().position(), 2);
+
+    let reader = CR::new(vec![V6, 0, 0], 1);
+    let addr = abandon(DeserIpAddr::from_reader(reader), 3);
+    assert_eq!(addr.already_read(), 2);
+    assert_eq!(addr.restore().unwrap().position(), 2);
+
+    let path = DeserPath::from_reader(CR::new(vec![0, 0, 0, 1, b'a'], 1));
+    assert_eq!(path.restore().unwrap().position(), 0);
+
+    // Wrapping deserializers give back the reader they wrap, both while reading the body and
+    // while reading what comes after it.
+    type ReadRecord = ReadLoggedRecord<ReadVarint<CrcReader<OffsetReader<CR>>>, CR, u64>;
+    let record = write::<WriteLoggedRecord<WriteVarint<CrcWriter<VW>>, VW>>(300);
+    for &polls in &[2, 5] {
+        let read_record = abandon(ReadRecord::from_reader(CR::new(record.clone(), 1)), polls);
+        let read = read_record.already_read();
+        assert_eq!(read_record.restore().unwrap().position(), read);
+    }
+    assert_eq!(abandon(ReadRecord::from_reader(CR::new(record, 1)), 5).already_read(), 4);
+
+    type ReadRedundantVarint = ReadRedundant<ReadVarint<RedundantReader<CR>>, CR, u64>;
+    for &polls in &[2, 4] {
+        let redundant = ReadRedundantVarint::from_reader(CR::new(vec![0xac, 0x02, 0xac, 0x02], 1));
+        let redundant = abandon(redundant, polls);
+        let read = redundant.already_read();
+        assert_eq!(redundant.restore().unwrap().position(), read);
+    }
+
+    let reader = BoundaryReader::new(CR::new(vec![0xac, 0x02], 1));
+    let checker = abandon(BoundaryChecker::<ReadVarint<_>, _>::from_reader(reader), 2);
+    let reader = checker.restore().unwrap();
+    assert_eq!((reader.position(), reader.into_inner().position()), (1, 1));
+
+    let envelope = write::<Envelope<VW, 2>>(300);
+    for &polls in &[6, 16, 19] {
+        let read_envelope = ReadEnvelopeV1To2::from_reader(CR::new(envelope.clone(), 1));
+        let read_envelope = abandon(read_envelope, polls);
+        assert_eq!(read_envelope.already_read(), polls - 1);
+        assert_eq!(read_envelope.restore().unwrap().position(), polls - 1);
+    }
+
+    // The parity byte of the final block is read after the body, at position 5.
+    type ReadParity = ReadWithParity<ReadBytes<ParityReader<CR>>, CR, Vec<u8>>;
+    type Parity = WriteWithParity<WriteBytes<ParityWriter<VW>>, VW>;
+    let (writer, _) = block_on(Parity::new(VecWriter::new(), 2, vec![1, 2, 3])).unwrap();
+    let parity = writer.into_inner();
+    for &polls in &[2, 6] {
+        let parity = ReadParity::new(CR::new(parity.clone(), 1), 2);
+        assert_eq!(abandon(parity, polls).restore().unwrap().position(), polls - 1);
+    }
+
+    let reader = CR::new(vec![0xac, 0x02], 1);
+    let validated = ReadVarint::from_reader(reader).validate(|val: &u64| *val < 1000, "too large");
+    assert_eq!(abandon(validated, 2).restore().unwrap().position(), 1);
+    let to_u8 = |val: u64| if val < 256 { Ok(val as u8) } else { Err(val) };
+    let converted = ReadVarint::from_reader(CR::new(vec![0xac, 0x02], 1)).convert(to_u8);
+    assert_eq!(abandon(converted, 2).restore().unwrap().position(), 1);
+    let reader = CR::new(vec![0xac, 0x02], 1);
+    let mapped = ReadVarint::from_reader(reader).map_reader(|reader| TakeReader::new(reader, 5));
+    assert_eq!(abandon(mapped, 2).restore().unwrap().get_ref().position(), 1);
+    let reader = CR::new(vec![0xac, 0x02], 1);
+    let map_err = ReadVarint::from_reader(reader).map_reader_error(|err: FutIoErr| Err(err.kind()));
+    assert_eq!(abandon(map_err, 2).restore().unwrap().position(), 1);
+
+    let capture: CaptureOnError<ReadVarint<Capture<CR>>> =
+        capture_on_error(CR::new(vec![0xac, 0x02], 1), 2);
+    assert_eq!(abandon(capture, 2).restore().unwrap().position(), 1);
+
+    // `Buffered` gives back its `Replay`, both while reading and while waiting to retry.
+    let buffered = Buffered::<ReadVarint<_>, _>::new(CR::new(vec![0xac, 0x02], 1));
+    assert_eq!(abandon(buffered, 2).restore().unwrap().recorded(), [0xac]);
+    let reader = FailingReader::new(vec![0xac, 0x02], Some((1, ErrorKind::Interrupted)));
+    let buffered = Buffered::<ReadVarint<_>, _>::new(reader);
+    assert_eq!(abandon(buffered, 1).restore().unwrap().recorded(), [0xac]);
+
+    for &polls in &[3, 7] {
+        let fold = sum(CR::new(vec![0, 0, 0, 2, 1, 0xac, 0x02], 1), 2);
+        assert_eq!(abandon(fold, polls).restore().unwrap().position(), polls - 1);
+    }
+}
+
+#[cfg(feature = "tokio-compat")]
+#[test]
+fn restorable_delimited() {
+    for &polls in &[3, 6] {
+        let reader = CR::new(vec![0, 0, 0, 2, 0xac, 0x02], 1);
+        let codec = LengthDelimitedCodec::new();
+        let frame = codec.read_frame::<ReadVarint<TakeReader<CR>>, _, _, _>(reader);
+        assert_eq!(abandon(frame, polls).restore().unwrap().position(), polls - 1);
+    }
+}
+
+#[cfg(feature = "telemetry")]
+#[test]
+fn telemetry() {
+    let store = Arc::new(TelemetryStore::new());
+
+    let writer = TelemetryWriter::new(ChunkedWriter::new(1), &store, "varint");
+    let (writer, _) = block_on(WriteVarint::from_val(writer, 300)).unwrap();
+    assert_eq!(writer.get_ref().bytes(), [0xac, 0x02]);
+    block_on(WriteVarint::from_val(writer, u64::MAX)).unwrap();
+
+    let writer = TelemetryWriter::new(VecWriter::new(), &store, "bytes");
+    block_on(WriteBytes::from_val(writer, vec![0; 11])).unwrap();
+    let writer = TelemetryWriter::new(VecWriter::new(), &store, "string");
+    block_on(WriteString::from_val(writer, "a".repeat(11))).unwrap();
+
+    assert_eq!(store.bytes_written("varint"), 12);
+    assert_eq!(store.bytes_written("unused"), 0);
+    assert_eq!(store.report(), [("bytes", 12), ("string", 12), ("varint", 12)]);
+
+    let writer = TelemetryWriter::new(QuotaWriter::new(VecWriter::new(), 1), &store, "varint");
+    assert!(block_on(WriteVarint::from_val(writer, 300)).is_err());
+    assert_eq!(store.report()[0], ("varint", 13));
+}
+
+// The sum of all bytes of the body, as a single byte.
+fn checksum(body: &[u8]) -> Vec<u8> {
+    vec![body.iter().fold(0u8, |acc, byte| acc.wrapping_add(*byte))]
+}
+
+#[test]
+fn reserve_and_fill() {
+    let fill = ReserveAndFill::<WriteVarint<_>, _, _>::new(ChunkedWriter::new(1), 300, 1, checksum);
+    assert_eq!(fill.remaining_bytes(), 3);
+    let (writer, written) = block_on(fill).unwrap();
+    assert_eq!((writer.bytes(), written), (&[0xae, 0xac, 0x02][..], 3));
+
+    let fill = ReserveAndFill::<WriteVarint<_>, _, _>::new(ChunkedWriter::new(1), 300, 2, checksum);
+    let (writer, err) = block_on(fill).err().unwrap();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    assert_eq!(writer.bytes(), []);
+}
+
+#[test]
+fn lenient_seq() {
+    type ReadSeq = ReadLenientSeq<ReadVarint<TakeReader<CR>>, CR, u64, VarintError>;
+
+    let mut bytes = vec![4, 2, 0xac, 0x02, 1, 0x80, 3, 0x05, 0xff, 0xff, 11];
+    bytes.extend_from_slice(&[0xff; 11]);
+    let len = bytes.len();
+    let (reader, seq, read) = block_on(ReadSeq::from_reader(CR::new(bytes, 1))).unwrap();
+    assert_eq!(seq.values, [300, 5]);
+    assert_eq!(seq.errors.len(), 2);
+    assert_eq!(seq.errors[0].0, 1);
+    assert!(is_eof(&seq.errors[0].1));
+    assert_eq!(seq.errors[1], (3, DeserializeError::DataError(VarintError::Overflow)));
+    assert_eq!((read, reader.position()), (len, len));
+
+    assert!(is_eof(&read_err::<ReadSeq, _, _>(vec![2, 1, 7, 3, 0])));
+    assert!(is_eof(&read_err::<ReadSeq, _, _>(vec![])));
+}
+
+type ReadVarintFrame = ReadFramed<ReadVarint<TakeReader<CR>>, CR, u64, VarintError>;
+
+#[test]
+fn framed_roundtrip() {
+    for &val in &[0, 300, u64::MAX] {
+        assert_roundtrip::<Message<WriteVarint<VW>, VW>, ReadVarintFrame, _, _>(val);
+    }
+    assert_eq!(write::<Message<WriteVarint<VW>, VW>>(300), [2, 0xac, 0x02]);
+
+    let message = Message::<WriteVarint<_>, _>::with_width(VecWriter::new(), LengthWidth::U16, 7);
+    let (writer, _) = block_on(message).unwrap();
+    assert_eq!(writer.bytes(), [0, 1, 7]);
+    let reader = CR::new(writer.into_inner(), 1);
+    let frame = ReadVarintFrame::with_width(reader, LengthWidth::U16, Trailing::Reject);
+    assert_eq!(block_on(frame).unwrap().1, 7);
+
+    // Skipped trailing bytes still count as read.
+    let frame = ReadVarintFrame::new(CR::new(vec![3, 5, 0, 0, 9], 1), Trailing::Skip);
+    let (reader, val, read) = block_on(frame).unwrap();
+    assert_eq!((val, read, reader.position()), (5, 4, 4));
+}
+
+#[test]
+fn framed_errors() {
+    // Every data error leaves the reader directly after the frame.
+    let read_frame = |bytes: Vec<u8>| {
+        match block_on(ReadVarintFrame::from_reader(CR::new(bytes, 1))) {
+            Ok((_, val, _)) => panic!("deserialization unexpectedly succeeded with {}", val),
+            Err((reader, err)) => (data_err(err), reader.position()),
+        }
+    };
+    let trailing = FramedError::TrailingBytes {
+        declared: 3,
+        consumed: 1,
+    };
+    assert_eq!(read_frame(vec![3, 5, 0, 0, 9]), (trailing, 4));
+    assert_eq!(read_frame(vec![1, 0x80, 9]), (FramedError::OverRead { declared: 1 }, 2));
+    let mut overflow = vec![11];
+    overflow.extend_from_slice(&[0xff; 11]);
+    assert_eq!(read_frame(overflow), (FramedError::Inner(VarintError::Overflow), 12));
+
+    assert_eq!(data_err(read_err::<ReadVarintFrame, _, _>(vec![0xff; 11])),
+               FramedError::VarintOverflow);
+    assert!(is_eof(&read_err::<ReadVarintFrame, _, _>(vec![2, 0x80])));
+}
+
+// One end of a duplex connection made of two pipes.
+#[derive(Debug)]
+struct Duplex(PipeWriter, PipeReader);
+
+impl AsyncWrite for Duplex {
+    fn poll_write(&mut self, cx: &mut Context, buf: &[u8]) -> Poll<usize, FutIoErr> {
+        self.0.poll_write(cx, buf)
+    }
+
+    fn poll_flush(&mut self, cx: &mut Context) -> Poll<(), FutIoErr> {
+        self.0.poll_flush(cx)
+    }
+
+    fn poll_close(&mut self, cx: &mut Context) -> Poll<(), FutIoErr> {
+        self.0.poll_close(cx)
+    }
+}
+
+impl AsyncRead for Duplex {
+    fn poll_read(&mut self, cx: &mut Context, buf: &mut [u8]) -> Poll<usize, FutIoErr> {
+        self.1.poll_read(cx, buf)
+    }
+}
+
+fn duplex(capacity: usize) -> (Duplex, Duplex) {
+    let (a_writer, b_reader) = pipe(capacity);
+    let (b_writer, a_reader) = pipe(capacity);
+    (Duplex(a_writer, a_reader), Duplex(b_writer, b_reader))
+}
+
+#[test]
+fn framed_duplex() {
+    let (a, b) = duplex(16);
+    let mut a = Framed::new(a);
+    let mut b = Framed::new(b);
+
+    let (_, written) = block_on(a.write_frame::<WriteVarint<_>>(300)).unwrap();
+    assert_eq!((written, b.get_ref().1.buffered()), (3, 3));
+    let (_, val, read) = block_on(b.read_frame::<ReadVarint<_>, _, _>()).unwrap();
+    assert_eq!((val, read), (300, 3));
+
+    let (_, written) = block_on(b.write_frame::<WriteString<_>>("hi".to_string())).unwrap();
+    let (_, val, read) = block_on(a.read_frame::<ReadString<_>, _, _>()).unwrap();
+    assert_eq!((&val[..], read, written), ("hi", 4, 4));
+
+    // The width of the `Framed` applies in both directions.
+    let (a, b) = duplex(16);
+    let mut a = Framed::with_width(a, LengthWidth::U16, Trailing::Skip);
+    let mut b = Framed::with_width(b, LengthWidth::U16, Trailing::Skip);
+    assert_eq!(block_on(a.write_frame::<WriteVarint<_>>(300)).unwrap().1, 4);
+    assert_eq!(block_on(b.read_frame::<ReadVarint<_>, _, _>()).unwrap().1, 300);
+    assert_eq!(b.width(), LengthWidth::U16);
+}
+
+#[test]
+fn framed_duplex_errors() {
+    // An oversized frame fails without writing anything.
+    let (a, b) = duplex(16);
+    let mut a = Framed::with_width(a, LengthWidth::U8, Trailing::Reject);
+    let (_, err) = block_on(a.write_frame::<WriteBytes<_>>(vec![0; 300])).err().unwrap();
+    assert_eq!((err.kind(), b.1.buffered()), (ErrorKind::InvalidInput, 0));
+
+    // A frame that is cut off by the peer closing the connection fails with an EOF error.
+    let (mut a, b) = duplex(16);
+    block_on(WriteVarint::from_val(&mut a.0, 5)).unwrap();
+    drop(a);
+    let mut b = Framed::new(b);
+    let (_, err) = block_on(b.read_frame::<ReadVarint<_>, _, _>()).err().unwrap();
+    assert!(is_eof(&err));
+}
+
+#[test]
+fn message() {
+    let builder = Message::builder().part::<WriteVarint<_>>(300).part::<WriteBytes<_>>(vec![1, 2]);
+    assert_eq!(builder.total_bytes(), 6);
+    let (writer, written) = block_on(builder.build(ChunkedWriter::new(1))).unwrap();
+    assert_eq!((writer.bytes(), written), (&[5, 0xac, 0x02, 2, 1, 2][..], 6));
+    assert_eq!(write::<Message<NoParts<VW>, VW>>(()), [0]);
+    let val = Message::<NoParts<VW>, VW>::builder().part::<WriteVarint<_>>(1).into_val();
+    assert_eq!(val, ((), 1));
+
+    let message = Message::<WriteBytes<_>, _>::with_width(VecWriter::new(),
+                                                          LengthWidth::U16,
+                                                          vec![9]);
+    assert_eq!(block_on(message).unwrap().0.into_inner(), [0, 2, 1, 9]);
+}
+
+#[test]
+fn message_errors() {
+    type BytesMessage<W> = Message<WriteBytes<W>, W>;
+    let too_long = ValidationError::TooLong { len: 258, max: 255 };
+    assert_eq!(BytesMessage::<VW>::validate_with_width(LengthWidth::U8, &vec![0; 256]),
+               Err(too_long));
+    assert_eq!(BytesMessage::<VW>::validate_with_width(LengthWidth::U8, &vec![0; 253]), Ok(()));
+    let message = BytesMessage::with_width(VecWriter::new(), LengthWidth::U8, vec![0; 256]);
+    let (writer, err) = block_on(message).err().unwrap();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    assert!(writer.bytes().is_empty());
+
+    assert_eq!(write_err::<BytesMessage<QW>>(vec![1, 2], 2).kind(), ErrorKind::WriteZero);
+}
+
+#[test]
+fn min_write_size() {
+    let mut writer = MinWriteSize::new(SizesWriter::new(usize::MAX), 4);
+    for val in 1..6 {
+        writer = block_on(WriteVarint::from_val(writer, val)).unwrap().0;
+    }
+    assert_eq!((writer.get_ref().sizes.as_slice(), writer.pending()), (&[4][..], 1));
+    let writer = flush(writer).ok().unwrap();
+    assert_eq!(writer.get_ref().sizes, [4, 1]);
+    assert_eq!(writer.get_ref().data, [1, 2, 3, 4, 5]);
+
+    // Long writes are forwarded directly.
+    let writer = MinWriteSize::new(SizesWriter::new(usize::MAX), 4);
+    let writer = block_on(WriteFixed64::from_val(writer, 0)).unwrap().0;
+    assert_eq!((writer.get_ref().sizes.as_slice(), writer.pending()), (&[8][..], 0));
+
+    // Partial writes of the wrapped writer are completed by later writes.
+    let writer = MinWriteSize::new(SizesWriter::new(3), 4);
+    let (writer, _) = block_on(WriteBytes::from_val(writer, vec![7; 5])).unwrap();
+    let writer = flush(writer).ok().unwrap();
+    assert_eq!(writer.get_ref().sizes, [3, 1, 2]);
+    assert_eq!(writer.into_inner().data, [5, 7, 7, 7, 7, 7]);
+
+    let writer = MinWriteSize::new(QuotaWriter::new(VecWriter::new(), 2), 4);
+    let (writer, _) = block_on(WriteFixed32::from_val(writer, 0)).unwrap();
+    let (writer, err) = flush(writer).err().unwrap();
+    assert_eq!(err.kind(), ErrorKind::WriteZero);
+    assert_eq!(writer.pending(), 2);
+}
+
+#[test]
+fn min_write_size_adaptive() {
+    let config = Adaptive {
+        min: 1,
+        max: 1024,
+        window: 4,
+        interval: 2,
+    };
+
+    // Starting far too high or too low, the threshold settles near the 7 bytes the wrapped writer
+    // accepts per write, only probing for more by doubling once in a while.
+    for &initial in &[1, 512] {
+        let mut writer = MinWriteSize::adaptive(CW::new(7), initial, config);
+        let mut thresholds = Vec::new();
+        for i in 0..50 {
+            writer = block_on(WriteBytes::from_val(writer, vec![i; 100])).unwrap().0;
+            thresholds.push(writer.threshold());
+        }
+        assert!(thresholds[10..].iter().all(|threshold| (7..=14).contains(threshold)),
+                "{:?}",
+                thresholds);
+
+        let bytes = flush(writer).ok().unwrap().into_inner().into_inner();
+        assert_eq!(bytes.len(), 50 * 101);
+        for (i, msg) in bytes.chunks(101).enumerate() {
+            assert_eq!((msg[0], &msg[1..]), (100, &[i as u8; 100][..]));
+        }
+    }
+}
+
+// Counts how often the task was woken.
+struct CountWakes(AtomicUsize);
+
+impl Wake for CountWakes {
+    fn wake(arc_self: &Arc<CountWakes>) {
+        arc_self.0.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+#[test]
+fn poll_budget() {
+    let bytes = write::<WriteBytes<VW>>(vec![7; 1000]);
+    let len = bytes.len();
+
+    let wakes = Arc::new(CountWakes(AtomicUsize::new(0)));
+    let waker = Waker::from(wakes.clone());
+    let mut map = LocalMap::new();
+    let mut cx = Context::without_spawn(&mut map, &waker);
+
+    // A minimal executor that alternates between the deserializer and another task, which only
+    // counts how often it gets to run.
+    let mut read_bytes = ReadBytes::with_poll_budget(CountingReader::new(bytes.clone()), 64);
+    let mut other_task_polls = 0;
+    let (reader, val, read) = loop {
+        match read_bytes.poll(&mut cx) {
+            Ok(Async::Ready(done)) => break done,
+            Ok(Async::Pending) => {
+                assert_eq!(read_bytes.already_read(), 64 * (other_task_polls + 1));
+                other_task_polls += 1;
+            }
+            Err((_, err)) => panic!("deserialization failed: {:?}", err),
+        }
+    };
+    assert_eq!((val, read, reader.into_inner().position()), (vec![7; 1000], len, len));
+    assert_eq!(other_task_polls, len / 64);
+    assert_eq!(wakes.0.load(Ordering::SeqCst), other_task_polls);
+
+    // Without a budget, the deserializer does not yield.
+    let mut read_bytes = ReadBytes::from_reader(CountingReader::new(bytes));
+    assert!(read_bytes.poll(&mut cx).ok().unwrap().is_ready());
+
+    let mut read_bytes = ReadBytes::with_poll_budget(CountingReader::new(vec![200, 1, 0]), 2);
+    assert!(read_bytes.poll(&mut cx).ok().unwrap().is_pending());
+    assert!(is_eof(&block_on(read_bytes).err().unwrap().1));
+}
+
+#[test]
+fn named() {
+    let mut named = Named::new("len", WriteVarint::from_val(ChunkedWriter::new(1), 300));
+    if cfg!(feature = "debug_names") {
+        assert_eq!(named.phase_name(), Some("len"));
+    } else {
+        assert_eq!(named.phase_name(), None);
+    }
+    assert_eq!(named.remaining_bytes(), 2);
+    named = abandon(named, 2);
+    assert_eq!((named.already_written(), named.remaining_bytes()), (1, 1));
+    assert_eq!(named.get_ref().already_written(), 1);
+    let (writer, written) = block_on(named).unwrap();
+    assert_eq!((writer.bytes(), written), (&[0xac, 0x02][..], 2));
+
+    let writer = QuotaWriter::new(VecWriter::new(), 1);
+    let named = Named::new("len", WriteVarint::from_val(writer, 300));
+    let (writer, err) = block_on(named).err().unwrap();
+    assert_eq!((writer.into_inner().into_inner(), err.kind()), (vec![0xac], ErrorKind::WriteZero));
+}
+
+#[test]
+fn cancellable() {
+    let flag = Arc::new(AtomicBool::new(false));
+    let writer = Cancellable::new(VecWriter::new(), flag.clone());
+    let (writer, _) = block_on(WriteVarint::from_val(writer, 300)).unwrap();
+    assert_eq!(writer.into_inner().into_inner(), [0xac, 0x02]);
+
+    flag.store(true, Ordering::SeqCst);
+    let writer = Cancellable::new(VecWriter::new(), flag.clone());
+    let (writer, err) = block_on(WriteVarint::from_val(writer, 300)).err().unwrap();
+    assert!(writer.is_cancelled() && Cancelled::is_cancelled(&err));
+    assert!(writer.into_inner().into_inner().is_empty());
+
+    // Cancelling a token wakes up the reader blocked on it, and the next read fails.
+    let token = CancellationToken::new();
+    let reader = Cancellable::with_token(CR::new(vec![0xac, 0x02], 1), token.clone());
+    let mut varint = abandon(ReadVarint::from_reader(reader), 1);
+    let wakes = Arc::new(CountWakes(AtomicUsize::new(0)));
+    let waker = Waker::from(wakes.clone());
+    let mut map = LocalMap::new();
+    let mut cx = Context::without_spawn(&mut map, &waker);
+    assert!(varint.poll(&mut cx).ok().unwrap().is_pending());
+    let woken_by_reader = wakes.0.load(Ordering::SeqCst);
+    token.cancel();
+    assert_eq!(wakes.0.load(Ordering::SeqCst), woken_by_reader + 1);
+    let (reader, err) = varint.poll(&mut cx).err().unwrap();
+    match err {
+        DeserializeError::ReaderError(err) => assert!(Cancelled::is_cancelled(&err)),
+        DeserializeError::DataError(err) => panic!("unexpected data error {:?}", err),
+    }
+    assert_eq!(reader.into_inner().position(), 1);
+}
+
+type Done<F> = Result<<F as Future>::Item, <F as Future>::Error>;
+
+// Poll `a` and `b` in turns until both have completed.
+fn join<A: Future, B: Future>(mut a: A, mut b: B) -> (Done<A>, Done<B>) {
+    let waker = Waker::from(Arc::new(CountWakes(AtomicUsize::new(0))));
+    let mut map = LocalMap::new();
+    let mut cx = Context::without_spawn(&mut map, &waker);
+    let (mut a_done, mut b_done) = (None, None);
+    loop {
+        if a_done.is_none() {
+            a_done = poll_done(&mut a, &mut cx);
+        }
+        if b_done.is_none() {
+            b_done = poll_done(&mut b, &mut cx);
+        }
+        if let (&Some(_), &Some(_)) = (&a_done, &b_done) {
+            return (a_done.unwrap(), b_done.unwrap());
+        }
+    }
+}
+
+fn poll_done<F: Future>(fut: &mut F, cx: &mut Context) -> Option<Done<F>> {
+    match fut.poll(cx) {
+        Ok(Async::Ready(item)) => Some(Ok(item)),
+        Ok(Async::Pending) => None,
+        Err(err) => Some(Err(err)),
+    }
+}
+
+// Two sessions connected by pipes of the given capacity.
+fn sessions(capacity: usize)
+            -> (Session<PipeReader, PipeWriter>, Session<PipeReader, PipeWriter>) {
+    let (a_writer, b_reader) = pipe(capacity);
+    let (b_writer, a_reader) = pipe(capacity);
+    (Session::new(a_reader, a_writer), Session::new(b_reader, b_writer))
+}
+
+#[test]
+fn session() {
+    let (a, b) = sessions(4);
+    let (stats_a, stats_b) = (a.stats(), b.stats());
+    for round in 1..3 {
+        let send = a.send::<WriteBytes<_>>(vec![7; 100]).ok().unwrap();
+        let recv = b.recv::<ReadBytes<_>, _, _>().unwrap();
+        let (written, received) = join(send, recv);
+        let (val, read) = received.unwrap();
+        assert_eq!((val, read, written.unwrap()), (vec![7; 100], 101, 101));
+        assert_eq!((stats_a.bytes_sent(), stats_a.messages_sent()), (101 * round, round));
+        assert_eq!((stats_b.bytes_received(), stats_b.messages_received()), (101 * round, round));
+    }
+    assert_eq!((stats_a.bytes_received(), stats_b.bytes_sent()), (0, 0));
+
+    // Both directions can be in flight at once.
+    let (a_send, b_send) = (a.send::<WriteVarint<_>>(1).ok().unwrap(),
+                            b.send::<WriteVarint<_>>(2).ok().unwrap());
+    let (a_recv, b_recv) = (a.recv::<ReadVarint<_>, _, _>().unwrap(),
+                            b.recv::<ReadVarint<_>, _, _>().unwrap());
+    let (a_sent, b_received) = join(a_send, b_recv);
+    let (b_sent, a_received) = join(b_send, a_recv);
+    assert_eq!((a_sent.unwrap(), b_received.unwrap()), (1, (1, 1)));
+    assert_eq!((b_sent.unwrap(), a_received.unwrap()), (1, (2, 1)));
+
+    let (a_reader, _) = block_on(a.shutdown().ok().unwrap()).ok().unwrap();
+    let recv = b.recv::<ReadVarint<_>, _, _>().unwrap();
+    assert!(is_eof(&block_on(recv).err().unwrap()));
+    drop(a_reader);
+    let send = b.send::<WriteVarint<_>>(3).ok().unwrap();
+    assert_eq!(block_on(send).err().unwrap().kind(), ErrorKind::BrokenPipe);
+    assert!(b.shutdown().is_ok());
+}
+
+#[test]
+fn session_in_flight() {
+    let (a, b) = sessions(4);
+    let stats = a.stats();
+    let send = abandon(a.send::<WriteBytes<_>>(vec![7; 100]).ok().unwrap(), 1);
+    assert_eq!(stats.bytes_sent(), 4);
+    assert_eq!(a.send::<WriteVarint<_>>(5).err(), Some(5));
+    let a = a.shutdown().err().unwrap();
+
+    let recv = b.recv::<ReadBytes<_>, _, _>().unwrap();
+    assert!(b.recv::<ReadBytes<_>, _, _>().is_none());
+    let (written, received) = join(send, recv);
+    assert_eq!((written.unwrap(), received.unwrap().1), (101, 101));
+    assert_eq!(stats.messages_sent(), 1);
+    assert!(a.send::<WriteVarint<_>>(5).is_ok());
+
+    // A dropped operation takes its half with it.
+    assert!(a.recv::<ReadVarint<_>, _, _>().is_some());
+    assert!(a.recv::<ReadVarint<_>, _, _>().is_none());
+}
+
+type CanonicalVarint = Canonical<ReadVarint<Recording<CR>>, WriteVarint<Comparison>, CR, u64>;
+
+#[test]
+fn canonical() {
+    for &val in &[0, 127, 300, u64::MAX] {
+        assert_roundtrip::<WriteVarint<VW>, CanonicalVarint, _, _>(val);
+    }
+
+    // A zero with a redundant continuation byte decodes fine, but is not canonical.
+    let non_canonical = CanonicalError::NonCanonical { first_mismatch_offset: 0 };
+    assert_eq!(data_err(read_err::<CanonicalVarint, _, _>(vec![0x80, 0x00])), non_canonical);
+    let non_canonical = CanonicalError::NonCanonical { first_mismatch_offset: 1 };
+    assert_eq!(data_err(read_err::<CanonicalVarint, _, _>(vec![0xac, 0x82, 0x00])),
+               non_canonical);
+    assert_eq!(data_err(read_err::<CanonicalVarint, _, _>(vec![0xff; 11])),
+               CanonicalError::Inner(VarintError::Overflow));
+    assert!(is_eof(&read_err::<CanonicalVarint, _, _>(vec![0x80])));
+
+    let canonical = abandon(CanonicalVarint::from_reader(CR::new(vec![0xac, 0x02], 1)), 1);
+    assert_eq!(canonical.restore().unwrap().position(), 0);
+    let canonical = abandon(CanonicalVarint::from_reader(CR::new(vec![0xac, 0x02], 1)), 2);
+    assert_eq!(canonical.restore().unwrap().position(), 1);
+}
+
+type EagerLen<W> = EagerHeader<W, WriteVarint<W>, WriteBytes<W>>;
+
+#[test]
+fn eager_header() {
+    assert_eq!(write::<EagerLen<VW>>((3, vec![1])), [3, 1, 1]);
+    assert_chunked_write::<EagerLen<CW>>((3, vec![1]), &[3, 1, 1]);
+
+    // The receiver can decode the header while the payload does not fit into the pipe yet.
+    let (writer, reader) = pipe(8);
+    let eager = abandon(EagerLen::from_val(writer, (101, vec![7; 100])), 1);
+    assert_eq!(eager.already_written(), 8);
+    let (reader, len, _) = block_on(ReadVarint::from_reader(reader)).unwrap();
+    assert_eq!(len, 101);
+    let (written, received) = join(eager, ReadBytes::from_reader(reader));
+    assert_eq!((written.unwrap().1, received.unwrap().1), (102, vec![7; 100]));
+
+    let header_err = |quota| {
+        let err = write_err::<EagerLen<QW>>((3, vec![1]), quota);
+        assert_eq!(err.kind(), ErrorKind::WriteZero);
+        err.get_ref().map(|inner| inner.is::<HeaderError>()).unwrap_or(false)
+    };
+    assert!(header_err(0));
+    assert!(!header_err(1));
+}
+
+type ReadRuns = DeserRLE<ReadVarint<CR>, CR, u64, VarintError>;
+
+#[test]
+fn run_length_roundtrip() {
+    let runs: [&[(u64, u16)]; 4] = [&[], &[(0, 4), (1, 2)], &[(300, 1), (300, 0)], &[(5, 1000)]];
+    let expanded: [&[u64]; 4] = [&[], &[0, 0, 0, 0, 1, 1], &[300], &[5; 1000]];
+    for (&runs, &expanded) in runs.iter().zip(&expanded) {
+        let ser = SerRLE::<WriteVarint<_>, _>::from_ref(VecWriter::new(), runs);
+        let (writer, written) = block_on(ser).unwrap();
+        let bytes = writer.into_inner();
+        assert_eq!(written, bytes.len());
+        assert_eq!(SerRLE::<WriteVarint<VW>, VW>::total_bytes(runs), bytes.len());
+
+        let chunked = SerRLE::<WriteVarint<_>, _>::from_ref(ChunkedWriter::new(1), runs);
+        let (chunked, _) = block_on(chunked).unwrap();
+        assert_eq!(chunked.bytes(), &bytes[..]);
+
+        let (_, val, read) = block_on(ReadRuns::from_reader(ChunkedReader::new(bytes, 1))).unwrap();
+        assert_eq!((&val[..], read), (expanded, written));
+    }
+
+    let ser = SerRLE::<WriteVarint<_>, _>::from_ref(VecWriter::new(), &[(0, 4), (1, 2)]);
+    assert_eq!(block_on(ser).unwrap().0.into_inner(), [2, 0, 4, 0, 0, 2, 1]);
+}
+
+#[test]
+fn run_length_errors() {
+    let err = read_err::<ReadRuns, _, _>(vec![0xff; 11]);
+    assert_eq!(data_err(err), RLEError::VarintOverflow);
+    let mut overflow = vec![1, 0, 1];
+    overflow.extend_from_slice(&[0xff; 11]);
+    assert_eq!(data_err(read_err::<ReadRuns, _, _>(overflow)),
+               RLEError::Inner(VarintError::Overflow));
+    assert!(is_eof(&read_err::<ReadRuns, _, _>(vec![2, 0, 4, 0])));
+    assert!(is_eof(&read_err::<ReadRuns, _, _>(vec![1, 0])));
+}
+
+#[test]
+fn capture() {
+    let capture = Capture::new(CR::new(vec![3, 1, 2, 3, 9], 2), 2);
+    let (capture, val, _) = block_on(ReadBytes::from_reader(capture)).unwrap();
+    assert_eq!((val, capture.captured(), capture.position()), (vec![1, 2, 3], vec![2, 3], 4));
+    assert_eq!(capture.into_inner().position(), 4);
+
+    type ReadChars<R> = DeserRLE<DeserAsciiChar<R>, R, char, AsciiCharError>;
+    let read_chars = |bytes: Vec<u8>| {
+        let reader = CR::new(bytes, 1);
+        let capture: CaptureOnError<ReadChars<Capture<CR>>> = capture_on_error(reader, 2);
+        block_on(capture)
+    };
+    let (reader, val, read) = read_chars(vec![1, 0, 2, b'a']).unwrap();
+    assert_eq!((val, read, reader.position()), (vec!['a', 'a'], 4, 4));
+
+    let (reader, err) = read_chars(vec![1, 0, 3, 0x80, 9]).err().unwrap();
+    let captured = Captured {
+        error: RLEError::Inner(AsciiCharError::NonAscii(0x80)),
+        bytes: vec![3, 0x80],
+        position: 4,
+    };
+    assert_eq!((data_err(err), reader.position()), (captured, 4));
+    assert_eq!(format!("{}", data_err(read_chars(vec![1, 0, 3, 0x80]).err().unwrap().1)),
+               "Non-ASCII byte 0x80 at offset 4, preceded by [03 80]");
+    assert!(is_eof(&read_chars(vec![1, 0]).err().unwrap().1));
+}
+
+#[test]
+fn short_circuit() {
+    let writer = ShortCircuitWriter::new(QuotaWriter::new(VecWriter::new(), 3));
+    let (writer, _) = block_on(WriteVarint::from_val(writer, 300)).unwrap();
+    assert!(!writer.is_failed());
+    let (mut writer, err) = block_on(WriteBytes::from_val(writer, vec![1, 2, 3])).err().unwrap();
+    assert!(writer.is_failed());
+    assert_eq!(err.kind(), ErrorKind::WriteZero);
+
+    // Later writes fail with the same error, even though the wrapped writer would accept them.
+    writer.get_mut().set_quota(100);
+    let (writer, later) = block_on(WriteVarint::from_val(writer, 1)).err().unwrap();
+    assert_eq!((later.kind(), later.to_string()), (err.kind(), err.to_string()));
+    let (mut writer, flush_err) = flush(writer).err().unwrap();
+    assert_eq!(flush_err.kind(), ErrorKind::WriteZero);
+    assert_eq!(writer.take_error().unwrap().to_string(), err.to_string());
+    assert!(writer.take_error().is_none() && writer.is_failed());
+    assert_eq!(writer.into_inner().into_inner().into_inner(), [0xac, 0x02, 3]);
+}
+
+#[test]
+fn ring_writer() {
+    let ring = RingWriter::<4>::new();
+    assert!(ring.is_empty());
+    let (ring, _) = block_on(WriteVarint::from_val(ring, 300)).unwrap();
+    assert_eq!((ring.contents(), ring.len(), ring.position()), (vec![0xac, 0x02], 2, 2));
+
+    // Wraps around within a write, and keeps only the tail of writes longer than the buffer.
+    let (ring, written) = block_on(WriteFixed32::from_val(ring, 0x0102_0304)).unwrap();
+    assert_eq!((ring.contents(), written, ring.position()), (vec![4, 3, 2, 1], 4, 6));
+    let (ring, written) = block_on(WriteBytes::from_val(ring, (1..10).collect())).unwrap();
+    assert_eq!((ring.contents(), written, ring.position()), (vec![6, 7, 8, 9], 10, 16));
+    let (full, _) = block_on(WriteBytes::from_val(RingWriter::<4>::new(), vec![1; 3])).unwrap();
+    assert_eq!(full.contents(), [3, 1, 1, 1]);
+
+    // A ring of size zero accepts everything, but holds nothing.
+    let (empty, written) = block_on(WriteVarint::from_val(RingWriter::<0>::new(), 300)).unwrap();
+    assert_eq!((empty.contents(), written, empty.position()), (vec![], 2, 2));
+    assert!(empty.is_empty());
+}
+
+#[test]
+fn tlv_roundtrip() {
+    let mut bytes = write::<WriteTlv<WriteVarint<VW>, VW>>((1, 300));
+    assert_eq!(bytes, [1, 2, 0xac, 0x02]);
+    bytes.extend(write::<WriteTlv<WriteBytes<VW>, VW>>((300, vec![7; 3])));
+    assert_chunked_write::<WriteTlv<WriteBytes<CW>, CW>>((300, vec![7; 3]), &bytes[4..]);
+
+    let mut tlv = TlvReader::new(ChunkedReader::new(bytes, 1));
+    let (tag, len, field) = block_on(tlv.next_field()).unwrap().unwrap();
+    assert_eq!((tag, len), (1, 2));
+    let (field, val, _) = block_on(ReadVarint::from_reader(field)).ok().unwrap();
+    assert_eq!((val, field.remaining()), (300, 0));
+
+    // Unknown fields can be skipped.
+    let (tag, len, field) = block_on(tlv.next_field()).unwrap().unwrap();
+    assert_eq!((tag, len), (300, 4));
+    block_on(field.skip_rest()).unwrap();
+    assert!(block_on(tlv.next_field()).unwrap().is_none());
+    assert_eq!(tlv.into_inner().position(), 11);
+}
+
+#[test]
+fn tlv_errors() {
+    let mut tlv = TlvReader::new(ChunkedReader::new(vec![1, 2, 0xac, 0x02], 1));
+    let (_, _, field) = block_on(tlv.next_field()).unwrap().unwrap();
+    assert_eq!(block_on(DeserAsciiChar::allow_extended(field)).ok().unwrap().1, '\u{ac}');
+    assert_eq!(data_err(block_on(tlv.next_field()).err().unwrap()),
+               TlvError::UnfinishedField { remaining: 1 });
+    block_on(tlv.skip_rest()).unwrap();
+    assert!(block_on(tlv.next_field()).unwrap().is_none());
+
+    let next_field = |bytes: Vec<u8>| {
+        let mut tlv = TlvReader::new(ChunkedReader::new(bytes, 1));
+        block_on(tlv.next_field()).err().unwrap()
+    };
+    assert_eq!(data_err(next_field(vec![0xff; 11])), TlvError::VarintOverflow);
+    assert!(is_eof(&next_field(vec![1])));
+
+    let mut tlv = TlvReader::new(ChunkedReader::new(vec![1, 2, 0xac], 1));
+    let (_, _, field) = block_on(tlv.next_field()).unwrap().unwrap();
+    assert!(is_eof(&block_on(ReadVarint::from_reader(field)).err().unwrap().1));
+}
+
+#[test]
+fn empty_values() {
+    type KW = CountingWriter;
+    type KR = CountingReader;
+    type ReadUnit = ReadFramed<NoFields<TakeReader<KR>, VarintError>, KR, (), VarintError>;
+
+    check_empty_value_behavior::<WriteVarint<KW>, ReadVarint<KR>, _, _>(0);
+    check_empty_value_behavior::<WriteFixed32<KW>, ReadFixed32<KR>, _, _>(0);
+    check_empty_value_behavior::<WriteFixed64<KW>, ReadFixed64<KR>, _, _>(0);
+    check_empty_value_behavior::<WriteBytes<KW>, ReadBytes<KR>, _, _>(vec![]);
+    check_empty_value_behavior::<WriteString<KW>, ReadString<KR>, _, _>(String::new());
+    check_empty_value_behavior::<SerArcBytes<KW>, DeserArcBytes<KR>, _, _>(Arc::from(&[][..]));
+    check_empty_value_behavior::<SerPathBuf<KW>, DeserPath<KR>, _, _>(PathBuf::new());
+    check_empty_value_behavior::<WriteTerminated<KW>, ReadTerminated<KR>, _, _>(vec![]);
+    check_empty_value_behavior::<Message<NoParts<KW>, KW>, ReadUnit, _, _>(());
+
+    check_empty_value_behavior::<SerOsString<KW>, DeserOsString<KR>, _, _>(OsString::new());
+    check_empty_value_behavior::<SerRange<KW>, DeserRange<KR>, _, _>(0..0);
+    check_empty_value_behavior::<SerRange<KW>, DeserRange<KR>, _, _>(5..5);
+
+    check_empty_ref_value_behavior::<WriteBitset<KW>, ReadBitset<KR>, Vec<bool>, _>(&[]);
+
+    // The remaining pairs need more than a value or a reader, or their values are not of the same
+    // type on both sides.
+    type ReadOption = DeserOption<DeserArcBytes<KR>, KR, Arc<[u8]>, ArcBytesError>;
+    let ser = SerOptionRef::<SerCowBytes<KW>, _>::from_ref(KW::new(), &None);
+    let total = SerOptionRef::<SerCowBytes<KW>, KW>::total_bytes(&None);
+    let de = ReadOption::from_reader;
+    assert_eq!(check_empty_behavior_with(ser, total, de, ReadOption::already_read), None);
+
+    type ReadList = DeserLinkedList<DeserArcBytes<KR>, KR, Arc<[u8]>, ArcBytesError>;
+    let list = LinkedList::new();
+    let ser = SerList::from_ref(KW::new(), &list);
+    let total = SerList::<KW>::total_bytes(&list);
+    let val = check_empty_behavior_with(ser, total, ReadList::from_reader, ReadList::already_read);
+    assert!(val.is_empty());
+    type ReadRuns = DeserRLE<ReadVarint<KR>, KR, u64, VarintError>;
+    let ser = SerRLE::<WriteVarint<KW>, _>::from_ref(KW::new(), &[]);
+    let total = SerRLE::<WriteVarint<KW>, KW>::total_bytes(&[]);
+    let val = check_empty_behavior_with(ser, total, ReadRuns::from_reader, ReadRuns::already_read);
+    assert_eq!(val, []);
+
+    let cow = Cow::Borrowed(&[][..]);
+    let ser = SerCowBytes::from_ref(KW::new(), &cow);
+    let total = SerCowBytes::<KW>::total_bytes(&cow);
+    let de = DeserArcBytes::from_reader;
+    let val = check_empty_behavior_with(ser, total, de, DeserArcBytes::already_read);
+    assert_eq!(&val[..], &cow[..]);
+    let cow = Cow::Borrowed("");
+    let ser = SerCowStr::from_ref(KW::new(), &cow);
+    let total = SerCowStr::<KW>::total_bytes(&cow);
+    let de = DeserArcBytes::from_reader;
+    let val = check_empty_behavior_with(ser, total, de, DeserArcBytes::already_read);
+    assert_eq!(&val[..], cow.as_bytes());
+
+    let ser = SerHexStr::from_val(KW::new(), vec![]);
+    let de = |reader| DeserHexStr::new(reader, 0);
+    let val = check_empty_behavior_with(ser, 0, de, DeserHexStr::already_read);
+    assert_eq!(val, []);
+
+    #[cfg(feature = "base64")]
+    {
+        let ser = SerBase64::new(KW::new(), Base64Config::STANDARD, &[]);
+        let de = |reader| DeserBase64::new(reader, Base64Config::STANDARD, Base64End::Len(0));
+        let val = check_empty_behavior_with(ser, 0, de, DeserBase64::already_read);
+        assert_eq!(val, []);
+    }
+
+    type ReadHeap = DeserBinaryHeap<ReadVarint<KR>, KR, u64, VarintError>;
+    let ser = SerBinaryHeap::<WriteVarint<KW>, _>::from_val(KW::new(), BinaryHeap::new());
+    let total = SerBinaryHeap::<WriteVarint<KW>, KW>::total_bytes(&BinaryHeap::new());
+    let val = check_empty_behavior_with(ser, total, ReadHeap::from_reader, ReadHeap::already_read);
+    assert!(val.is_empty());
+
+    type ReadSparse = DeserSparse<ReadVarint<KR>, KR, u64, VarintError>;
+    let ser = SerSparse::<WriteVarint<KW>, _>::new(KW::new(), 0, &[]);
+    let total = SerSparse::<WriteVarint<KW>, KW>::total_bytes(0, &[]);
+    let de = |reader| ReadSparse::new(reader, 0);
+    let val = check_empty_behavior_with(ser, total, de, ReadSparse::already_read);
+    assert_eq!(val, []);
+
+    let ser = WriteFrontCoded::from_ref(KW::new(), &[]);
+    let total = WriteFrontCoded::<KW>::total_bytes(&[]);
+    let de = ReadFrontCoded::from_reader;
+    let val = check_empty_behavior_with(ser, total, de, ReadFrontCoded::already_read);
+    assert_eq!(val, Vec::<String>::new());
+
+    // The mantissa and the scale are read separately, but must only be counted once.
+    let waker = Waker::from(Arc::new(CountWakes(AtomicUsize::new(0))));
+    let mut map = LocalMap::new();
+    let mut cx = Context::without_spawn(&mut map, &waker);
+    let mut de = ReadFixedPoint::from_reader(CountingReader::new(vec![0, 0]));
+    let (_, val, read) = poll_done(&mut de, &mut cx).unwrap().unwrap();
+    assert_eq!((val, read, de.already_read()), (FixedPoint::new(0, 0), 2, 2));
+    assert!(poll_done(&mut de, &mut cx).is_none());
+}
+
+#[test]
+fn flatten() {
+    type Nested = DeserializeError<DeserializeError<VarintError>>;
+    let eof = || FutIoErr::new(ErrorKind::UnexpectedEof, "eof");
+
+    let overflow = DeserializeError::DataError(VarintError::Overflow);
+    let data: Nested = DeserializeError::DataError(overflow);
+    assert_eq!(data.flatten(), DeserializeError::DataError(VarintError::Overflow));
+
+    let inner: Nested = DeserializeError::DataError(DeserializeError::ReaderError(eof()));
+    assert!(is_eof(&inner.flatten()));
+    let outer: Nested = DeserializeError::ReaderError(eof());
+    assert!(is_eof(&outer.flatten()));
+}
+
+type Hello<W, R> = Handshake<W, R, WriteBytes<W>, ReadBytes<R>>;
+
+#[test]
+fn handshake() {
+    // Both hellos are much larger than the pipes, so neither side can finish sending before the
+    // other one reads.
+    let (a_writer, b_reader) = pipe(1);
+    let (b_writer, a_reader) = pipe(1);
+    let a = Hello::new(a_writer, a_reader, vec![1; 100]);
+    let b = Hello::new(b_writer, b_reader, vec![2; 50]);
+    let (a, b) = join(a, b);
+    let (_, _, written, hello, read) = a.ok().unwrap();
+    assert_eq!((written, hello, read), (101, vec![2; 50], 51));
+    let (_, _, written, hello, read) = b.ok().unwrap();
+    assert_eq!((written, hello, read), (51, vec![1; 100], 101));
+}
+
+#[test]
+fn handshake_errors() {
+    // The hello is still sent if the hello of the peer is truncated.
+    let (a_writer, b_reader) = pipe(16);
+    let (b_writer, a_reader) = pipe(16);
+    drop(block_on(WriteVarint::from_val(b_writer, 5)).ok().unwrap());
+    let hello = Hello::new(a_writer, a_reader, vec![1; 10]);
+    let (writer, reader, err) = block_on(hello).err().unwrap();
+    match err {
+        HandshakeError::Receive(err) => assert!(is_eof(&err)),
+        HandshakeError::Send(err) => panic!("unexpected send error {}", err),
+    }
+    assert_eq!((writer.buffered(), reader.buffered(), b_reader.buffered()), (11, 0, 11));
+
+    let (a_writer, b_reader) = pipe(16);
+    let (b_writer, a_reader) = pipe(16);
+    drop((b_reader, b_writer));
+    let (_, _, err) = block_on(Hello::new(a_writer, a_reader, vec![1; 10])).err().unwrap();
+    match err {
+        HandshakeError::Send(err) => assert_eq!(err.kind(), ErrorKind::BrokenPipe),
+        HandshakeError::Receive(err) => panic!("unexpected receive error {:?}", err),
+    }
+}
+
+#[cfg(feature = "fuzz_support")]
+#[test]
+fn fuzz_support() {
+    type D<'a> = ReadVarint<FuzzReader<'a>>;
+    assert_eq!(drive_from_bytes::<D, _, _>(&[0xac, 0x02, 0xff]).unwrap(), (300, 2));
+    let err = drive_from_bytes::<D, _, _>(&[0xff; 11]).unwrap_err();
+    assert_eq!(data_err(err), VarintError::Overflow);
+    assert!(is_eof(&drive_from_bytes::<D, _, _>(&[0x80]).unwrap_err()));
+}
+
+// An always-ready reader over `data` that fails once with an error of the given kind when it
+// reaches the given position.
+#[derive(Debug)]
+struct FailingReader {
+    data: Vec<u8>,
+    position: usize,
+    fail: Option<(usize, ErrorKind)>,
+}
+
+impl FailingReader {
+    fn new(data: Vec<u8>, fail: Option<(usize, ErrorKind)>) -> FailingReader {
+        FailingReader {
+            data,
+            position: 0,
+            fail,
+        }
+    }
+}
+
+impl AsyncRead for FailingReader {
+    fn poll_read(&mut self, _: &mut Context, buf: &mut [u8]) -> Poll<usize, FutIoErr> {
+        let end = match self.fail {
+            Some((at, kind)) if at == self.position => {
+                self.fail = None;
+                return Err(FutIoErr::new(kind, "injected error"));
+            }
+            Some((at, _)) => at,
+            None => self.data.len(),
+        };
+        let len = buf.len().min(end - self.position);
+        buf[..len].copy_from_slice(&self.data[self.position..self.position + len]);
+        self.position += len;
+        Ok(Async::Ready(len))
+    }
+}
+
+#[test]
+fn buffered() {
+    let reader = FailingReader::new(vec![0xac, 0x02], Some((1, ErrorKind::Interrupted)));
+    let (replay, val, read) = block_on(Buffered::<ReadVarint<_>, _>::new(reader)).ok().unwrap();
+    assert_eq!((val, read, replay.recorded()), (300, 2, &[0xac, 0x02][..]));
+    assert_eq!(replay.pending_replay(), 0);
+
+    // After a fatal error, deserializing continues with the recorded bytes on a new reader.
+    let reader = FailingReader::new(vec![0xac, 0x02], Some((1, ErrorKind::ConnectionReset)));
+    let (mut replay, err) = block_on(Buffered::<ReadVarint<_>, _>::new(reader)).err().unwrap();
+    match err {
+        DeserializeError::ReaderError(err) => assert_eq!(err.kind(), ErrorKind::ConnectionReset),
+        DeserializeError::DataError(err) => panic!("unexpected data error {:?}", err),
+    }
+    assert_eq!(replay.recorded(), [0xac]);
+    replay.replace(FailingReader::new(vec![0x02], None));
+    let (_, val, read) = block_on(Buffered::<ReadVarint<_>, _>::retry(replay)).ok().unwrap();
+    assert_eq!((val, read), (300, 2));
+}
+
+#[test]
+fn grow_buf() {
+    let mut buf = GrowBuf::new(4, 20);
+    assert_eq!(buf.capacity(), 0);
+    let mut capacities = vec![];
+    for byte in 0..20 {
+        buf.push(byte).unwrap();
+        capacities.push(buf.capacity());
+    }
+    assert_eq!(capacities, [[4; 4], [8; 4], [16; 4], [16; 4], [20; 4]].concat());
+    assert_eq!(buf.push(20), Err(LimitExceeded { limit: 20 }));
+    assert_eq!((buf.len(), buf.capacity()), (20, 20));
+
+    // Once warmed up, a recycled buffer is never reallocated.
+    let mut reader = CountingReader::new(b"abcde\0".repeat(10_000));
+    let mut vec = Vec::new();
+    let mut allocation = None;
+    for _ in 0..10_000 {
+        let de = ReadTerminated::new(reader, 0, GrowBuf::seeded(vec, 4, 64));
+        let (new_reader, val, read) = block_on(de).ok().unwrap();
+        assert_eq!((&val[..], read, val.capacity()), (&b"abcde"[..], 6, 8));
+        assert_eq!(*allocation.get_or_insert(val.as_ptr()), val.as_ptr());
+        reader = new_reader;
+        vec = val;
+    }
+}
+
+const ENVELOPE_MAGIC: u32 = 0x4153_4552;
+type Envelope<W, const VERSION: u16> =
+    WriteEnvelope<WriteVarint<CrcWriter<W>>, W, ENVELOPE_MAGIC, VERSION>;
+type ReadEnvelopeV1To2 =
+    ReadEnvelope<ReadVarint<CrcReader<TakeReader<CR>>>, CR, u64, ENVELOPE_MAGIC, 1, 2>;
+
+#[test]
+fn envelope_roundtrip() {
+    let bytes = write::<Envelope<VW, 2>>(300);
+    assert_eq!(bytes[..14], [0x41, 0x53, 0x45, 0x52, 0, 2, 0, 0, 0, 0, 0, 0, 0, 2]);
+    assert_eq!(bytes[14..16], [0xac, 0x02]);
+    assert_eq!(bytes[16..], crc32(0, &[0xac, 0x02]).to_be_bytes());
+    assert_chunked_write::<Envelope<CW, 2>>(300, &bytes);
+
+    for version in &[1, 2] {
+        let mut bytes = bytes.clone();
+        bytes[5] = *version;
+        let de = ReadEnvelopeV1To2::from_reader(ChunkedReader::new(bytes, 1));
+        let (_, val, read) = block_on(de).unwrap();
+        assert_eq!((val, read), ((u16::from(*version), 300), 20));
+    }
+}
+
+#[test]
+fn envelope_errors() {
+    type Error = EnvelopeError<VarintError>;
+    let bytes = write::<Envelope<VW, 2>>(300);
+
+    let mut bad_magic = bytes.clone();
+    bad_magic[0] = 0;
+    assert_eq!(data_err(read_err::<ReadEnvelopeV1To2, _, _>(bad_magic)),
+               Error::BadMagic { found: 0x0053_4552 });
+    let unsupported = write::<Envelope<VW, 3>>(300);
+    assert_eq!(data_err(read_err::<ReadEnvelopeV1To2, _, _>(unsupported)),
+               Error::UnsupportedVersion { version: 3 });
+
+    let mut corrupted = bytes.clone();
+    corrupted[19] ^= 1;
+    let actual = crc32(0, &[0xac, 0x02]);
+    assert_eq!(data_err(read_err::<ReadEnvelopeV1To2, _, _>(corrupted)),
+               Error::CrcMismatch {
+                   expected: actual ^ 1,
+                   actual,
+               });
+
+    let mut too_long = bytes.clone();
+    too_long[13] = 3;
+    too_long.insert(16, 0);
+    assert_eq!(data_err(read_err::<ReadEnvelopeV1To2, _, _>(too_long)),
+               Error::LengthMismatch {
+                   declared: 3,
+                   consumed: 2,
+               });
+
+    let mut overflow = bytes[..13].to_vec();
+    overflow.push(11);
+    overflow.extend_from_slice(&[0xff; 11]);
+    assert_eq!(data_err(read_err::<ReadEnvelopeV1To2, _, _>(overflow)),
+               Error::Inner(VarintError::Overflow));
+    assert!(is_eof(&read_err::<ReadEnvelopeV1To2, _, _>(bytes[..18].to_vec())));
+}
+
+fn reader_err<E: Debug>(err: DeserializeError<E>) -> FutIoErr {
+    match err {
+        DeserializeError::ReaderError(err) => err,
+        DeserializeError::DataError(err) => panic!("unexpected data error {:?}", err),
+    }
+}
+
+#[test]
+fn read_timeout() {
+    let clock = MockClock::new();
+    let second = Duration::from_secs(1);
+    let reader = ReadTimeout::new(CR::new(vec![0xac, 0x02], 1), clock.clone(), second);
+    assert_eq!(block_on(ReadVarint::from_reader(reader)).unwrap().1, 300);
+
+    let wakes = Arc::new(CountWakes(AtomicUsize::new(0)));
+    let waker = Waker::from(wakes.clone());
+    let mut map = LocalMap::new();
+    let mut cx = Context::without_spawn(&mut map, &waker);
+    let (mut writer, reader) = pipe(4);
+    let mut varint = ReadVarint::from_reader(ReadTimeout::new(reader, clock.clone(), second));
+
+    // Polling twice registers the task with the clock only once.
+    assert!(poll_done(&mut varint, &mut cx).is_none());
+    assert!(poll_done(&mut varint, &mut cx).is_none());
+    clock.advance(Duration::from_millis(999));
+    assert_eq!(wakes.0.load(Ordering::SeqCst), 1);
+
+    // Reading a byte restarts the timer.
+    assert_eq!(writer.poll_write(&mut cx, &[0xac]).unwrap(), Async::Ready(1));
+    assert!(poll_done(&mut varint, &mut cx).is_none());
+    clock.advance(Duration::from_millis(999));
+    assert!(poll_done(&mut varint, &mut cx).is_none());
+    clock.advance(Duration::from_millis(1));
+    let (_, err) = poll_done(&mut varint, &mut cx).unwrap().err().unwrap();
+    assert_eq!(reader_err(err).kind(), ErrorKind::TimedOut);
+}
+
+#[test]
+fn write_timeout() {
+    let clock = MockClock::new();
+    let waker = Waker::from(Arc::new(CountWakes(AtomicUsize::new(0))));
+    let mut map = LocalMap::new();
+    let mut cx = Context::without_spawn(&mut map, &waker);
+    let (writer, mut reader) = pipe(1);
+    let writer = WriteTimeout::new(writer, clock.clone(), Duration::from_secs(1));
+
+    let mut varint = WriteVarint::from_val(writer, 300);
+    assert!(poll_done(&mut varint, &mut cx).is_none());
+    clock.advance(Duration::from_millis(999));
+    assert_eq!(reader.poll_read(&mut cx, &mut [0]).unwrap(), Async::Ready(1));
+    let (writer, written) = poll_done(&mut varint, &mut cx).unwrap().ok().unwrap();
+    assert_eq!(written, 2);
+
+    // The pipe is full, so nothing can be written until the timeout fires.
+    let mut varint = WriteVarint::from_val(writer, 1);
+    assert!(poll_done(&mut varint, &mut cx).is_none());
+    clock.advance(Duration::from_secs(1));
+    let (writer, err) = poll_done(&mut varint, &mut cx).unwrap().err().unwrap();
+    assert_eq!(err.kind(), ErrorKind::TimedOut);
+    assert_eq!(writer.into_inner().buffered(), 1);
+}
+
+#[test]
+fn idle_timeout() {
+    let clock = MockClock::new();
+    let waker = Waker::from(Arc::new(CountWakes(AtomicUsize::new(0))));
+    let mut map = LocalMap::new();
+    let mut cx = Context::without_spawn(&mut map, &waker);
+    let (mut writer, reader) = pipe(4);
+    let mut reader = IdleTimeout::new(reader, clock.clone(), Duration::from_secs(1));
+    let mut buf = [0; 4];
+
+    // Ready reads succeed, and restart the timer.
+    clock.advance(Duration::from_millis(600));
+    assert_eq!(writer.poll_write(&mut cx, &[1]).unwrap(), Async::Ready(1));
+    assert_eq!(reader.poll_read(&mut cx, &mut buf).unwrap(), Async::Ready(1));
+    clock.advance(Duration::from_millis(600));
+    assert!(reader.poll_read(&mut cx, &mut buf).unwrap().is_pending());
+    clock.advance(Duration::from_millis(400));
+    assert_eq!(reader.poll_read(&mut cx, &mut buf).unwrap_err().kind(), ErrorKind::TimedOut);
+
+    // The time in which no read is pending counts as well.
+    let (_writer, reader) = pipe(4);
+    let mut reader = IdleTimeout::new(reader, clock.clone(), Duration::from_secs(1));
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(reader.poll_read(&mut cx, &mut buf).unwrap_err().kind(), ErrorKind::TimedOut);
+}
+
+#[test]
+fn throttled() {
+    let clock = MockClock::new();
+    let wakes = Arc::new(CountWakes(AtomicUsize::new(0)));
+    let waker = Waker::from(wakes.clone());
+    let mut map = LocalMap::new();
+    let mut cx = Context::without_spawn(&mut map, &waker);
+    let second = Duration::from_secs(1);
+
+    let writer = Throttled::new(VecWriter::new(), clock.clone(), 2, second);
+    let mut bytes = WriteBytes::from_val(writer, vec![7; 5]);
+    for written in &[2, 4] {
+        assert!(poll_done(&mut bytes, &mut cx).is_none());
+        assert!(poll_done(&mut bytes, &mut cx).is_none());
+        assert_eq!(bytes.already_written(), *written);
+        let woken = wakes.0.load(Ordering::SeqCst);
+        clock.advance(second);
+        assert_eq!(wakes.0.load(Ordering::SeqCst), woken + 1);
+    }
+    let (writer, written) = poll_done(&mut bytes, &mut cx).unwrap().ok().unwrap();
+    assert_eq!((writer.into_inner().into_inner(), written), (vec![5, 7, 7, 7, 7, 7], 6));
+
+    let reader = Throttled::new(CountingReader::new(vec![0xac, 0x02]), clock.clone(), 1, second);
+    let mut varint = ReadVarint::from_reader(reader);
+    assert!(poll_done(&mut varint, &mut cx).is_none());
+    clock.advance(second);
+    assert_eq!(poll_done(&mut varint, &mut cx).unwrap().unwrap().1, 300);
+
+    let writer = Throttled::new(QW::new(VecWriter::new(), 1), clock.clone(), 2, second);
+    let (_, err) = block_on(WriteVarint::from_val(writer, 300)).err().unwrap();
+    assert_eq!(err.kind(), ErrorKind::WriteZero);
+}
+
+// Serializes like `WriteVarint`, but reports one byte too many once it is done.
+struct MiscountedVarint<W: AsyncWrite>(WriteVarint<W>);
+
+impl<W: AsyncWrite> Future for MiscountedVarint<W> {
+    type Item = (W, usize);
+    type Error = (W, FutIoErr);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        self.0.poll(cx)
+    }
+}
+
+impl<W: AsyncWrite> AsyncWriterFuture<W> for MiscountedVarint<W> {
+    fn already_written(&self) -> usize {
+        self.0.already_written() + usize::from(self.0.remaining_bytes() == 0)
+    }
+}
+
+impl<W: AsyncWrite> AsyncSerialize<W> for MiscountedVarint<W> {
+    type Serialized = u64;
+
+    fn from_val(writer: W, val: u64) -> Self {
+        MiscountedVarint(WriteVarint::from_val(writer, val))
+    }
+}
+
+#[test]
+fn consistency_checker() {
+    type Checked<W> = ConsistencyChecker<WriteVarint<WriteCounter<W>>, W>;
+    for &val in &[0, 300, u64::MAX] {
+        assert_roundtrip::<Checked<VW>, ReadVarint<CR>, _, _>(val);
+        assert_chunked_write::<Checked<CW>>(val, &write::<WriteVarint<VW>>(val));
+    }
+    assert_eq!(write_err::<Checked<QW>>(300, 1).kind(), ErrorKind::WriteZero);
+}
+
+#[test]
+#[should_panic(expected = "serializer reports 3 written bytes after completion, but 2 bytes")]
+fn consistency_checker_miscounted() {
+    let checker = ConsistencyChecker::<MiscountedVarint<_>, _>::from_val(VecWriter::new(), 300);
+    let _ = block_on(checker);
+}
+
+#[test]
+fn watchdog() {
+    // Pending before every byte, but making progress after every wakeup.
+    let writer = WatchdogWriter::new(ChunkedWriter::new(1), 1);
+    let (writer, written) = block_on(WriteBytes::from_val(writer, vec![7; 10])).unwrap();
+    let bytes = writer.into_inner().bytes().to_vec();
+    assert_eq!(written, 11);
+
+    let reader = WatchdogReader::new(ChunkedReader::new(bytes, 1), 1);
+    let (_, val, read) = block_on(ReadBytes::from_reader(reader)).unwrap();
+    assert_eq!((val, read), (vec![7; 10], 11));
+}
+
+#[test]
+#[should_panic(expected = "poll_read was called 4 times in a row without making progress")]
+fn watchdog_never_woken() {
+    let waker = Waker::from(Arc::new(CountWakes(AtomicUsize::new(0))));
+    let mut map = LocalMap::new();
+    let mut cx = Context::without_spawn(&mut map, &waker);
+    let (_writer, reader) = pipe(1);
+    let mut varint = ReadVarint::from_reader(WatchdogReader::new(reader, 3));
+    for _ in 0..4 {
+        assert!(poll_done(&mut varint, &mut cx).is_none());
+    }
+}
+
+type Goodbye<W, R> = GracefulClose<W, R, WriteVarint<W>, ReadVarint<R>, u64, MockClock>;
+
+fn goodbye<W, R>(writer: W, reader: R, collect: bool, clock: MockClock) -> Goodbye<W, R>
+    where W: AsyncWrite,
+          R: AsyncRead
+{
+    GracefulClose::new(writer,
+                       reader,
+                       0,
+                       |msg| *msg == 0,
+                       collect,
+                       clock,
+                       Duration::from_secs(1))
+}
+
+#[test]
+fn graceful_close() {
+    let (writer, reader, written, read, stragglers) =
+        block_on(goodbye(VW::new(), CR::new(vec![5, 7, 0, 9], 1), true, MockClock::new())).unwrap();
+    assert_eq!(writer.bytes(), &[0]);
+    assert_eq!((written, read, stragglers), (1, 3, vec![5, 7]));
+    assert_eq!(reader.position(), 3);
+
+    let (_, _, _, read, stragglers) =
+        block_on(goodbye(VW::new(), CR::new(vec![5, 7, 0], 1), false, MockClock::new())).unwrap();
+    assert_eq!((read, stragglers), (3, vec![]));
+}
+
+#[test]
+fn graceful_close_errors() {
+    let close = goodbye(QW::new(VW::new(), 0), CR::new(vec![0], 1), true, MockClock::new());
+    let (writer, reader, err) = block_on(close).err().unwrap();
+    match err {
+        GracefulCloseError::Send(err) => assert_eq!(err.kind(), ErrorKind::WriteZero),
+        _ => panic!("expected a send error"),
+    }
+    assert_eq!((writer.remaining_quota(), reader.unwrap().position()), (0, 0));
+
+    let close = goodbye(VW::new(), CR::new(vec![5], 1), true, MockClock::new());
+    let (writer, reader, err) = block_on(close).err().unwrap();
+    match err {
+        GracefulCloseError::Receive(err) => assert!(is_eof(&err)),
+        _ => panic!("expected a receive error"),
+    }
+    assert_eq!((writer.bytes(), reader.unwrap().position()), (&[0][..], 1));
+
+    // Timing out gives back the reader of the pending receive.
+    let waker = Waker::from(Arc::new(CountWakes(AtomicUsize::new(0))));
+    let mut map = LocalMap::new();
+    let mut cx = Context::without_spawn(&mut map, &waker);
+    let clock = MockClock::new();
+    let (mut peer, reader) = pipe(4);
+    let mut close = goodbye(VW::new(), reader, true, clock.clone());
+    assert_eq!(peer.poll_write(&mut cx, &[5, 0x80]).unwrap(), Async::Ready(2));
+    assert!(poll_done(&mut close, &mut cx).is_none());
+    clock.advance(Duration::from_secs(1));
+    let (writer, reader, err) = poll_done(&mut close, &mut cx).unwrap().err().unwrap();
+    match err {
+        GracefulCloseError::Timeout => {}
+        _ => panic!("expected a timeout"),
+    }
+    assert_eq!((writer.bytes(), reader.unwrap().buffered()), (&[0][..], 0));
+    assert!(poll_done(&mut close, &mut cx).is_none());
+
+    // A receiver that can not give back its reader still times out.
+    let clock = MockClock::new();
+    let (_peer, reader) = pipe(4);
+    let mut close: GracefulClose<_, _, WriteVarint<_>, Unrestorable<_>, _, _> =
+        GracefulClose::new(VW::new(),
+                           reader,
+                           0,
+                           |msg| *msg == 0,
+                           true,
+                           clock.clone(),
+                           Duration::from_secs(1));
+    assert!(poll_done(&mut close, &mut cx).is_none());
+    clock.advance(Duration::from_secs(1));
+    let (writer, reader, err) = poll_done(&mut close, &mut cx).unwrap().err().unwrap();
+    assert!(matches!(err, GracefulCloseError::Timeout));
+    assert_eq!(writer.bytes(), [0]);
+    assert!(reader.is_none());
+}
+
+// A `ReadVarint` that never gives back its reader.
+struct Unrestorable<R>(ReadVarint<R>);
+
+impl<R: AsyncRead> Future for Unrestorable<R> {
+    type Item = (R, u64, usize);
+    type Error = (R, DeserializeError<VarintError>);
+
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::Item, Self::Error> {
+        self.0.poll(cx)
+    }
+}
+
+impl<R: AsyncRead> AsyncDeserialize<R, u64, VarintError> for Unrestorable<R> {
+    fn from_reader(reader: R) -> Self {
+        Unrestorable(ReadVarint::from_reader(reader))
+    }
+
+    fn already_read(&self) -> usize {
+        self.0.already_read()
+    }
+}
+
+impl<R> Restorable<R> for Unrestorable<R> {
+    fn restore(self) -> Option<R> {
+        None
+    }
+}
+
+type ReadEntry<R> = DeserPair<ReadVarint<R>, DeserAsciiChar<R>, R, u64, char, VarintError,
+                              AsciiCharError>;
+
+#[test]
+fn pair() {
+    let entry = ReadEntry::from_reader(CR::new(vec![0xac, 0x02, b'x', 9], 1));
+    let (reader, pair, read) = block_on(entry).unwrap();
+    assert_eq!((pair, read, reader.position()), ((300, 'x'), 3, 3));
+
+    let err = read_err::<ReadEntry<_>, _, _>(vec![0xff; 11]);
+    assert_eq!(data_err(err), PairError::Key(VarintError::Overflow));
+    let err = read_err::<ReadEntry<_>, _, _>(vec![1, 0xff]);
+    assert_eq!(data_err(err), PairError::Value(AsciiCharError::NonAscii(0xff)));
+    assert!(is_eof(&read_err::<ReadEntry<_>, _, _>(vec![1])));
+
+    // Partially deserialized pairs keep the key, and point at the value.
+    let partial = ReadEntry::from_reader_partial(CR::new(vec![0xac, 0x02, 0xff], 1));
+    let (_, partial, read) = block_on(partial).unwrap();
+    assert_eq!(partial,
+               PartialResult::Partial {
+                   parsed: Some(300),
+                   error: PairError::Value(AsciiCharError::NonAscii(0xff)),
+                   offset: 2,
+               });
+    assert_eq!(read, 3);
+    let partial = ReadEntry::from_reader_partial(CR::new(vec![0xff; 11], 1));
+    let (_, partial, _) = block_on(partial).unwrap();
+    match partial {
+        PartialResult::Partial { parsed: None, offset: 0, .. } => {}
+        _ => panic!("expected the key to fail"),
+    }
+}
+
+type Sum<R> = ReadFold<ReadVarint<R>, R, u64, fn(u64, u64) -> u64>;
+
+fn sum<R: AsyncRead>(reader: R, max_count: u32) -> Sum<R> {
+    ReadFold::new(reader, max_count, 0, |acc, val| acc + val)
+}
+
+#[test]
+fn fold() {
+    let bytes = vec![0, 0, 0, 3, 1, 0xac, 0x02, 5, 9];
+    let (reader, total, read) = block_on(sum(CR::new(bytes, 1), 3)).unwrap();
+    assert_eq!((total, read, reader.position()), (306, 8, 8));
+    let (_, total, read) = block_on(sum(CR::new(vec![0; 4], 1), 0)).unwrap();
+    assert_eq!((total, read), (0, 4));
+
+    // The count is checked before any element is read.
+    let (reader, err) = block_on(sum(CR::new(vec![0, 0, 0, 4, 1, 2, 3, 4], 1), 3))
+        .err()
+        .unwrap();
+    assert_eq!(data_err(err), FoldError::TooMany { count: 4, max: 3 });
+    assert_eq!(reader.position(), 4);
+
+    let mut bytes = vec![0, 0, 0, 2, 1];
+    bytes.extend_from_slice(&[0xff; 11]);
+    let (_, err) = block_on(sum(CR::new(bytes, 1), 2)).err().unwrap();
+    assert_eq!(data_err(err), FoldError::Element(VarintError::Overflow));
+    let (_, err) = block_on(sum(CR::new(vec![0, 0, 0, 2, 1], 1), 2)).err().unwrap();
+    assert!(is_eof(&err));
+}
+
+type Merge =
+    MergeReader<ReadVarint<CR>, ReadVarint<CR>, CR, CR, u64, fn(&u64, &u64) -> CmpOrdering>;
+type MergeErr = MergeError<VarintError, VarintError>;
+
+// Merge two sequences, returning the merged elements and the error that ended the merge, if any.
+fn merged(a: Vec<u8>, b: Vec<u8>) -> (Vec<u64>, Option<MergeErr>, Merge) {
+    let waker = Waker::from(Arc::new(CountWakes(AtomicUsize::new(0))));
+    let mut map = LocalMap::new();
+    let mut cx = Context::without_spawn(&mut map, &waker);
+
+    let mut merge = Merge::new(CR::new(a, 1), CR::new(b, 1), |a, b| a.cmp(b));
+    let mut elements = vec![];
+    for _ in 0..1000 {
+        match merge.poll_next(&mut cx) {
+            Ok(Async::Ready(Some(element))) => elements.push(element),
+            Ok(Async::Ready(None)) => return (elements, None, merge),
+            Ok(Async::Pending) => {}
+            Err(err) => {
+                // Once failed, the stream stays pending rather than reading on.
+                for _ in 0..4 {
+                    assert!(merge.poll_next(&mut cx).unwrap().is_pending());
+                }
+                return (elements, Some(err), merge);
+            }
+        }
+    }
+    panic!("merge did not finish");
+}
+
+#[test]
+fn merge_reader() {
+    let (elements, err, merge) =
+        merged(vec![0, 0, 0, 3, 1, 4, 9], vec![0, 0, 0, 3, 2, 4, 0xac, 0x02]);
+    assert_eq!((elements, err.is_none()), (vec![1, 2, 4, 4, 9, 300], true));
+    let (a, b) = merge.into_inner().unwrap();
+    assert_eq!((a.position(), b.position()), (7, 8));
+
+    // Once one side ends, the rest of the other one is yielded.
+    let (elements, ..) = merged(vec![0, 0, 0, 1, 7], vec![0, 0, 0, 3, 1, 2, 8]);
+    assert_eq!(elements, [1, 2, 7, 8]);
+    let (elements, ..) = merged(vec![0, 0, 0, 2, 3, 5], vec![0, 0, 0, 0]);
+    assert_eq!(elements, [3, 5]);
+    let (elements, ..) = merged(vec![0; 4], vec![0; 4]);
+    assert_eq!(elements, []);
+}
+
+#[test]
+fn merge_reader_errors() {
+    let (elements, err, merge) = merged(vec![0, 0, 0, 2, 1], vec![0, 0, 0, 2, 2, 3]);
+    assert_eq!(elements, [1]);
+    match err {
+        Some(MergeError::A(err)) => assert!(is_eof(&err)),
+        other => panic!("expected the first sequence to fail, got {:?}", other),
+    }
+    let (a, b) = merge.into_inner().unwrap();
+    assert_eq!((a.position(), b.position()), (5, 5));
+
+    let mut b = vec![0, 0, 0, 1];
+    b.extend_from_slice(&[0xff; 11]);
+    let (elements, err, _) = merged(vec![0, 0, 0, 1, 1], b);
+    assert_eq!(elements, []);
+    match err {
+        Some(MergeError::B(err)) => assert_eq!(data_err(err), VarintError::Overflow),
+        other => panic!("expected the second sequence to fail, got {:?}", other),
+    }
+}
+
+#[test]
+fn quota_writer() {
+    let writer = QuotaWriter::new(VecWriter::new(), 5);
+    let (writer, written) = block_on(WriteVarint::from_val(writer, 300)).unwrap();
+    assert_eq!((written, writer.remaining_quota()), (2, 3));
+
+    // A value that does not fit is cut off where the quota ends.
+    assert!(WriteFixed32::<QW>::total_bytes(&7) as u64 > writer.remaining_quota());
+    let (mut writer, err) = block_on(WriteFixed32::from_val(writer, 7)).err().unwrap();
+    assert_eq!(err.kind(), ErrorKind::WriteZero);
+    assert_eq!(writer.remaining_quota(), 0);
+    assert_eq!(writer.get_ref().bytes(), &[0xac, 0x02, 7, 0, 0]);
+
+    writer.set_quota(1);
+    let (writer, _) = block_on(WriteVarint::from_val(writer, 1)).unwrap();
+    assert_eq!(writer.remaining_quota(), 0);
+    assert_eq!(write_err::<WriteVarint<_>>(1, 0).kind(), ErrorKind::WriteZero);
+    assert_eq!(writer.into_inner().into_inner(), [0xac, 0x02, 7, 0, 0, 1]);
+}
+
+type Counted<W> = SerializeCounted<W, WriteVarint<Spool<W>>, ::std::vec::IntoIter<u64>>;
+
+fn write_counted<W: AsyncWrite>(writer: W, elements: Vec<u64>, strategy: Strategy) -> Counted<W> {
+    SerializeCounted::new(writer, elements.into_iter(), strategy)
+}
+
+#[test]
+fn counted_buffer() {
+    let expected = [0, 0, 0, 3, 1, 0xac, 0x02, 5];
+    let counted = write_counted(CW::new(1), vec![1, 300, 5], Strategy::Buffer { max: 3 });
+    let (writer, written) = block_on(counted).unwrap();
+    assert_eq!((writer.bytes(), written), (&expected[..], 8));
+    let (_, total, _) = block_on(sum(CR::new(expected.to_vec(), 1), 3)).unwrap();
+    assert_eq!(total, 306);
+
+    // Too many elements are rejected without writing anything.
+    let counted = write_counted(VW::new(), vec![1, 300, 5], Strategy::Buffer { max: 2 });
+    let (writer, err) = block_on(counted).err().unwrap();
+    assert_eq!((err.kind(), writer.bytes()), (ErrorKind::InvalidInput, &[][..]));
+    let strategy = Strategy::Buffer { max: 3 };
+    let counted = write_counted(QW::new(VW::new(), 5), vec![1, 300, 5], strategy);
+    let (writer, err) = block_on(counted).err().unwrap();
+    assert_eq!((err.kind(), writer.get_ref().bytes()), (ErrorKind::WriteZero, &expected[..5]));
+}
+
+#[test]
+fn counted_backpatch() {
+    // Backpatching produces the same bytes as buffering the elements.
+    let counted = write_counted(CW::new(1), vec![1, 300, 5], Strategy::Backpatch);
+    let (writer, written) = block_on(counted).unwrap();
+    assert_eq!((writer.bytes(), written), (&[0, 0, 0, 3, 1, 0xac, 0x02, 5][..], 8));
+    let counted = write_counted(VW::new(), vec![], Strategy::Backpatch);
+    assert_eq!(block_on(counted).unwrap().0.into_inner(), [0, 0, 0, 0]);
+
+    // Nothing reaches the writer before all elements have been serialized.
+    let counted = write_counted(QW::new(VW::new(), 3), vec![1, 300, 5], Strategy::Backpatch);
+    assert_eq!(counted.already_written(), 0);
+    let (writer, err) = block_on(counted).err().unwrap();
+    assert_eq!((err.kind(), writer.get_ref().bytes()), (ErrorKind::WriteZero, &[0, 0, 0][..]));
+}
+
+#[test]
+fn counted_chunked() {
+    let counted = write_counted(CW::new(1), vec![1, 300, 5], Strategy::Chunked { chunk: 2 });
+    let (writer, written) = block_on(counted).unwrap();
+    let expected = [0, 0, 0, 2, 1, 0xac, 0x02, 0, 0, 0, 1, 5, 0, 0, 0, 0];
+    assert_eq!((writer.bytes(), written), (&expected[..], 16));
+
+    // Every chunk reads like a whole sequence, up to the empty one.
+    let mut reader = CR::new(expected.to_vec(), 1);
+    let mut totals = Vec::new();
+    loop {
+        let (next, total, read) = block_on(sum(reader, 2)).unwrap();
+        reader = next;
+        if read == 4 {
+            break;
+        }
+        totals.push(total);
+    }
+    assert_eq!((totals, reader.position()), (vec![301, 5], 16));
+
+    // A single chunk is the buffered encoding followed by the terminator.
+    let counted = write_counted(VW::new(), vec![1, 300, 5], Strategy::Chunked { chunk: 3 });
+    let bytes = block_on(counted).unwrap().0.into_inner();
+    assert_eq!(bytes, [0, 0, 0, 3, 1, 0xac, 0x02, 5, 0, 0, 0, 0]);
+
+    let strategy = Strategy::Chunked { chunk: 2 };
+    let counted = write_counted(QW::new(VW::new(), 6), vec![1, 300, 5], strategy);
+    let (writer, err) = block_on(counted).err().unwrap();
+    assert_eq!((err.kind(), writer.get_ref().bytes()), (ErrorKind::WriteZero, &expected[..6]));
+}
+
+#[test]
+#[should_panic(expected = "Chunked needs a positive chunk")]
+fn counted_chunked_empty_chunk() {
+    write_counted(VW::new(), vec![1], Strategy::Chunked { chunk: 0 });
+}
+
+type SerList<'val, W> = SerLinkedList<'val, SerCowBytes<'val, W>, W>;
+
+#[test]
+fn linked_list_roundtrip() {
+    type ReadList = DeserLinkedList<DeserArcBytes<CR>, CR, Arc<[u8]>, ArcBytesError>;
+
+    let list: LinkedList<Cow<[u8]>> = vec![Cow::Borrowed(&b"hi"[..]), Cow::Owned(vec![])]
+        .into_iter()
+        .collect();
+    let (writer, written) = block_on(SerList::from_ref(VW::new(), &list)).unwrap();
+    let bytes = writer.into_inner();
+    assert_eq!(bytes, [0, 0, 0, 2, 0, 0, 0, 2, b'h', b'i', 0, 0, 0, 0]);
+    assert_eq!(written, SerList::<VW>::total_bytes(&list));
+    let (writer, _) = block_on(SerList::from_ref(CW::new(1), &list)).unwrap();
+    assert_eq!(writer.bytes(), &bytes[..]);
+
+    let (reader, read_list, read) = block_on(ReadList::from_reader(CR::new(bytes, 1))).unwrap();
+    let read_list: Vec<&[u8]> = read_list.iter().map(|bytes| &bytes[..]).collect();
+    assert_eq!((read_list, read, reader.position()), (vec![&b"hi"[..], &[][..]], 14, 14));
+}
+
+#[test]
+fn linked_list_errors() {
+    type ReadList = DeserLinkedList<ReadVarint<CR>, CR, u64, VarintError>;
+
+    let list: LinkedList<Cow<[u8]>> = Some(Cow::Borrowed(&b"hi"[..])).into_iter().collect();
+    let ser = SerList::from_ref(QW::new(VW::new(), 5), &list);
+    let (writer, err) = block_on(ser).err().unwrap();
+    assert_eq!((err.kind(), writer.remaining_quota()), (ErrorKind::WriteZero, 0));
+
+    let mut bytes = vec![0, 0, 0, 2, 1];
+    bytes.extend_from_slice(&[0xff; 11]);
+    assert_eq!(data_err(read_err::<ReadList, _, _>(bytes.clone())), VarintError::Overflow);
+    assert!(is_eof(&read_err::<ReadList, _, _>(vec![0, 0, 0, 2, 1])));
+    assert!(is_eof(&read_err::<ReadList, _, _>(vec![0, 0])));
+
+    let partial = ReadList::from_reader_partial(CR::new(bytes, 1));
+    let (_, partial, _) = block_on(partial).unwrap();
+    assert_eq!(partial,
+               PartialResult::Partial {
+                   parsed: Some(1).into_iter().collect(),
+                   error: VarintError::Overflow,
+                   offset: 5,
+               });
+}
+
+type ReadSizedVarint = ReadSized<ReadVarint<SizedReader<CR>>, CR, u64, VarintError>;
+
+#[test]
+fn read_sized() {
+    let de = ReadSizedVarint::new(CR::new(vec![0xac, 0x02, 7], 1), 2);
+    let (reader, val, read) = block_on(de).unwrap();
+    assert_eq!((val, read, reader.position()), (300, 2, 2));
+}
+
+#[test]
+fn read_sized_errors() {
+    // The varint ends after two of the three declared bytes.
+    let de = ReadSizedVarint::new(CR::new(vec![0xac, 0x02, 7], 1), 3);
+    let (reader, err) = block_on(de).err().unwrap();
+    assert_eq!((data_err(err), reader.position()),
+               (SizedError::UnderRead {
+                    declared: 3,
+                    consumed: 2,
+                },
+                2));
+
+    // The varint continues past the declared byte.
+    let de = ReadSizedVarint::new(CR::new(vec![0xac, 0x02, 7], 1), 1);
+    match block_on(de).err().unwrap() {
+        (reader, DeserializeError::ReaderError(err)) => {
+            assert_eq!((err.kind(), reader.position()), (ErrorKind::InvalidData, 1));
+        }
+        (_, DeserializeError::DataError(err)) => panic!("unexpected data error {:?}", err),
+    }
+
+    let mut bytes = vec![0xff; 11];
+    bytes.push(0);
+    let de = ReadSizedVarint::new(CR::new(bytes, 1), 12);
+    assert_eq!(data_err(block_on(de).err().unwrap().1), SizedError::Inner(VarintError::Overflow));
+}
+
+#[test]
+fn range() {
+    for range in [0..0, 3..7, 5..5, 0..u64::MAX].iter() {
+        assert_roundtrip::<SerRange<VW>, DeserRange<CR>, _, _>(range.clone());
+    }
+    for range in [0..=0, 3..=7, 0..=u64::MAX].iter() {
+        assert_roundtrip::<SerRangeInclusive<VW>, DeserRangeInclusive<CR>, _, _>(range.clone());
+    }
+
+    // Both kinds of ranges share their encoding.
+    let expected = [0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 7];
+    assert_eq!(write::<SerRange<VW>>(3..7), expected);
+    assert_eq!(write::<SerRangeInclusive<VW>>(3..=7), expected);
+    assert_chunked_write::<SerRange<CW>>(3..7, &expected);
+}
+
+#[test]
+fn range_errors() {
+    let bytes = [7u64.to_be_bytes(), 3u64.to_be_bytes()].concat();
+    let (reader, err) = block_on(DeserRange::from_reader(CR::new(bytes.clone(), 1))).err().unwrap();
+    assert_eq!((data_err(err), reader.position()),
+               (RangeError::InvalidRange { start: 7, end: 3 }, RANGE_BYTES));
+    assert_eq!(data_err(read_err::<DeserRangeInclusive<CR>, _, _>(bytes)),
+               RangeError::InvalidRange { start: 7, end: 3 });
+
+    assert!(is_eof(&read_err::<DeserRange<CR>, _, _>(vec![0; RANGE_BYTES - 1])));
+    assert_eq!(write_err::<SerRange<QW>>(3..7, 8).kind(), ErrorKind::WriteZero);
+}
+
+#[test]
+fn fixed_point() {
+    for &(mantissa, scale) in &[(0, 0), (-150, 2), (i64::MAX, MAX_SCALE), (i64::MIN, 3)] {
+        let val = FixedPoint::new(mantissa, scale);
+        assert_roundtrip::<WriteFixedPoint<VW>, ReadFixedPoint<CR>, _, _>(val);
+    }
+    assert_eq!(write::<WriteFixedPoint<VW>>(FixedPoint::new(-150, 2)), [0xab, 0x02, 2]);
+    assert_eq!(FixedPoint::new(-150, 2).to_string(), "-1.50");
+}
+
+#[test]
+fn fixed_point_errors() {
+    assert_eq!(data_err(read_err::<ReadFixedPoint<CR>, _, _>(vec![0xab, 0x02, MAX_SCALE + 1])),
+               FixedPointError::InvalidScale {
+                   scale: MAX_SCALE + 1,
+                   max: MAX_SCALE,
+               });
+
+    // A custom maximum, the scale is checked after reading it.
+    let de = ReadFixedPoint::new(CR::new(vec![0xab, 0x02, 3, 0], 1), 2);
+    let (reader, err) = block_on(de).err().unwrap();
+    assert_eq!((data_err(err), reader.position()),
+               (FixedPointError::InvalidScale { scale: 3, max: 2 }, 3));
+    let de = ReadFixedPoint::new(CR::new(vec![0xab, 0x02, 2], 1), 2);
+    assert_eq!(block_on(de).unwrap().1, FixedPoint::new(-150, 2));
+
+    let mut bytes = vec![0xff; 11];
+    bytes.push(0);
+    assert_eq!(data_err(read_err::<ReadFixedPoint<CR>, _, _>(bytes)),
+               FixedPointError::VarintOverflow);
+    assert!(is_eof(&read_err::<ReadFixedPoint<CR>, _, _>(vec![0xab, 0x02])));
+}
+
+#[test]
+fn binary_heap_roundtrip() {
+    type WriteHeap<W> = SerBinaryHeap<WriteVarint<W>, W>;
+    type ReadHeap = DeserBinaryHeap<ReadVarint<CR>, CR, u64, VarintError>;
+
+    let heap: BinaryHeap<u64> = vec![300, 1, 7, 1].into_iter().collect();
+    // The elements are written in ascending order.
+    let expected = [0, 0, 0, 4, 1, 1, 7, 0xac, 0x02];
+    assert_eq!(write::<WriteHeap<VW>>(heap.clone()), expected);
+    assert_chunked_write::<WriteHeap<CW>>(heap, &expected);
+
+    // But they can be read in any order.
+    let bytes = vec![0, 0, 0, 3, 7, 0xac, 0x02, 1];
+    let (reader, heap, read) = block_on(ReadHeap::from_reader(CR::new(bytes, 1))).unwrap();
+    assert_eq!((heap.into_sorted_vec(), read, reader.position()), (vec![1, 7, 300], 8, 8));
+}
+
+#[test]
+fn binary_heap_errors() {
+    type WriteHeap = SerBinaryHeap<WriteVarint<QW>, QW>;
+    type ReadHeap = DeserBinaryHeap<ReadVarint<CR>, CR, u64, VarintError>;
+
+    let heap: BinaryHeap<u64> = vec![300, 1].into_iter().collect();
+    assert_eq!(write_err::<WriteHeap>(heap.clone(), 2).kind(), ErrorKind::WriteZero);
+    assert_eq!(write_err::<WriteHeap>(heap, 6).kind(), ErrorKind::WriteZero);
+
+    let mut bytes = vec![0, 0, 0, 2, 1];
+    bytes.extend_from_slice(&[0xff; 11]);
+    assert_eq!(data_err(read_err::<ReadHeap, _, _>(bytes)), VarintError::Overflow);
+    assert!(is_eof(&read_err::<ReadHeap, _, _>(vec![0, 0, 0, 2, 1])));
+    assert!(is_eof(&read_err::<ReadHeap, _, _>(vec![0, 0, 0])));
+}
+
+#[test]
+#[should_panic(expected = "deserializer reported a wrong number of read bytes")]
+fn assert_roundtrip_leftover() {
+    type Redundant<W> = WriteRedundant<WriteVarint<RedundantWriter<W>>, W>;
+
+    // Only the first copy of a redundant value is read.
+    assert_roundtrip::<Redundant<VW>, ReadVarint<CR>, _, _>(1);
+}
+
+type Heartbeats<W> = WithHeartbeat<WriteBytes<W>, W, MockClock, ::std::vec::IntoIter<Vec<u8>>>;
+
+fn heartbeats<W: AsyncWrite>(writer: W, values: Vec<Vec<u8>>, clock: &MockClock) -> Heartbeats<W> {
+    let second = Duration::from_secs(1);
+    WithHeartbeat::new(writer, values.into_iter(), vec![0xff], clock.clone(), second)
+}
+
+#[test]
+fn heartbeat() {
+    let waker = Waker::from(Arc::new(CountWakes(AtomicUsize::new(0))));
+    let mut map = LocalMap::new();
+    let mut cx = Context::without_spawn(&mut map, &waker);
+    let clock = MockClock::new();
+
+    // A heartbeat is written before the next value once the interval has passed, and the interval
+    // starts over after it.
+    let mut fut = heartbeats(CW::new(1), vec![vec![1], vec![2], vec![3]], &clock);
+    clock.advance(Duration::from_secs(1));
+    for _ in 0..5 {
+        assert!(poll_done(&mut fut, &mut cx).is_none());
+    }
+    assert_eq!(fut.heartbeat_bytes(), 1);
+    clock.advance(Duration::from_secs(1));
+    let (writer, written, sent) = block_on(fut).unwrap();
+    assert_eq!((writer.bytes(), written, sent), (&[0xff, 1, 1, 1, 2, 0xff, 1, 3][..], 6, 2));
+
+    let fut = heartbeats(VW::new(), vec![vec![1], vec![2]], &clock);
+    let (writer, written, sent) = block_on(fut).unwrap();
+    assert_eq!((writer.bytes(), written, sent), (&[1, 1, 1, 2][..], 4, 0));
+}
+
+#[test]
+fn heartbeat_between_values() {
+    let waker = Waker::from(Arc::new(CountWakes(AtomicUsize::new(0))));
+    let mut map = LocalMap::new();
+    let mut cx = Context::without_spawn(&mut map, &waker);
+    let clock = MockClock::new();
+
+    // The interval passes repeatedly while the first value is written one byte at a time, but
+    // the heartbeat waits for the value to be complete.
+    let mut fut = heartbeats(CW::new(1), vec![vec![1, 2, 3], vec![4]], &clock);
+    for _ in 0..3 {
+        assert!(poll_done(&mut fut, &mut cx).is_none());
+        clock.advance(Duration::from_secs(1));
+    }
+    let (writer, written, sent) = block_on(fut).unwrap();
+    assert_eq!((writer.bytes(), written, sent), (&[3, 1, 2, 3, 0xff, 1, 4][..], 6, 1));
+}
+
+#[test]
+fn heartbeat_errors() {
+    let clock = MockClock::new();
+    clock.advance(Duration::from_secs(1));
+    let fut = heartbeats(QW::new(VW::new(), 0), vec![vec![1]], &clock);
+    let (writer, err) = block_on(fut).err().unwrap();
+    assert_eq!((err.kind(), writer.get_ref().bytes()), (ErrorKind::WriteZero, &[][..]));
+
+    let fut = heartbeats(QW::new(VW::new(), 2), vec![vec![1, 2]], &MockClock::new());
+    let (writer, err) = block_on(fut).err().unwrap();
+    assert_eq!((err.kind(), writer.get_ref().bytes()), (ErrorKind::WriteZero, &[2, 1][..]));
+}
+
+
+
+#[test]
+fn read_progress() {
+    let bytes = vec![0, 0, 0, 3, 1, 2, 3];
+    let mut events = vec![];
+    let (_, val, read) = {
+        let de = DeserArcBytes::from_reader(CR::new(bytes.clone(), 1));
+        block_on(WithReadProgress::new(de, |read, remaining| events.push((read, remaining))))
+            .unwrap()
+    };
+    assert_eq!((&val[..], read), (&[1, 2, 3][..], 7));
+    assert_eq!(events,
+               vec![(1, None),
+                    (2, None),
+                    (3, None),
+                    (4, Some(3)),
+                    (5, Some(2)),
+                    (6, Some(1)),
+                    (7, Some(0))]);
+
+    // A deserializer that reads everything in a single poll only reports its completion.
+    let mut events = vec![];
+    {
+        let de = DeserArcBytes::from_reader(&bytes[..]);
+        block_on(WithReadProgress::new(de, |read, remaining| events.push((read, remaining))))
+            .unwrap();
+    }
+    assert_eq!(events, vec![(7, Some(0))]);
+
+    // Used as a plain deserializer, the progress is still available from the wrapper.
+    let reader = CR::new(bytes, 1);
+    let mut de: WithReadProgress<DeserArcBytes<_>, _> = AsyncDeserialize::from_reader(reader);
+    let waker = Waker::from(Arc::new(CountWakes(AtomicUsize::new(0))));
+    let mut map = LocalMap::new();
+    let mut cx = Context::without_spawn(&mut map, &waker);
+    for _ in 0..6 {
+        assert!(de.poll(&mut cx).unwrap().is_pending());
+    }
+    assert_eq!((de.already_read(), de.payload_remaining()), (5, Some(2)));
+    let (_, val, read) = block_on(de).unwrap();
+    assert_eq!((&val[..], read), (&[1, 2, 3][..], 7));
+}
+
+#[test]
+fn read_progress_errors() {
+    let mut events = vec![];
+    let (_, err) = {
+        let de = DeserArcBytes::from_reader(CR::new(vec![0, 0, 0, 3, 1], 1));
+        block_on(WithReadProgress::new(de, |read, remaining| events.push((read, remaining))))
+            .err()
+            .unwrap()
+    };
+    assert!(is_eof(&err));
+    // No final report for a failed deserialization.
+    assert_eq!(events, vec![(1, None), (2, None), (3, None), (4, Some(3)), (5, Some(2))]);
+}
+
+
+
+// Encodes a value as its index among the previously seen values, or as 100 plus the value if it
+// has not been seen yet.
+fn dictionary<W: AsyncWrite>(seen: &mut Vec<u64>, writer: W, val: u64) -> WriteVarint<W> {
+    let encoded = match seen.iter().position(|s| *s == val) {
+        Some(index) => index as u64,
+        None => {
+            seen.push(val);
+            100 + val
+        }
+    };
+    WriteVarint::from_val(writer, encoded)
+}
+
+#[test]
+fn stateful_encoder() {
+    let mut encoder = StatefulEncoder::new(vec![], dictionary);
+    let mut writer = CW::new(1);
+    for val in &[7, 3, 7, 7, 3, 1] {
+        let field = encoder.serialize(writer, *val);
+        assert_eq!(field.remaining_bytes(), 1);
+        let (w, written) = block_on(field).unwrap();
+        assert_eq!(written, 1);
+        writer = w;
+    }
+    assert_eq!(writer.bytes(), [107, 103, 0, 0, 1, 101]);
+    assert_eq!(encoder.into_state(), vec![7, 3, 1]);
+
+    // The context is updated when the field is created, not when it is written.
+    let mut encoder = StatefulEncoder::new(vec![], dictionary);
+    let field = encoder.serialize(VecWriter::new(), 5);
+    assert_eq!(field.state(), &vec![5]);
+    drop(field);
+    let (writer, _) = block_on(encoder.serialize(VecWriter::new(), 5)).unwrap();
+    assert_eq!(writer.bytes(), [0]);
+}
+
+#[test]
+fn stateful_encoder_errors() {
+    let mut encoder = StatefulEncoder::new(vec![], dictionary);
+    let (writer, err) = block_on(encoder.serialize(QuotaWriter::new(VecWriter::new(), 0), 9))
+        .err()
+        .unwrap();
+    assert_eq!(err.kind(), ErrorKind::WriteZero);
+    assert_eq!(writer.get_ref().bytes(), []);
+    // The failed field still updated the context, so the stream can not be continued.
+    assert_eq!(encoder.state(), &vec![9]);
+}
+
+